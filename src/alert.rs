@@ -0,0 +1,146 @@
+use alloy::transports::http::reqwest::{Client, Url};
+use daggboard::indexer::Indexer;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::sleep;
+use tracing::warn;
+
+// Seconds between lag checks.
+const CHECK_INTERVAL_SECS: u64 = 60;
+
+// Consecutive checks over the threshold before alerting, so that a short spike
+// (eg a slow rpc for a minute) doesn't trigger an alert.
+const SUSTAINED_CHECKS: u32 = 3;
+
+// Decides, from the lag seen at each check, when a rollup is due an alert.
+struct LagTracker {
+    threshold: u64,
+    cooldown: Duration,
+    over_threshold: HashMap<u32, u32>,
+    last_alert: HashMap<u32, Instant>,
+}
+
+impl LagTracker {
+    fn new(threshold: u64, cooldown: Duration) -> Self {
+        LagTracker {
+            threshold,
+            cooldown,
+            over_threshold: HashMap::new(),
+            last_alert: HashMap::new(),
+        }
+    }
+
+    // Records a check and returns whether an alert should be sent.
+    fn check(&mut self, rollup_id: u32, distance: u64) -> bool {
+        if distance <= self.threshold {
+            self.over_threshold.remove(&rollup_id);
+            return false;
+        }
+
+        let checks = self.over_threshold.entry(rollup_id).or_insert(0);
+        *checks += 1;
+        if *checks < SUSTAINED_CHECKS {
+            return false;
+        }
+
+        let in_cooldown = self
+            .last_alert
+            .get(&rollup_id)
+            .map(|sent| sent.elapsed() < self.cooldown)
+            .unwrap_or(false);
+        !in_cooldown
+    }
+
+    // Only delivered alerts start the cooldown, so a failed one is retried.
+    fn alerted(&mut self, rollup_id: u32) {
+        self.last_alert.insert(rollup_id, Instant::now());
+    }
+}
+
+// Posts an alert to `webhook` when an indexer stays more than `threshold`
+// blocks behind the head. Alerts for the same rollup are sent at most once
+// per `cooldown`. Runs forever, meant to be spawned.
+pub async fn watch_lag(indexers: Vec<Indexer>, webhook: Url, threshold: u64, cooldown: Duration) {
+    let client = Client::new();
+    let mut tracker = LagTracker::new(threshold, cooldown);
+
+    loop {
+        sleep(Duration::from_secs(CHECK_INTERVAL_SECS)).await;
+
+        for indexer in indexers.iter() {
+            let rollup_id = indexer.rollup_id;
+            // The rpc being down is reported by the indexer health, not here.
+            let distance = match indexer.distance_head().await.map_err(|e| e.to_string()) {
+                Ok(distance) => distance,
+                Err(e) => {
+                    warn!(rollup_id, "Lag check failed: {}", e);
+                    continue;
+                }
+            };
+
+            if !tracker.check(rollup_id, distance) {
+                continue;
+            }
+
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            let payload = json!({
+                "rollup_id": rollup_id,
+                "distance": distance,
+                "timestamp": timestamp,
+            });
+
+            match client.post(webhook.clone()).json(&payload).send().await {
+                Ok(response) if response.status().is_success() => {
+                    tracker.alerted(rollup_id);
+                }
+                Ok(response) => {
+                    warn!(rollup_id, "Alert webhook returned {}", response.status());
+                }
+                Err(e) => {
+                    warn!(rollup_id, "Error sending alert: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alerts_on_sustained_lag_once_per_cooldown() {
+        let mut tracker = LagTracker::new(100, Duration::from_secs(3600));
+
+        // A spike that recovers doesn't alert, and resets the count.
+        assert!(!tracker.check(1, 500));
+        assert!(!tracker.check(1, 500));
+        assert!(!tracker.check(1, 100));
+        assert!(!tracker.check(1, 500));
+        assert!(!tracker.check(1, 500));
+        assert!(tracker.check(1, 500));
+
+        // Undelivered alerts are retried at the next check.
+        assert!(tracker.check(1, 500));
+        tracker.alerted(1);
+        assert!(!tracker.check(1, 500));
+
+        // Other rollups are tracked on their own.
+        assert!(!tracker.check(2, 500));
+    }
+
+    #[test]
+    fn alerts_again_after_the_cooldown() {
+        let mut tracker = LagTracker::new(100, Duration::ZERO);
+        for _ in 1..SUSTAINED_CHECKS {
+            assert!(!tracker.check(1, 101));
+        }
+        assert!(tracker.check(1, 101));
+        tracker.alerted(1);
+        assert!(tracker.check(1, 101));
+    }
+}
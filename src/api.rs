@@ -1,249 +1,4016 @@
+use alloy::primitives::{Address, Bytes, I256};
 use axum::{
     extract::{Extension, Path, Query},
-    routing::get,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
     Json, Router,
 };
-use daggboard::indexer::Indexer;
-use duckdb::Connection;
+use daggboard::database::Database;
+use daggboard::indexer::{Histogram, Indexer, DURATION_BUCKETS};
+use daggboard::models::{BridgeEventRow, ClaimEventRow, TransferRow};
+use daggboard::utils::{decode_token_metadata, redact_url};
+use duckdb::{Connection, OptionalExt};
+use lru::LruCache;
 use serde_json::Value as JsonValue;
 use serde_json::{json, Value};
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    num::NonZeroUsize,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use subtle::ConstantTimeEq;
 use tokio::sync::Mutex;
+use tower::ServiceBuilder;
+use tower_http::request_id::{
+    MakeRequestId, PropagateRequestIdLayer, RequestId, SetRequestIdLayer,
+};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::{error, info, Level};
 
 // TODO: Improve error handling, no unwraps
 
-pub fn create_router(db: Arc<Mutex<Connection>>, indexers: Vec<Indexer>) -> Router {
+// Rows returned by the purpose-built endpoints when no limit is given.
+const DEFAULT_LIMIT: usize = 100;
+
+pub fn create_router(
+    db: Arc<Mutex<Connection>>,
+    indexers: Vec<Indexer>,
+    server_config: ServerConfig,
+    cache: ResponseCache,
+) -> Router {
     Router::new()
         .route("/tables", get(list_tables))
+        .route("/tables/detailed", get(list_tables_detailed))
         .route("/table/{table_name}", get(get_all_rows))
         .route("/table/{table_name}/filter", get(filter_rows))
+        .route("/table/{table_name}/schema", get(get_table_schema))
+        .route("/table/{table_name}/{id}", get(get_row_by_id))
         .route("/wrapped_balance", get(get_circulating_supply))
         .route("/bridge_balance", get(get_balance_bridge))
+        .route("/reconcile", get(reconcile))
+        .route("/rollup/{rollup_id}/tokens/top", get(top_tokens))
+        .route("/tokens/active", get(active_tokens))
+        .route("/bridges", get(get_bridges))
+        .route("/bridges/top", get(top_bridge_transfers))
+        .route("/holders", get(token_holders))
+        .route("/proxy_events", get(get_proxy_events))
+        .route("/anomalies", get(get_anomalies))
+        .route("/rollups", get(list_rollups))
+        .route("/networks", get(list_networks))
+        .route("/stats/cardinality", get(get_cardinality))
         .route("/sync/{rollup_id}", get(sync_rollup))
+        .route("/coverage", get(get_coverage))
+        .route("/indexer/{rollup_id}/rescan", post(rescan_rollup))
+        .route("/reindex-token", post(reindex_token))
+        .route("/blocks/{number}", get(get_block_events))
+        .route("/activity", get(get_activity))
+        .route("/search", get(search_address))
+        .route("/config", get(get_config))
+        .route("/openapi.json", get(openapi))
+        .route("/docs", get(docs))
         .layer(Extension(db))
         .layer(Extension(indexers))
+        .layer(Extension(server_config))
+        .layer(Extension(cache))
 }
 
-async fn sync_rollup(
-    Extension(db): Extension<Arc<Mutex<Connection>>>, // retained to keep layer order but unused
-    Extension(indexers): Extension<Vec<Indexer>>,
-    Path(rollup_id): Path<u32>,
-) -> Json<Value> {
-    if let Some(indexer) = indexers.iter().find(|i| i.rollup_id == rollup_id) {
-        match indexer.distance_head().await {
-            Ok(distance) => Json(json!({ "distance": distance })),
-            Err(e) => Json(json!({ "error": format!("{}", e) })),
+// Settings of the process that are not part of the indexers, already with
+// secrets redacted. Built in main from the cli, see /config.
+#[derive(Debug, Clone)]
+pub struct ServerConfig(pub Value);
+
+// Tags each request with an x-request-id, the client one if sent or a
+// generated one, and echoes it in the response. Requests are logged within a
+// span carrying the id, so are the logs of their handlers, eg to tie a slow
+// /query to them.
+pub fn trace_requests<S>(router: Router<S>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router.layer(
+        ServiceBuilder::new()
+            .layer(SetRequestIdLayer::x_request_id(GeneratedRequestId))
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(request_span)
+                    .on_response(DefaultOnResponse::new().level(Level::INFO)),
+            )
+            .layer(PropagateRequestIdLayer::x_request_id()),
+    )
+}
+
+fn request_span(request: &axum::http::Request<axum::body::Body>) -> tracing::Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %request.uri().path(),
+        request_id,
+    )
+}
+
+// Id of the requests sent without one, see trace_requests.
+#[derive(Clone)]
+struct GeneratedRequestId;
+
+impl MakeRequestId for GeneratedRequestId {
+    fn make_request_id<B>(&mut self, _: &axum::http::Request<B>) -> Option<RequestId> {
+        header::HeaderValue::from_str(&generate_request_id())
+            .ok()
+            .map(RequestId::new)
+    }
+}
+
+// Unique within the process and unlikely to clash across restarts.
+fn generate_request_id() -> String {
+    static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("{:x}-{:x}", nanos, count)
+}
+
+// Limits applied to the expensive endpoints (not /health nor /metrics).
+// Requests over a limit get a 429 straight away instead of queueing on the db.
+#[derive(Clone)]
+pub struct ApiLimits {
+    concurrency: Option<Arc<tokio::sync::Semaphore>>,
+    rate: Option<Arc<std::sync::Mutex<TokenBucket>>>,
+}
+
+// Allows `per_sec` requests per second, with bursts of up to one second worth.
+struct TokenBucket {
+    per_sec: f64,
+    tokens: f64,
+    last: std::time::Instant,
+}
+
+impl TokenBucket {
+    fn try_take(&mut self) -> bool {
+        let now = std::time::Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(self.per_sec);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
         }
-    } else {
-        Json(json!({ "error": "Rollup not found" }))
     }
 }
 
-async fn list_tables(Extension(db): Extension<Arc<Mutex<Connection>>>) -> Json<Value> {
-    let db = db.lock().await;
-    let mut stmt = match db.prepare("PRAGMA show_tables") {
-        Ok(s) => s,
-        Err(e) => return Json(json!({ "error": format!("{}", e) })),
-    };
-    let mut tables = Vec::new();
-    let mut rows = match stmt.query([]) {
-        Ok(r) => r,
-        Err(e) => return Json(json!({ "error": format!("{}", e) })),
-    };
-    while let Ok(Some(row)) = rows.next() {
-        if let Ok(t) = row.get::<usize, String>(0) {
-            tables.push(t);
+impl ApiLimits {
+    pub fn new(max_concurrency: Option<usize>, rate_per_sec: Option<u32>) -> Self {
+        ApiLimits {
+            concurrency: max_concurrency.map(|n| Arc::new(tokio::sync::Semaphore::new(n))),
+            rate: rate_per_sec.map(|r| {
+                Arc::new(std::sync::Mutex::new(TokenBucket {
+                    per_sec: r as f64,
+                    tokens: r as f64,
+                    last: std::time::Instant::now(),
+                }))
+            }),
         }
     }
-    Json(json!({ "tables": tables }))
 }
 
-async fn get_all_rows(
-    Extension(db): Extension<Arc<Mutex<Connection>>>,
-    Path(table_name): Path<String>,
-    Query(params): Query<HashMap<String, String>>,
-) -> Json<Value> {
-    let db = db.lock().await;
+// Responses kept by ResponseCache, least recently used evicted first.
+const RESPONSE_CACHE_SIZE: usize = 1024;
 
-    let columns = match fetch_columns(&db, &table_name) {
-        Ok(cols) => cols,
-        Err(e) => return Json(json!({ "error": format!("{}", e) })),
-    };
+// Responses of the balance and stats endpoints, keyed by endpoint and query
+// parameters. An entry is served until its ttl expires or an event of its
+// rollup is inserted (see Database::generation), so dashboards polling
+// historical data don't rerun the aggregations. A zero ttl disables it.
+#[derive(Clone)]
+pub struct ResponseCache {
+    database: Database,
+    ttl: Duration,
+    entries: Arc<std::sync::Mutex<LruCache<String, CachedResponse>>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
 
-    let struct_pack_expr = format!("STRUCT_PACK({})", columns.join(", "));
+struct CachedResponse {
+    rollup_id: u32,
+    generation: u64,
+    stored_at: Instant,
+    value: Value,
+}
 
-    let limit_clause = params
-        .get("limit")
-        .and_then(|l| l.parse::<usize>().ok())
-        .map(|l| format!("LIMIT {}", l))
-        .unwrap_or_default();
+impl ResponseCache {
+    pub fn new(database: Database, ttl: Duration) -> Self {
+        let capacity = NonZeroUsize::new(RESPONSE_CACHE_SIZE).unwrap();
+        ResponseCache {
+            database,
+            ttl,
+            entries: Arc::new(std::sync::Mutex::new(LruCache::new(capacity))),
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+        }
+    }
 
-    let query = format!(
-        "SELECT to_json({}) AS row_json FROM {} {}",
-        struct_pack_expr, table_name, limit_clause
-    );
+    fn get(&self, key: &str) -> Option<Value> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let fresh = entries.get(key).and_then(|entry| {
+            (entry.stored_at.elapsed() < self.ttl
+                && entry.generation == self.database.generation(entry.rollup_id))
+            .then(|| entry.value.clone())
+        });
+        match fresh {
+            Some(value) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(value)
+            }
+            None => {
+                entries.pop(key);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
 
-    let mut stmt = match db.prepare(&query) {
-        Ok(s) => s,
-        Err(_) => return Json(json!({ "error": "Invalid query" })),
+    // The generation is read by the caller before running the query, so an
+    // insert racing with it invalidates the entry rather than being missed.
+    fn insert(&self, key: String, rollup_id: u32, generation: u64, value: &Value) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        self.entries.lock().unwrap().put(
+            key,
+            CachedResponse {
+                rollup_id,
+                generation,
+                stored_at: Instant::now(),
+                value: value.clone(),
+            },
+        );
+    }
+}
+
+// Cache key of a request, the endpoint and its sorted query parameters.
+fn cache_key(endpoint: &str, params: &HashMap<String, String>) -> String {
+    let params: BTreeMap<_, _> = params.iter().collect();
+    format!("{}?{:?}", endpoint, params)
+}
+
+pub async fn limit_requests(
+    axum::extract::State(limits): axum::extract::State<ApiLimits>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    if let Some(rate) = &limits.rate {
+        if !rate.lock().unwrap().try_take() {
+            return (StatusCode::TOO_MANY_REQUESTS, "rate limit exceeded").into_response();
+        }
+    }
+    // Held until the response is built.
+    let _permit = match &limits.concurrency {
+        Some(semaphore) => match semaphore.clone().try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            Err(_) => {
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "too many concurrent requests",
+                )
+                    .into_response()
+            }
+        },
+        None => None,
     };
+    next.run(request).await
+}
 
-    let mut rows = match stmt.query([]) {
-        Ok(r) => r,
-        Err(_) => return Json(json!({ "error": "Query execution failed" })),
+// Re-encodes json responses as msgpack when the client sends
+// `Accept: application/msgpack`. Applied to the whole app, so it covers every
+// endpoint including /query. The json is buffered to re-encode it, responses
+// over `max_bytes` (see --max-response-bytes) fail with a 413 instead.
+#[cfg(feature = "msgpack")]
+pub async fn negotiate_encoding(
+    axum::extract::State(max_bytes): axum::extract::State<Option<usize>>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let wants_msgpack = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.contains("application/msgpack"))
+        .unwrap_or(false);
+
+    let response = next.run(request).await;
+    if !wants_msgpack || !is_json(&response) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, max_bytes.unwrap_or(usize::MAX)).await {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Response larger than the limit, paginate with limit or request json",
+            )
+                .into_response()
+        }
     };
+    let encoded = serde_json::from_slice::<Value>(&bytes)
+        .map_err(|e| e.to_string())
+        .and_then(|value| rmp_serde::to_vec_named(&value).map_err(|e| e.to_string()));
+    match encoded {
+        Ok(encoded) => {
+            parts.headers.insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static("application/msgpack"),
+            );
+            parts.headers.remove(header::CONTENT_LENGTH);
+            axum::response::Response::from_parts(parts, axum::body::Body::from(encoded))
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
 
-    let mut result = Vec::new();
-    while let Ok(Some(row)) = rows.next() {
-        let row_json: String = row.get("row_json").unwrap_or_default();
-        if let Ok(json_value) = serde_json::from_str::<JsonValue>(&row_json) {
-            result.push(json_value);
+// Drops the 0x prefix of the addresses, hashes, roots and metadata in json
+// responses, lowercasing them (see --no-0x-prefix). Only applied with the
+// flag. The json is buffered to rewrite it, responses over `max_bytes` (see
+// --max-response-bytes) fail with a 413 instead.
+pub async fn strip_hex_prefix(
+    axum::extract::State(max_bytes): axum::extract::State<Option<usize>>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let response = next.run(request).await;
+    if !is_json(&response) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, max_bytes.unwrap_or(usize::MAX)).await {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Response larger than the limit, paginate with limit",
+            )
+                .into_response()
+        }
+    };
+    let mut value = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(value) => value,
+        Err(_) => {
+            return axum::response::Response::from_parts(parts, axum::body::Body::from(bytes))
+        }
+    };
+    strip_hex_fields(&mut value);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    axum::response::Response::from_parts(parts, axum::body::Body::from(value.to_string()))
+}
+
+// Strips the hex strings under the keys of hex fields, wherever they are
+// nested. Other strings are left alone, even if they look like hex.
+fn strip_hex_fields(value: &mut Value) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(strip_hex_fields),
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_hex_field(key) {
+                    strip_hex_strings(value);
+                } else {
+                    strip_hex_fields(value);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn strip_hex_strings(value: &mut Value) {
+    match value {
+        Value::String(s) => {
+            if let Some(digits) = s.strip_prefix("0x").filter(|digits| is_hex_digits(digits)) {
+                *s = digits.to_ascii_lowercase();
+            }
         }
+        Value::Array(items) => items.iter_mut().for_each(strip_hex_strings),
+        _ => {}
     }
+}
 
-    Json(json!({ "data": result }))
+fn is_hex_field(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    ["address", "addresses", "hash", "root", "metadata"]
+        .iter()
+        .any(|suffix| key.ends_with(suffix))
 }
 
-async fn filter_rows(
+// An empty value keeps its 0x, an empty string would read as missing.
+fn is_hex_digits(digits: &str) -> bool {
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_hex(value: &str) -> bool {
+    value.strip_prefix("0x").is_some_and(is_hex_digits)
+}
+
+fn is_json(response: &axum::response::Response) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("application/json"))
+        .unwrap_or(false)
+}
+
+// Router with the operational endpoints. Served either by the main api or on
+// a separate (internal) address, see --metrics-addr.
+pub fn create_metrics_router(
+    indexers: Vec<Indexer>,
+    database: Database,
+    cache: ResponseCache,
+) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics))
+        .route("/health", get(health))
+        .route("/ready", get(ready))
+        .layer(Extension(indexers))
+        .layer(Extension(database))
+        .layer(Extension(cache))
+}
+
+// Router with the maintenance endpoints. Every request must carry the admin
+// api key in the x-api-key header.
+pub fn create_admin_router(database: Database, api_key: String) -> Router {
+    Router::new()
+        .route("/admin/checkpoint", post(checkpoint))
+        .route("/admin/stats", get(admin_stats))
+        .layer(axum::middleware::from_fn_with_state(
+            Arc::new(api_key),
+            require_api_key,
+        ))
+        .layer(Extension(database.db().clone()))
+        .layer(Extension(database))
+        .layer(Extension(LastCheckpoint::default()))
+}
+
+// The key is compared in constant time, so that the response time doesn't
+// tell how much of a guess was right.
+async fn require_api_key(
+    axum::extract::State(api_key): axum::extract::State<Arc<String>>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let authorized = request
+        .headers()
+        .get("x-api-key")
+        .map(|v| bool::from(v.as_bytes().ct_eq(api_key.as_bytes())))
+        .unwrap_or(false);
+    if !authorized {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({ "error": "Invalid or missing api key" })),
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+// Unix time of the last checkpoint triggered through the api. duckdb also
+// checkpoints on its own, those are not tracked.
+#[derive(Clone, Default)]
+struct LastCheckpoint(Arc<std::sync::Mutex<Option<u64>>>);
+
+async fn checkpoint(
     Extension(db): Extension<Arc<Mutex<Connection>>>,
-    Path(table_name): Path<String>,
-    Query(params): Query<HashMap<String, String>>,
+    Extension(last_checkpoint): Extension<LastCheckpoint>,
+) -> (StatusCode, Json<Value>) {
+    let db = db.lock().await;
+    if let Err(e) = db.execute_batch("CHECKPOINT;") {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": format!("{}", e) })),
+        );
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    *last_checkpoint.0.lock().unwrap() = Some(now);
+    (StatusCode::OK, Json(json!({ "checkpoint_at": now })))
+}
+
+// Database size as reported by duckdb and the row count of each table.
+async fn admin_stats(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Extension(database): Extension<Database>,
+    Extension(last_checkpoint): Extension<LastCheckpoint>,
 ) -> Json<Value> {
+    let last_write_ok = database.last_write_ok();
+    let ignored_duplicates: serde_json::Map<String, Value> = database
+        .ignored_duplicates()
+        .into_iter()
+        .map(|(rollup_id, count)| (rollup_id.to_string(), json!(count)))
+        .collect();
     let db = db.lock().await;
 
-    let columns = match fetch_columns(&db, &table_name) {
-        Ok(cols) => cols,
+    let size = db.query_row(
+        "SELECT database_size, block_size, total_blocks, used_blocks, wal_size \
+        FROM pragma_database_size() LIMIT 1",
+        [],
+        |row| {
+            Ok(json!({
+                "database_size": row.get::<usize, String>(0)?,
+                "block_size": row.get::<usize, i64>(1)?,
+                "total_blocks": row.get::<usize, i64>(2)?,
+                "used_blocks": row.get::<usize, i64>(3)?,
+                "wal_size": row.get::<usize, String>(4)?,
+            }))
+        },
+    );
+    let size = match size {
+        Ok(size) => size,
         Err(e) => return Json(json!({ "error": format!("{}", e) })),
     };
 
-    let struct_pack_expr = format!("STRUCT_PACK({})", columns.join(", "));
+    let tables = match table_names(&db) {
+        Ok(tables) => tables,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
 
-    let mut conditions = params
-        .iter()
-        .filter(|(k, _)| *k != "limit")
-        .map(|(k, v)| format!("{} = '{}'", k, v.replace("'", "''")))
-        .collect::<Vec<_>>();
+    let mut row_counts = serde_json::Map::new();
+    for table in tables {
+        match row_count(&db, &table) {
+            Ok(count) => {
+                row_counts.insert(table, json!(count));
+            }
+            Err(e) => return Json(json!({ "error": format!("{}", e) })),
+        }
+    }
 
-    let where_clause = if conditions.is_empty() {
-        String::new()
-    } else {
-        format!("WHERE {}", conditions.join(" AND "))
-    };
+    let last_checkpoint = *last_checkpoint.0.lock().unwrap();
+    Json(json!({
+        "size": size,
+        "row_counts": row_counts,
+        "last_checkpoint": last_checkpoint,
+        "last_write_ok": last_write_ok,
+        "ignored_duplicates": ignored_duplicates,
+    }))
+}
 
-    let limit_clause = params
-        .get("limit")
-        .and_then(|l| l.parse::<usize>().ok())
-        .map(|l| format!("LIMIT {}", l))
-        .unwrap_or_default();
+// Documented endpoints as (path, method, summary, parameters), parameters
+// being (name, location, required). Update it along with the routes.
+type ApiParam = (&'static str, &'static str, bool);
+const API_ENDPOINTS: [(&str, &str, &str, &[ApiParam]); 36] = [
+    (
+        "/query",
+        "get",
+        "Runs a read only sql query",
+        &[("q", "query", true)],
+    ),
+    (
+        "/query/batch",
+        "post",
+        "Runs several read only sql queries, body is [{q, params}]",
+        &[],
+    ),
+    ("/tables", "get", "Lists the tables", &[]),
+    (
+        "/tables/detailed",
+        "get",
+        "Lists the tables with their row count and columns",
+        &[],
+    ),
+    (
+        "/table/{table_name}",
+        "get",
+        "Rows of a table",
+        &[("table_name", "path", true), ("limit", "query", false)],
+    ),
+    (
+        "/table/{table_name}/filter",
+        "get",
+        "Rows of a table matching every other query parameter as column = value",
+        &[("table_name", "path", true), ("limit", "query", false)],
+    ),
+    (
+        "/table/{table_name}/schema",
+        "get",
+        "Columns of a table with their type, nullability and primary key flag",
+        &[("table_name", "path", true)],
+    ),
+    (
+        "/table/{table_name}/{id}",
+        "get",
+        "Single event row by its id",
+        &[("table_name", "path", true), ("id", "path", true)],
+    ),
+    (
+        "/wrapped_balance",
+        "get",
+        "Circulating supply of a wrapped token",
+        &[
+            ("rollup_id", "query", true),
+            ("token_address", "query", true),
+        ],
+    ),
+    (
+        "/bridge_balance",
+        "get",
+        "Balance of the bridge in a token",
+        &[
+            ("rollup_id", "query", true),
+            ("token_address", "query", true),
+        ],
+    ),
+    (
+        "/reconcile",
+        "get",
+        "Bridge balance against the circulating supply of a token",
+        &[
+            ("rollup_id", "query", true),
+            ("token_address", "query", true),
+        ],
+    ),
+    (
+        "/rollup/{rollup_id}/tokens/top",
+        "get",
+        "Top tokens of a rollup by supply or transfers",
+        &[
+            ("rollup_id", "path", true),
+            ("by", "query", false),
+            ("limit", "query", false),
+        ],
+    ),
+    (
+        "/tokens/active",
+        "get",
+        "Tokens by their last transfer, most recently active first",
+        &[
+            ("rollup_id", "query", false),
+            ("since", "query", false),
+            ("since_block", "query", false),
+            ("limit", "query", false),
+        ],
+    ),
+    (
+        "/bridges",
+        "get",
+        "Bridge events",
+        &[
+            ("rollup_id", "query", false),
+            ("origin_network", "query", false),
+            ("destination_network", "query", false),
+            ("leaf_type", "query", false),
+            ("include_unconfirmed", "query", false),
+            ("network_names", "query", false),
+            ("token_metadata", "query", false),
+            ("limit", "query", false),
+        ],
+    ),
+    (
+        "/bridges/top",
+        "get",
+        "Largest transfers in and out of the bridge of a rollup",
+        &[
+            ("rollup_id", "query", true),
+            ("token_address", "query", false),
+            ("limit", "query", false),
+        ],
+    ),
+    (
+        "/holders",
+        "get",
+        "Addresses holding a wrapped token by balance, largest first",
+        &[
+            ("rollup_id", "query", true),
+            ("token_address", "query", true),
+            ("limit", "query", false),
+        ],
+    ),
+    (
+        "/proxy_events",
+        "get",
+        "Admin, upgrade, initialization and emergency state events of the bridges",
+        &[
+            ("rollup_id", "query", false),
+            ("event", "query", false),
+            ("limit", "query", false),
+        ],
+    ),
+    (
+        "/anomalies",
+        "get",
+        "Claims inconsistent with their deposit or the l1 global exit roots (--reorg-safe-l1)",
+        &[
+            ("rollup_id", "query", false),
+            ("reason", "query", false),
+            ("limit", "query", false),
+        ],
+    ),
+    (
+        "/rollups",
+        "get",
+        "Indexed rollups with their metadata",
+        &[],
+    ),
+    (
+        "/networks",
+        "get",
+        "Names of the network ids used in bridge and claim events",
+        &[],
+    ),
+    (
+        "/stats/cardinality",
+        "get",
+        "Distinct tokens, senders and receivers of a rollup",
+        &[("rollup_id", "query", true)],
+    ),
+    (
+        "/sync/{rollup_id}",
+        "get",
+        "Sync status of a rollup",
+        &[("rollup_id", "path", true)],
+    ),
+    (
+        "/config",
+        "get",
+        "Effective configuration, with rpc urls redacted",
+        &[],
+    ),
+    (
+        "/coverage",
+        "get",
+        "Indexed block ranges and gaps of a rollup",
+        &[("rollup_id", "query", true)],
+    ),
+    (
+        "/indexer/{rollup_id}/rescan",
+        "post",
+        "Indexes the last blocks of a rollup again",
+        &[("rollup_id", "path", true), ("blocks", "query", true)],
+    ),
+    (
+        "/reindex-token",
+        "post",
+        "Backfills the transfers of a wrapped token",
+        &[
+            ("rollup_id", "query", true),
+            ("token_address", "query", true),
+            ("from_block", "query", true),
+        ],
+    ),
+    (
+        "/blocks/{number}",
+        "get",
+        "Events of a block",
+        &[("number", "path", true), ("rollup_id", "query", true)],
+    ),
+    (
+        "/activity",
+        "get",
+        "Events of a rollup across tables in chronological order",
+        &[
+            ("rollup_id", "query", true),
+            ("from", "query", false),
+            ("to", "query", false),
+            ("from_time", "query", false),
+            ("to_time", "query", false),
+            ("limit", "query", false),
+            ("offset", "query", false),
+        ],
+    ),
+    (
+        "/search",
+        "get",
+        "Events involving an address",
+        &[
+            ("address", "query", true),
+            ("rollup_id", "query", false),
+            ("limit", "query", false),
+        ],
+    ),
+    ("/openapi.json", "get", "This document", &[]),
+    ("/docs", "get", "Swagger UI for this document", &[]),
+    // Served on --metrics-addr instead if set.
+    ("/metrics", "get", "Prometheus metrics", &[]),
+    ("/health", "get", "Liveness", &[]),
+    (
+        "/ready",
+        "get",
+        "Readiness, 503 if the database doesn't answer",
+        &[],
+    ),
+    (
+        "/admin/checkpoint",
+        "post",
+        "Checkpoints the database, requires the x-api-key header",
+        &[],
+    ),
+    (
+        "/admin/stats",
+        "get",
+        "Database stats, requires the x-api-key header",
+        &[],
+    ),
+];
 
-    let query = format!(
-        "SELECT to_json({}) AS row_json FROM {} {} {}",
-        struct_pack_expr, table_name, where_clause, limit_clause
-    );
+async fn openapi() -> Json<Value> {
+    let mut paths = serde_json::Map::new();
+    for (path, method, summary, params) in API_ENDPOINTS.iter() {
+        let parameters: Vec<Value> = params
+            .iter()
+            .map(|(name, location, required)| {
+                json!({
+                    "name": name,
+                    "in": location,
+                    "required": required,
+                    "schema": { "type": "string" },
+                })
+            })
+            .collect();
+        let operation = json!({
+            "summary": summary,
+            "parameters": parameters,
+            "responses": {
+                "200": {
+                    "description": "OK",
+                    "content": { "application/json": { "schema": { "type": "object" } } },
+                },
+            },
+        });
+        let entry = paths.entry(path.to_string()).or_insert_with(|| json!({}));
+        entry[*method] = operation;
+    }
 
-    let mut stmt = match db.prepare(&query) {
-        Ok(s) => s,
-        Err(_) => return Json(json!({ "error": "Table not found or invalid query" })),
-    };
+    Json(json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "daggboard",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+    }))
+}
 
-    let mut rows = match stmt.query([]) {
-        Ok(r) => r,
-        Err(_) => return Json(json!({ "error": "Invalid query" })),
-    };
+// Swagger UI for /openapi.json, loaded from a cdn.
+async fn docs() -> axum::response::Html<&'static str> {
+    axum::response::Html(
+        r##"<!DOCTYPE html>
+<html>
+<head>
+  <title>daggboard api</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>"##,
+    )
+}
 
-    let mut result = Vec::new();
-    while let Ok(Some(row)) = rows.next() {
-        let row_json: String = row.get("row_json").unwrap_or_default();
-        if let Ok(json_value) = serde_json::from_str::<JsonValue>(&row_json) {
-            result.push(json_value);
+// Ready to serve: the database answers. 503 otherwise, so a load balancer
+// stops routing to the instance.
+async fn ready(Extension(database): Extension<Database>) -> (StatusCode, Json<Value>) {
+    let last_write_ok = database.last_write_ok();
+    match database.health_check().await.map_err(|e| e.to_string()) {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(json!({ "ready": true, "last_write_ok": last_write_ok })),
+        ),
+        Err(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "ready": false, "error": e, "last_write_ok": last_write_ok })),
+        ),
+    }
+}
+
+async fn health() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+// Exposes the sync status of each indexer in prometheus text format.
+async fn metrics(
+    Extension(indexers): Extension<Vec<Indexer>>,
+    Extension(database): Extension<Database>,
+    Extension(cache): Extension<ResponseCache>,
+) -> impl IntoResponse {
+    // Every rollup metric is labeled with the rollup id and name, so that a
+    // dashboard can be templated over either.
+    let names = network_names(&*database.db().lock().await).unwrap_or_default();
+
+    let mut last_indexed = String::new();
+    let mut distance = String::new();
+    let mut ignored = String::new();
+    let mut window_duration = String::new();
+    let mut get_logs_duration = String::new();
+    for indexer in indexers.iter() {
+        let name = names
+            .get(&(indexer.rollup_id as i64))
+            .cloned()
+            .flatten()
+            .unwrap_or_default();
+        let labels = format!(
+            "rollup_id=\"{}\",network_name=\"{}\"",
+            indexer.rollup_id,
+            escape_label(&name)
+        );
+        let count = indexer
+            .database
+            .ignored_duplicates()
+            .get(&indexer.rollup_id)
+            .copied()
+            .unwrap_or_default();
+        ignored.push_str(&format!(
+            "daggboard_ignored_duplicates_total{{{}}} {}\n",
+            labels, count
+        ));
+        if let Ok(Some(block)) = indexer.database.last_indexed_block(indexer.rollup_id).await {
+            last_indexed.push_str(&format!(
+                "daggboard_last_indexed_block{{{}}} {}\n",
+                labels, block
+            ));
         }
+        if let Ok(d) = indexer.distance_head().await {
+            distance.push_str(&format!(
+                "daggboard_distance_head_blocks{{{}}} {}\n",
+                labels, d
+            ));
+        }
+        let timings = indexer.timings.lock().unwrap().clone();
+        window_duration.push_str(&histogram_lines(
+            "daggboard_window_duration_seconds",
+            &labels,
+            &timings.window,
+        ));
+        get_logs_duration.push_str(&histogram_lines(
+            "daggboard_get_logs_duration_seconds",
+            &labels,
+            &timings.get_logs,
+        ));
+    }
+
+    let body = format!(
+        "# HELP daggboard_last_indexed_block Last block indexed by the rollup indexer.\n\
+         # TYPE daggboard_last_indexed_block gauge\n\
+         {}\
+         # HELP daggboard_distance_head_blocks Blocks between the last indexed block and the chain head.\n\
+         # TYPE daggboard_distance_head_blocks gauge\n\
+         {}\
+         # HELP daggboard_ignored_duplicates_total Event inserts ignored because the row was already indexed.\n\
+         # TYPE daggboard_ignored_duplicates_total counter\n\
+         {}\
+         # HELP daggboard_window_duration_seconds Time to fetch and store a window of blocks.\n\
+         # TYPE daggboard_window_duration_seconds histogram\n\
+         {}\
+         # HELP daggboard_get_logs_duration_seconds Latency of the eth_getLogs calls.\n\
+         # TYPE daggboard_get_logs_duration_seconds histogram\n\
+         {}\
+         # HELP daggboard_api_cache_hits_total Balance and stats responses served from the cache.\n\
+         # TYPE daggboard_api_cache_hits_total counter\n\
+         daggboard_api_cache_hits_total {}\n\
+         # HELP daggboard_api_cache_misses_total Balance and stats responses computed from the database.\n\
+         # TYPE daggboard_api_cache_misses_total counter\n\
+         daggboard_api_cache_misses_total {}\n",
+        last_indexed,
+        distance,
+        ignored,
+        window_duration,
+        get_logs_duration,
+        cache.hits.load(Ordering::Relaxed),
+        cache.misses.load(Ordering::Relaxed)
+    );
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+// Prometheus text lines of a histogram: the cumulative buckets, sum and count.
+fn histogram_lines(name: &str, labels: &str, histogram: &Histogram) -> String {
+    let mut lines = String::new();
+    for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.buckets) {
+        lines.push_str(&format!(
+            "{}_bucket{{{},le=\"{}\"}} {}\n",
+            name, labels, bound, count
+        ));
     }
+    lines.push_str(&format!(
+        "{}_bucket{{{},le=\"+Inf\"}} {}\n\
+         {}_sum{{{}}} {}\n\
+         {}_count{{{}}} {}\n",
+        name, labels, histogram.count, name, labels, histogram.sum, name, labels, histogram.count
+    ));
+    lines
+}
 
-    Json(json!({ "data": result }))
+// Escapes a prometheus label value.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
 }
 
-async fn get_circulating_supply(
+async fn sync_rollup(
+    Extension(_db): Extension<Arc<Mutex<Connection>>>, // retained to keep layer order but unused
+    Extension(indexers): Extension<Vec<Indexer>>,
+    Path(rollup_id): Path<u32>,
+) -> Json<Value> {
+    if let Some(indexer) = indexers.iter().find(|i| i.rollup_id == rollup_id) {
+        let health = indexer.health();
+        let distance = indexer.distance_head().await.map_err(|e| format!("{}", e));
+        let mut response = json!({
+            "pinned_head": indexer.pinned_head,
+            "paused": health.paused,
+            "consecutive_failures": health.consecutive_failures,
+            "last_error": health.last_error,
+        });
+        match distance {
+            Ok(distance) => response["distance"] = json!(distance),
+            Err(e) => response["error"] = json!(e),
+        }
+        Json(response)
+    } else {
+        Json(json!({ "error": "Rollup not found" }))
+    }
+}
+
+// Indexed block ranges of a rollup, merged, and the gaps between them from
+// the start block up to the sync marker. Blocks indexed before the ranges were
+// recorded are covered by a range seeded up to the cursor of that time.
+async fn get_coverage(
     Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Extension(indexers): Extension<Vec<Indexer>>,
     Query(params): Query<HashMap<String, String>>,
 ) -> Json<Value> {
-    let db = db.lock().await;
-
-    let rollup_id = match params.get("rollup_id") {
-        Some(id) => id,
+    let rollup_id = match params.get("rollup_id").map(|id| id.parse::<u32>()) {
+        Some(Ok(id)) => id,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid rollup_id parameter" })),
         None => return Json(json!({ "error": "Missing rollup_id parameter" })),
     };
+    let Some(indexer) = indexers.iter().find(|i| i.rollup_id == rollup_id) else {
+        return Json(json!({ "error": "Rollup not found" }));
+    };
+    let last_indexed_block = match indexer.database.last_indexed_block(rollup_id).await {
+        Ok(block) => block,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
 
-    let token_address = match params.get("token_address") {
-        Some(address) => address,
-        None => return Json(json!({ "error": "Missing token_address parameter" })),
+    let db = db.lock().await;
+    let mut stmt = match db.prepare(
+        "SELECT from_block, to_block FROM indexed_ranges WHERE rollup_id = ? \
+        ORDER BY from_block, to_block",
+    ) {
+        Ok(s) => s,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
+    let ranges = stmt.query_map([rollup_id], |row| {
+        Ok((
+            row.get::<usize, i64>(0)? as u64,
+            row.get::<usize, i64>(1)? as u64,
+        ))
+    });
+    let ranges = match ranges.and_then(|rows| rows.collect::<Result<Vec<_>, _>>()) {
+        Ok(ranges) => ranges,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
     };
 
-    let query = format!(
-        "SELECT SUM(CASE \
-            WHEN from_address = '0x0000000000000000000000000000000000000000' THEN CAST(value AS HUGEINT) \
-            WHEN to_address = '0x0000000000000000000000000000000000000000' THEN -CAST(value AS HUGEINT) \
-            ELSE 0 END) AS balance \
-        FROM wrapped_transfer_events \
-        WHERE LOWER(token_address) = LOWER('{}') AND rollup_id = {}",
-        token_address, rollup_id
-    );
+    let merged = merge_ranges(ranges);
+    // Nothing is expected to be covered before the first window.
+    let gaps = last_indexed_block
+        .map(|block| coverage_gaps(&merged, indexer.start_block, block))
+        .unwrap_or_default();
+    Json(json!({
+        "start_block": indexer.start_block,
+        "last_indexed_block": last_indexed_block,
+        "ranges": merged,
+        "gaps": gaps,
+    }))
+}
+
+// Merges sorted, possibly overlapping or adjacent, block ranges.
+pub fn merge_ranges(ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    let mut merged: Vec<(u64, u64)> = Vec::new();
+    for (from, to) in ranges {
+        match merged.last_mut() {
+            Some(last) if from <= last.1 + 1 => last.1 = last.1.max(to),
+            _ => merged.push((from, to)),
+        }
+    }
+    merged
+}
 
-    let balance = aggregate_bigint(&db, &query).unwrap_or_else(|_| "0".to_string());
-    Json(json!({ "circulating_supply": balance }))
+// Ranges in [start, end] not covered by the merged ranges.
+pub fn coverage_gaps(merged: &[(u64, u64)], start: u64, end: u64) -> Vec<(u64, u64)> {
+    let mut gaps = Vec::new();
+    let mut next = start;
+    for (from, to) in merged {
+        if *from > next && next <= end {
+            gaps.push((next, (*from - 1).min(end)));
+        }
+        next = next.max(to + 1);
+    }
+    if next <= end {
+        gaps.push((next, end));
+    }
+    gaps
 }
 
-async fn get_balance_bridge(
-    Extension(db): Extension<Arc<Mutex<Connection>>>,
-    Query(params): Query<HashMap<String, String>>,
+// Effective configuration, for debugging. Rpc urls are redacted.
+async fn get_config(
+    Extension(indexers): Extension<Vec<Indexer>>,
+    Extension(server_config): Extension<ServerConfig>,
 ) -> Json<Value> {
-    let db = db.lock().await;
+    let indexer_config = indexers.first().map(|i| {
+        let c = &i.config;
+        json!({
+            "max_filter_addresses": c.max_filter_addresses,
+            "progress_chunk_size": c.progress_chunk_size,
+            "max_consecutive_failures": c.max_consecutive_failures,
+            "health_probe_interval_secs": c.health_probe_interval_secs,
+            "rpc_pool_max_idle": c.rpc_pool_max_idle,
+            "rpc_timeout_secs": c.rpc_timeout_secs,
+            "per_rollup_concurrency": c.per_rollup_concurrency,
+            "backfill_direction": format!("{:?}", c.backfill_direction).to_lowercase(),
+            "index_transfers": c.index_transfers,
+            "skip_zero_value_transfers": c.skip_zero_value_transfers,
+            "index_block_timestamps": c.index_block_timestamps,
+            "block_timestamp_cache_size": c.block_timestamp_cache_size,
+            "confirmations": c.confirmations,
+            "strict_decode": c.strict_decode,
+            "max_rescan_blocks": c.max_rescan_blocks,
+        })
+    });
 
-    let rollup_id = match params.get("rollup_id") {
-        Some(id) => id,
-        None => return Json(json!({ "error": "Missing rollup_id parameter" })),
+    let rollups: Vec<Value> = indexers
+        .iter()
+        .map(|i| {
+            let (bridge_address, rpc_url) = i.endpoint.lock().unwrap().clone();
+            json!({
+                "rollup_id": i.rollup_id,
+                "rpc_url": redact_url(&rpc_url),
+                "bridge_address": bridge_address.to_string(),
+                "start_block": i.start_block,
+                "block_increment": i.get_block_increment(),
+                "rollup_manager_address": i.rollup_manager_address.map(|a| a.to_string()),
+                "pinned_head": i.pinned_head,
+            })
+        })
+        .collect();
+
+    Json(json!({
+        "server": server_config.0,
+        "indexer": indexer_config,
+        "rollups": rollups,
+    }))
+}
+
+// Rewinds the sync marker of a rollup by `blocks` so the running indexer
+// indexes them again.
+async fn rescan_rollup(
+    Extension(indexers): Extension<Vec<Indexer>>,
+    Path(rollup_id): Path<u32>,
+    Query(params): Query<HashMap<String, String>>,
+) -> (StatusCode, Json<Value>) {
+    let blocks = match params.get("blocks").map(|b| b.parse::<u64>()) {
+        Some(Ok(b)) => b,
+        Some(Err(_)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "Invalid blocks parameter" })),
+            )
+        }
+        None => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "Missing blocks parameter" })),
+            )
+        }
     };
 
-    let token_address = match params.get("token_address") {
-        Some(address) => address,
-        None => return Json(json!({ "error": "Missing token_address parameter" })),
+    let Some(indexer) = indexers.iter().find(|i| i.rollup_id == rollup_id) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Rollup not found" })),
+        );
     };
+    if blocks > indexer.config.max_rescan_blocks {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": format!(
+                    "blocks is above the maximum of {} (--max-rescan-blocks)",
+                    indexer.config.max_rescan_blocks
+                )
+            })),
+        );
+    }
+    match indexer.request_rescan(blocks).await {
+        Ok(from) => (
+            StatusCode::OK,
+            Json(json!({ "rollup_id": rollup_id, "rescan_from": from })),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": format!("{}", e) })),
+        ),
+    }
+}
 
-    // TODO: Bridge address is hardcoded
-    let query = format!(
-        "SELECT SUM(CASE \
-            WHEN LOWER(from_address) = LOWER('0x2a3dd3eb832af982ec71669e178424b10dca2ede') THEN -CAST(value AS HUGEINT) \
-            WHEN LOWER(to_address) = LOWER('0x2a3dd3eb832af982ec71669e178424b10dca2ede') THEN CAST(value AS HUGEINT) \
-            ELSE 0 END) AS balance \
-        FROM bridge_transfer_events \
-        WHERE LOWER(token_address) = LOWER('{}') AND rollup_id = {}",
-        token_address, rollup_id
-    );
+// Tables holding indexed events, all with rollup_id and block_number columns.
+const EVENT_TABLES: [&str; 6] = [
+    "bridge_events",
+    "claim_events",
+    "new_wrapped_token_events",
+    "wrapped_transfer_events",
+    "holder_transfer_events",
+    "bridge_transfer_events",
+];
 
-    let balance = aggregate_bigint(&db, &query).unwrap_or_else(|_| "0".to_string());
-    Json(json!({ "balance_bridge": balance }))
-}
+// Every indexed event of a rollup in a given block, keyed by the table it
+// comes from and sorted by log index.
+async fn get_block_events(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Path(number): Path<u64>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let rollup_id = match params.get("rollup_id").map(|id| id.parse::<u32>()) {
+        Some(Ok(id)) => id,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid rollup_id parameter" })),
+        None => return Json(json!({ "error": "Missing rollup_id parameter" })),
+    };
 
-// Helper to fetch column names
-fn fetch_columns(db: &Connection, table_name: &str) -> Result<Vec<String>, duckdb::Error> {
-    let mut stmt = db.prepare(&format!("PRAGMA table_info('{}')", table_name))?;
-    let column_rows = stmt.query_map([], |row| row.get::<usize, String>(1))?;
-    let mut columns = Vec::new();
-    for col_result in column_rows {
-        columns.push(col_result?);
+    let db = db.lock().await;
+    let mut events = serde_json::Map::new();
+    for table in EVENT_TABLES {
+        let columns = match fetch_columns(&db, table) {
+            Ok(cols) => cols,
+            Err(e) => return Json(json!({ "error": format!("{}", e) })),
+        };
+        let query = format!(
+            "SELECT to_json(STRUCT_PACK({})) AS row_json FROM {} \
+            WHERE rollup_id = ? AND block_number = ? ORDER BY log_index",
+            columns.join(", "),
+            table
+        );
+
+        let mut stmt = match db.prepare(&query) {
+            Ok(s) => s,
+            Err(e) => return Json(json!({ "error": format!("{}", e) })),
+        };
+        let rows = stmt.query_map(duckdb::params![rollup_id, number as i64], |row| {
+            row.get::<usize, String>(0)
+        });
+        let rows = match rows.and_then(|rows| rows.collect::<Result<Vec<_>, _>>()) {
+            Ok(rows) => rows,
+            Err(e) => return Json(json!({ "error": format!("{}", e) })),
+        };
+
+        let rows: Vec<JsonValue> = rows
+            .iter()
+            .filter_map(|r| serde_json::from_str(r).ok())
+            .collect();
+        events.insert(table.to_string(), JsonValue::Array(rows));
     }
-    Ok(columns)
+
+    Json(json!({
+        "rollup_id": rollup_id,
+        "block_number": number,
+        "events": events,
+    }))
 }
 
-// Helper to run aggregation queries returning Option<i128>
-fn aggregate_bigint(db: &Connection, query: &str) -> Result<String, duckdb::Error> {
-    let mut stmt = db.prepare(query)?;
-    let mut rows = stmt.query([])?;
-    if let Some(row) = rows.next()? {
-        let val: Option<i128> = row.get(0)?;
-        Ok(val
-            .map(|v| v.to_string())
-            .unwrap_or_else(|| "0".to_string()))
-    } else {
-        Ok("0".to_string())
+// Most events returned by /activity, whatever the limit asked.
+const MAX_ACTIVITY_LIMIT: usize = 1_000;
+
+// Events of a rollup across the event tables in chronological order, tagged
+// with the table they come from. Bounded by block numbers (from, to) and, with
+// --index-block-timestamps, by unix timestamps (from_time, to_time); events
+// without a stored timestamp are left out when those are set. Paginated with
+// limit and offset, the limit capped to MAX_ACTIVITY_LIMIT.
+async fn get_activity(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let rollup_id = match params.get("rollup_id").map(|id| id.parse::<u32>()) {
+        Some(Ok(id)) => id,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid rollup_id parameter" })),
+        None => return Json(json!({ "error": "Missing rollup_id parameter" })),
+    };
+    let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+        Some(Ok(l)) => l.min(MAX_ACTIVITY_LIMIT),
+        Some(Err(_)) => return Json(json!({ "error": "Invalid limit parameter" })),
+        None => DEFAULT_LIMIT,
+    };
+    let offset = match params.get("offset").map(|o| o.parse::<usize>()) {
+        Some(Ok(o)) => o,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid offset parameter" })),
+        None => 0,
+    };
+
+    let mut conditions = vec!["e.rollup_id = ?".to_string()];
+    let mut values = vec![rollup_id.to_string()];
+    for (param, condition) in [
+        ("from", "e.block_number >= ?"),
+        ("to", "e.block_number <= ?"),
+        ("from_time", "t.timestamp >= ?"),
+        ("to_time", "t.timestamp <= ?"),
+    ] {
+        match params.get(param).map(|v| v.parse::<u64>()) {
+            Some(Ok(v)) => {
+                conditions.push(condition.to_string());
+                values.push(v.to_string());
+            }
+            Some(Err(_)) => {
+                return Json(json!({ "error": format!("Invalid {} parameter", param) }))
+            }
+            None => {}
+        }
+    }
+
+    let db = db.lock().await;
+    let mut selects = Vec::new();
+    for table in EVENT_TABLES {
+        let columns = match fetch_columns(&db, table) {
+            Ok(cols) => cols,
+            Err(e) => return Json(json!({ "error": format!("{}", e) })),
+        };
+        selects.push(format!(
+            "SELECT '{0}' AS source, rollup_id, block_number, log_index, \
+            to_json(STRUCT_PACK({1})) AS row_json FROM {0}",
+            table,
+            columns.join(", ")
+        ));
+    }
+    // One extra row to know if there is a next page.
+    let query = format!(
+        "SELECT e.source, e.block_number, e.log_index, t.timestamp, e.row_json \
+        FROM ({}) e \
+        LEFT JOIN block_timestamps t \
+            ON t.rollup_id = e.rollup_id AND t.block_number = e.block_number \
+        WHERE {} \
+        ORDER BY e.block_number, e.log_index \
+        LIMIT {} OFFSET {}",
+        selects.join(" UNION ALL "),
+        conditions.join(" AND "),
+        limit + 1,
+        offset
+    );
+
+    let mut stmt = match db.prepare(&query) {
+        Ok(s) => s,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
+    let rows = stmt.query_map(duckdb::params_from_iter(values.iter()), |row| {
+        let event = row.get::<usize, String>(4)?;
+        Ok(json!({
+            "source": row.get::<usize, String>(0)?,
+            "block_number": row.get::<usize, i64>(1)?,
+            "log_index": row.get::<usize, i64>(2)?,
+            "timestamp": row.get::<usize, Option<i64>>(3)?,
+            "event": serde_json::from_str::<JsonValue>(&event).unwrap_or(JsonValue::Null),
+        }))
+    });
+
+    match rows.and_then(|rows| rows.collect::<Result<Vec<_>, _>>()) {
+        Ok(events) => {
+            let mut response = truncated_response(events, Some(limit));
+            response["offset"] = json!(offset);
+            Json(response)
+        }
+        Err(e) => Json(json!({ "error": format!("{}", e) })),
+    }
+}
+
+// Address columns searched by /search, per table.
+const SEARCH_COLUMNS: [(&str, &[&str]); 6] = [
+    ("bridge_events", &["originAddress", "destinationAddress"]),
+    ("claim_events", &["originAddress", "destinationAddress"]),
+    (
+        "new_wrapped_token_events",
+        &["originTokenAddress", "wrappedTokenAddress"],
+    ),
+    (
+        "wrapped_transfer_events",
+        &["from_address", "to_address", "token_address"],
+    ),
+    (
+        "holder_transfer_events",
+        &["from_address", "to_address", "token_address"],
+    ),
+    (
+        "bridge_transfer_events",
+        &["from_address", "to_address", "token_address"],
+    ),
+];
+
+// All the activity of an address, keyed by the table it was found in. Each
+// table returns at most `limit` rows, newest first.
+async fn search_address(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let address = match params.get("address").map(|a| a.parse::<Address>()) {
+        Some(Ok(a)) => a,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid address parameter" })),
+        None => return Json(json!({ "error": "Missing address parameter" })),
+    };
+    let rollup_id = match params.get("rollup_id").map(|id| id.parse::<u32>()) {
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => return Json(json!({ "error": "Invalid rollup_id parameter" })),
+        None => None,
+    };
+    let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+        Some(Ok(l)) => l,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid limit parameter" })),
+        None => DEFAULT_LIMIT,
+    };
+
+    // Addresses are stored checksummed, so compare lowercase.
+    let address = address.to_string().to_lowercase();
+
+    let db = db.lock().await;
+    let mut results = serde_json::Map::new();
+    for (table, address_columns) in SEARCH_COLUMNS {
+        let columns = match fetch_columns(&db, table) {
+            Ok(cols) => cols,
+            Err(e) => return Json(json!({ "error": format!("{}", e) })),
+        };
+
+        let mut values = vec![address.clone(); address_columns.len()];
+        let mut conditions = vec![format!(
+            "({})",
+            address_columns
+                .iter()
+                .map(|c| format!("LOWER({}) = ?", c))
+                .collect::<Vec<_>>()
+                .join(" OR ")
+        )];
+        if let Some(rollup_id) = rollup_id {
+            conditions.push("rollup_id = ?".to_string());
+            values.push(rollup_id.to_string());
+        }
+
+        let query = format!(
+            "SELECT to_json(STRUCT_PACK({})) AS row_json FROM {} WHERE {} \
+            ORDER BY block_number DESC, log_index DESC LIMIT {}",
+            columns.join(", "),
+            table,
+            conditions.join(" AND "),
+            limit
+        );
+
+        let mut stmt = match db.prepare(&query) {
+            Ok(s) => s,
+            Err(e) => return Json(json!({ "error": format!("{}", e) })),
+        };
+        let rows = stmt.query_map(duckdb::params_from_iter(values.iter()), |row| {
+            row.get::<usize, String>(0)
+        });
+        let rows = match rows.and_then(|rows| rows.collect::<Result<Vec<_>, _>>()) {
+            Ok(rows) => rows,
+            Err(e) => return Json(json!({ "error": format!("{}", e) })),
+        };
+
+        let rows: Vec<JsonValue> = rows
+            .iter()
+            .filter_map(|r| serde_json::from_str(r).ok())
+            .collect();
+        results.insert(table.to_string(), JsonValue::Array(rows));
+    }
+
+    Json(json!({ "address": address, "results": results, "limit": limit }))
+}
+
+// Backfills the mint/burn transfers of a wrapped token from `from_block` to the
+// head, for tokens discovered after their first transfers. Runs in the
+// background, progress is only printed.
+async fn reindex_token(
+    Extension(indexers): Extension<Vec<Indexer>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let rollup_id = match params.get("rollup_id").map(|id| id.parse::<u32>()) {
+        Some(Ok(id)) => id,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid rollup_id parameter" })),
+        None => return Json(json!({ "error": "Missing rollup_id parameter" })),
+    };
+    let token = match params.get("token_address").map(|t| t.parse::<Address>()) {
+        Some(Ok(t)) => t,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid token_address parameter" })),
+        None => return Json(json!({ "error": "Missing token_address parameter" })),
+    };
+    let from_block = match params.get("from_block").map(|b| b.parse::<u64>()) {
+        Some(Ok(b)) => b,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid from_block parameter" })),
+        None => return Json(json!({ "error": "Missing from_block parameter" })),
+    };
+
+    let Some(indexer) = indexers.iter().find(|i| i.rollup_id == rollup_id).cloned() else {
+        return Json(json!({ "error": "Rollup not found" }));
+    };
+    if !indexer.config.index_transfers {
+        return Json(json!({ "error": "Transfer data not indexed (--no-transfer-indexing)" }));
+    }
+
+    // Only known wrapped tokens, anything else would pollute the supply.
+    match indexer.database.fetch_wrapped_tokens(rollup_id).await {
+        Ok(tokens) if tokens.contains(&token) => {}
+        Ok(_) => return Json(json!({ "error": "Not a wrapped token of this rollup" })),
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    }
+
+    tokio::spawn(async move {
+        let result = indexer
+            .backfill_wrapped_token(token, from_block)
+            .await
+            .map_err(|e| e.to_string());
+        match result {
+            Ok(n) => info!(rollup_id, "Reindexed {} transfers of token {}", n, token),
+            Err(e) => error!(rollup_id, "Error reindexing token {}: {}", token, e),
+        }
+    });
+
+    Json(json!({
+        "rollup_id": rollup_id,
+        "token_address": token.to_string(),
+        "from_block": from_block,
+        "status": "started",
+    }))
+}
+
+// Known rollups with the metadata reported by the rollup manager. Rollup 0 is
+// the l1 and has no metadata.
+async fn list_rollups(Extension(db): Extension<Arc<Mutex<Connection>>>) -> Json<Value> {
+    let db = db.lock().await;
+    let mut stmt = match db.prepare(
+        "SELECT rollup_id, network_name, latest_bridge_synced_block, rollup_address, \
+            chain_id, fork_id, rollup_type_id, verifier_type, verifier, onchain_name \
+        FROM rollups ORDER BY rollup_id",
+    ) {
+        Ok(s) => s,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
+
+    let rows = stmt.query_map([], |row| {
+        let verifier_type: Option<i64> = row.get(7)?;
+        Ok(json!({
+            "rollup_id": row.get::<usize, i64>(0)?,
+            "network_name": row.get::<usize, Option<String>>(1)?,
+            "latest_bridge_synced_block": row.get::<usize, Option<i64>>(2)?,
+            "rollup_address": row.get::<usize, Option<String>>(3)?,
+            "chain_id": row.get::<usize, Option<i64>>(4)?,
+            "fork_id": row.get::<usize, Option<i64>>(5)?,
+            "rollup_type_id": row.get::<usize, Option<i64>>(6)?,
+            "verifier_type": verifier_type,
+            "verifier_type_name": verifier_type.map(verifier_type_name),
+            "verifier": row.get::<usize, Option<String>>(8)?,
+            "onchain_name": row.get::<usize, Option<String>>(9)?,
+        }))
+    });
+
+    match rows.and_then(|rows| rows.collect::<Result<Vec<_>, _>>()) {
+        Ok(data) => Json(json!({ "rollups": data })),
+        Err(e) => Json(json!({ "error": format!("{}", e) })),
+    }
+}
+
+// Network id to name. In the agglayer the network id of a rollup is its
+// rollup id, 0 being the l1.
+async fn list_networks(Extension(db): Extension<Arc<Mutex<Connection>>>) -> Json<Value> {
+    let db = db.lock().await;
+    match network_names(&db) {
+        Ok(names) => {
+            let networks: serde_json::Map<String, Value> = names
+                .into_iter()
+                .map(|(id, name)| (id.to_string(), json!(name)))
+                .collect();
+            Json(json!({ "networks": networks }))
+        }
+        Err(e) => Json(json!({ "error": format!("{}", e) })),
+    }
+}
+
+fn network_names(db: &Connection) -> Result<BTreeMap<i64, Option<String>>, duckdb::Error> {
+    let mut stmt = db.prepare("SELECT rollup_id, network_name FROM rollups ORDER BY rollup_id")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+// Names of the rollup manager VerifierType enum.
+fn verifier_type_name(verifier_type: i64) -> &'static str {
+    match verifier_type {
+        0 => "StateTransition",
+        1 => "Pessimistic",
+        2 => "ALGateway",
+        _ => "Unknown",
+    }
+}
+
+async fn list_tables(Extension(db): Extension<Arc<Mutex<Connection>>>) -> Json<Value> {
+    let db = db.lock().await;
+    let mut stmt = match db.prepare("PRAGMA show_tables") {
+        Ok(s) => s,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
+    let mut tables = Vec::new();
+    let mut rows = match stmt.query([]) {
+        Ok(r) => r,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
+    while let Ok(Some(row)) = rows.next() {
+        if let Ok(t) = row.get::<usize, String>(0) {
+            tables.push(t);
+        }
+    }
+    Json(json!({ "tables": tables }))
+}
+
+// Tables and views with their row count and columns.
+async fn list_tables_detailed(Extension(db): Extension<Arc<Mutex<Connection>>>) -> Json<Value> {
+    let db = db.lock().await;
+    let tables = match table_names(&db) {
+        Ok(tables) => tables,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
+
+    let mut detailed = Vec::new();
+    for table in tables {
+        let columns = match fetch_columns(&db, &table) {
+            Ok(cols) => cols,
+            Err(e) => return Json(json!({ "error": format!("{}", e) })),
+        };
+        let count = match row_count(&db, &table) {
+            Ok(count) => count,
+            Err(e) => return Json(json!({ "error": format!("{}", e) })),
+        };
+        detailed.push(json!({ "name": table, "row_count": count, "columns": columns }));
+    }
+    Json(json!({ "tables": detailed }))
+}
+
+async fn get_all_rows(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Path(table_name): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> (StatusCode, Json<Value>) {
+    let db = db.lock().await;
+
+    let columns = match fetch_columns(&db, &table_name) {
+        Ok(cols) => cols,
+        Err(e) => return columns_error_response(e),
+    };
+
+    // Fetch one extra row to know if there is more data past the limit.
+    let limit = params.get("limit").and_then(|l| l.parse::<usize>().ok());
+    let limit_clause = limit
+        .map(|l| format!("LIMIT {}", l + 1))
+        .unwrap_or_default();
+
+    let query = format!(
+        "SELECT {} FROM {} {}",
+        row_select(&table_name, &columns),
+        table_name,
+        limit_clause
+    );
+
+    let mut stmt = match db.prepare(&query) {
+        Ok(s) => s,
+        Err(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "Invalid query" })),
+            )
+        }
+    };
+
+    let mut rows = match stmt.query([]) {
+        Ok(r) => r,
+        Err(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "Query execution failed" })),
+            )
+        }
+    };
+
+    let mut result = Vec::new();
+    while let Ok(Some(row)) = rows.next() {
+        if let Ok(json_value) = row_value(&table_name, row) {
+            result.push(json_value);
+        }
+    }
+
+    (StatusCode::OK, Json(truncated_response(result, limit)))
+}
+
+// Columns of a table or view, eg for clients building typed queries.
+async fn get_table_schema(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Path(table_name): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    let db = db.lock().await;
+    match fetch_schema(&db, &table_name) {
+        Ok(columns) => (
+            StatusCode::OK,
+            Json(json!({ "table": table_name, "columns": columns })),
+        ),
+        Err(e) => columns_error_response(e),
+    }
+}
+
+// Single event row by its id (see utils::hash_log).
+async fn get_row_by_id(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Path((table_name, id)): Path<(String, String)>,
+) -> (StatusCode, Json<Value>) {
+    let db = db.lock().await;
+
+    let columns = match fetch_columns(&db, &table_name) {
+        Ok(cols) => cols,
+        Err(e) => return columns_error_response(e),
+    };
+    if !columns.iter().any(|c| c == "id") {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "Table has no id column" })),
+        );
+    }
+
+    let query = format!(
+        "SELECT {} FROM {} WHERE id = ?",
+        row_select(&table_name, &columns),
+        table_name
+    );
+
+    let value = db.query_row(&query, [&id], |row| Ok(row_value(&table_name, row)));
+    match value {
+        Ok(value) => match value {
+            Ok(value) => (StatusCode::OK, Json(json!({ "data": value }))),
+            Err(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": e })),
+            ),
+        },
+        Err(duckdb::Error::QueryReturnedNoRows) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Row not found" })),
+        ),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{}", e) })),
+        ),
+    }
+}
+
+async fn filter_rows(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Path(table_name): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> (StatusCode, Json<Value>) {
+    let db = db.lock().await;
+
+    let schema = match fetch_schema(&db, &table_name) {
+        Ok(schema) => schema,
+        Err(e) => return columns_error_response(e),
+    };
+    let columns: Vec<String> = schema
+        .iter()
+        .filter_map(|column| column["name"].as_str().map(|name| name.to_string()))
+        .collect();
+
+    // Addresses are stored checksummed while clients send them in any case,
+    // so hex values are compared lowercase on the text columns.
+    let mut conditions = Vec::new();
+    let mut values = Vec::new();
+    for (k, v) in params.iter().filter(|(k, _)| *k != "limit") {
+        let Some(column) = schema.iter().find(|column| column["name"] == k.as_str()) else {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": format!("Unknown column {}", k) })),
+            );
+        };
+        if is_hex(v) && column["type"] == "VARCHAR" {
+            conditions.push(format!("LOWER(\"{}\") = ?", k));
+            values.push(v.to_lowercase());
+        } else {
+            conditions.push(format!("\"{}\" = ?", k));
+            values.push(v.clone());
+        }
+    }
+
+    let where_clause = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    };
+
+    // Fetch one extra row to know if there is more data past the limit.
+    let limit = params
+        .get("limit")
+        .and_then(|l| l.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_LIMIT);
+    let limit_clause = format!("LIMIT {}", limit + 1);
+
+    let query = format!(
+        "SELECT {} FROM {} {} {}",
+        row_select(&table_name, &columns),
+        table_name,
+        where_clause,
+        limit_clause
+    );
+
+    let mut stmt = match db.prepare(&query) {
+        Ok(s) => s,
+        Err(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "Table not found or invalid query" })),
+            )
+        }
+    };
+
+    let mut rows = match stmt.query(duckdb::params_from_iter(values.iter())) {
+        Ok(r) => r,
+        Err(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "Invalid query" })),
+            )
+        }
+    };
+
+    let mut result = Vec::new();
+    while let Ok(Some(row)) = rows.next() {
+        if let Ok(json_value) = row_value(&table_name, row) {
+            result.push(json_value);
+        }
+    }
+
+    (
+        StatusCode::OK,
+        Json(truncated_response(result, Some(limit))),
+    )
+}
+
+// Balances are computed from the indexed transfers, which are skipped with
+// --no-transfer-indexing. All indexers share the same config.
+fn transfers_indexed(indexers: &[Indexer]) -> bool {
+    indexers
+        .first()
+        .map(|i| i.config.index_transfers)
+        .unwrap_or(true)
+}
+
+// Supply comes from the token Transfer events, not from bridge_events, so
+// message bridges (leafType 1, no token amount) never count towards it.
+async fn get_circulating_supply(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Extension(indexers): Extension<Vec<Indexer>>,
+    Extension(cache): Extension<ResponseCache>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    if !transfers_indexed(&indexers) {
+        return Json(json!({ "error": "Transfer data not indexed (--no-transfer-indexing)" }));
+    }
+    let rollup_id = match params.get("rollup_id") {
+        Some(id) => id,
+        None => return Json(json!({ "error": "Missing rollup_id parameter" })),
+    };
+
+    let token_address = match params.get("token_address") {
+        Some(address) => address,
+        None => return Json(json!({ "error": "Missing token_address parameter" })),
+    };
+
+    // Unparseable rollup ids match nothing, they are not worth caching.
+    let cached_rollup = rollup_id.parse::<u32>().ok();
+    let key = cache_key("/wrapped_balance", &params);
+    if cached_rollup.is_some() {
+        if let Some(value) = cache.get(&key) {
+            return Json(value);
+        }
+    }
+    let generation = cached_rollup.map(|id| (id, cache.database.generation(id)));
+    let db = db.lock().await;
+
+    let response = match token_balance(&db, rollup_id, token_address, "supply") {
+        Ok(balance) => {
+            let response = json!({ "circulating_supply": balance });
+            if let Some((id, generation)) = generation {
+                cache.insert(key, id, generation, &response);
+            }
+            response
+        }
+        Err(_) => json!({ "circulating_supply": "0" }),
+    };
+    Json(response)
+}
+
+async fn get_balance_bridge(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Extension(indexers): Extension<Vec<Indexer>>,
+    Extension(cache): Extension<ResponseCache>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    if !transfers_indexed(&indexers) {
+        return Json(json!({ "error": "Transfer data not indexed (--no-transfer-indexing)" }));
+    }
+    let rollup_id = match params.get("rollup_id") {
+        Some(id) => id,
+        None => return Json(json!({ "error": "Missing rollup_id parameter" })),
+    };
+
+    let token_address = match params.get("token_address") {
+        Some(address) => address,
+        None => return Json(json!({ "error": "Missing token_address parameter" })),
+    };
+
+    // Unparseable rollup ids match nothing, they are not worth caching.
+    let cached_rollup = rollup_id.parse::<u32>().ok();
+    let key = cache_key("/bridge_balance", &params);
+    if cached_rollup.is_some() {
+        if let Some(value) = cache.get(&key) {
+            return Json(value);
+        }
+    }
+    let generation = cached_rollup.map(|id| (id, cache.database.generation(id)));
+    let db = db.lock().await;
+
+    let response = match token_balance(&db, rollup_id, token_address, "bridge") {
+        Ok(balance) => {
+            let response = json!({ "balance_bridge": balance });
+            if let Some((id, generation)) = generation {
+                cache.insert(key, id, generation, &response);
+            }
+            response
+        }
+        Err(_) => json!({ "balance_bridge": "0" }),
+    };
+    Json(response)
+}
+
+// Tokens locked in the bridge against the circulating wrapped supply. A non
+// zero difference hints at an indexing gap or an exploit.
+async fn reconcile(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Extension(indexers): Extension<Vec<Indexer>>,
+    Extension(cache): Extension<ResponseCache>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    if !transfers_indexed(&indexers) {
+        return Json(json!({ "error": "Transfer data not indexed (--no-transfer-indexing)" }));
+    }
+    let rollup_id = match params.get("rollup_id").map(|id| id.parse::<u32>()) {
+        Some(Ok(id)) => id,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid rollup_id parameter" })),
+        None => return Json(json!({ "error": "Missing rollup_id parameter" })),
+    };
+    let token_address = match params.get("token_address").map(|t| t.parse::<Address>()) {
+        Some(Ok(t)) => t.to_string(),
+        Some(Err(_)) => return Json(json!({ "error": "Invalid token_address parameter" })),
+        None => return Json(json!({ "error": "Missing token_address parameter" })),
+    };
+
+    let key = cache_key("/reconcile", &params);
+    if let Some(value) = cache.get(&key) {
+        return Json(value);
+    }
+    let generation = cache.database.generation(rollup_id);
+
+    let db = db.lock().await;
+    let rollup = rollup_id.to_string();
+    let result = token_balance(&db, &rollup, &token_address, "bridge").and_then(|bridge_balance| {
+        let supply = token_balance(&db, &rollup, &token_address, "supply")?;
+        Ok((bridge_balance, supply))
+    });
+    let (bridge_balance, circulating_supply) = match result {
+        Ok(balances) => balances,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
+
+    let difference = match (
+        I256::from_dec_str(&bridge_balance),
+        I256::from_dec_str(&circulating_supply),
+    ) {
+        (Ok(bridge), Ok(supply)) => bridge.checked_sub(supply).map(|d| d.to_string()),
+        _ => None,
+    };
+    let Some(difference) = difference else {
+        return Json(json!({ "error": "Balances out of range" }));
+    };
+
+    let response = json!({
+        "bridge_balance": bridge_balance,
+        "circulating_supply": circulating_supply,
+        "difference": difference,
+    });
+    cache.insert(key, rollup_id, generation, &response);
+    Json(response)
+}
+
+// Top tokens of a rollup, either by circulating supply of the wrapped tokens
+// (`by=supply`, the default) or by amount of transfers (`by=transfers`).
+// Tokens are ranked approximately (as doubles) and the supply of each one is
+// then computed exactly.
+async fn top_tokens(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Extension(cache): Extension<ResponseCache>,
+    Path(rollup_id): Path<u32>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let ranking_query = match params.get("by").map(|b| b.as_str()) {
+        Some("supply") | None => {
+            "SELECT token_address, COUNT(*) AS transfers FROM wrapped_transfer_events \
+            WHERE rollup_id = ? GROUP BY token_address \
+            ORDER BY SUM(TRY_CAST(value AS DOUBLE) * CASE \
+                WHEN from_address = '0x0000000000000000000000000000000000000000' THEN 1 \
+                WHEN to_address = '0x0000000000000000000000000000000000000000' THEN -1 \
+                ELSE 0 END) DESC \
+            LIMIT ?"
+        }
+        Some("transfers") => {
+            "SELECT token_address, COUNT(*) AS transfers FROM all_transfers \
+            WHERE rollup_id = ? GROUP BY token_address \
+            ORDER BY transfers DESC \
+            LIMIT ?"
+        }
+        Some(_) => {
+            return Json(json!({ "error": "Invalid by parameter, use supply or transfers" }))
+        }
+    };
+    let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+        Some(Ok(l)) => l,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid limit parameter" })),
+        None => DEFAULT_LIMIT,
+    };
+
+    let key = cache_key(&format!("/rollup/{}/tokens/top", rollup_id), &params);
+    if let Some(value) = cache.get(&key) {
+        return Json(value);
+    }
+    let generation = cache.database.generation(rollup_id);
+
+    let db = db.lock().await;
+    let mut stmt = match db.prepare(ranking_query) {
+        Ok(s) => s,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
+    let ranked = stmt.query_map(duckdb::params![rollup_id, limit as i64], |row| {
+        Ok((row.get::<usize, String>(0)?, row.get::<usize, i64>(1)?))
+    });
+    let ranked = match ranked.and_then(|rows| rows.collect::<Result<Vec<_>, _>>()) {
+        Ok(ranked) => ranked,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
+
+    let mut tokens = Vec::with_capacity(ranked.len());
+    for (token_address, transfers) in ranked {
+        let supply = token_balance(&db, &rollup_id.to_string(), &token_address, "supply")
+            .unwrap_or_else(|_| "0".to_string());
+
+        // Only wrapped tokens have an origin and metadata.
+        let origin = db.query_row(
+            "SELECT originNetwork, originTokenAddress, metadata FROM new_wrapped_token_events \
+            WHERE rollup_id = ? AND LOWER(wrappedTokenAddress) = LOWER(?) LIMIT 1",
+            duckdb::params![rollup_id, token_address],
+            |row| {
+                Ok((
+                    row.get::<usize, i64>(0)?,
+                    row.get::<usize, String>(1)?,
+                    row.get::<usize, String>(2)?,
+                ))
+            },
+        );
+        let (origin_network, origin_token_address, metadata) = match origin {
+            Ok((network, address, metadata)) => (
+                Some(network),
+                Some(address),
+                metadata
+                    .parse::<Bytes>()
+                    .ok()
+                    .and_then(|m| decode_token_metadata(&m)),
+            ),
+            Err(_) => (None, None, None),
+        };
+
+        tokens.push(json!({
+            "token_address": token_address,
+            "circulating_supply": supply,
+            "transfers": transfers,
+            "origin_network": origin_network,
+            "origin_token_address": origin_token_address,
+            "name": metadata.as_ref().map(|m| m.0.clone()),
+            "symbol": metadata.as_ref().map(|m| m.1.clone()),
+            "decimals": metadata.as_ref().map(|m| m.2),
+        }));
+    }
+
+    let response = json!({ "rollup_id": rollup_id, "data": tokens });
+    cache.insert(key, rollup_id, generation, &response);
+    Json(response)
+}
+
+// Bridge events filtered by rollup and origin/destination network. Unlike
+// filter_rows, the filters are validated and bound as query parameters.
+async fn get_bridges(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let mut conditions = Vec::new();
+    let mut values: Vec<i64> = Vec::new();
+    for (param, column) in [
+        ("rollup_id", "rollup_id"),
+        ("origin_network", "originNetwork"),
+        ("destination_network", "destinationNetwork"),
+        // 0 for asset bridges, 1 for message bridges.
+        ("leaf_type", "leafType"),
+    ] {
+        if let Some(value) = params.get(param) {
+            match value.parse::<u32>() {
+                Ok(v) => {
+                    conditions.push(format!("{} = ?", column));
+                    values.push(v as i64);
+                }
+                Err(_) => return Json(json!({ "error": format!("Invalid {} parameter", param) })),
+            }
+        }
+    }
+
+    let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+        Some(Ok(l)) => l,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid limit parameter" })),
+        None => DEFAULT_LIMIT,
+    };
+
+    let where_clause = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    };
+
+    // Unconfirmed events (see --index-unconfirmed) come after the confirmed
+    // ones, tagged with confirmed false. Those confirmed in the meantime are
+    // left out.
+    let include_unconfirmed = params.get("include_unconfirmed").map(|v| v.as_str()) == Some("true");
+    let with_network_names = params.get("network_names").map(|v| v.as_str()) == Some("true");
+    let with_token_metadata = params.get("token_metadata").map(|v| v.as_str()) == Some("true");
+    let mut query = if include_unconfirmed {
+        let unconfirmed_clause = if conditions.is_empty() {
+            "WHERE id NOT IN (SELECT id FROM bridge_events)".to_string()
+        } else {
+            format!(
+                "{} AND id NOT IN (SELECT id FROM bridge_events)",
+                where_clause
+            )
+        };
+        values.extend(values.clone());
+        format!(
+            "SELECT * FROM (
+                SELECT {0}, TRUE AS confirmed FROM bridge_events {1}
+                UNION ALL
+                SELECT {0}, FALSE AS confirmed FROM bridge_events_unconfirmed {2}
+            ) ORDER BY confirmed DESC, block_number, log_index LIMIT {3}",
+            BridgeEventRow::COLUMNS,
+            where_clause,
+            unconfirmed_clause,
+            limit
+        )
+    } else {
+        format!(
+            "SELECT {}, TRUE AS confirmed FROM bridge_events {} ORDER BY block_number, log_index LIMIT {}",
+            BridgeEventRow::COLUMNS,
+            where_clause,
+            limit
+        )
+    };
+    // The metadata of a token is only in the bridge events that carry it,
+    // the others (eg a wrapped token bridged back) get it from the
+    // NewWrappedToken of any rollup that wrapped the token.
+    if with_token_metadata {
+        query = format!(
+            "SELECT b.*, m.token_metadata FROM ({}) b
+            LEFT JOIN (
+                SELECT originNetwork AS token_network,
+                    LOWER(originTokenAddress) AS token_address,
+                    ANY_VALUE(metadata) AS token_metadata
+                FROM new_wrapped_token_events
+                GROUP BY originNetwork, LOWER(originTokenAddress)
+            ) m ON m.token_network = b.originNetwork AND m.token_address = LOWER(b.originAddress)
+            ORDER BY b.confirmed DESC, b.block_number, b.log_index",
+            query
+        );
+    }
+
+    let db = db.lock().await;
+    let names = if with_network_names {
+        match network_names(&db) {
+            Ok(names) => names,
+            Err(e) => return Json(json!({ "error": format!("{}", e) })),
+        }
+    } else {
+        BTreeMap::new()
+    };
+    let mut stmt = match db.prepare(&query) {
+        Ok(s) => s,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
+
+    let rows = stmt.query_map(duckdb::params_from_iter(values.iter()), |row| {
+        let confirmed: bool = row.get("confirmed")?;
+        let token_metadata: Option<String> = if with_token_metadata {
+            row.get("token_metadata")?
+        } else {
+            None
+        };
+        Ok((BridgeEventRow::from_row(row)?, confirmed, token_metadata))
+    });
+
+    match rows.and_then(|rows| rows.collect::<Result<Vec<_>, _>>()) {
+        Ok(rows) => {
+            let data: Vec<Value> = rows
+                .into_iter()
+                .map(|(row, confirmed, token_metadata)| {
+                    // Message bridges have no token, their metadata is the
+                    // message.
+                    let metadata = if with_token_metadata && row.leaf_type == 0 {
+                        [Some(&row.metadata), token_metadata.as_ref()]
+                            .into_iter()
+                            .flatten()
+                            .find_map(|m| {
+                                m.parse::<Bytes>()
+                                    .ok()
+                                    .and_then(|m| decode_token_metadata(&m))
+                            })
+                    } else {
+                        None
+                    };
+                    let mut row = json!(row);
+                    if include_unconfirmed {
+                        row["confirmed"] = json!(confirmed);
+                    }
+                    if with_token_metadata {
+                        row["token_name"] = json!(metadata.as_ref().map(|m| m.0.clone()));
+                        row["token_symbol"] = json!(metadata.as_ref().map(|m| m.1.clone()));
+                        row["token_decimals"] = json!(metadata.as_ref().map(|m| m.2));
+                    }
+                    if with_network_names {
+                        for field in ["origin_network", "destination_network"] {
+                            let name = row[field]
+                                .as_i64()
+                                .and_then(|id| names.get(&id).cloned().flatten());
+                            row[format!("{}_name", field)] = json!(name);
+                        }
+                    }
+                    row
+                })
+                .collect();
+            Json(json!({ "data": data }))
+        }
+        Err(e) => Json(json!({ "error": format!("{}", e) })),
+    }
+}
+
+// Largest transfers in and out of the bridge of a rollup, optionally of a
+// single token. Values are ranked exactly on their decimal text, where a
+// longer number is a bigger one. Values stored as NULL (see --amount-type)
+// are ranked last.
+async fn top_bridge_transfers(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let rollup_id = match params.get("rollup_id").map(|id| id.parse::<u32>()) {
+        Some(Ok(id)) => id.to_string(),
+        Some(Err(_)) => return Json(json!({ "error": "Invalid rollup_id parameter" })),
+        None => return Json(json!({ "error": "Missing rollup_id parameter" })),
+    };
+    let token_address = match params.get("token_address").map(|t| t.parse::<Address>()) {
+        Some(Ok(t)) => Some(t.to_string()),
+        Some(Err(_)) => return Json(json!({ "error": "Invalid token_address parameter" })),
+        None => None,
+    };
+    let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+        Some(Ok(l)) => l,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid limit parameter" })),
+        None => DEFAULT_LIMIT,
+    };
+
+    let mut values = vec![rollup_id];
+    let token_condition = match token_address {
+        Some(token_address) => {
+            values.push(token_address);
+            "AND LOWER(token_address) = LOWER(?)"
+        }
+        None => "",
+    };
+    values.push(limit.to_string());
+    let query = format!(
+        "SELECT transaction_hash, block_number, log_index, from_address, to_address, \
+            token_address, CAST(value AS TEXT), direction \
+        FROM bridge_transfer_events \
+        WHERE rollup_id = ? {} \
+        ORDER BY LENGTH(CAST(value AS TEXT)) DESC NULLS LAST, CAST(value AS TEXT) DESC, \
+            block_number, log_index \
+        LIMIT CAST(? AS BIGINT)",
+        token_condition
+    );
+
+    let db = db.lock().await;
+    let mut stmt = match db.prepare(&query) {
+        Ok(s) => s,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
+    let rows = stmt.query_map(duckdb::params_from_iter(values.iter()), |row| {
+        Ok(json!({
+            "transaction_hash": row.get::<usize, String>(0)?,
+            "block_number": row.get::<usize, i64>(1)?,
+            "log_index": row.get::<usize, i64>(2)?,
+            "from_address": row.get::<usize, String>(3)?,
+            "to_address": row.get::<usize, String>(4)?,
+            "token_address": row.get::<usize, String>(5)?,
+            "value": row.get::<usize, Option<String>>(6)?,
+            "direction": row.get::<usize, Option<String>>(7)?,
+        }))
+    });
+
+    match rows.and_then(|rows| rows.collect::<Result<Vec<_>, _>>()) {
+        Ok(data) => Json(json!({ "data": data })),
+        Err(e) => Json(json!({ "error": format!("{}", e) })),
+    }
+}
+
+// Governance and security events of the bridges, oldest first, optionally of
+// a single rollup or event type (eg Upgraded).
+async fn get_proxy_events(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    oldest_rows(&db, "proxy_events", "event", &params).await
+}
+
+// Claims flagged as inconsistent by --reorg-safe-l1, oldest first,
+// optionally of a single rollup or reason (eg deposit_mismatch).
+async fn get_anomalies(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    oldest_rows(&db, "claim_anomalies", "reason", &params).await
+}
+
+// Rows of a table with a block_number and log_index, oldest first, filtered by
+// the rollup_id parameter and the text column `filter` if given.
+async fn oldest_rows(
+    db: &Mutex<Connection>,
+    table: &str,
+    filter: &str,
+    params: &HashMap<String, String>,
+) -> Json<Value> {
+    let mut conditions = Vec::new();
+    let mut values = Vec::new();
+    match params.get("rollup_id").map(|id| id.parse::<u32>()) {
+        Some(Ok(id)) => {
+            conditions.push("rollup_id = ?".to_string());
+            values.push(id.to_string());
+        }
+        Some(Err(_)) => return Json(json!({ "error": "Invalid rollup_id parameter" })),
+        None => {}
+    }
+    if let Some(value) = params.get(filter) {
+        conditions.push(format!("{} = ?", filter));
+        values.push(value.clone());
+    }
+    let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+        Some(Ok(l)) => l,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid limit parameter" })),
+        None => DEFAULT_LIMIT,
+    };
+
+    let where_clause = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    };
+
+    let db = db.lock().await;
+    let columns = match fetch_columns(&db, table) {
+        Ok(cols) => cols,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
+    let query = format!(
+        "SELECT to_json(STRUCT_PACK({})) AS row_json FROM {} {} \
+        ORDER BY block_number, log_index LIMIT {}",
+        columns.join(", "),
+        table,
+        where_clause,
+        limit + 1
+    );
+    let mut stmt = match db.prepare(&query) {
+        Ok(s) => s,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
+    let rows = stmt.query_map(duckdb::params_from_iter(values.iter()), |row| {
+        row.get::<usize, String>(0)
+    });
+    let rows = match rows.and_then(|rows| rows.collect::<Result<Vec<_>, _>>()) {
+        Ok(rows) => rows,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
+
+    let rows: Vec<JsonValue> = rows
+        .iter()
+        .filter_map(|r| serde_json::from_str(r).ok())
+        .collect();
+    Json(truncated_response(rows, Some(limit)))
+}
+
+// Tokens with a transfer since a unix timestamp (since, needs
+// --index-block-timestamps) or block (since_block), most recently active
+// first. Without either it lists every token, the last ones are the dormant.
+async fn active_tokens(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let mut conditions = Vec::new();
+    let mut values: Vec<i64> = Vec::new();
+    for (param, condition) in [
+        ("rollup_id", "rollup_id = ?"),
+        ("since", "last_timestamp >= ?"),
+        ("since_block", "last_block >= ?"),
+    ] {
+        match params.get(param).map(|v| v.parse::<u64>()) {
+            Some(Ok(v)) => {
+                conditions.push(condition);
+                values.push(v as i64);
+            }
+            Some(Err(_)) => {
+                return Json(json!({ "error": format!("Invalid {} parameter", param) }))
+            }
+            None => {}
+        }
+    }
+    let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+        Some(Ok(l)) => l,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid limit parameter" })),
+        None => DEFAULT_LIMIT,
+    };
+
+    let where_clause = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    };
+    let query = format!(
+        "SELECT rollup_id, token_address, last_block, last_timestamp \
+        FROM token_last_activity {} \
+        ORDER BY last_block DESC, rollup_id, token_address LIMIT {}",
+        where_clause,
+        limit + 1
+    );
+
+    let db = db.lock().await;
+    let mut stmt = match db.prepare(&query) {
+        Ok(s) => s,
+        Err(e) => return Json(json!({ "error": format!("{}", e) })),
+    };
+    let rows = stmt.query_map(duckdb::params_from_iter(values.iter()), |row| {
+        Ok(json!({
+            "rollup_id": row.get::<usize, i64>(0)?,
+            "token_address": row.get::<usize, String>(1)?,
+            "last_block": row.get::<usize, i64>(2)?,
+            "last_timestamp": row.get::<usize, Option<i64>>(3)?,
+        }))
+    });
+
+    match rows.and_then(|rows| rows.collect::<Result<Vec<_>, _>>()) {
+        Ok(tokens) => Json(truncated_response(tokens, Some(limit))),
+        Err(e) => Json(json!({ "error": format!("{}", e) })),
+    }
+}
+
+// Most holders returned by /holders, whatever the limit asked.
+const MAX_HOLDERS_LIMIT: usize = 1_000;
+
+// Balance per holder of a wrapped token, see Database::token_holders. Holder
+// to holder transfers are only indexed with --events holder, without them the
+// balances ignore every transfer after the mint. It sums every transfer of the
+// token on each call, so the results are capped to MAX_HOLDERS_LIMIT.
+async fn token_holders(
+    Extension(database): Extension<Database>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let rollup_id = match params.get("rollup_id").map(|id| id.parse::<u32>()) {
+        Some(Ok(id)) => id,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid rollup_id parameter" })),
+        None => return Json(json!({ "error": "Missing rollup_id parameter" })),
+    };
+    let token_address = match params.get("token_address").map(|t| t.parse::<Address>()) {
+        Some(Ok(t)) => t,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid token_address parameter" })),
+        None => return Json(json!({ "error": "Missing token_address parameter" })),
+    };
+    let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+        Some(Ok(l)) => l.min(MAX_HOLDERS_LIMIT),
+        Some(Err(_)) => return Json(json!({ "error": "Invalid limit parameter" })),
+        None => DEFAULT_LIMIT,
+    };
+
+    match database.token_holders(rollup_id, token_address).await {
+        Ok(holders) => {
+            // One extra row tells if there are more holders past the limit.
+            let holders = holders
+                .into_iter()
+                .take(limit + 1)
+                .map(|(address, balance)| json!({ "address": address, "balance": balance }))
+                .collect();
+            Json(truncated_response(holders, Some(limit)))
+        }
+        Err(e) => Json(json!({ "error": format!("{}", e) })),
+    }
+}
+
+// Wraps rows fetched with a limit + 1 lookahead, reporting whether there were
+// more rows than the limit.
+fn truncated_response(mut rows: Vec<JsonValue>, limit: Option<usize>) -> Value {
+    let truncated = match limit {
+        Some(l) if rows.len() > l => {
+            rows.truncate(l);
+            true
+        }
+        _ => false,
+    };
+    json!({ "data": rows, "truncated": truncated, "limit": limit })
+}
+
+// Amount of distinct tokens, senders and receivers across the transfers of a
+// rollup.
+async fn get_cardinality(
+    Extension(db): Extension<Arc<Mutex<Connection>>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let rollup_id = match params.get("rollup_id").map(|id| id.parse::<u32>()) {
+        Some(Ok(id)) => id,
+        Some(Err(_)) => return Json(json!({ "error": "Invalid rollup_id parameter" })),
+        None => return Json(json!({ "error": "Missing rollup_id parameter" })),
+    };
+
+    let db = db.lock().await;
+    let result = db.query_row(
+        "SELECT COUNT(DISTINCT LOWER(token_address)), \
+            COUNT(DISTINCT LOWER(from_address)), \
+            COUNT(DISTINCT LOWER(to_address)) \
+        FROM ( \
+            SELECT token_address, from_address, to_address FROM wrapped_transfer_events WHERE rollup_id = ? \
+            UNION ALL \
+            SELECT token_address, from_address, to_address FROM bridge_transfer_events WHERE rollup_id = ? \
+        )",
+        duckdb::params![rollup_id, rollup_id],
+        |row| {
+            Ok(json!({
+                "rollup_id": rollup_id,
+                "distinct_tokens": row.get::<usize, i64>(0)?,
+                "distinct_from_addresses": row.get::<usize, i64>(1)?,
+                "distinct_to_addresses": row.get::<usize, i64>(2)?,
+            }))
+        },
+    );
+
+    match result {
+        Ok(stats) => Json(stats),
+        Err(e) => Json(json!({ "error": format!("{}", e) })),
+    }
+}
+
+// Names of all the tables and views.
+fn table_names(db: &Connection) -> Result<Vec<String>, duckdb::Error> {
+    let mut stmt = db.prepare("PRAGMA show_tables")?;
+    let names = stmt
+        .query_map([], |row| row.get::<usize, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(names)
+}
+
+fn row_count(db: &Connection, table_name: &str) -> Result<i64, duckdb::Error> {
+    db.query_row(
+        &format!("SELECT COUNT(*) FROM \"{}\"", table_name),
+        [],
+        |row| row.get::<usize, i64>(0),
+    )
+}
+
+// Error of fetch_columns, so that handlers can tell a missing table apart.
+#[derive(Debug)]
+enum ColumnsError {
+    TableNotFound,
+    Db(duckdb::Error),
+}
+
+impl std::fmt::Display for ColumnsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColumnsError::TableNotFound => write!(f, "Table not found"),
+            ColumnsError::Db(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<duckdb::Error> for ColumnsError {
+    fn from(e: duckdb::Error) -> Self {
+        ColumnsError::Db(e)
+    }
+}
+
+fn columns_error_response(e: ColumnsError) -> (StatusCode, Json<Value>) {
+    let status = match e {
+        ColumnsError::TableNotFound => StatusCode::NOT_FOUND,
+        ColumnsError::Db(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, Json(json!({ "error": format!("{}", e) })))
+}
+
+// Builds a row of a table with a model (see models.rs) as json.
+type RowModel = fn(&duckdb::Row) -> duckdb::Result<JsonValue>;
+
+// Select list and row builder of the tables with a model. The rows of the
+// other tables are built with to_json of every column.
+fn table_model(table_name: &str) -> Option<(&'static str, RowModel)> {
+    match table_name {
+        "bridge_events" => Some((BridgeEventRow::COLUMNS, |row| {
+            Ok(json!(BridgeEventRow::from_row(row)?))
+        })),
+        "claim_events" => Some((ClaimEventRow::COLUMNS, |row| {
+            Ok(json!(ClaimEventRow::from_row(row)?))
+        })),
+        "all_transfers" => Some((TransferRow::COLUMNS, |row| {
+            Ok(json!(TransferRow::from_row(row)?))
+        })),
+        _ => None,
+    }
+}
+
+// Select list of the rows of a table, read back with row_value.
+fn row_select(table_name: &str, columns: &[String]) -> String {
+    match table_model(table_name) {
+        Some((select, _)) => select.to_string(),
+        None => format!("to_json(STRUCT_PACK({})) AS row_json", columns.join(", ")),
+    }
+}
+
+fn row_value(table_name: &str, row: &duckdb::Row) -> Result<JsonValue, String> {
+    match table_model(table_name) {
+        Some((_, model)) => model(row).map_err(|e| e.to_string()),
+        None => {
+            let row_json: String = row.get("row_json").map_err(|e| e.to_string())?;
+            serde_json::from_str(&row_json).map_err(|e| e.to_string())
+        }
+    }
+}
+
+// Helper to fetch column names. Works for views too (e.g. all_transfers).
+// A missing table is TableNotFound, see table_exists.
+fn fetch_columns(db: &Connection, table_name: &str) -> Result<Vec<String>, ColumnsError> {
+    if !table_exists(db, table_name)? {
+        return Err(ColumnsError::TableNotFound);
+    }
+    let mut stmt = db.prepare(&format!("PRAGMA table_info('{}')", table_name))?;
+    let column_rows = stmt.query_map([], |row| row.get::<usize, String>(1))?;
+    let mut columns = Vec::new();
+    for col_result in column_rows {
+        columns.push(col_result?);
+    }
+    if columns.is_empty() {
+        return Err(ColumnsError::TableNotFound);
+    }
+    Ok(columns)
+}
+
+// Whether a table or view exists. PRAGMA table_info raises a catalog error
+// for a missing one instead of returning no rows.
+fn table_exists(db: &Connection, table_name: &str) -> Result<bool, duckdb::Error> {
+    let tables: i64 = db.query_row(
+        "SELECT COUNT(*) FROM information_schema.tables
+        WHERE table_catalog = current_database() AND table_schema = 'main' AND table_name = ?",
+        [table_name],
+        |row| row.get(0),
+    )?;
+    Ok(tables > 0)
+}
+
+// Like fetch_columns, but with the type, nullability and primary key flag of
+// each column.
+fn fetch_schema(db: &Connection, table_name: &str) -> Result<Vec<Value>, ColumnsError> {
+    if !table_exists(db, table_name)? {
+        return Err(ColumnsError::TableNotFound);
+    }
+    let mut stmt = db.prepare(&format!(
+        "PRAGMA table_info('{}')",
+        table_name.replace('\'', "''")
+    ))?;
+    // cid, name, type, notnull, dflt_value, pk
+    let columns = stmt
+        .query_map([], |row| {
+            Ok(json!({
+                "name": row.get::<usize, String>(1)?,
+                "type": row.get::<usize, String>(2)?,
+                "nullable": !row.get::<usize, bool>(3)?,
+                "pk": row.get::<usize, bool>(5)?,
+            }))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    if columns.is_empty() {
+        return Err(ColumnsError::TableNotFound);
+    }
+    Ok(columns)
+}
+
+// Materialized balance of a token, see Database::recompute_token_balances.
+// kind is "supply" or "bridge". 0 for tokens without transfers.
+fn token_balance(
+    db: &Connection,
+    rollup_id: &str,
+    token_address: &str,
+    kind: &str,
+) -> Result<String, duckdb::Error> {
+    let balance = db
+        .query_row(
+            "SELECT balance FROM token_balances \
+            WHERE rollup_id = ? AND token_address = LOWER(?) AND kind = ?",
+            [rollup_id, token_address, kind],
+            |row| row.get::<usize, String>(0),
+        )
+        .optional()?;
+    Ok(balance.unwrap_or_else(|| "0".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use daggboard::contracts::PolygonZkEVMBridgeV2::BridgeEvent;
+    use daggboard::database::{AmountType, DbLocation};
+
+    async fn memory_db() -> Arc<Mutex<Connection>> {
+        Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap()
+            .db()
+            .clone()
+    }
+
+    fn query(params: &[(&str, &str)]) -> Query<HashMap<String, String>> {
+        Query(
+            params
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    // A deposit of 1 wei of the gas token from network 0 to network 1.
+    fn deposit(deposit_count: u32) -> BridgeEvent {
+        BridgeEvent {
+            leafType: 0,
+            originNetwork: 0,
+            originAddress: Address::ZERO,
+            destinationNetwork: 1,
+            destinationAddress: Address::repeat_byte(0x11),
+            amount: alloy::primitives::U256::from(1),
+            metadata: Bytes::new(),
+            depositCount: deposit_count,
+        }
+    }
+
+    // Stores `event` at block 1, in a transaction of its own.
+    async fn insert_bridge_event(database: &Database, rollup_id: u32, event: BridgeEvent) {
+        use alloy::primitives::{Log as Log2, B256};
+        use alloy::rpc::types::Log;
+
+        let transaction_hash = Some(B256::with_last_byte(event.depositCount as u8));
+        let log = Log {
+            inner: Log2 {
+                address: Address::repeat_byte(0xbb),
+                data: event,
+            },
+            block_hash: Some(B256::with_last_byte(1)),
+            block_number: Some(1),
+            block_timestamp: None,
+            transaction_hash,
+            transaction_index: Some(0),
+            log_index: Some(0),
+            removed: false,
+        };
+        database.insert_bridge_event(&log, rollup_id).await.unwrap();
+    }
+
+    async fn insert_deposit(database: &Database, rollup_id: u32, deposit_count: u32) {
+        insert_bridge_event(database, rollup_id, deposit(deposit_count)).await;
+    }
+
+    #[tokio::test]
+    async fn bridges_filter_on_origin_and_destination_network() {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        for (deposit_count, origin, destination) in [(0, 0, 1), (1, 0, 2), (2, 3, 2)] {
+            let event = BridgeEvent {
+                originNetwork: origin,
+                destinationNetwork: destination,
+                ..deposit(deposit_count)
+            };
+            insert_bridge_event(&database, 1, event).await;
+        }
+        let deposits = |params: &'static [(&'static str, &'static str)]| {
+            let db = database.db().clone();
+            async move {
+                let Json(response) = get_bridges(Extension(db), query(params)).await;
+                let mut deposits: Vec<u64> = response["data"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|row| row["deposit_count"].as_u64().unwrap())
+                    .collect();
+                deposits.sort();
+                deposits
+            }
+        };
+
+        assert_eq!(deposits(&[("destination_network", "2")]).await, vec![1, 2]);
+        assert_eq!(deposits(&[("origin_network", "0")]).await, vec![0, 1]);
+        assert_eq!(
+            deposits(&[("origin_network", "0"), ("destination_network", "2")]).await,
+            vec![1]
+        );
+        let Json(response) = get_bridges(
+            Extension(database.db().clone()),
+            query(&[("origin_network", "mainnet")]),
+        )
+        .await;
+        assert_eq!(response["error"], "Invalid origin_network parameter");
+    }
+
+    #[tokio::test]
+    async fn bridges_filter_asset_and_message_leaves() {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        insert_deposit(&database, 1, 0).await;
+        let message = BridgeEvent {
+            leafType: 1,
+            ..deposit(1)
+        };
+        insert_bridge_event(&database, 1, message).await;
+
+        for (leaf_type, deposit_count) in [("0", 0), ("1", 1)] {
+            let Json(response) = get_bridges(
+                Extension(database.db().clone()),
+                query(&[("leaf_type", leaf_type)]),
+            )
+            .await;
+            let data = response["data"].as_array().unwrap();
+            assert_eq!(data.len(), 1);
+            assert_eq!(data[0]["deposit_count"], deposit_count);
+            assert_eq!(data[0]["leaf_type"], leaf_type.parse::<u64>().unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn rollups_are_listed_with_their_metadata() {
+        use daggboard::database::RollupMetadata;
+
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        database.insert_rollup(1, "zkevm", None).await.unwrap();
+        let metadata = RollupMetadata {
+            rollup_address: Address::repeat_byte(0xaa),
+            chain_id: 1101,
+            fork_id: 12,
+            rollup_type_id: 5,
+            verifier_type: 1,
+            verifier: Address::repeat_byte(0xcc),
+        };
+        database.update_rollup_metadata(1, &metadata).await.unwrap();
+
+        let Json(response) = list_rollups(Extension(database.db().clone())).await;
+        let rollup = &response["rollups"][0];
+        assert_eq!(rollup["rollup_id"], 1);
+        assert_eq!(rollup["chain_id"], 1101);
+        assert_eq!(rollup["fork_id"], 12);
+        assert_eq!(rollup["rollup_type_id"], 5);
+        assert_eq!(rollup["verifier_type"], 1);
+        assert_eq!(rollup["verifier_type_name"], "Pessimistic");
+        assert_eq!(rollup["verifier"], Address::repeat_byte(0xcc).to_string());
+        assert_eq!(
+            rollup["rollup_address"],
+            Address::repeat_byte(0xaa).to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn cached_responses_expire_on_events_of_their_rollup() {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        let cache = ResponseCache::new(database.clone(), Duration::from_secs(60));
+        let response = json!({ "total": 1 });
+        cache.insert("stats".to_string(), 1, database.generation(1), &response);
+        assert_eq!(cache.get("stats"), Some(response.clone()));
+
+        insert_deposit(&database, 2, 0).await;
+        assert_eq!(cache.get("stats"), Some(response));
+
+        insert_deposit(&database, 1, 0).await;
+        assert_eq!(cache.get("stats"), None);
+        assert_eq!(cache.hits.load(Ordering::Relaxed), 2);
+        assert_eq!(cache.misses.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn zero_ttl_disables_the_cache() {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        let cache = ResponseCache::new(database.clone(), Duration::ZERO);
+        cache.insert("stats".to_string(), 1, 0, &json!({}));
+        assert_eq!(cache.get("stats"), None);
+    }
+
+    #[tokio::test]
+    async fn top_bridge_transfers_ranks_values_past_hugeint() {
+        let db = memory_db().await;
+        // 2^200 doesn't fit a hugeint, 10 sorts before 9 as text.
+        let huge = (alloy::primitives::U256::from(1) << 200usize).to_string();
+        for (log_index, value) in ["9", huge.as_str(), "10"].into_iter().enumerate() {
+            db.lock()
+                .await
+                .execute(
+                    "INSERT INTO bridge_transfer_events
+                        (id, rollup_id, transaction_hash, block_number, log_index,
+                        from_address, to_address, token_address, value)
+                    VALUES (?, 1, '0x01', 1, ?, '0xa', '0xb', '0xc', ?)",
+                    duckdb::params![log_index.to_string(), log_index as i64, value],
+                )
+                .unwrap();
+        }
+
+        let Json(response) =
+            top_bridge_transfers(Extension(db), query(&[("rollup_id", "1")])).await;
+        let values: Vec<&str> = response["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row["value"].as_str().unwrap())
+            .collect();
+        assert_eq!(values, vec![huge.as_str(), "10", "9"]);
+    }
+
+    #[test]
+    fn histogram_lines_are_cumulative() {
+        let mut histogram = Histogram::default();
+        histogram.observe(0.25);
+        histogram.observe(3.0);
+        let lines = histogram_lines("d", "rollup_id=\"1\"", &histogram);
+        assert!(lines.contains("d_bucket{rollup_id=\"1\",le=\"0.1\"} 0\n"));
+        assert!(lines.contains("d_bucket{rollup_id=\"1\",le=\"0.25\"} 1\n"));
+        assert!(lines.contains("d_bucket{rollup_id=\"1\",le=\"5\"} 2\n"));
+        assert!(lines.contains("d_bucket{rollup_id=\"1\",le=\"+Inf\"} 2\n"));
+        assert!(lines.contains("d_sum{rollup_id=\"1\"} 3.25\n"));
+        assert!(lines.contains("d_count{rollup_id=\"1\"} 2\n"));
+    }
+
+    #[test]
+    fn lookahead_rows_mark_the_response_truncated() {
+        let rows = vec![json!(1), json!(2), json!(3)];
+        assert_eq!(
+            truncated_response(rows.clone(), Some(2)),
+            json!({ "data": [1, 2], "truncated": true, "limit": 2 })
+        );
+        assert_eq!(
+            truncated_response(rows.clone(), Some(3)),
+            json!({ "data": [1, 2, 3], "truncated": false, "limit": 3 })
+        );
+        assert_eq!(
+            truncated_response(rows, None),
+            json!({ "data": [1, 2, 3], "truncated": false, "limit": null })
+        );
+    }
+
+    #[tokio::test]
+    async fn circulating_supply_past_hugeint_is_exact() {
+        use alloy::primitives::{Log as Log2, B256, U256};
+        use alloy::rpc::types::Log;
+        use daggboard::contracts::ERC20::Transfer;
+
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        let token = Address::repeat_byte(0x22);
+        // Two mints of 2^200 each, well past the hugeint range.
+        for block in 1..=2u64 {
+            let log = Log {
+                inner: Log2 {
+                    address: token,
+                    data: Transfer {
+                        from: Address::ZERO,
+                        to: Address::repeat_byte(0x33),
+                        value: U256::from(1) << 200,
+                    },
+                },
+                block_hash: Some(B256::with_last_byte(block as u8)),
+                block_number: Some(block),
+                block_timestamp: None,
+                transaction_hash: Some(B256::with_last_byte(block as u8)),
+                transaction_index: Some(0),
+                log_index: Some(0),
+                removed: false,
+            };
+            database
+                .insert_wrapped_transfer_event(&log, 1)
+                .await
+                .unwrap();
+        }
+
+        let cache = ResponseCache::new(database.clone(), Duration::from_secs(60));
+        let Json(response) = get_circulating_supply(
+            Extension(database.db().clone()),
+            Extension(Vec::new()),
+            Extension(cache),
+            query(&[("rollup_id", "1"), ("token_address", &token.to_string())]),
+        )
+        .await;
+        assert_eq!(
+            response["circulating_supply"],
+            (U256::from(1) << 201usize).to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn tables_with_a_model_are_served_from_it() {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        insert_deposit(&database, 1, 3).await;
+
+        let (status, Json(response)) = get_all_rows(
+            Extension(database.db().clone()),
+            Path("bridge_events".to_string()),
+            query(&[]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response["data"][0]["deposit_count"], 3);
+        assert_eq!(response["data"][0]["amount"], "1");
+
+        let (status, Json(response)) = get_all_rows(
+            Extension(database.db().clone()),
+            Path("rollups".to_string()),
+            query(&[]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(response["data"].is_array());
+    }
+
+    #[tokio::test]
+    async fn rows_are_fetched_by_id() {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        insert_deposit(&database, 1, 3).await;
+        let id: String = database
+            .db()
+            .lock()
+            .await
+            .query_row("SELECT id FROM bridge_events", [], |row| row.get(0))
+            .unwrap();
+
+        let row = |table: &str, id: &str| {
+            get_row_by_id(
+                Extension(database.db().clone()),
+                Path((table.to_string(), id.to_string())),
+            )
+        };
+        let (status, Json(response)) = row("bridge_events", &id).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response["data"]["id"], id);
+        assert_eq!(response["data"]["deposit_count"], 3);
+
+        let (status, _) = row("bridge_events", "missing").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = row("rollups", &id).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    // The spec is written by hand, so every route must have an entry and
+    // every entry a route.
+    #[test]
+    fn every_route_is_in_the_spec() {
+        let mut routes = std::collections::BTreeSet::new();
+        for source in [include_str!("api.rs"), include_str!("main.rs")] {
+            let code = source.split("#[cfg(test)]").next().unwrap();
+            for route in code.split(".route(\"").skip(1) {
+                let (path, rest) = route.split_once('"').unwrap();
+                let method = rest.trim_start_matches(", ").split('(').next().unwrap();
+                routes.insert((path.to_string(), method.to_string()));
+            }
+        }
+        let documented = API_ENDPOINTS
+            .iter()
+            .map(|(path, method, _, _)| (path.to_string(), method.to_string()))
+            .collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(routes, documented);
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_rate_limit_are_rejected() {
+        use tower::ServiceExt;
+
+        let app = Router::new().route("/", get(|| async { "ok" })).layer(
+            axum::middleware::from_fn_with_state(ApiLimits::new(None, Some(2)), limit_requests),
+        );
+        let request = || {
+            axum::http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        // A burst of up to one second worth of requests is allowed.
+        for _ in 0..2 {
+            let response = app.clone().oneshot(request()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // A token is back after half a second.
+        tokio::time::sleep(Duration::from_millis(550)).await;
+        let response = app.oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn request_ids_round_trip() {
+        use tower::ServiceExt;
+
+        let app = trace_requests(Router::new().route("/", get(|| async { "ok" })));
+        let request = |id: Option<&str>| {
+            let mut request = axum::http::Request::builder().uri("/");
+            if let Some(id) = id {
+                request = request.header("x-request-id", id);
+            }
+            request.body(axum::body::Body::empty()).unwrap()
+        };
+
+        let response = app.clone().oneshot(request(Some("abc-123"))).await.unwrap();
+        assert_eq!(response.headers()["x-request-id"], "abc-123");
+
+        let response = app.oneshot(request(None)).await.unwrap();
+        let generated = response.headers()["x-request-id"].to_str().unwrap();
+        assert!(!generated.is_empty());
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[tokio::test]
+    async fn msgpack_responses_stop_at_the_byte_limit() {
+        use tower::ServiceExt;
+
+        let app = |max_bytes: Option<usize>| {
+            Router::new()
+                .route(
+                    "/",
+                    get(|| async { Json(json!({ "data": "x".repeat(100) })) }),
+                )
+                .layer(axum::middleware::from_fn_with_state(
+                    max_bytes,
+                    negotiate_encoding,
+                ))
+        };
+        let request = || {
+            axum::http::Request::builder()
+                .uri("/")
+                .header(header::ACCEPT, "application/msgpack")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let response = app(Some(1_000)).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/msgpack"
+        );
+        let response = app(Some(50)).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn address_filters_ignore_the_stored_casing() {
+        let db = memory_db().await;
+        db.lock()
+            .await
+            .execute(
+                "INSERT INTO bridge_transfer_events
+                    (id, rollup_id, transaction_hash, block_number, log_index,
+                    from_address, to_address, token_address, value)
+                VALUES ('1', 1, '0x01', 1, 0, '0xAbCd', '0xb', '0xc', '1')",
+                [],
+            )
+            .unwrap();
+
+        for address in ["0xabcd", "0xABCD"] {
+            let (status, Json(response)) = filter_rows(
+                Extension(db.clone()),
+                Path("bridge_transfer_events".to_string()),
+                query(&[("from_address", address)]),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(response["data"].as_array().unwrap().len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn cardinality_counts_distinct_addresses_of_both_transfer_tables() {
+        let db = memory_db().await;
+        db.lock()
+            .await
+            .execute_batch(
+                "INSERT INTO wrapped_transfer_events
+                    (id, rollup_id, transaction_hash, block_number, log_index,
+                    from_address, to_address, token_address, value)
+                VALUES ('1', 1, '0x01', 1, 0, '0xA', '0xb', '0xt1', '1'),
+                    ('2', 1, '0x02', 2, 0, '0xa', '0xc', '0xT1', '1'),
+                    ('3', 2, '0x03', 3, 0, '0xd', '0xe', '0xt9', '1');
+                INSERT INTO bridge_transfer_events
+                    (id, rollup_id, transaction_hash, block_number, log_index,
+                    from_address, to_address, token_address, value)
+                VALUES ('4', 1, '0x04', 4, 0, '0xb', '0xa', '0xt2', '1');",
+            )
+            .unwrap();
+
+        let Json(response) =
+            get_cardinality(Extension(db.clone()), query(&[("rollup_id", "1")])).await;
+        assert_eq!(response["distinct_tokens"], 2);
+        assert_eq!(response["distinct_from_addresses"], 2);
+        assert_eq!(response["distinct_to_addresses"], 3);
+
+        let Json(response) = get_cardinality(Extension(db), query(&[])).await;
+        assert_eq!(response["error"], "Missing rollup_id parameter");
+    }
+
+    #[tokio::test]
+    async fn admin_endpoints_require_the_api_key() {
+        use tower::ServiceExt;
+
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        let app = create_admin_router(database, "secret".to_string());
+        let request = |key: Option<&str>| {
+            let mut request = axum::http::Request::builder().uri("/admin/stats");
+            if let Some(key) = key {
+                request = request.header("x-api-key", key);
+            }
+            request.body(axum::body::Body::empty()).unwrap()
+        };
+
+        for key in [None, Some("wrong"), Some("secre"), Some("secret!")] {
+            let response = app.clone().oneshot(request(key)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{:?}", key);
+        }
+        let response = app.oneshot(request(Some("secret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn filters_on_unknown_columns_are_rejected() {
+        let db = memory_db().await;
+        let (status, Json(response)) = filter_rows(
+            Extension(db),
+            Path("bridge_transfer_events".to_string()),
+            query(&[("value = '1' OR 1", "1")]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(response["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Unknown column"));
+    }
+
+    #[tokio::test]
+    async fn hex_prefixes_are_only_stripped_from_hex_fields() {
+        use tower::ServiceExt;
+
+        let app = |max_bytes: Option<usize>| {
+            Router::new()
+                .route(
+                    "/",
+                    get(|| async {
+                        Json(json!({
+                            "data": [{
+                                "from_address": "0xAbCd",
+                                "transaction_hash": "0xAB01",
+                                "metadata": "0x",
+                                "network_name": "0xbeef",
+                            }],
+                        }))
+                    }),
+                )
+                .layer(axum::middleware::from_fn_with_state(
+                    max_bytes,
+                    strip_hex_prefix,
+                ))
+        };
+        let request = || {
+            axum::http::Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let response = app(None).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["data"][0],
+            json!({
+                "from_address": "abcd",
+                "transaction_hash": "ab01",
+                "metadata": "0x",
+                "network_name": "0xbeef",
+            })
+        );
+
+        let response = app(Some(10)).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn rescans_above_the_maximum_are_rejected() {
+        use daggboard::indexer::IndexerConfig;
+
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        database.insert_rollup(1, "test", None).await.unwrap();
+        database.synced_till_block(1, 100, false).await.unwrap();
+        let config = IndexerConfig {
+            write_queue_capacity: 0,
+            max_rescan_blocks: 10,
+            ..IndexerConfig::default()
+        };
+        let indexer = Indexer::new(
+            Address::repeat_byte(0xbb),
+            "http://localhost:8545".parse().unwrap(),
+            1,
+            database,
+            config,
+        )
+        .await
+        .unwrap();
+
+        let rescan = |rollup_id: u32, blocks: &str| {
+            rescan_rollup(
+                Extension(vec![indexer.clone()]),
+                Path(rollup_id),
+                query(&[("blocks", blocks)]),
+            )
+        };
+        assert_eq!(rescan(1, "11").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(rescan(2, "5").await.0, StatusCode::NOT_FOUND);
+        let (status, Json(response)) = rescan(1, "10").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response["rescan_from"], 91);
+    }
+
+    #[tokio::test]
+    async fn reindexing_is_limited_to_known_wrapped_tokens() {
+        use daggboard::indexer::IndexerConfig;
+
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        database
+            .db()
+            .lock()
+            .await
+            .execute(
+                "INSERT INTO new_wrapped_token_events (
+                    id, rollup_id, originNetwork, originTokenAddress, wrappedTokenAddress, metadata
+                ) VALUES ('1', 1, 0, ?, ?, '0x')",
+                [
+                    Address::repeat_byte(0x11).to_string(),
+                    Address::repeat_byte(0x22).to_string(),
+                ],
+            )
+            .unwrap();
+        let indexer = |index_transfers| {
+            let database = database.clone();
+            async move {
+                let config = IndexerConfig {
+                    write_queue_capacity: 0,
+                    index_transfers,
+                    ..IndexerConfig::default()
+                };
+                Indexer::new(
+                    Address::repeat_byte(0xbb),
+                    "http://localhost:8545".parse().unwrap(),
+                    1,
+                    database,
+                    config,
+                )
+                .await
+                .unwrap()
+            }
+        };
+        let reindex = |indexer: Indexer, rollup_id: &'static str, token: String| {
+            reindex_token(
+                Extension(vec![indexer]),
+                Query(HashMap::from([
+                    ("rollup_id".to_string(), rollup_id.to_string()),
+                    ("token_address".to_string(), token),
+                    ("from_block".to_string(), "0".to_string()),
+                ])),
+            )
+        };
+        let known = Address::repeat_byte(0x22).to_string();
+        let unknown = Address::repeat_byte(0x33).to_string();
+
+        let Json(response) = reindex(indexer(true).await, "1", unknown).await;
+        assert_eq!(response["error"], "Not a wrapped token of this rollup");
+        let Json(response) = reindex(indexer(true).await, "2", known.clone()).await;
+        assert_eq!(response["error"], "Rollup not found");
+        let Json(response) = reindex(indexer(false).await, "1", known.clone()).await;
+        assert_eq!(
+            response["error"],
+            "Transfer data not indexed (--no-transfer-indexing)"
+        );
+        let Json(response) = reindex(indexer(true).await, "1", "0x22".to_string()).await;
+        assert_eq!(response["error"], "Invalid token_address parameter");
+    }
+
+    #[tokio::test]
+    async fn activity_limit_is_capped() {
+        let Json(response) = get_activity(
+            Extension(memory_db().await),
+            query(&[("rollup_id", "1"), ("limit", "1000000")]),
+        )
+        .await;
+        assert_eq!(response["limit"], MAX_ACTIVITY_LIMIT);
+    }
+
+    #[tokio::test]
+    async fn block_events_are_grouped_by_table() {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        insert_deposit(&database, 1, 0).await;
+        insert_deposit(&database, 1, 1).await;
+        insert_deposit(&database, 2, 2).await;
+        let block = |number: u64, params: &'static [(&'static str, &'static str)]| {
+            get_block_events(
+                Extension(database.db().clone()),
+                Path(number),
+                query(params),
+            )
+        };
+
+        let Json(response) = block(1, &[("rollup_id", "1")]).await;
+        assert_eq!(response["block_number"], 1);
+        let events = response["events"].as_object().unwrap();
+        assert_eq!(events.len(), EVENT_TABLES.len());
+        assert_eq!(events["bridge_events"].as_array().unwrap().len(), 2);
+        assert!(events["claim_events"].as_array().unwrap().is_empty());
+
+        let Json(response) = block(2, &[("rollup_id", "1")]).await;
+        assert!(response["events"]["bridge_events"]
+            .as_array()
+            .unwrap()
+            .is_empty());
+        let Json(response) = block(1, &[]).await;
+        assert_eq!(response["error"], "Missing rollup_id parameter");
+    }
+
+    #[tokio::test]
+    async fn search_finds_the_activity_of_an_address() {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        insert_deposit(&database, 1, 0).await;
+        insert_deposit(&database, 2, 1).await;
+        let search = |params: Vec<(&'static str, String)>| {
+            let db = database.db().clone();
+            async move {
+                let params: Vec<(&str, &str)> =
+                    params.iter().map(|(k, v)| (*k, v.as_str())).collect();
+                let Json(response) = search_address(Extension(db), query(&params)).await;
+                response
+            }
+        };
+        let destination = Address::repeat_byte(0x11).to_string();
+
+        let response = search(vec![("address", destination.to_lowercase())]).await;
+        assert_eq!(
+            response["results"]["bridge_events"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(response["results"]["claim_events"]
+            .as_array()
+            .unwrap()
+            .is_empty());
+        let response = search(vec![
+            ("address", destination),
+            ("rollup_id", "2".to_string()),
+        ])
+        .await;
+        let rows = response["results"]["bridge_events"].as_array().unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["rollup_id"], 2);
+
+        let response = search(vec![("address", Address::repeat_byte(0x12).to_string())]).await;
+        assert!(response["results"]["bridge_events"]
+            .as_array()
+            .unwrap()
+            .is_empty());
+        let response = search(vec![("address", "0x11".to_string())]).await;
+        assert_eq!(response["error"], "Invalid address parameter");
+    }
+
+    #[tokio::test]
+    async fn detailed_tables_have_row_counts_and_columns() {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        insert_deposit(&database, 1, 0).await;
+        insert_deposit(&database, 1, 1).await;
+
+        let Json(response) = list_tables_detailed(Extension(database.db().clone())).await;
+        let tables = response["tables"].as_array().unwrap();
+        let table = |name: &str| {
+            tables
+                .iter()
+                .find(|table| table["name"] == name)
+                .unwrap_or_else(|| panic!("{} not listed", name))
+        };
+        let names = table_names(&*database.db().lock().await).unwrap();
+        assert_eq!(tables.len(), names.len());
+
+        assert_eq!(table("bridge_events")["row_count"], 2);
+        assert_eq!(table("claim_events")["row_count"], 0);
+        let columns = table("bridge_events")["columns"].as_array().unwrap();
+        assert!(columns.iter().any(|column| column == "rollup_id"));
+        assert!(columns.iter().any(|column| column == "amount"));
+    }
+
+    #[tokio::test]
+    async fn reconcile_flags_a_difference() {
+        use alloy::primitives::{Log as Log2, B256, U256};
+        use alloy::rpc::types::Log;
+        use daggboard::contracts::ERC20::Transfer;
+
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        let token = Address::repeat_byte(0x22);
+        let bridge = Address::repeat_byte(0xbb);
+        let transfer = |from: Address, to: Address, value: u64, block: u64| Log {
+            inner: Log2 {
+                address: token,
+                data: Transfer {
+                    from,
+                    to,
+                    value: U256::from(value),
+                },
+            },
+            block_hash: Some(B256::with_last_byte(block as u8)),
+            block_number: Some(block),
+            block_timestamp: None,
+            transaction_hash: Some(B256::with_last_byte(block as u8)),
+            transaction_index: Some(0),
+            log_index: Some(0),
+            removed: false,
+        };
+        let reconciled = || {
+            let database = database.clone();
+            async move {
+                let cache = ResponseCache::new(database.clone(), Duration::ZERO);
+                let Json(response) = reconcile(
+                    Extension(database.db().clone()),
+                    Extension(Vec::new()),
+                    Extension(cache),
+                    query(&[("rollup_id", "1"), ("token_address", &token.to_string())]),
+                )
+                .await;
+                response
+            }
+        };
+
+        // 5 locked in the bridge and 5 minted.
+        let holder = Address::repeat_byte(0x33);
+        database
+            .insert_bridge_transfer_event(&transfer(holder, bridge, 5, 1), 1, "in", bridge)
+            .await
+            .unwrap();
+        database
+            .insert_wrapped_transfer_event(&transfer(Address::ZERO, holder, 5, 2), 1)
+            .await
+            .unwrap();
+        let response = reconciled().await;
+        assert_eq!(response["bridge_balance"], "5");
+        assert_eq!(response["circulating_supply"], "5");
+        assert_eq!(response["difference"], "0");
+
+        // 3 more locked that were never minted.
+        database
+            .insert_bridge_transfer_event(&transfer(holder, bridge, 3, 3), 1, "in", bridge)
+            .await
+            .unwrap();
+        let response = reconciled().await;
+        assert_eq!(response["bridge_balance"], "8");
+        assert_eq!(response["difference"], "3");
+    }
+
+    #[tokio::test]
+    async fn top_tokens_rank_by_supply_or_transfers() {
+        use alloy::primitives::{Log as Log2, B256, U256};
+        use alloy::rpc::types::Log;
+        use daggboard::contracts::ERC20::Transfer;
+
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        let large = Address::repeat_byte(0x22);
+        let busy = Address::repeat_byte(0x44);
+        // One mint of 100 of the first token, three of 10 of the second.
+        for (token, value, block) in [
+            (large, 100u64, 1u8),
+            (busy, 10, 2),
+            (busy, 10, 3),
+            (busy, 10, 4),
+        ] {
+            let log = Log {
+                inner: Log2 {
+                    address: token,
+                    data: Transfer {
+                        from: Address::ZERO,
+                        to: Address::repeat_byte(0x33),
+                        value: U256::from(value),
+                    },
+                },
+                block_hash: Some(B256::with_last_byte(block)),
+                block_number: Some(block as u64),
+                block_timestamp: None,
+                transaction_hash: Some(B256::with_last_byte(block)),
+                transaction_index: Some(0),
+                log_index: Some(0),
+                removed: false,
+            };
+            database
+                .insert_wrapped_transfer_event(&log, 1)
+                .await
+                .unwrap();
+        }
+        let top = |params: &'static [(&'static str, &'static str)]| {
+            let database = database.clone();
+            async move {
+                let cache = ResponseCache::new(database.clone(), Duration::ZERO);
+                let Json(response) = top_tokens(
+                    Extension(database.db().clone()),
+                    Extension(cache),
+                    Path(1),
+                    query(params),
+                )
+                .await;
+                response
+            }
+        };
+        let ranked = |response: &Value| -> Vec<Address> {
+            response["data"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|token| token["token_address"].as_str().unwrap().parse().unwrap())
+                .collect()
+        };
+
+        let response = top(&[]).await;
+        assert_eq!(ranked(&response), vec![large, busy]);
+        assert_eq!(response["data"][0]["circulating_supply"], "100");
+        assert_eq!(response["data"][1]["circulating_supply"], "30");
+        let response = top(&[("by", "transfers")]).await;
+        assert_eq!(ranked(&response), vec![busy, large]);
+        assert_eq!(response["data"][0]["transfers"], 3);
+        let response = top(&[("by", "supply"), ("limit", "1")]).await;
+        assert_eq!(ranked(&response), vec![large]);
+        let response = top(&[("by", "holders")]).await;
+        assert_eq!(
+            response["error"],
+            "Invalid by parameter, use supply or transfers"
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_tables_are_not_found() {
+        let db = memory_db().await;
+
+        let (status, Json(response)) = get_all_rows(
+            Extension(db.clone()),
+            Path("no_such_table".to_string()),
+            query(&[]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(response["error"], "Table not found");
+
+        let (status, Json(response)) = get_row_by_id(
+            Extension(db),
+            Path(("no_such_table".to_string(), "1".to_string())),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(response["error"], "Table not found");
+    }
+
+    #[tokio::test]
+    async fn coverage_reports_gaps_in_the_indexed_ranges() {
+        use daggboard::indexer::IndexerConfig;
+
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        database.insert_rollup(1, "test", None).await.unwrap();
+        database.synced_till_block(1, 100, false).await.unwrap();
+        // Blocks 40 to 59 were never indexed.
+        for (from, to) in [(0, 19), (20, 39), (60, 100)] {
+            database
+                .insert_indexed_range(1, from, to, "bridge,claim")
+                .await
+                .unwrap();
+        }
+        let indexer = Indexer::new(
+            Address::repeat_byte(0xbb),
+            "http://localhost:8545".parse().unwrap(),
+            1,
+            database.clone(),
+            IndexerConfig {
+                write_queue_capacity: 0,
+                ..IndexerConfig::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let Json(response) = get_coverage(
+            Extension(database.db().clone()),
+            Extension(vec![indexer]),
+            query(&[("rollup_id", "1")]),
+        )
+        .await;
+        assert_eq!(response["last_indexed_block"], 100);
+        assert_eq!(response["ranges"], json!([[0, 39], [60, 100]]));
+        assert_eq!(response["gaps"], json!([[40, 59]]));
+
+        // Gaps at the edges too.
+        assert_eq!(coverage_gaps(&[(10, 20)], 0, 30), vec![(0, 9), (21, 30)]);
+    }
+
+    #[tokio::test]
+    async fn rollup_name_override_keeps_the_onchain_name() {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        database
+            .insert_rollup(1, "zkevm", Some("Polygon zkEVM"))
+            .await
+            .unwrap();
+        database.insert_rollup(2, "xlayer", None).await.unwrap();
+
+        let Json(response) = list_rollups(Extension(database.db().clone())).await;
+        let rollups = response["rollups"].as_array().unwrap();
+        let rollup = |id: u32| rollups.iter().find(|r| r["rollup_id"] == id).unwrap();
+        assert_eq!(rollup(1)["network_name"], "Polygon zkEVM");
+        assert_eq!(rollup(1)["onchain_name"], "zkevm");
+        assert_eq!(rollup(2)["network_name"], "xlayer");
+        assert_eq!(rollup(2)["onchain_name"], "xlayer");
+
+        // Restarting without the override goes back to the on-chain name.
+        database.insert_rollup(1, "zkevm", None).await.unwrap();
+        let Json(response) = list_rollups(Extension(database.db().clone())).await;
+        let rollup = response["rollups"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|r| r["rollup_id"] == 1)
+            .unwrap()
+            .clone();
+        assert_eq!(rollup["network_name"], "zkevm");
+    }
+
+    #[tokio::test]
+    async fn config_masks_the_rpc_key() {
+        use daggboard::indexer::IndexerConfig;
+
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        let mut indexers = Vec::new();
+        for rollup_id in [1, 3] {
+            let indexer = Indexer::new(
+                Address::repeat_byte(0xbb),
+                "https://rpc.example.com/v3/secretkey".parse().unwrap(),
+                rollup_id,
+                database.clone(),
+                IndexerConfig {
+                    write_queue_capacity: 0,
+                    confirmations: 5,
+                    ..IndexerConfig::default()
+                },
+            )
+            .await
+            .unwrap();
+            indexers.push(indexer);
+        }
+
+        let Json(response) = get_config(
+            Extension(indexers),
+            Extension(ServerConfig(json!({ "port": 8080 }))),
+        )
+        .await;
+        assert_eq!(response["server"]["port"], 8080);
+        assert_eq!(response["indexer"]["confirmations"], 5);
+        // Rollup 3 (OK X) has a lower block range than the default.
+        assert_eq!(response["rollups"][0]["block_increment"], 10_000);
+        assert_eq!(response["rollups"][1]["block_increment"], 1_000);
+        assert_eq!(
+            response["rollups"][0]["rpc_url"],
+            "https://rpc.example.com/***"
+        );
+        assert!(!response.to_string().contains("secretkey"));
+    }
+
+    #[tokio::test]
+    async fn networks_map_ids_to_names() {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        database.insert_rollup(1, "zkevm", None).await.unwrap();
+        database.insert_rollup(2, "astar", None).await.unwrap();
+        insert_deposit(&database, 1, 0).await;
+
+        let Json(response) = list_networks(Extension(database.db().clone())).await;
+        assert_eq!(response["networks"], json!({ "1": "zkevm", "2": "astar" }));
+
+        // Network 0 is mainnet, which isn't a rollup.
+        let Json(response) = get_bridges(
+            Extension(database.db().clone()),
+            query(&[("network_names", "true")]),
+        )
+        .await;
+        let row = &response["data"][0];
+        assert_eq!(row["origin_network_name"], Value::Null);
+        assert_eq!(row["destination_network_name"], "zkevm");
+    }
+
+    #[tokio::test]
+    async fn metrics_are_labeled_per_rollup() {
+        use daggboard::indexer::IndexerConfig;
+
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        database.insert_rollup(1, "zkevm", None).await.unwrap();
+        database.insert_rollup(2, "astar", None).await.unwrap();
+        database.synced_till_block(1, 100, false).await.unwrap();
+        let mut indexers = Vec::new();
+        for rollup_id in [1, 2] {
+            let indexer = Indexer::new(
+                Address::repeat_byte(0xbb),
+                "http://localhost:8545".parse().unwrap(),
+                rollup_id,
+                database.clone(),
+                IndexerConfig {
+                    write_queue_capacity: 0,
+                    ..IndexerConfig::default()
+                },
+            )
+            .await
+            .unwrap();
+            indexers.push(indexer);
+        }
+
+        let response = metrics(
+            Extension(indexers),
+            Extension(database.clone()),
+            Extension(ResponseCache::new(database, Duration::ZERO)),
+        )
+        .await
+        .into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        for line in [
+            "daggboard_last_indexed_block{rollup_id=\"1\",network_name=\"zkevm\"} 100",
+            "daggboard_ignored_duplicates_total{rollup_id=\"1\",network_name=\"zkevm\"} 0",
+            "daggboard_ignored_duplicates_total{rollup_id=\"2\",network_name=\"astar\"} 0",
+            "daggboard_window_duration_seconds_count{rollup_id=\"2\",network_name=\"astar\"} 0",
+            "daggboard_get_logs_duration_seconds_count{rollup_id=\"1\",network_name=\"zkevm\"} 0",
+        ] {
+            assert!(
+                body.lines().any(|l| l == line),
+                "missing {} in\n{}",
+                line,
+                body
+            );
+        }
+        // Rollup 2 has no cursor yet.
+        assert!(!body.contains("daggboard_last_indexed_block{rollup_id=\"2\""));
+    }
+
+    #[tokio::test]
+    async fn table_schema_lists_the_column_types() {
+        let db = memory_db().await;
+
+        let (status, Json(response)) =
+            get_table_schema(Extension(db.clone()), Path("rollups".to_string())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response["table"], "rollups");
+        let columns = response["columns"].as_array().unwrap();
+        assert_eq!(columns[0]["name"], "rollup_id");
+        assert_eq!(columns[0]["type"], "INTEGER");
+        assert_eq!(columns[0]["pk"], true);
+        assert_eq!(
+            columns[1],
+            json!({ "name": "network_name", "type": "VARCHAR", "nullable": true, "pk": false })
+        );
+
+        let (status, _) = get_table_schema(Extension(db), Path("no_such_table".to_string())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn bridges_are_enriched_with_token_metadata() {
+        use alloy::sol_types::{sol_data, SolType};
+
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        let metadata = <(sol_data::String, sol_data::String, sol_data::Uint<8>)>::abi_encode_params(
+            &("Wrapped Ether".to_string(), "WETH".to_string(), 18u8),
+        );
+        let known = BridgeEvent {
+            originAddress: Address::repeat_byte(0x22),
+            metadata: metadata.into(),
+            ..deposit(0)
+        };
+        insert_bridge_event(&database, 1, known).await;
+        let unknown = BridgeEvent {
+            originAddress: Address::repeat_byte(0x33),
+            ..deposit(1)
+        };
+        insert_bridge_event(&database, 1, unknown).await;
+
+        let Json(response) = get_bridges(
+            Extension(database.db().clone()),
+            query(&[("token_metadata", "true")]),
+        )
+        .await;
+        let data = response["data"].as_array().unwrap();
+        let token = |deposit_count: u64| {
+            let row = data
+                .iter()
+                .find(|row| row["deposit_count"] == deposit_count)
+                .unwrap();
+            (
+                row["token_name"].clone(),
+                row["token_symbol"].clone(),
+                row["token_decimals"].clone(),
+            )
+        };
+        assert_eq!(token(0), (json!("Wrapped Ether"), json!("WETH"), json!(18)));
+        assert_eq!(token(1), (Value::Null, Value::Null, Value::Null));
+
+        // Without the parameter the rows are left as they are.
+        let Json(response) = get_bridges(Extension(database.db().clone()), query(&[])).await;
+        assert!(response["data"][0].get("token_name").is_none());
     }
 }
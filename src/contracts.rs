@@ -1,4 +1,5 @@
 use alloy::sol;
+use alloy::sol_types::SolEvent;
 
 sol!(
     #[allow(missing_docs)]
@@ -42,6 +43,17 @@ sol!(
     "abi/ERC20.json"
 );
 
+// Global exit root manager on l1, only the events indexed for
+// --reorg-safe-l1. UpdateGlobalExitRoot is emitted before the l1 info tree
+// upgrade and UpdateL1InfoTree after it, with the same fields.
+sol!(
+    #[allow(missing_docs)]
+    contract PolygonZkEVMGlobalExitRootV2 {
+        event UpdateGlobalExitRoot(bytes32 indexed mainnetExitRoot, bytes32 indexed rollupExitRoot);
+        event UpdateL1InfoTree(bytes32 indexed mainnetExitRoot, bytes32 indexed rollupExitRoot);
+    }
+);
+
 /* TODO: Maybe use this
 sol!(
     #[allow(missing_docs)]
@@ -58,3 +70,102 @@ sol!(
     }
 );
  */
+
+// Events the indexer decodes, with the signature they must have. The abi
+// files in abi/ are compiled in, so a bad update only shows up as logs that
+// silently stop decoding. Checked at startup by check_abis.
+const EXPECTED_EVENTS: [(&str, &str, &str); 11] = [
+    (
+        "PolygonZkEVMBridgeV2",
+        PolygonZkEVMBridgeV2::BridgeEvent::SIGNATURE,
+        "BridgeEvent(uint8,uint32,address,uint32,address,uint256,bytes,uint32)",
+    ),
+    (
+        "PolygonZkEVMBridgeV2",
+        PolygonZkEVMBridgeV2::ClaimEvent::SIGNATURE,
+        "ClaimEvent(uint256,uint32,address,address,uint256)",
+    ),
+    (
+        "PolygonZkEVMBridge",
+        PolygonZkEVMBridge::ClaimEvent::SIGNATURE,
+        "ClaimEvent(uint32,uint32,address,address,uint256)",
+    ),
+    (
+        "PolygonZkEVMBridgeV2",
+        PolygonZkEVMBridgeV2::NewWrappedToken::SIGNATURE,
+        "NewWrappedToken(uint32,address,address,bytes)",
+    ),
+    (
+        "PolygonZkEVMBridgeV2",
+        PolygonZkEVMBridgeV2::EmergencyStateActivated::SIGNATURE,
+        "EmergencyStateActivated()",
+    ),
+    (
+        "PolygonZkEVMBridgeV2",
+        PolygonZkEVMBridgeV2::EmergencyStateDeactivated::SIGNATURE,
+        "EmergencyStateDeactivated()",
+    ),
+    (
+        "ERC20",
+        ERC20::Transfer::SIGNATURE,
+        "Transfer(address,address,uint256)",
+    ),
+    (
+        "TransparentUpgradeableProxy",
+        TransparentUpgradeableProxy::Upgraded::SIGNATURE,
+        "Upgraded(address)",
+    ),
+    (
+        "TransparentUpgradeableProxy",
+        TransparentUpgradeableProxy::AdminChanged::SIGNATURE,
+        "AdminChanged(address,address)",
+    ),
+    (
+        "PolygonRollupManager",
+        PolygonRollupManager::OnSequenceBatches::SIGNATURE,
+        "OnSequenceBatches(uint32,uint64)",
+    ),
+    (
+        "PolygonRollupManager",
+        PolygonRollupManager::VerifyBatchesTrustedAggregator::SIGNATURE,
+        "VerifyBatchesTrustedAggregator(uint32,uint64,bytes32,bytes32,address)",
+    ),
+];
+
+// Fails naming every contract whose events don't match the expected
+// signatures, eg after updating an abi file.
+pub fn check_abis() -> Result<(), String> {
+    let mismatches: Vec<String> = EXPECTED_EVENTS
+        .iter()
+        .filter(|(_, signature, expected)| signature != expected)
+        .map(|(contract, signature, expected)| {
+            format!("{}: expected {} got {}", contract, expected, signature)
+        })
+        .collect();
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("abi mismatch: {}", mismatches.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::b256;
+
+    #[test]
+    fn known_selectors_resolve() {
+        check_abis().unwrap();
+        // topic0 of the logs on chain.
+        assert_eq!(
+            ERC20::Transfer::SIGNATURE_HASH,
+            b256!("ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef")
+        );
+        assert_eq!(
+            PolygonZkEVMBridgeV2::BridgeEvent::SIGNATURE_HASH,
+            b256!("501781209a1f8899323b96b4ef08b168df93e0a90c673d1e4cce39366cb62f9b")
+        );
+    }
+}
@@ -1,149 +1,536 @@
+use crate::contracts::PolygonRollupManager::{OnSequenceBatches, VerifyBatchesTrustedAggregator};
 use crate::contracts::PolygonZkEVMBridgeV2::{BridgeEvent, ClaimEvent, NewWrappedToken};
+use crate::contracts::PolygonZkEVMGlobalExitRootV2::UpdateL1InfoTree;
 use crate::contracts::ERC20::Transfer;
-use crate::utils::hash_log;
-use alloy::primitives::Address;
+use crate::utils::{
+    decode_global_index, decode_token_metadata, hash_log, log_position, LogPosition,
+};
+use alloy::primitives::{Address, Bytes, Signed, U256};
 use alloy::rpc::types::Log;
-use duckdb::{Connection, Result};
+use duckdb::{Connection, OptionalExt, Result};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+// A wrapped token as announced by the NewWrappedToken event, with the origin
+// token metadata decoded when available.
+#[derive(Debug, Clone)]
+pub struct WrappedToken {
+    pub address: Address,
+    pub origin_network: u32,
+    pub origin_token_address: Address,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+}
+
+impl WrappedToken {
+    pub fn new(
+        address: Address,
+        origin_network: u32,
+        origin_token_address: Address,
+        metadata: &[u8],
+    ) -> Self {
+        let (name, symbol, decimals) = match decode_token_metadata(metadata) {
+            Some((name, symbol, decimals)) => (Some(name), Some(symbol), Some(decimals)),
+            None => (None, None, None),
+        };
+        WrappedToken {
+            address,
+            origin_network,
+            origin_token_address,
+            name,
+            symbol,
+            decimals,
+        }
+    }
+}
+
+// Governance and security events of the bridge, stored in proxy_events:
+// changes of the proxy admin and implementation, and the bridge's own
+// initialization and emergency state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyEvent {
+    AdminChanged {
+        previous_admin: Address,
+        new_admin: Address,
+    },
+    Upgraded {
+        implementation: Address,
+    },
+    Initialized {
+        version: u8,
+    },
+    EmergencyStateActivated,
+    EmergencyStateDeactivated,
+}
+
+impl ProxyEvent {
+    // Name of the event, as stored in the event column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProxyEvent::AdminChanged { .. } => "AdminChanged",
+            ProxyEvent::Upgraded { .. } => "Upgraded",
+            ProxyEvent::Initialized { .. } => "Initialized",
+            ProxyEvent::EmergencyStateActivated => "EmergencyStateActivated",
+            ProxyEvent::EmergencyStateDeactivated => "EmergencyStateDeactivated",
+        }
+    }
+
+    // Emergency state the event leaves the bridge in, None for the others.
+    pub fn emergency_state(&self) -> Option<bool> {
+        match self {
+            ProxyEvent::EmergencyStateActivated => Some(true),
+            ProxyEvent::EmergencyStateDeactivated => Some(false),
+            _ => None,
+        }
+    }
+}
+
+// Rollup information returned by the rollup manager, used to classify rollups.
+#[derive(Debug, Clone)]
+pub struct RollupMetadata {
+    pub rollup_address: Address,
+    pub chain_id: u64,
+    pub fork_id: u64,
+    pub rollup_type_id: u64,
+    pub verifier_type: u8,
+    pub verifier: Address,
+}
+
+// Column type of the token amounts, see --amount-type. Text keeps every
+// uint256 exact, the numeric types are easier to aggregate but amounts out of
+// their range (above ~1.7e38 for hugeint, 1e38 for decimal) are stored as NULL
+// and recorded in indexing_errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AmountType {
+    #[default]
+    Varchar,
+    Hugeint,
+    Decimal38,
+}
+
+impl AmountType {
+    pub fn sql_type(&self) -> &'static str {
+        match self {
+            AmountType::Varchar => "TEXT",
+            AmountType::Hugeint => "HUGEINT",
+            AmountType::Decimal38 => "DECIMAL(38, 0)",
+        }
+    }
+
+    pub fn fits(&self, amount: U256) -> bool {
+        match self {
+            AmountType::Varchar => true,
+            AmountType::Hugeint => amount <= U256::from(i128::MAX as u128),
+            AmountType::Decimal38 => amount < U256::from(10).pow(U256::from(38)),
+        }
+    }
+}
+
+impl FromStr for AmountType {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "varchar" => Ok(AmountType::Varchar),
+            "hugeint" => Ok(AmountType::Hugeint),
+            "decimal38" => Ok(AmountType::Decimal38),
+            _ => Err(format!(
+                "invalid amount type: {} (hugeint, decimal38 or varchar)",
+                s
+            )),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Database {
     db: Arc<Mutex<Connection>>,
+    // Directory of the file each rollup is indexed into, see for_rollup. None
+    // if the rollups share this database.
+    rollup_dir: Option<PathBuf>,
+    amount_type: AmountType,
+    // Unix time of the last successful insert, 0 if none yet. Shared with the
+    // databases of the rollups.
+    last_write_ok: Arc<AtomicU64>,
+    // Event inserts ignored as duplicates, per rollup. Shared like
+    // last_write_ok.
+    ignored_duplicates: Arc<std::sync::Mutex<HashMap<u32, u64>>>,
+    // Bumped on every event inserted, per rollup, so that cached query results
+    // can tell when a rollup changed. Shared like last_write_ok.
+    generations: Arc<std::sync::Mutex<HashMap<u32, u64>>>,
+    // Bridge and claim events are written to the *_unconfirmed tables, see
+    // unconfirmed.
+    unconfirmed: bool,
+    // Writes go to a transaction left open until commit, see begin_batch.
+    batch: bool,
+}
+
+// Where the database is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DbLocation {
+    Memory,
+    File(PathBuf),
+}
+
+impl Default for DbLocation {
+    fn default() -> Self {
+        DbLocation::File(PathBuf::from("data.duckdb"))
+    }
 }
 
 impl Database {
-    pub async fn new(use_in_memory: bool) -> Result<Self> {
-        // Determine the database path based on the flag
-        let db_path = if use_in_memory {
-            ":memory:"
+    #[deprecated(note = "use Database::open with a DbLocation")]
+    pub async fn new(use_in_memory: bool, amount_type: AmountType) -> Result<Self> {
+        let location = if use_in_memory {
+            DbLocation::Memory
         } else {
-            "data.duckdb"
+            DbLocation::default()
         };
+        Self::open(location, amount_type).await
+    }
 
-        let db = Arc::new(Mutex::new(Connection::open(db_path)?));
-        {
-            let conn = db.lock().await;
+    pub async fn open(location: DbLocation, amount_type: AmountType) -> Result<Self> {
+        let conn = match &location {
+            DbLocation::Memory => Connection::open_in_memory()?,
+            DbLocation::File(path) => Connection::open(path)?,
+        };
+        let db = Arc::new(Mutex::new(conn));
+        create_schema(&*db.lock().await, amount_type)?;
 
-            // Maps to BridgeEvent
-            conn.execute(
-                "CREATE TABLE IF NOT EXISTS bridge_events (
-                id TEXT PRIMARY KEY,
-                rollup_id INTEGER,
-                transaction_hash TEXT,
-                block_hash TEXT,
-                block_number INTEGER,
-                transaction_index INTEGER,
-                log_index INTEGER,
-                leafType INTEGER,
-                originNetwork INTEGER,
-                originAddress TEXT,
-                destinationNetwork INTEGER,
-                destinationAddress TEXT,
-                amount TEXT,
-                metadata TEXT,
-                depositCount INTEGER
-            );",
-                [],
-            )?;
+        let database = Database {
+            db,
+            rollup_dir: None,
+            amount_type,
+            last_write_ok: Arc::new(AtomicU64::new(0)),
+            ignored_duplicates: Default::default(),
+            generations: Default::default(),
+            unconfirmed: false,
+            batch: false,
+        };
+        // Databases indexed before the balances were materialized.
+        if database.token_balances_missing().await? {
+            database.recompute_token_balances().await?;
+        }
+        Ok(database)
+    }
 
-            // Maps to ClaimEvent
-            conn.execute(
-                "CREATE TABLE IF NOT EXISTS claim_events (
-                id TEXT PRIMARY KEY,
-                rollup_id INTEGER,
-                transaction_hash TEXT,
-                block_hash TEXT,
-                block_number INTEGER,
-                transaction_index INTEGER,
-                log_index INTEGER,
-                version INTEGER,
-                globalIndex TEXT,
-                originNetwork INTEGER,
-                originAddress TEXT,
-                destinationAddress TEXT,
-                amount TEXT
-            );",
-                [],
-            )?;
+    // In memory database for --db-per-rollup. The file of each rollup, in
+    // `dir`, is attached to it as rollup_{id} (see for_rollup) and queried
+    // through the views created by combine_rollups.
+    pub async fn new_combined(dir: &Path, amount_type: AmountType) -> Result<Self> {
+        Ok(Database {
+            db: Arc::new(Mutex::new(Connection::open_in_memory()?)),
+            rollup_dir: Some(dir.to_path_buf()),
+            amount_type,
+            last_write_ok: Arc::new(AtomicU64::new(0)),
+            ignored_duplicates: Default::default(),
+            generations: Default::default(),
+            unconfirmed: false,
+            batch: false,
+        })
+    }
 
-            // Maps to NewWrappedToken event
-            conn.execute(
-                "CREATE TABLE IF NOT EXISTS new_wrapped_token_events (
-                id TEXT PRIMARY KEY,
-                rollup_id INTEGER,
-                transaction_hash TEXT,
-                block_hash TEXT,
-                block_number INTEGER,
-                transaction_index INTEGER,
-                log_index INTEGER,
-                originNetwork INTEGER,
-                originTokenAddress TEXT,
-                wrappedTokenAddress TEXT,
-                metadata TEXT
-            );",
-                [],
-            )?;
+    // Database a rollup is indexed into. With --db-per-rollup it's the rollup
+    // own file, data-rollup-{id}.duckdb, written through a connection to the
+    // combined database since duckdb only lets one instance open a file.
+    // Otherwise all the rollups share this database.
+    pub async fn for_rollup(&self, rollup_id: u32) -> Result<Self> {
+        let Some(dir) = &self.rollup_dir else {
+            return Ok(self.clone());
+        };
 
-            // Store each rollup information.
-            // By now its only to know how synced the rollup is.
-            // Note that 0 is the l1.
-            conn.execute(
-                "CREATE TABLE IF NOT EXISTS rollups (
-                rollup_id INTEGER PRIMARY KEY,
-                network_name TEXT,
-                latest_bridge_synced_block BIGINT
-            );",
-                [],
-            )?;
+        let path = dir.join(format!("data-rollup-{}.duckdb", rollup_id));
+        let conn = {
+            let conn = self.db.lock().await;
+            conn.execute_batch(&format!(
+                "ATTACH IF NOT EXISTS '{}' AS rollup_{};",
+                path.display().to_string().replace('\'', "''"),
+                rollup_id
+            ))?;
+            conn.try_clone()?
+        };
+        conn.execute_batch(&format!("USE rollup_{};", rollup_id))?;
+        create_schema(&conn, self.amount_type)?;
 
-            conn.execute(
-                "CREATE TABLE IF NOT EXISTS wrapped_transfer_events (
-                id TEXT PRIMARY KEY,
-                rollup_id INTEGER,
-                transaction_hash TEXT,
-                block_hash TEXT,
-                block_number INTEGER,
-                transaction_index INTEGER,
-                log_index INTEGER,
-                from_address TEXT,
-                to_address TEXT,
-                token_address TEXT,
-                value TEXT
-            );",
-                [],
-            )?;
+        let database = Database {
+            db: Arc::new(Mutex::new(conn)),
+            rollup_dir: None,
+            amount_type: self.amount_type,
+            last_write_ok: self.last_write_ok.clone(),
+            ignored_duplicates: self.ignored_duplicates.clone(),
+            generations: self.generations.clone(),
+            unconfirmed: false,
+            batch: false,
+        };
+        if database.token_balances_missing().await? {
+            database.recompute_token_balances().await?;
+        }
+        Ok(database)
+    }
+
+    // Creates a view for every table of the attached rollups that unions the
+    // table across all of them, so that the api queries them as if they were
+    // a single database.
+    pub async fn combine_rollups(&self, rollup_ids: &[u32]) -> Result<()> {
+        let Some(first) = rollup_ids.first() else {
+            return Ok(());
+        };
+        let conn = self.db.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT table_name FROM information_schema.tables
+            WHERE table_catalog = ? AND table_schema = 'main'",
+        )?;
+        let tables = stmt
+            .query_map([format!("rollup_{}", first)], |row| {
+                row.get::<usize, String>(0)
+            })?
+            .collect::<Result<Vec<_>>>()?;
+
+        for table in tables {
+            let selects = rollup_ids
+                .iter()
+                .map(|id| format!("SELECT * FROM rollup_{}.main.{}", id, table))
+                .collect::<Vec<_>>()
+                .join(" UNION ALL BY NAME ");
+            conn.execute_batch(&format!("CREATE OR REPLACE VIEW {} AS {};", table, selects))?;
+        }
+        Ok(())
+    }
+
+    // Same database on a connection of its own, whose writes form a single
+    // transaction until commit or rollback. Other connections don't see them
+    // meanwhile.
+    pub async fn begin_batch(&self) -> Result<Self> {
+        let conn = {
+            let conn = self.db.lock().await;
+            let catalog: String =
+                conn.query_row("SELECT current_database()", [], |row| row.get(0))?;
+            let batch = conn.try_clone()?;
+            batch.execute_batch(&format!("USE \"{}\"; BEGIN TRANSACTION;", catalog))?;
+            batch
+        };
+        Ok(Database {
+            db: Arc::new(Mutex::new(conn)),
+            batch: true,
+            ..self.clone()
+        })
+    }
+
+    // Commits the writes of a batch. The generation of the rollup changes
+    // again, results cached while the batch was open missed them.
+    pub async fn commit(&self, rollup_id: u32) -> Result<()> {
+        self.db.lock().await.execute_batch("COMMIT")?;
+        *self
+            .generations
+            .lock()
+            .unwrap()
+            .entry(rollup_id)
+            .or_insert(0) += 1;
+        Ok(())
+    }
+
+    // Discards the writes of a batch.
+    pub async fn rollback(&self) -> Result<()> {
+        self.db.lock().await.execute_batch("ROLLBACK")
+    }
+
+    // Runs `write` in a transaction, the one of the batch if any.
+    fn atomically<T>(
+        &self,
+        conn: &Connection,
+        write: impl FnOnce(&Connection) -> Result<T>,
+    ) -> Result<T> {
+        if self.batch {
+            return write(conn);
+        }
+        conn.execute_batch("BEGIN TRANSACTION")?;
+        match write(conn) {
+            Ok(value) => {
+                conn.execute_batch("COMMIT")?;
+                Ok(value)
+            }
+            Err(e) => {
+                let _ = conn.execute_batch("ROLLBACK");
+                Err(e)
+            }
+        }
+    }
+
+    // Same database, writing bridge and claim events to the
+    // bridge_events_unconfirmed and claim_events_unconfirmed tables. Those
+    // hold the events above the confirmed head, see --index-unconfirmed.
+    pub fn unconfirmed(&self) -> Self {
+        Database {
+            unconfirmed: true,
+            ..self.clone()
+        }
+    }
 
+    // Deletes the unconfirmed events of a rollup, before storing the current
+    // ones.
+    pub async fn clear_unconfirmed(&self, rollup_id: u32) -> Result<()> {
+        let conn = self.db.lock().await;
+        for table in UNCONFIRMED_TABLES {
             conn.execute(
-                "CREATE TABLE IF NOT EXISTS bridge_transfer_events (
-                id TEXT PRIMARY KEY,
-                rollup_id INTEGER,
-                transaction_hash TEXT,
-                block_hash TEXT,
-                block_number INTEGER,
-                transaction_index INTEGER,
-                log_index INTEGER,
-                from_address TEXT,
-                to_address TEXT,
-                token_address TEXT,
-                value TEXT
-            );",
-                [],
+                &format!("DELETE FROM {}_unconfirmed WHERE rollup_id = ?", table),
+                [rollup_id],
             )?;
         }
+        Ok(())
+    }
+
+    // Table events of `name` are written to.
+    fn table(&self, name: &str) -> String {
+        if self.unconfirmed {
+            format!("{}_unconfirmed", name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    pub fn per_rollup(&self) -> bool {
+        self.rollup_dir.is_some()
+    }
+
+    // Cheap liveness probe of the database. Fails if the connection can't be
+    // acquired in a second, eg stuck behind a long write.
+    pub async fn health_check(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let conn = tokio::time::timeout(Duration::from_secs(1), self.db.lock())
+            .await
+            .map_err(|_| "timed out waiting for the database")?;
+        conn.query_row("SELECT 1", [], |row| row.get::<usize, i32>(0))?;
+        Ok(())
+    }
+
+    // Unix time of the last successful insert, None if nothing was inserted
+    // since the start.
+    pub fn last_write_ok(&self) -> Option<u64> {
+        match self.last_write_ok.load(Ordering::Relaxed) {
+            0 => None,
+            timestamp => Some(timestamp),
+        }
+    }
+
+    // Event inserts of each rollup that were ignored because the row was
+    // already indexed, eg on overlapping windows or rescans.
+    pub fn ignored_duplicates(&self) -> HashMap<u32, u64> {
+        self.ignored_duplicates.lock().unwrap().clone()
+    }
+
+    // Records the outcome of an INSERT OR IGNORE of an event given the rows it
+    // changed, returning whether the row was inserted.
+    fn record_insert(&self, rollup_id: u32, changes: usize) -> bool {
+        self.record_write();
+        let counter = if changes == 0 {
+            &self.ignored_duplicates
+        } else {
+            &self.generations
+        };
+        *counter.lock().unwrap().entry(rollup_id).or_insert(0) += 1;
+        changes > 0
+    }
+
+    // Records an amount of an inserted event that doesn't fit --amount-type,
+    // and so was stored as NULL, in indexing_errors.
+    fn record_amount_out_of_range(
+        &self,
+        conn: &Connection,
+        rollup_id: u32,
+        position: &LogPosition,
+        kind: &str,
+        amount: U256,
+    ) -> Result<()> {
+        if self.amount_type.fits(amount) {
+            return Ok(());
+        }
+        warn!(
+            rollup_id,
+            "Amount {} of {} {} log {} is out of the range of {}, stored as NULL",
+            amount,
+            kind,
+            position.transaction_hash,
+            position.log_index,
+            self.amount_type.sql_type()
+        );
+        conn.execute(
+            "INSERT OR IGNORE INTO indexing_errors
+                (rollup_id, transaction_hash, log_index, block_number, kind, error)
+            VALUES (?, ?, ?, ?, ?, ?)",
+            duckdb::params![
+                rollup_id,
+                position.transaction_hash,
+                position.log_index as i64,
+                position.block_number as i64,
+                kind,
+                format!(
+                    "amount {} out of the range of {}, stored as NULL",
+                    amount,
+                    self.amount_type.sql_type()
+                ),
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Changes every time an event of the rollup is inserted.
+    pub fn generation(&self, rollup_id: u32) -> u64 {
+        self.generations
+            .lock()
+            .unwrap()
+            .get(&rollup_id)
+            .copied()
+            .unwrap_or_default()
+    }
 
-        Ok(Database { db })
+    fn record_write(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.last_write_ok.store(now, Ordering::Relaxed);
+    }
+
+    // Installs and loads the given duckdb extensions, eg parquet or httpfs.
+    // Installing an extension that is already installed is a no-op.
+    pub async fn load_extensions(
+        &self,
+        extensions: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+        for extension in extensions {
+            // Names end up in the statement, so only plain identifiers.
+            if !extension
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                return Err(format!("invalid duckdb extension name: {}", extension).into());
+            }
+            conn.execute_batch(&format!("INSTALL {0}; LOAD {0};", extension))
+                .map_err(|e| format!("failed to load duckdb extension {}: {}", extension, e))?;
+        }
+        Ok(())
     }
 
     pub async fn insert_bridge_event(
         &self,
         log: &Log<BridgeEvent>,
         rollup_id: u32,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some((id, position)) = required_fields(log, rollup_id) else {
+            return Ok(false);
+        };
         let conn = self.db.lock().await;
 
-        conn.execute(
-            "INSERT OR IGNORE INTO bridge_events (
+        let inserted = conn.execute(
+            &format!(
+                "INSERT OR IGNORE INTO {} (
             id,
             rollup_id,
             transaction_hash,
@@ -159,15 +546,18 @@ impl Database {
             amount,
             metadata,
             depositCount
-        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);",
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, TRY_CAST(? AS {}), ?, ?);",
+                self.table("bridge_events"),
+                self.amount_type.sql_type()
+            ),
             &[
-                &hash_log(log, rollup_id),
+                &id,
                 &rollup_id.to_string(),
-                &log.transaction_hash.unwrap().to_string(),
-                &log.block_hash.unwrap().to_string(),
-                &log.block_number.unwrap().to_string(),
-                &log.transaction_index.unwrap().to_string(),
-                &log.log_index.unwrap().to_string(),
+                &position.transaction_hash,
+                &position.block_hash,
+                &position.block_number.to_string(),
+                &position.transaction_index.to_string(),
+                &position.log_index.to_string(),
                 &log.inner.leafType.to_string(),
                 &log.inner.originNetwork.to_string(),
                 &log.inner.originAddress.to_string(),
@@ -178,7 +568,16 @@ impl Database {
                 &log.inner.depositCount.to_string(),
             ],
         )?;
-        Ok(())
+        if inserted > 0 {
+            self.record_amount_out_of_range(
+                &conn,
+                rollup_id,
+                &position,
+                "BridgeEvent",
+                log.inner.amount,
+            )?;
+        }
+        Ok(self.record_insert(rollup_id, inserted))
     }
 
     pub async fn insert_claim_event(
@@ -186,10 +585,15 @@ impl Database {
         log: &Log<ClaimEvent>,
         rollup_id: u32,
         version: u32,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some((id, position)) = required_fields(log, rollup_id) else {
+            return Ok(false);
+        };
+        let decoded = (version >= 2).then(|| decode_global_index(log.inner.globalIndex));
         let conn = self.db.lock().await;
-        conn.execute(
-            "INSERT OR IGNORE INTO claim_events (
+        let inserted = conn.execute(
+            &format!(
+                "INSERT OR IGNORE INTO {} (
             id,
             rollup_id,
             transaction_hash,
@@ -202,24 +606,82 @@ impl Database {
             originNetwork,
             originAddress,
             destinationAddress,
-            amount
-        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);",
-            &[
-                &hash_log(log, rollup_id),
-                &rollup_id.to_string(),
-                &log.transaction_hash.unwrap().to_string(),
-                &log.block_hash.unwrap().to_string(),
-                &log.block_number.unwrap().to_string(),
-                &log.transaction_index.unwrap().to_string(),
-                &log.log_index.unwrap().to_string(),
-                &version.to_string(),
-                &log.inner.globalIndex.to_string(),
-                &log.inner.originNetwork.to_string(),
-                &log.inner.originAddress.to_string(),
-                &log.inner.destinationAddress.to_string(),
-                &log.inner.amount.to_string(),
+            amount,
+            mainnet_flag,
+            source_rollup_id,
+            local_exit_index
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, TRY_CAST(? AS {}), ?, ?, ?);",
+                self.table("claim_events"),
+                self.amount_type.sql_type()
+            ),
+            duckdb::params![
+                id,
+                rollup_id,
+                position.transaction_hash,
+                position.block_hash,
+                position.block_number as i64,
+                position.transaction_index as i64,
+                position.log_index as i64,
+                version,
+                log.inner.globalIndex.to_string(),
+                log.inner.originNetwork,
+                log.inner.originAddress.to_string(),
+                log.inner.destinationAddress.to_string(),
+                log.inner.amount.to_string(),
+                decoded.map(|(mainnet_flag, _, _)| mainnet_flag),
+                decoded.map(|(_, source_rollup_id, _)| source_rollup_id),
+                decoded.map(|(_, _, local_exit_index)| local_exit_index),
             ],
         )?;
+        if inserted > 0 {
+            self.record_amount_out_of_range(
+                &conn,
+                rollup_id,
+                &position,
+                "ClaimEvent",
+                log.inner.amount,
+            )?;
+        }
+        Ok(self.record_insert(rollup_id, inserted))
+    }
+
+    // Sets the leafType of the claims whose deposit is known, once the events of
+    // blocks [from_block, to_block] of a rollup are stored: the claims of the
+    // blocks, and the claims stored before the deposits of the blocks. One set
+    // based update each rather than a lookup per event. See leafType in
+    // create_schema.
+    pub async fn fill_claim_leaf_types(
+        &self,
+        rollup_id: u32,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+        conn.execute(
+            &format!(
+                "UPDATE {0} SET leafType = bridge.leafType
+                FROM bridge_events AS bridge
+                WHERE {0}.leafType IS NULL
+                    AND {0}.rollup_id = ? AND {0}.block_number BETWEEN ? AND ?
+                    AND bridge.rollup_id = {0}.source_rollup_id
+                    AND bridge.depositCount = {0}.local_exit_index
+                    AND bridge.destinationNetwork = {0}.rollup_id",
+                self.table("claim_events")
+            ),
+            duckdb::params![rollup_id, from_block as i64, to_block as i64],
+        )?;
+        if !self.unconfirmed {
+            conn.execute(
+                "UPDATE claim_events SET leafType = bridge.leafType
+                FROM bridge_events AS bridge
+                WHERE claim_events.leafType IS NULL
+                    AND bridge.rollup_id = ? AND bridge.block_number BETWEEN ? AND ?
+                    AND bridge.rollup_id = claim_events.source_rollup_id
+                    AND bridge.depositCount = claim_events.local_exit_index
+                    AND bridge.destinationNetwork = claim_events.rollup_id",
+                duckdb::params![rollup_id, from_block as i64, to_block as i64],
+            )?;
+        }
         Ok(())
     }
 
@@ -227,9 +689,12 @@ impl Database {
         &self,
         log: &Log<NewWrappedToken>,
         rollup_id: u32,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some((id, position)) = required_fields(log, rollup_id) else {
+            return Ok(false);
+        };
         let conn = self.db.lock().await;
-        conn.execute(
+        let inserted = conn.execute(
             "INSERT OR IGNORE INTO new_wrapped_token_events (
             id,
             rollup_id,
@@ -244,31 +709,35 @@ impl Database {
             metadata
         ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);",
             &[
-                &hash_log(log, rollup_id),
+                &id,
                 &rollup_id.to_string(),
-                &log.transaction_hash.unwrap().to_string(),
-                &log.block_hash.unwrap().to_string(),
-                &log.block_number.unwrap().to_string(),
-                &log.transaction_index.unwrap().to_string(),
-                &log.log_index.unwrap().to_string(),
+                &position.transaction_hash,
+                &position.block_hash,
+                &position.block_number.to_string(),
+                &position.transaction_index.to_string(),
+                &position.log_index.to_string(),
                 &log.inner.originNetwork.to_string(),
                 &log.inner.originTokenAddress.to_string(),
                 &log.inner.wrappedTokenAddress.to_string(),
                 &log.inner.metadata.to_string(),
             ],
         )?;
-        Ok(())
+        Ok(self.record_insert(rollup_id, inserted))
     }
 
-    pub async fn insert_wrapped_transfer_event(
+    // `rollup_id` is the one of the indexer (the l1), the rollup the batches
+    // belong to comes from the event.
+    pub async fn insert_verify_batches_event(
         &self,
-        log: &Log<Transfer>,
+        log: &Log<VerifyBatchesTrustedAggregator>,
         rollup_id: u32,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some((id, position)) = required_fields(log, rollup_id) else {
+            return Ok(false);
+        };
         let conn = self.db.lock().await;
-
-        conn.execute(
-            "INSERT OR IGNORE INTO wrapped_transfer_events (
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO verify_batches_events (
             id,
             rollup_id,
             transaction_hash,
@@ -276,37 +745,40 @@ impl Database {
             block_number,
             transaction_index,
             log_index,
-            from_address,
-            to_address,
-            token_address,
-            value
-        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);",
-            &[
-                &hash_log(log, rollup_id),
-                &rollup_id.to_string(),
-                &log.transaction_hash.unwrap().to_string(),
-                &log.block_hash.unwrap().to_string(),
-                &log.block_number.unwrap().to_string(),
-                &log.transaction_index.unwrap().to_string(),
-                &log.log_index.unwrap().to_string(),
-                &log.inner.from.to_string(),
-                &log.inner.to.to_string(),
-                &log.address().to_string(),
-                &log.inner.value.to_string(),
+            event,
+            num_batch,
+            state_root,
+            exit_root,
+            aggregator
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, 'VerifyBatchesTrustedAggregator', ?, ?, ?, ?);",
+            [
+                &id,
+                &log.inner.rollupID.to_string(),
+                &position.transaction_hash,
+                &position.block_hash,
+                &position.block_number.to_string(),
+                &position.transaction_index.to_string(),
+                &position.log_index.to_string(),
+                &log.inner.numBatch.to_string(),
+                &log.inner.stateRoot.to_string(),
+                &log.inner.exitRoot.to_string(),
+                &log.inner.aggregator.to_string(),
             ],
         )?;
-        Ok(())
+        Ok(self.record_insert(rollup_id, inserted))
     }
 
-    pub async fn insert_bridge_transfer_event(
+    pub async fn insert_sequence_batches_event(
         &self,
-        log: &Log<Transfer>,
+        log: &Log<OnSequenceBatches>,
         rollup_id: u32,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some((id, position)) = required_fields(log, rollup_id) else {
+            return Ok(false);
+        };
         let conn = self.db.lock().await;
-
-        conn.execute(
-            "INSERT OR IGNORE INTO bridge_transfer_events (
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO verify_batches_events (
             id,
             rollup_id,
             transaction_hash,
@@ -314,122 +786,746 @@ impl Database {
             block_number,
             transaction_index,
             log_index,
-            from_address,
-            to_address,
-            token_address,
-            value
-        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);",
-            &[
-                &hash_log(log, rollup_id),
-                &rollup_id.to_string(),
-                &log.transaction_hash.unwrap().to_string(),
-                &log.block_hash.unwrap().to_string(),
-                &log.block_number.unwrap().to_string(),
-                &log.transaction_index.unwrap().to_string(),
-                &log.log_index.unwrap().to_string(),
-                &log.inner.from.to_string(),
-                &log.inner.to.to_string(),
-                &log.address().to_string(),
-                &log.inner.value.to_string(),
+            event,
+            num_batch
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, 'OnSequenceBatches', ?);",
+            [
+                &id,
+                &log.inner.rollupID.to_string(),
+                &position.transaction_hash,
+                &position.block_hash,
+                &position.block_number.to_string(),
+                &position.transaction_index.to_string(),
+                &position.log_index.to_string(),
+                &log.inner.lastBatchSequenced.to_string(),
             ],
         )?;
-        Ok(())
+        Ok(self.record_insert(rollup_id, inserted))
     }
 
-    pub async fn insert_rollup(
+    // Global exit root update of the l1, see --reorg-safe-l1.
+    pub async fn insert_global_exit_root_event(
         &self,
+        log: &Log<UpdateL1InfoTree>,
         rollup_id: u32,
-        network_name: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some((id, position)) = required_fields(log, rollup_id) else {
+            return Ok(false);
+        };
         let conn = self.db.lock().await;
-
-        let mut stmt = conn.prepare(
-            "SELECT latest_bridge_synced_block FROM rollups WHERE rollup_id = ? LIMIT 1",
-        )?;
-        let mut rows = stmt.query(&[&rollup_id.to_string()])?;
-
-        // TODO: Most likely not the best way to do this.
-        if let Some(row) = rows.next()? {
-            // Try to get an Option<i64>
-            let block_i64_opt: Option<i64> = row.get(0)?;
-            let block: u64 = match block_i64_opt {
-                Some(v) => v as u64,
-                None => {
-                    eprintln!("Got NULL for block number (rollup {})", rollup_id);
-                    0
-                }
-            };
-            println!("rollup: {:?} block: {:?} already known", rollup_id, block);
-            // …use `block`…
-        } else {
-            conn.execute(
-                "INSERT INTO rollups (
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO global_exit_root_events (
+            id,
+            rollup_id,
+            transaction_hash,
+            block_hash,
+            block_number,
+            transaction_index,
+            log_index,
+            mainnet_exit_root,
+            rollup_exit_root
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?);",
+            duckdb::params![
+                id,
                 rollup_id,
-                network_name,
-                latest_bridge_synced_block)
-            VALUES (?, ?, ?);",
-                &[&rollup_id.to_string(), network_name, &(-1).to_string()],
-            )?;
-        }
-
-        Ok(())
+                position.transaction_hash,
+                position.block_hash,
+                position.block_number as i64,
+                position.transaction_index as i64,
+                position.log_index as i64,
+                log.inner.mainnetExitRoot.to_string(),
+                log.inner.rollupExitRoot.to_string(),
+            ],
+        )?;
+        Ok(self.record_insert(rollup_id, inserted))
     }
 
-    pub async fn last_indexed_block(
+    // Flags the v2 claims of a rollup in [from_block, to_block] that are not
+    // consistent with the indexed events, see --reorg-safe-l1: the deposit
+    // their global index points to has other fields than the claim
+    // (deposit_mismatch), or is on l1 with no global exit root update at or
+    // after it (exit_root_not_updated). Claims of deposits not indexed yet are
+    // left unchecked. Returns the claims flagged.
+    pub async fn check_claims(
         &self,
         rollup_id: u32,
-    ) -> Result<u64, Box<dyn std::error::Error>> {
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
         let conn = self.db.lock().await;
-        let mut stmt = conn.prepare(
-            "SELECT latest_bridge_synced_block
-             FROM rollups
-             WHERE rollup_id = ?",
+        let flagged = conn.execute(
+            "INSERT OR IGNORE INTO claim_anomalies (
+                claim_id, rollup_id, transaction_hash, block_number, log_index,
+                globalIndex, source_rollup_id, local_exit_index, deposit_id, reason
+            )
+            SELECT id, rollup_id, transaction_hash, block_number, log_index,
+                globalIndex, source_rollup_id, local_exit_index, deposit_id,
+                CASE WHEN mismatch THEN 'deposit_mismatch' ELSE 'exit_root_not_updated' END
+            FROM (
+                SELECT claim.*, bridge.id AS deposit_id,
+                    bridge.originNetwork IS DISTINCT FROM claim.originNetwork
+                        OR bridge.originAddress IS DISTINCT FROM claim.originAddress
+                        OR bridge.destinationAddress IS DISTINCT FROM claim.destinationAddress
+                        OR bridge.amount IS DISTINCT FROM claim.amount AS mismatch,
+                    claim.mainnet_flag AND NOT EXISTS (
+                        SELECT 1 FROM global_exit_root_events AS ger
+                        WHERE ger.rollup_id = 0 AND ger.block_number >= bridge.block_number
+                    ) AS exit_root_missing
+                FROM claim_events AS claim
+                JOIN bridge_events AS bridge
+                    ON bridge.rollup_id = claim.source_rollup_id
+                    AND bridge.depositCount = claim.local_exit_index
+                    AND bridge.destinationNetwork = claim.rollup_id
+                WHERE claim.rollup_id = ? AND claim.block_number BETWEEN ? AND ?
+                    AND claim.version >= 2
+            )
+            WHERE mismatch OR exit_root_missing",
+            duckdb::params![rollup_id, from_block as i64, to_block as i64],
         )?;
-        // bind rollup_id as a u32 (no need to stringify)
-        let mut rows = stmt.query(&[&rollup_id])?;
-
-        if let Some(row) = rows.next()? {
-            // Fetch as Option<i64> so that NULL → None
-            let block_opt: Option<i64> = row.get(0)?;
-            let block: u64 = match block_opt {
-                Some(v) => {
-                    // If it was non‐NULL, cast to u64.
-                    // (Assuming “v” is never negative in your data.)
-                    if v < 0 {
-                        eprintln!(
-                            "latest_bridge_synced_block is negative for rollup {}",
-                            rollup_id
-                        );
-                        0
-                        // ALL THIS IS CIMPETE SHIT, REDO IT.
-                    } else {
-                        v as u64
-                    }
-                }
-                None => {
-                    // It was actually NULL/empty → return 0 (or whatever fallback you want)
-                    eprintln!(
-                        "latest_bridge_synced_block is NULL for rollup {}",
-                        rollup_id
-                    );
-                    0
-                }
-            };
-
-            Ok(block)
-        } else {
-            // No row matched. Return 0 (or handle as error).
-            Ok(0)
+        if flagged > 0 {
+            warn!(rollup_id, "{:?} inconsistent claims flagged", flagged);
         }
+        Ok(flagged)
     }
 
-    pub async fn synced_till_block(
+    pub async fn insert_proxy_event(
         &self,
+        log: &Log<ProxyEvent>,
         rollup_id: u32,
-        block: u64,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        println!("Rollup: {:?} Synced till block: {:?}", rollup_id, block);
-        let conn = self.db.lock().await;
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some((id, position)) = required_fields(log, rollup_id) else {
+            return Ok(false);
+        };
+        let (previous_admin, new_admin, implementation, version) = match &log.inner.data {
+            ProxyEvent::AdminChanged {
+                previous_admin,
+                new_admin,
+            } => (
+                Some(previous_admin.to_string()),
+                Some(new_admin.to_string()),
+                None,
+                None,
+            ),
+            ProxyEvent::Upgraded { implementation } => {
+                (None, None, Some(implementation.to_string()), None)
+            }
+            ProxyEvent::Initialized { version } => (None, None, None, Some(*version)),
+            _ => (None, None, None, None),
+        };
+        let conn = self.db.lock().await;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO proxy_events (
+            id,
+            rollup_id,
+            transaction_hash,
+            block_hash,
+            block_number,
+            transaction_index,
+            log_index,
+            contract_address,
+            event,
+            previous_admin,
+            new_admin,
+            implementation,
+            version,
+            emergency_state
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);",
+            duckdb::params![
+                id,
+                rollup_id,
+                position.transaction_hash,
+                position.block_hash,
+                position.block_number as i64,
+                position.transaction_index as i64,
+                position.log_index as i64,
+                log.address().to_string(),
+                log.inner.data.name(),
+                previous_admin,
+                new_admin,
+                implementation,
+                version,
+                log.inner.data.emergency_state(),
+            ],
+        )?;
+        Ok(self.record_insert(rollup_id, inserted))
+    }
+
+    pub async fn insert_wrapped_transfer_event(
+        &self,
+        log: &Log<Transfer>,
+        rollup_id: u32,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some((id, position)) = required_fields(log, rollup_id) else {
+            return Ok(false);
+        };
+        let conn = self.db.lock().await;
+        // Along with the balance it changes.
+        let inserted = self.atomically(&conn, |tx| {
+            let inserted = tx.execute(
+                &format!(
+                    "INSERT OR IGNORE INTO wrapped_transfer_events (
+                id,
+                rollup_id,
+                transaction_hash,
+                block_hash,
+                block_number,
+                transaction_index,
+                log_index,
+                from_address,
+                to_address,
+                token_address,
+                value
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, TRY_CAST(? AS {}));",
+                    self.amount_type.sql_type()
+                ),
+                [
+                    &id,
+                    &rollup_id.to_string(),
+                    &position.transaction_hash,
+                    &position.block_hash,
+                    &position.block_number.to_string(),
+                    &position.transaction_index.to_string(),
+                    &position.log_index.to_string(),
+                    &log.inner.from.to_string(),
+                    &log.inner.to.to_string(),
+                    &log.address().to_string(),
+                    &log.inner.value.to_string(),
+                ],
+            )?;
+            if inserted > 0 {
+                self.record_amount_out_of_range(
+                    tx,
+                    rollup_id,
+                    &position,
+                    "WrappedTransfer",
+                    log.inner.value,
+                )?;
+                let sign = supply_sign(&log.inner.from.to_string(), &log.inner.to.to_string());
+                add_to_balance(
+                    tx,
+                    rollup_id,
+                    &log.address(),
+                    "supply",
+                    log.inner.value,
+                    sign,
+                )?;
+                record_token_activity(
+                    tx,
+                    rollup_id,
+                    &log.address(),
+                    position.block_number,
+                    log.block_timestamp,
+                )?;
+            }
+            Ok(inserted)
+        })?;
+        Ok(self.record_insert(rollup_id, inserted))
+    }
+
+    // A transfer of a wrapped token between two holders. Unlike mints and
+    // burns it doesn't change the supply, so no balance is updated.
+    pub async fn insert_holder_transfer_event(
+        &self,
+        log: &Log<Transfer>,
+        rollup_id: u32,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some((id, position)) = required_fields(log, rollup_id) else {
+            return Ok(false);
+        };
+        let conn = self.db.lock().await;
+        let inserted = conn.execute(
+            &format!(
+                "INSERT OR IGNORE INTO holder_transfer_events (
+            id,
+            rollup_id,
+            transaction_hash,
+            block_hash,
+            block_number,
+            transaction_index,
+            log_index,
+            from_address,
+            to_address,
+            token_address,
+            value
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, TRY_CAST(? AS {}));",
+                self.amount_type.sql_type()
+            ),
+            [
+                &id,
+                &rollup_id.to_string(),
+                &position.transaction_hash,
+                &position.block_hash,
+                &position.block_number.to_string(),
+                &position.transaction_index.to_string(),
+                &position.log_index.to_string(),
+                &log.inner.from.to_string(),
+                &log.inner.to.to_string(),
+                &log.address().to_string(),
+                &log.inner.value.to_string(),
+            ],
+        )?;
+        if inserted > 0 {
+            self.record_amount_out_of_range(
+                &conn,
+                rollup_id,
+                &position,
+                "HolderTransfer",
+                log.inner.value,
+            )?;
+        }
+        Ok(self.record_insert(rollup_id, inserted))
+    }
+
+    pub async fn insert_bridge_transfer_event(
+        &self,
+        log: &Log<Transfer>,
+        rollup_id: u32,
+        direction: &str,
+        bridge_address: Address,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some((id, position)) = required_fields(log, rollup_id) else {
+            return Ok(false);
+        };
+        let conn = self.db.lock().await;
+        // Along with the balance it changes.
+        let inserted = self.atomically(&conn, |tx| {
+            let inserted = tx.execute(
+                &format!(
+                    "INSERT OR IGNORE INTO bridge_transfer_events (
+                id,
+                rollup_id,
+                transaction_hash,
+                block_hash,
+                block_number,
+                transaction_index,
+                log_index,
+                from_address,
+                to_address,
+                token_address,
+                value,
+                direction,
+                bridge_address
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, TRY_CAST(? AS {}), ?, ?);",
+                    self.amount_type.sql_type()
+                ),
+                [
+                    &id,
+                    &rollup_id.to_string(),
+                    &position.transaction_hash,
+                    &position.block_hash,
+                    &position.block_number.to_string(),
+                    &position.transaction_index.to_string(),
+                    &position.log_index.to_string(),
+                    &log.inner.from.to_string(),
+                    &log.inner.to.to_string(),
+                    &log.address().to_string(),
+                    &log.inner.value.to_string(),
+                    &direction.to_string(),
+                    &bridge_address.to_string(),
+                ],
+            )?;
+            if inserted > 0 {
+                self.record_amount_out_of_range(
+                    tx,
+                    rollup_id,
+                    &position,
+                    "BridgeTransfer",
+                    log.inner.value,
+                )?;
+                let sign = bridge_sign(
+                    Some(direction),
+                    &log.inner.from.to_string(),
+                    &log.inner.to.to_string(),
+                    &[],
+                );
+                add_to_balance(
+                    tx,
+                    rollup_id,
+                    &log.address(),
+                    "bridge",
+                    log.inner.value,
+                    sign,
+                )?;
+                record_token_activity(
+                    tx,
+                    rollup_id,
+                    &log.address(),
+                    position.block_number,
+                    log.block_timestamp,
+                )?;
+            }
+            Ok(inserted)
+        })?;
+        Ok(self.record_insert(rollup_id, inserted))
+    }
+
+    // Records a log that could not be decoded. A log failing again when its
+    // window is retried is only recorded once.
+    pub async fn insert_indexing_error(
+        &self,
+        rollup_id: u32,
+        log: &Log,
+        kind: &str,
+        error: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let topics = log
+            .topics()
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let conn = self.db.lock().await;
+        conn.execute(
+            "INSERT OR IGNORE INTO indexing_errors
+                (rollup_id, transaction_hash, log_index, block_number, kind, topics, error)
+            VALUES (?, ?, ?, ?, ?, ?, ?)",
+            duckdb::params![
+                rollup_id,
+                log.transaction_hash.map(|h| h.to_string()),
+                log.log_index.map(|i| i as i64),
+                log.block_number.map(|b| b as i64),
+                kind,
+                topics,
+                error,
+            ],
+        )?;
+        self.record_write();
+        Ok(())
+    }
+
+    pub async fn insert_block_timestamp(
+        &self,
+        rollup_id: u32,
+        block_number: u64,
+        timestamp: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+        conn.execute(
+            "INSERT OR IGNORE INTO block_timestamps (rollup_id, block_number, timestamp)
+            VALUES (?, ?, ?)",
+            duckdb::params![rollup_id, block_number as i64, timestamp as i64],
+        )?;
+        self.record_write();
+        Ok(())
+    }
+
+    // `events` are the event types indexed in the range, see --events.
+    pub async fn insert_indexed_range(
+        &self,
+        rollup_id: u32,
+        from_block: u64,
+        to_block: u64,
+        events: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+        conn.execute(
+            "INSERT INTO indexed_ranges (rollup_id, from_block, to_block, events) VALUES (?, ?, ?, ?)",
+            duckdb::params![rollup_id, from_block as i64, to_block as i64, events],
+        )?;
+        Ok(())
+    }
+
+    // Event type sets the ranges of a rollup were indexed with, each with the
+    // first block indexed with it. Ranges recorded without a set predate
+    // --events, every type but holder transfers was indexed in them.
+    pub async fn indexed_event_sets(
+        &self,
+        rollup_id: u32,
+    ) -> Result<Vec<(String, u64)>, Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT COALESCE(events, 'bridge,claim,wrapped,transfer') AS events, \
+            MIN(from_block) FROM indexed_ranges WHERE rollup_id = ? GROUP BY 1",
+        )?;
+        let sets = stmt
+            .query_map([rollup_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as u64))
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(sets)
+    }
+
+    // Forgets the ranges of a rollup starting at `from_block` or later, eg
+    // before they are indexed again.
+    pub async fn remove_indexed_ranges(
+        &self,
+        rollup_id: u32,
+        from_block: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+        conn.execute(
+            "DELETE FROM indexed_ranges WHERE rollup_id = ? AND from_block >= ?",
+            duckdb::params![rollup_id, from_block as i64],
+        )?;
+        Ok(())
+    }
+
+    // Stores the rollup, or refreshes its names if already known. network_name
+    // is `name_override` if given (see --rollup-name), otherwise the on-chain
+    // name, which is always kept in onchain_name.
+    pub async fn insert_rollup(
+        &self,
+        rollup_id: u32,
+        onchain_name: &str,
+        name_override: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let network_name = name_override.unwrap_or(onchain_name);
+        let conn = self.db.lock().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT latest_bridge_synced_block FROM rollups WHERE rollup_id = ? LIMIT 1",
+        )?;
+        let mut rows = stmt.query(&[&rollup_id.to_string()])?;
+
+        // TODO: Most likely not the best way to do this.
+        if let Some(row) = rows.next()? {
+            // Try to get an Option<i64>
+            let block: Option<i64> = row.get(0)?;
+            info!(rollup_id, "block: {:?} already known", block);
+            // …use `block`…
+            conn.execute(
+                "UPDATE rollups SET network_name = ?, onchain_name = ? WHERE rollup_id = ?",
+                [network_name, onchain_name, &rollup_id.to_string()],
+            )?;
+        } else {
+            conn.execute(
+                "INSERT INTO rollups (
+                rollup_id,
+                network_name,
+                onchain_name,
+                latest_bridge_synced_block)
+            VALUES (?, ?, ?, NULL);",
+                [&rollup_id.to_string(), network_name, onchain_name],
+            )?;
+        }
+
+        self.record_write();
+        Ok(())
+    }
+
+    pub async fn update_rollup_metadata(
+        &self,
+        rollup_id: u32,
+        metadata: &RollupMetadata,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+        conn.execute(
+            "UPDATE rollups SET
+                rollup_address = ?,
+                chain_id = ?,
+                fork_id = ?,
+                rollup_type_id = ?,
+                verifier_type = ?,
+                verifier = ?
+            WHERE rollup_id = ?",
+            [
+                &metadata.rollup_address.to_string(),
+                &metadata.chain_id.to_string(),
+                &metadata.fork_id.to_string(),
+                &metadata.rollup_type_id.to_string(),
+                &metadata.verifier_type.to_string(),
+                &metadata.verifier.to_string(),
+                &rollup_id.to_string(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Block the rollup bridge was deployed at, if it was already detected.
+    pub async fn deploy_block(&self, rollup_id: u32) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+        let mut stmt = conn.prepare("SELECT deploy_block FROM rollups WHERE rollup_id = ?")?;
+        let mut rows = stmt.query([&rollup_id])?;
+        if let Some(row) = rows.next()? {
+            let block: Option<i64> = row.get(0)?;
+            Ok(block.map(|b| b as u64))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub async fn set_deploy_block(
+        &self,
+        rollup_id: u32,
+        block: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+        conn.execute(
+            "UPDATE rollups SET deploy_block = ? WHERE rollup_id = ?",
+            [&block.to_string(), &rollup_id.to_string()],
+        )?;
+        Ok(())
+    }
+
+    // Block resolved for the given --start-timestamp, None if it was never
+    // resolved or it was resolved for another timestamp.
+    pub async fn start_timestamp_block(
+        &self,
+        rollup_id: u32,
+        timestamp: u64,
+    ) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT start_timestamp_block FROM rollups WHERE rollup_id = ? AND start_timestamp = ?",
+        )?;
+        let mut rows = stmt.query([&rollup_id.to_string(), &timestamp.to_string()])?;
+        if let Some(row) = rows.next()? {
+            let block: Option<i64> = row.get(0)?;
+            Ok(block.map(|b| b as u64))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub async fn set_start_timestamp_block(
+        &self,
+        rollup_id: u32,
+        timestamp: u64,
+        block: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+        conn.execute(
+            "UPDATE rollups SET start_timestamp = ?, start_timestamp_block = ? WHERE rollup_id = ?",
+            [
+                &timestamp.to_string(),
+                &block.to_string(),
+                &rollup_id.to_string(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Lowest block indexed by a descending backfill, None if the rollup was
+    // never indexed in descending order.
+    pub async fn backfilled_down_to(
+        &self,
+        rollup_id: u32,
+    ) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+        let mut stmt =
+            conn.prepare("SELECT backfilled_down_to FROM rollups WHERE rollup_id = ?")?;
+        let mut rows = stmt.query([&rollup_id])?;
+        if let Some(row) = rows.next()? {
+            let block: Option<i64> = row.get(0)?;
+            Ok(block.map(|b| b as u64))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Position (block number, log index) of the last log stored for a rollup
+    // in any of the event tables, None if there are none.
+    pub async fn last_stored_log(
+        &self,
+        rollup_id: u32,
+    ) -> Result<Option<(u64, u64)>, Box<dyn std::error::Error>> {
+        let selects = EVENT_TABLES
+            .iter()
+            .map(|table| {
+                format!(
+                    "SELECT block_number, log_index FROM {} WHERE rollup_id = ?",
+                    table
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" UNION ALL ");
+        let conn = self.db.lock().await;
+        let position = conn
+            .query_row(
+                &format!(
+                    "SELECT block_number, log_index FROM ({})
+                    ORDER BY block_number DESC, log_index DESC LIMIT 1",
+                    selects
+                ),
+                duckdb::params_from_iter(std::iter::repeat_n(rollup_id, EVENT_TABLES.len())),
+                |row| {
+                    Ok((
+                        row.get::<usize, i64>(0)? as u64,
+                        row.get::<usize, i64>(1)? as u64,
+                    ))
+                },
+            )
+            .optional()?;
+        Ok(position)
+    }
+
+    // Last block of the ranges indexed for a rollup, None if no range was
+    // recorded yet.
+    pub async fn last_indexed_range_end(
+        &self,
+        rollup_id: u32,
+    ) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+        let block: Option<i64> = conn.query_row(
+            "SELECT MAX(to_block) FROM indexed_ranges WHERE rollup_id = ?",
+            [rollup_id],
+            |row| row.get(0),
+        )?;
+        Ok(block.map(|b| b as u64))
+    }
+
+    pub async fn set_backfilled_down_to(
+        &self,
+        rollup_id: u32,
+        block: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+        conn.execute(
+            "UPDATE rollups SET backfilled_down_to = ? WHERE rollup_id = ?",
+            [&block.to_string(), &rollup_id.to_string()],
+        )?;
+        Ok(())
+    }
+
+    // Last block of the forward sync cursor, None if the rollup is unknown or
+    // nothing was indexed yet (not even block 0).
+    pub async fn last_indexed_block(
+        &self,
+        rollup_id: u32,
+    ) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+        let block: Option<i64> = conn
+            .query_row(
+                "SELECT latest_bridge_synced_block FROM rollups WHERE rollup_id = ?",
+                [rollup_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        Ok(block.map(|block| block as u64))
+    }
+
+    // Moves the sync cursor of a rollup. Moving it backwards is refused unless
+    // `allow_rewind` is set (eg a rescan), so a buggy caller can't silently
+    // cause reprocessing or gaps.
+    pub async fn synced_till_block(
+        &self,
+        rollup_id: u32,
+        block: u64,
+        allow_rewind: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        info!(rollup_id, "Synced till block: {:?}", block);
+        let conn = self.db.lock().await;
+        let current: Option<i64> = conn
+            .query_row(
+                "SELECT latest_bridge_synced_block FROM rollups WHERE rollup_id = ?",
+                [&rollup_id],
+                |row| row.get(0),
+            )
+            .unwrap_or(None);
+        if let Some(current) = current.filter(|c| *c > block as i64) {
+            if !allow_rewind {
+                return Err(format!(
+                    "refusing to move rollup {} cursor back from {} to {}",
+                    rollup_id, current, block
+                )
+                .into());
+            }
+            info!(
+                rollup_id,
+                "Rewinding cursor from {:?} to {:?}", current, block
+            );
+        }
         conn.execute(
             "UPDATE rollups SET latest_bridge_synced_block = ? WHERE rollup_id = ?",
             &[&block.to_string(), &rollup_id.to_string()],
@@ -437,6 +1533,18 @@ impl Database {
         Ok(())
     }
 
+    // Clears the sync cursor of a rollup so that it is indexed again from its
+    // start block, block 0 included.
+    pub async fn reset_cursor(&self, rollup_id: u32) -> Result<(), Box<dyn std::error::Error>> {
+        info!(rollup_id, "Resetting cursor");
+        let conn = self.db.lock().await;
+        conn.execute(
+            "UPDATE rollups SET latest_bridge_synced_block = NULL WHERE rollup_id = ?",
+            [rollup_id],
+        )?;
+        Ok(())
+    }
+
     pub async fn fetch_wrapped_tokens(
         &self,
         rollup_id: u32,
@@ -462,7 +1570,2231 @@ impl Database {
         Ok(wrapped_tokens)
     }
 
-    pub fn db(&self) -> &Arc<Mutex<Connection>> {
-        &self.db
+    // Same as fetch_wrapped_tokens but also returns the origin and the decoded
+    // metadata of each token.
+    pub async fn fetch_wrapped_tokens_full(
+        &self,
+        rollup_id: u32,
+    ) -> Result<Vec<WrappedToken>, Box<dyn std::error::Error>> {
+        let conn = self.db.lock().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT wrappedTokenAddress, originNetwork, originTokenAddress, metadata
+             FROM new_wrapped_token_events
+             WHERE rollup_id = ?",
+        )?;
+
+        let rows: Vec<(String, i64, String, String)> = stmt
+            .query_map([&rollup_id.to_string()], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?
+            .collect::<Result<_, _>>()?;
+
+        let mut wrapped_tokens = Vec::with_capacity(rows.len());
+        for (address, origin_network, origin_token_address, metadata) in rows {
+            // Metadata is stored as a 0x prefixed hex string.
+            let metadata = metadata.parse::<Bytes>().unwrap_or_default();
+            wrapped_tokens.push(WrappedToken::new(
+                address.parse::<Address>()?,
+                origin_network as u32,
+                origin_token_address.parse::<Address>()?,
+                &metadata,
+            ));
+        }
+
+        Ok(wrapped_tokens)
+    }
+
+    async fn token_balances_missing(&self) -> Result<bool> {
+        let conn = self.db.lock().await;
+        let balances: i64 =
+            conn.query_row("SELECT COUNT(*) FROM token_balances", [], |row| row.get(0))?;
+        let transfers: i64 =
+            conn.query_row("SELECT COUNT(*) FROM all_transfers", [], |row| row.get(0))?;
+        Ok(balances == 0 && transfers > 0)
+    }
+
+    // Deletes bridge transfers that are also stored as wrapped token mints or
+    // burns, indexed before each transfer was classified into a single table
+    // (see dedup_logs in the indexer). Balances must be recomputed after.
+    // Returns the transfers deleted.
+    pub async fn remove_duplicate_transfers(&self) -> Result<usize> {
+        let conn = self.db.lock().await;
+        conn.execute(
+            "DELETE FROM bridge_transfer_events AS bridge
+            USING wrapped_transfer_events AS wrapped
+            WHERE bridge.rollup_id = wrapped.rollup_id
+                AND bridge.transaction_hash = wrapped.transaction_hash
+                AND bridge.log_index = wrapped.log_index",
+            [],
+        )
+    }
+
+    // Rebuilds token_balances from the full transfer history. Returns how many
+    // balances differed from the materialized ones, which should be none.
+    pub async fn recompute_token_balances(&self) -> Result<usize> {
+        let mut conn = self.db.lock().await;
+        let tx = conn.transaction()?;
+
+        let mut bridges: HashMap<i64, Vec<String>> = HashMap::new();
+        {
+            let mut stmt = tx.prepare(
+                "SELECT DISTINCT rollup_id, bridge_address FROM bridge_transfer_events
+                WHERE bridge_address IS NOT NULL",
+            )?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                bridges.entry(row.get(0)?).or_default().push(row.get(1)?);
+            }
+        }
+
+        let mut balances: HashMap<(i64, String, String), Balance> = HashMap::new();
+        {
+            let mut stmt = tx.prepare(
+                "SELECT 'supply', rollup_id, token_address, CAST(value AS TEXT), from_address,
+                    to_address, NULL
+                FROM wrapped_transfer_events
+                UNION ALL
+                SELECT 'bridge', rollup_id, token_address, CAST(value AS TEXT), from_address,
+                    to_address, direction
+                FROM bridge_transfer_events",
+            )?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                let kind: String = row.get(0)?;
+                let rollup_id: i64 = row.get(1)?;
+                let token_address: String = row.get(2)?;
+                // NULL for amounts out of the range of --amount-type.
+                let Some(value): Option<String> = row.get(3)? else {
+                    continue;
+                };
+                let from_address: String = row.get(4)?;
+                let to_address: String = row.get(5)?;
+                let direction: Option<String> = row.get(6)?;
+                let sign = match kind.as_str() {
+                    "supply" => supply_sign(&from_address, &to_address),
+                    _ => bridge_sign(
+                        direction.as_deref(),
+                        &from_address,
+                        &to_address,
+                        bridges
+                            .get(&rollup_id)
+                            .map(|b| b.as_slice())
+                            .unwrap_or_default(),
+                    ),
+                };
+                let value = signed_value(parse_balance(&value, 3)?, sign);
+                let balance = balances
+                    .entry((rollup_id, token_address.to_lowercase(), kind))
+                    .or_insert(Balance::ZERO);
+                *balance = add_balances(*balance, value)?;
+            }
+        }
+
+        let mut current: HashMap<(i64, String, String), String> = HashMap::new();
+        {
+            let mut stmt =
+                tx.prepare("SELECT rollup_id, token_address, kind, balance FROM token_balances")?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                current.insert((row.get(0)?, row.get(1)?, row.get(2)?), row.get(3)?);
+            }
+        }
+        let mismatches = balances
+            .iter()
+            .filter(|(key, balance)| current.remove(*key) != Some(balance.to_string()))
+            .count()
+            + current.len();
+
+        tx.execute("DELETE FROM token_balances", [])?;
+        for ((rollup_id, token_address, kind), balance) in balances.iter() {
+            tx.execute(
+                "INSERT INTO token_balances (rollup_id, token_address, kind, balance)
+                VALUES (?, ?, ?, ?)",
+                duckdb::params![rollup_id, token_address, kind, balance.to_string()],
+            )?;
+        }
+        tx.commit()?;
+        Ok(mismatches)
+    }
+
+    // Balance of every holder of a wrapped token with a non zero one, largest
+    // first: its mints and the transfers it received minus its burns and the
+    // transfers it sent, summed exactly. Transfers between holders are only
+    // indexed with --events holder, and transfers from or to the bridge are
+    // stored as bridge transfers. Fails on amounts stored as NULL, out of the
+    // range of --amount-type.
+    pub async fn token_holders(
+        &self,
+        rollup_id: u32,
+        token_address: Address,
+    ) -> Result<Vec<(String, String)>> {
+        let conn = self.db.lock().await;
+        let selects = HOLDER_TRANSFER_TABLES
+            .iter()
+            .map(|table| {
+                format!(
+                    "SELECT id, LOWER(from_address), LOWER(to_address), CAST(value AS TEXT)
+                    FROM {} WHERE rollup_id = ? AND LOWER(token_address) = ?",
+                    table
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" UNION ALL ");
+        let token_address = token_address.to_string().to_lowercase();
+        let mut stmt = conn.prepare(&selects)?;
+        let mut rows = stmt.query(duckdb::params_from_iter(
+            HOLDER_TRANSFER_TABLES
+                .iter()
+                .flat_map(|_| [rollup_id.to_string(), token_address.clone()]),
+        ))?;
+
+        // A log stored in two tables by indexers with different --events
+        // counts once.
+        let mut seen = HashSet::new();
+        let mut balances: HashMap<String, Balance> = HashMap::new();
+        while let Some(row) = rows.next()? {
+            let id: String = row.get(0)?;
+            if !seen.insert(id) {
+                continue;
+            }
+            let from_address: String = row.get(1)?;
+            let to_address: String = row.get(2)?;
+            let value: Option<String> = row.get(3)?;
+            let Some(value) = value else {
+                return Err(duckdb::Error::FromSqlConversionFailure(
+                    3,
+                    duckdb::types::Type::Null,
+                    "amount out of the range of --amount-type".into(),
+                ));
+            };
+            let value = parse_balance(&value, 3)?;
+            for (holder, sign) in [(from_address, -1), (to_address, 1)] {
+                let balance = balances.entry(holder).or_insert(Balance::ZERO);
+                *balance = add_balances(*balance, signed_value(value, sign))?;
+            }
+        }
+
+        let zero = Address::ZERO.to_string().to_lowercase();
+        let mut holders: Vec<(String, Balance)> = balances
+            .into_iter()
+            .filter(|(holder, balance)| *holder != zero && !balance.is_zero())
+            .collect();
+        holders.sort_by(|(a, a_balance), (b, b_balance)| b_balance.cmp(a_balance).then(a.cmp(b)));
+        Ok(holders
+            .into_iter()
+            .map(|(holder, balance)| (holder, balance.to_string()))
+            .collect())
+    }
+
+    // Copies every table to parquet files under `target`, a local directory or
+    // an s3://bucket/prefix url written through the httpfs extension (with the
+    // usual duckdb s3 settings). Tables with a block_number are split per
+    // rollup in partitions of SNAPSHOT_PARTITION_BLOCKS blocks, and the
+    // manifest.json next to them lists the complete ones with their block
+    // range. Partitions already in the manifest are skipped, so a failed
+    // snapshot is resumed by running it again. Returns the partitions written.
+    pub async fn snapshot(&self, target: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        let target = target.trim_end_matches('/');
+        let remote = target.contains("://");
+        let quote = |path: &str| path.replace('\'', "''");
+        let manifest_path = format!("{}/manifest.json", target);
+
+        let conn = self.db.lock().await;
+        if remote {
+            conn.execute_batch("INSTALL httpfs; LOAD httpfs;")?;
+        } else {
+            std::fs::create_dir_all(target)?;
+        }
+
+        conn.execute_batch(
+            "CREATE OR REPLACE TEMP TABLE snapshot_manifest (
+                table_name TEXT,
+                rollup_id BIGINT,
+                from_block BIGINT,
+                to_block BIGINT,
+                path TEXT
+            );",
+        )?;
+        let resumed = conn.execute(
+            &format!(
+                "INSERT INTO snapshot_manifest
+                SELECT table_name, rollup_id, from_block, to_block, path
+                FROM read_json_auto('{}')",
+                quote(&manifest_path)
+            ),
+            [],
+        );
+        match resumed {
+            Ok(count) => info!("Resuming snapshot, {} partitions already written", count),
+            Err(_) => info!("No manifest at {}, starting a new snapshot", manifest_path),
+        }
+
+        let tables: Vec<(String, bool)> = table_columns(&conn)?
+            .into_iter()
+            .filter(|(table, _)| !SNAPSHOT_SKIPPED_TABLES.contains(&table.as_str()))
+            .map(|(table, columns)| {
+                let has_blocks = columns.iter().any(|c| c == "block_number");
+                (table, has_blocks)
+            })
+            .collect();
+
+        let mut synced: HashMap<i64, i64> = HashMap::new();
+        {
+            let mut stmt = conn.prepare(
+                "SELECT rollup_id, latest_bridge_synced_block FROM rollups
+                WHERE latest_bridge_synced_block IS NOT NULL",
+            )?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                synced.insert(row.get(0)?, row.get(1)?);
+            }
+        }
+
+        let mut written = 0;
+        for (table, has_blocks) in tables.iter() {
+            // Small tables without blocks are copied whole every time.
+            if !has_blocks {
+                let path = format!("{}/{}.parquet", target, table);
+                conn.execute_batch(&format!(
+                    "COPY {} TO '{}' (FORMAT PARQUET);",
+                    table,
+                    quote(&path)
+                ))?;
+                written += 1;
+                continue;
+            }
+
+            let mut partitions: Vec<(i64, i64)> = Vec::new();
+            {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT DISTINCT rollup_id, block_number // {} FROM {}
+                    WHERE block_number IS NOT NULL
+                    ORDER BY 1, 2",
+                    SNAPSHOT_PARTITION_BLOCKS, table
+                ))?;
+                let mut rows = stmt.query([])?;
+                while let Some(row) = rows.next()? {
+                    partitions.push((row.get(0)?, row.get(1)?));
+                }
+            }
+
+            for (rollup_id, partition) in partitions {
+                let from_block = partition * SNAPSHOT_PARTITION_BLOCKS;
+                let to_block = from_block + SNAPSHOT_PARTITION_BLOCKS - 1;
+                let done: i64 = conn.query_row(
+                    "SELECT COUNT(*) FROM snapshot_manifest
+                    WHERE table_name = ? AND rollup_id = ? AND from_block = ?",
+                    duckdb::params![table, rollup_id, from_block],
+                    |row| row.get(0),
+                )?;
+                if done > 0 {
+                    continue;
+                }
+
+                let dir = format!("{}/{}/rollup_id={}", target, table, rollup_id);
+                if !remote {
+                    std::fs::create_dir_all(&dir)?;
+                }
+                let path = format!("{}/blocks_{}_{}.parquet", dir, from_block, to_block);
+                conn.execute_batch(&format!(
+                    "COPY (SELECT * FROM {} WHERE rollup_id = {} AND block_number BETWEEN {} AND {})
+                    TO '{}' (FORMAT PARQUET);",
+                    table,
+                    rollup_id,
+                    from_block,
+                    to_block,
+                    quote(&path)
+                ))?;
+                written += 1;
+
+                // A partition the indexer hasn't synced past can still get
+                // rows, so it's left out of the manifest and written again.
+                if synced
+                    .get(&rollup_id)
+                    .is_some_and(|block| *block >= to_block)
+                {
+                    conn.execute(
+                        "INSERT INTO snapshot_manifest VALUES (?, ?, ?, ?, ?)",
+                        duckdb::params![table, rollup_id, from_block, to_block, path],
+                    )?;
+                    conn.execute_batch(&format!(
+                        "COPY snapshot_manifest TO '{}' (FORMAT JSON);",
+                        quote(&manifest_path)
+                    ))?;
+                }
+            }
+        }
+
+        conn.execute_batch("DROP TABLE snapshot_manifest;")?;
+        Ok(written)
+    }
+
+    // Loads rows exported by snapshot, or any parquet or json lines file with
+    // the columns of a table. `from` is either the target of a snapshot, whose
+    // files are matched to the tables by name, or a single file imported into
+    // `table` (by default the name of the file). Rows already in the database
+    // are skipped, the sync cursors of the rollups are moved up to the
+    // imported ones so that their blocks aren't indexed again (only as far as
+    // the partitions of a snapshot are complete, see snapshot_complete_till),
+    // and the token balances are recomputed. Returns the rows inserted.
+    pub async fn import(
+        &self,
+        from: &str,
+        table: Option<&str>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let from = from.trim_end_matches('/');
+        let quote = |path: &str| path.replace('\'', "''");
+        let reader = |path: &str| {
+            if path.ends_with(".parquet") {
+                format!("read_parquet('{}', hive_partitioning = false)", quote(path))
+            } else {
+                format!(
+                    "read_json_auto('{}', format = 'newline_delimited')",
+                    quote(path)
+                )
+            }
+        };
+        let is_file = [".parquet", ".ndjson", ".jsonl", ".json"]
+            .iter()
+            .any(|extension| from.ends_with(extension));
+
+        let mut conn = self.db.lock().await;
+        if from.contains("://") {
+            conn.execute_batch("INSTALL httpfs; LOAD httpfs;")?;
+        }
+        let columns: HashMap<String, Vec<String>> = table_columns(&conn)?.into_iter().collect();
+        let complete_till = if is_file {
+            HashMap::new()
+        } else {
+            snapshot_complete_till(&conn, from)?
+        };
+
+        // (table, file pattern) of every file set to import.
+        let mut sources: Vec<(String, String)> = Vec::new();
+        if is_file {
+            let table = match table {
+                Some(table) => table.to_string(),
+                None => from
+                    .rsplit('/')
+                    .next()
+                    .and_then(|name| name.split('.').next())
+                    .unwrap_or_default()
+                    .to_string(),
+            };
+            sources.push((table, from.to_string()));
+        } else {
+            let mut tables: Vec<&String> = columns
+                .keys()
+                .filter(|table| !IMPORT_SKIPPED_TABLES.contains(&table.as_str()))
+                .collect();
+            // The rollups go first, see below.
+            tables.sort_by_key(|table| (table.as_str() != "rollups", table.to_string()));
+            for table in tables {
+                for pattern in [
+                    format!("{}/{}.parquet", from, table),
+                    format!("{}/{}/*/*.parquet", from, table),
+                    format!("{}/{}.ndjson", from, table),
+                    format!("{}/{}.jsonl", from, table),
+                ] {
+                    let files: i64 = conn.query_row(
+                        &format!("SELECT COUNT(*) FROM glob('{}')", quote(&pattern)),
+                        [],
+                        |row| row.get(0),
+                    )?;
+                    if files > 0 {
+                        sources.push((table.clone(), pattern));
+                    }
+                }
+            }
+        }
+
+        let tx = conn.transaction()?;
+        let mut imported = 0;
+        for (table, pattern) in sources.iter() {
+            let Some(expected) = columns.get(table) else {
+                return Err(format!("unknown table {} for {}", table, pattern).into());
+            };
+            let mut source = reader(pattern);
+
+            let mut found = Vec::new();
+            let mut unknown = Vec::new();
+            {
+                let mut stmt = tx.prepare(&format!("DESCRIBE SELECT * FROM {}", source))?;
+                let mut rows = stmt.query([])?;
+                while let Some(row) = rows.next()? {
+                    let column: String = row.get(0)?;
+                    if !expected.contains(&column) {
+                        unknown.push(column.clone());
+                    }
+                    found.push(column);
+                }
+            }
+            if !unknown.is_empty() {
+                return Err(format!(
+                    "{} has columns that are not in {}: {}",
+                    pattern,
+                    table,
+                    unknown.join(", ")
+                )
+                .into());
+            }
+
+            // Rollups that are already known keep their row, only their
+            // cursor is moved forward. The imported cursors are moved back to
+            // the end of the complete part of the snapshot first, so that the
+            // blocks of incomplete or missing partitions are indexed again.
+            if table == "rollups" {
+                tx.execute_batch(&format!(
+                    "CREATE OR REPLACE TEMP TABLE imported_rollups AS SELECT * FROM {};",
+                    source
+                ))?;
+                for (rollup_id, block) in complete_till.iter() {
+                    let cursor = if *block < 0 {
+                        "NULL"
+                    } else {
+                        "LEAST(latest_bridge_synced_block, ?)"
+                    };
+                    let mut params: Vec<i64> = Vec::new();
+                    if *block >= 0 {
+                        params.push(*block);
+                    }
+                    params.push(*rollup_id);
+                    tx.execute(
+                        &format!(
+                            "UPDATE imported_rollups SET latest_bridge_synced_block = {}
+                            WHERE rollup_id = ? AND latest_bridge_synced_block IS NOT NULL",
+                            cursor
+                        ),
+                        duckdb::params_from_iter(params),
+                    )?;
+                }
+                source = "imported_rollups".to_string();
+
+                tx.execute(
+                    &format!(
+                        "UPDATE rollups SET latest_bridge_synced_block = imported.latest_bridge_synced_block
+                        FROM {} AS imported
+                        WHERE rollups.rollup_id = imported.rollup_id
+                            AND imported.latest_bridge_synced_block
+                                > COALESCE(rollups.latest_bridge_synced_block, -1)",
+                        source
+                    ),
+                    [],
+                )?;
+            }
+
+            // Tables without a key (eg indexed_ranges) skip the rows that are
+            // already there column by column.
+            let keyed: i64 = tx.query_row(
+                "SELECT COUNT(*) FROM duckdb_constraints()
+                WHERE schema_name = 'main' AND table_name = ?
+                    AND constraint_type IN ('PRIMARY KEY', 'UNIQUE')",
+                [table],
+                |row| row.get(0),
+            )?;
+            let insert = if keyed > 0 {
+                format!(
+                    "INSERT OR IGNORE INTO {} BY NAME SELECT * FROM {}",
+                    table, source
+                )
+            } else {
+                let same_row = found
+                    .iter()
+                    .map(|column| format!("t.{0} IS NOT DISTINCT FROM s.{0}", column))
+                    .collect::<Vec<_>>()
+                    .join(" AND ");
+                format!(
+                    "INSERT INTO {0} BY NAME SELECT DISTINCT * FROM {1} s
+                    WHERE NOT EXISTS (SELECT 1 FROM {0} t WHERE {2})",
+                    table, source, same_row
+                )
+            };
+            let rows = tx.execute(&insert, [])?;
+            info!("Imported {} rows into {} from {}", rows, table, pattern);
+            imported += rows;
+        }
+        tx.execute_batch("DROP TABLE IF EXISTS imported_rollups;")?;
+        tx.commit()?;
+        drop(conn);
+
+        self.recompute_token_balances().await?;
+        Ok(imported)
+    }
+
+    pub fn db(&self) -> &Arc<Mutex<Connection>> {
+        &self.db
+    }
+}
+
+// Columns of every table and view of the current database, by table name.
+fn table_columns(conn: &Connection) -> Result<Vec<(String, Vec<String>)>> {
+    let mut stmt = conn.prepare(
+        "SELECT table_name, column_name FROM information_schema.columns
+        WHERE table_catalog = current_database() AND table_schema = 'main'
+        ORDER BY table_name, ordinal_position",
+    )?;
+    let mut tables: Vec<(String, Vec<String>)> = Vec::new();
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let table: String = row.get(0)?;
+        let column: String = row.get(1)?;
+        match tables.last_mut() {
+            Some((last, columns)) if *last == table => columns.push(column),
+            _ => tables.push((table, vec![column])),
+        }
+    }
+    Ok(tables)
+}
+
+// Creates the tables and views if they don't exist yet, and migrates tables
+// created by older versions. Amount columns of existing tables keep their type.
+fn create_schema(conn: &Connection, amount_type: AmountType) -> Result<()> {
+    // The *_unconfirmed tables were created without their primary key by
+    // older versions. They only hold the events of the last few blocks.
+    for table in UNCONFIRMED_TABLES {
+        let table = format!("{}_unconfirmed", table);
+        let primary_keys: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM duckdb_constraints()
+            WHERE database_name = current_database() AND schema_name = 'main'
+                AND table_name = ? AND constraint_type = 'PRIMARY KEY'",
+            [&table],
+            |row| row.get(0),
+        )?;
+        if primary_keys == 0 {
+            conn.execute_batch(&format!("DROP TABLE IF EXISTS {};", table))?;
+        }
+    }
+
+    // Events above the confirmed head are stored in tables with the same
+    // columns, see Database::unconfirmed.
+    for suffix in ["", "_unconfirmed"] {
+        create_event_tables(conn, amount_type, suffix)?;
+    }
+
+    // The claim event doesn't carry the leaf type, it's taken from the bridge
+    // event it claims (0 asset, 1 message). Set after each indexed chunk (see
+    // Database::fill_claim_leaf_types), and here for the claims stored before
+    // the column existed. NULL for v1 claims and claims of deposits not indexed (in the
+    // same database) yet.
+    conn.execute_batch(
+        "UPDATE claim_events SET leafType = bridge.leafType
+        FROM bridge_events AS bridge
+        WHERE claim_events.leafType IS NULL
+            AND bridge.rollup_id = claim_events.source_rollup_id
+            AND bridge.depositCount = claim_events.local_exit_index
+            AND bridge.destinationNetwork = claim_events.rollup_id;",
+    )?;
+
+    // Maps to NewWrappedToken event
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS new_wrapped_token_events (
+        id TEXT PRIMARY KEY,
+        rollup_id INTEGER,
+        transaction_hash TEXT,
+        block_hash TEXT,
+        block_number INTEGER,
+        transaction_index INTEGER,
+        log_index INTEGER,
+        originNetwork INTEGER,
+        originTokenAddress TEXT,
+        wrappedTokenAddress TEXT,
+        metadata TEXT
+    );",
+        [],
+    )?;
+
+    // Store each rollup information.
+    // By now its only to know how synced the rollup is.
+    // Note that 0 is the l1.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS rollups (
+        rollup_id INTEGER PRIMARY KEY,
+        network_name TEXT,
+        latest_bridge_synced_block BIGINT
+    );",
+        [],
+    )?;
+
+    // Rollup metadata from the rollup manager. Added after the table was
+    // created, so existing databases are migrated in place.
+    conn.execute_batch(
+        "ALTER TABLE rollups ADD COLUMN IF NOT EXISTS rollup_address TEXT;
+        ALTER TABLE rollups ADD COLUMN IF NOT EXISTS chain_id BIGINT;
+        ALTER TABLE rollups ADD COLUMN IF NOT EXISTS fork_id BIGINT;
+        ALTER TABLE rollups ADD COLUMN IF NOT EXISTS rollup_type_id BIGINT;
+        ALTER TABLE rollups ADD COLUMN IF NOT EXISTS verifier_type INTEGER;
+        ALTER TABLE rollups ADD COLUMN IF NOT EXISTS verifier TEXT;
+        ALTER TABLE rollups ADD COLUMN IF NOT EXISTS deploy_block BIGINT;
+        ALTER TABLE rollups ADD COLUMN IF NOT EXISTS backfilled_down_to BIGINT;
+        ALTER TABLE rollups ADD COLUMN IF NOT EXISTS start_timestamp BIGINT;
+        ALTER TABLE rollups ADD COLUMN IF NOT EXISTS start_timestamp_block BIGINT;
+    ALTER TABLE rollups ADD COLUMN IF NOT EXISTS onchain_name TEXT;",
+    )?;
+
+    // Not synced rollups used to be stored with a -1 cursor, now NULL.
+    conn.execute(
+        "UPDATE rollups SET latest_bridge_synced_block = NULL \
+        WHERE latest_bridge_synced_block < 0",
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS wrapped_transfer_events (
+        id TEXT PRIMARY KEY,
+        rollup_id INTEGER,
+        transaction_hash TEXT,
+        block_hash TEXT,
+        block_number INTEGER,
+        transaction_index INTEGER,
+        log_index INTEGER,
+        from_address TEXT,
+        to_address TEXT,
+        token_address TEXT,
+        value {amount_type}
+    );",
+            amount_type = amount_type.sql_type()
+        ),
+        [],
+    )?;
+
+    // Transfers of wrapped tokens between two holders, see --events holder.
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS holder_transfer_events (
+        id TEXT PRIMARY KEY,
+        rollup_id INTEGER,
+        transaction_hash TEXT,
+        block_hash TEXT,
+        block_number INTEGER,
+        transaction_index INTEGER,
+        log_index INTEGER,
+        from_address TEXT,
+        to_address TEXT,
+        token_address TEXT,
+        value {amount_type}
+    );",
+            amount_type = amount_type.sql_type()
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS bridge_transfer_events (
+        id TEXT PRIMARY KEY,
+        rollup_id INTEGER,
+        transaction_hash TEXT,
+        block_hash TEXT,
+        block_number INTEGER,
+        transaction_index INTEGER,
+        log_index INTEGER,
+        from_address TEXT,
+        to_address TEXT,
+        token_address TEXT,
+        value {amount_type}
+    );",
+            amount_type = amount_type.sql_type()
+        ),
+        [],
+    )?;
+
+    // Batches sequenced and verified, from the rollup manager events on
+    // l1 (see --index-verify-batches). rollup_id is the rollup the
+    // batches belong to. Roots and aggregator are NULL for sequences.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS verify_batches_events (
+        id TEXT PRIMARY KEY,
+        rollup_id INTEGER,
+        transaction_hash TEXT,
+        block_hash TEXT,
+        block_number INTEGER,
+        transaction_index INTEGER,
+        log_index INTEGER,
+        event TEXT,
+        num_batch BIGINT,
+        state_root TEXT,
+        exit_root TEXT,
+        aggregator TEXT
+    );",
+        [],
+    )?;
+
+    // Global exit roots committed on l1 by the global exit root manager,
+    // see --reorg-safe-l1.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS global_exit_root_events (
+        id TEXT PRIMARY KEY,
+        rollup_id INTEGER,
+        transaction_hash TEXT,
+        block_hash TEXT,
+        block_number INTEGER,
+        transaction_index INTEGER,
+        log_index INTEGER,
+        mainnet_exit_root TEXT,
+        rollup_exit_root TEXT
+    );",
+        [],
+    )?;
+
+    // Claims inconsistent with the deposit their global index points to or
+    // with the global exit roots, see Database::check_claims.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS claim_anomalies (
+        claim_id TEXT,
+        rollup_id INTEGER,
+        transaction_hash TEXT,
+        block_number INTEGER,
+        log_index INTEGER,
+        globalIndex TEXT,
+        source_rollup_id INTEGER,
+        local_exit_index BIGINT,
+        deposit_id TEXT,
+        reason TEXT,
+        detected_at TIMESTAMP DEFAULT current_timestamp,
+        PRIMARY KEY (claim_id, reason)
+    );",
+        [],
+    )?;
+
+    // Admin, implementation, initialization and emergency state changes of
+    // the bridge, see ProxyEvent. Only the columns of the event are set.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS proxy_events (
+        id TEXT PRIMARY KEY,
+        rollup_id INTEGER,
+        transaction_hash TEXT,
+        block_hash TEXT,
+        block_number INTEGER,
+        transaction_index INTEGER,
+        log_index INTEGER,
+        contract_address TEXT,
+        event TEXT,
+        previous_admin TEXT,
+        new_admin TEXT,
+        implementation TEXT,
+        version INTEGER,
+        emergency_state BOOLEAN
+    );",
+        [],
+    )?;
+
+    // Timestamp of the blocks with indexed events, see
+    // --index-block-timestamps.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS block_timestamps (
+        rollup_id INTEGER,
+        block_number BIGINT,
+        timestamp BIGINT,
+        PRIMARY KEY (rollup_id, block_number)
+    );",
+        [],
+    )?;
+
+    // Direction of the transfer relative to the bridge: in, out, or self
+    // for bridge to bridge transfers, and the bridge address it was relative
+    // to, which changes if the bridge is migrated. NULL for rows indexed
+    // before they were recorded.
+    conn.execute_batch(
+        "ALTER TABLE bridge_transfer_events ADD COLUMN IF NOT EXISTS direction TEXT;
+    ALTER TABLE bridge_transfer_events ADD COLUMN IF NOT EXISTS bridge_address TEXT;",
+    )?;
+
+    // Block ranges fully indexed, one row per indexed chunk, see /coverage.
+    let tracking_ranges: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM duckdb_tables()
+        WHERE database_name = current_database() AND schema_name = 'main'
+            AND table_name = 'indexed_ranges'",
+        [],
+        |row| row.get(0),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS indexed_ranges (
+        rollup_id INTEGER,
+        from_block BIGINT,
+        to_block BIGINT,
+        indexed_at TIMESTAMP DEFAULT current_timestamp
+    );",
+        [],
+    )?;
+    // Event types the range was indexed with, see --events.
+    conn.execute(
+        "ALTER TABLE indexed_ranges ADD COLUMN IF NOT EXISTS events TEXT;",
+        [],
+    )?;
+    // Rollups indexed before the ranges were recorded are covered up to
+    // their cursor, with the event types indexed back then (NULL).
+    if tracking_ranges == 0 {
+        conn.execute(
+            "INSERT INTO indexed_ranges (rollup_id, from_block, to_block)
+            SELECT rollup_id,
+                COALESCE(backfilled_down_to, start_timestamp_block, deploy_block, 0),
+                latest_bridge_synced_block
+            FROM rollups WHERE latest_bridge_synced_block IS NOT NULL",
+            [],
+        )?;
+    }
+
+    // Logs that could not be decoded, see --strict-decode.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS indexing_errors (
+        rollup_id INTEGER,
+        transaction_hash TEXT,
+        log_index INTEGER,
+        block_number INTEGER,
+        kind TEXT,
+        topics TEXT,
+        error TEXT,
+        recorded_at TIMESTAMP DEFAULT current_timestamp,
+        PRIMARY KEY (rollup_id, transaction_hash, log_index)
+    );",
+        [],
+    )?;
+
+    // Balances kept up to date by the transfer inserts, so that they
+    // don't have to be aggregated on each request. kind is "supply"
+    // for the circulating supply of a wrapped token and "bridge" for
+    // the balance of the bridge. Tokens are stored lowercase.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS token_balances (
+        rollup_id INTEGER,
+        token_address TEXT,
+        kind TEXT,
+        balance TEXT,
+        PRIMARY KEY (rollup_id, token_address, kind)
+    );",
+        [],
+    )?;
+
+    // Both transfer tables share a schema, so expose them together with
+    // the table each row comes from. Listed and queried like a table.
+    conn.execute(
+        "CREATE OR REPLACE VIEW all_transfers AS
+        SELECT 'wrapped' AS source, id, rollup_id, transaction_hash, block_hash,
+            block_number, transaction_index, log_index, from_address, to_address,
+            token_address, value
+        FROM wrapped_transfer_events
+        UNION ALL
+        SELECT 'bridge' AS source, id, rollup_id, transaction_hash, block_hash,
+            block_number, transaction_index, log_index, from_address, to_address,
+            token_address, value
+        FROM bridge_transfer_events;",
+        [],
+    )?;
+
+    // Block (and timestamp, with --index-block-timestamps) of the last
+    // transfer of each token, kept up to date by the transfer inserts like
+    // token_balances. Tokens are stored lowercase. Filled from the stored
+    // transfers when the table is new.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS token_last_activity (
+        rollup_id INTEGER,
+        token_address TEXT,
+        last_block BIGINT,
+        last_timestamp BIGINT,
+        PRIMARY KEY (rollup_id, token_address)
+    );",
+        [],
+    )?;
+    conn.execute(
+        "INSERT INTO token_last_activity (rollup_id, token_address, last_block, last_timestamp)
+        SELECT a.rollup_id, a.token_address, a.last_block, b.timestamp
+        FROM (
+            SELECT rollup_id, LOWER(token_address) AS token_address,
+                MAX(block_number) AS last_block
+            FROM all_transfers
+            GROUP BY rollup_id, LOWER(token_address)
+        ) a
+        LEFT JOIN block_timestamps b
+            ON b.rollup_id = a.rollup_id AND b.block_number = a.last_block
+        WHERE NOT EXISTS (SELECT 1 FROM token_last_activity)",
+        [],
+    )?;
+    Ok(())
+}
+
+// Creates bridge_events and claim_events, or their unconfirmed copies with
+// `suffix` "_unconfirmed", and migrates their columns.
+fn create_event_tables(conn: &Connection, amount_type: AmountType, suffix: &str) -> Result<()> {
+    // Maps to BridgeEvent
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS bridge_events{suffix} (
+        id TEXT PRIMARY KEY,
+        rollup_id INTEGER,
+        transaction_hash TEXT,
+        block_hash TEXT,
+        block_number INTEGER,
+        transaction_index INTEGER,
+        log_index INTEGER,
+        leafType INTEGER,
+        originNetwork INTEGER,
+        originAddress TEXT,
+        destinationNetwork INTEGER,
+        destinationAddress TEXT,
+        amount {amount_type},
+        metadata TEXT,
+        depositCount INTEGER
+    );",
+            amount_type = amount_type.sql_type(),
+            suffix = suffix
+        ),
+        [],
+    )?;
+
+    // Maps to ClaimEvent
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS claim_events{suffix} (
+        id TEXT PRIMARY KEY,
+        rollup_id INTEGER,
+        transaction_hash TEXT,
+        block_hash TEXT,
+        block_number INTEGER,
+        transaction_index INTEGER,
+        log_index INTEGER,
+        version INTEGER,
+        globalIndex TEXT,
+        originNetwork INTEGER,
+        originAddress TEXT,
+        destinationAddress TEXT,
+        amount {amount_type}
+    );",
+            amount_type = amount_type.sql_type(),
+            suffix = suffix
+        ),
+        [],
+    )?;
+
+    // Decoded from globalIndex, see utils::decode_global_index. NULL
+    // for v1 claims, whose index is just the deposit count. leafType is
+    // taken from the claimed bridge event, see create_schema.
+    conn.execute_batch(&format!(
+        "ALTER TABLE claim_events{0} ADD COLUMN IF NOT EXISTS mainnet_flag BOOLEAN;
+        ALTER TABLE claim_events{0} ADD COLUMN IF NOT EXISTS source_rollup_id INTEGER;
+        ALTER TABLE claim_events{0} ADD COLUMN IF NOT EXISTS local_exit_index BIGINT;
+        ALTER TABLE claim_events{0} ADD COLUMN IF NOT EXISTS leafType INTEGER;",
+        suffix
+    ))?;
+    Ok(())
+}
+
+// Last block of each rollup up to which the snapshot at `from` is complete:
+// the block before its first partition, of any table, that was written but
+// isn't in the manifest yet, or that is in the manifest but missing. Rollups
+// with only complete partitions are left out.
+fn snapshot_complete_till(conn: &Connection, from: &str) -> Result<HashMap<i64, i64>> {
+    let quote = |path: &str| path.replace('\'', "''");
+    let manifest_path = format!("{}/manifest.json", from);
+    let has_manifest: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM glob('{}')", quote(&manifest_path)),
+        [],
+        |row| row.get(0),
+    )?;
+    let manifest = if has_manifest > 0 {
+        format!(
+            "SELECT table_name, CAST(rollup_id AS BIGINT), CAST(from_block AS BIGINT)
+            FROM read_json_auto('{}')",
+            quote(&manifest_path)
+        )
+    } else {
+        "SELECT NULL::TEXT, NULL::BIGINT, NULL::BIGINT WHERE FALSE".to_string()
+    };
+    let files = format!(
+        "SELECT regexp_extract(file, '([^/]+)/rollup_id=\\d+/blocks_', 1),
+            CAST(regexp_extract(file, 'rollup_id=(\\d+)/', 1) AS BIGINT),
+            CAST(regexp_extract(file, 'blocks_(\\d+)_', 1) AS BIGINT)
+        FROM glob('{}/*/rollup_id=*/blocks_*.parquet')",
+        quote(from)
+    );
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT rollup_id, MIN(from_block) - 1 FROM (
+            SELECT * FROM ({files} EXCEPT {manifest})
+            UNION ALL
+            SELECT * FROM ({manifest} EXCEPT {files})
+        ) AS incomplete (table_name, rollup_id, from_block)
+        GROUP BY rollup_id",
+        files = files,
+        manifest = manifest
+    ))?;
+    let mut complete_till = HashMap::new();
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        complete_till.insert(row.get(0)?, row.get(1)?);
+    }
+    Ok(complete_till)
+}
+
+// Moves the last activity of a token forward to `block_number`, see
+// token_last_activity. Without a timestamp in the log, the one in
+// block_timestamps is used, if any.
+fn record_token_activity(
+    conn: &Connection,
+    rollup_id: u32,
+    token_address: &Address,
+    block_number: u64,
+    timestamp: Option<u64>,
+) -> Result<()> {
+    let token_address = token_address.to_string().to_lowercase();
+    let last_block: Option<i64> = conn
+        .query_row(
+            "SELECT last_block FROM token_last_activity
+            WHERE rollup_id = ? AND token_address = ?",
+            duckdb::params![rollup_id, token_address],
+            |row| row.get(0),
+        )
+        .optional()?;
+    // Desc backfills and rescans insert older blocks.
+    if last_block.is_some_and(|last| last > block_number as i64) {
+        return Ok(());
+    }
+    let timestamp = match timestamp {
+        Some(timestamp) => Some(timestamp as i64),
+        None => conn
+            .query_row(
+                "SELECT timestamp FROM block_timestamps
+                WHERE rollup_id = ? AND block_number = ?",
+                duckdb::params![rollup_id, block_number as i64],
+                |row| row.get(0),
+            )
+            .optional()?,
+    };
+    conn.execute(
+        "INSERT OR REPLACE INTO token_last_activity
+            (rollup_id, token_address, last_block, last_timestamp)
+        VALUES (?, ?, ?, ?)",
+        duckdb::params![rollup_id, token_address, block_number as i64, timestamp],
+    )?;
+    Ok(())
+}
+
+// Id and position of a log, or None if it lacks any of the fields required to
+// store it. Such logs are skipped (and logged) instead of panicking.
+fn required_fields<T>(log: &Log<T>, rollup_id: u32) -> Option<(String, LogPosition)> {
+    match (hash_log(log, rollup_id), log_position(log)) {
+        (Some(id), Some(position)) => Some((id, position)),
+        _ => {
+            warn!(
+                rollup_id,
+                "Skipping log with missing fields: {:?}", log.transaction_hash
+            );
+            None
+        }
+    }
+}
+
+// Sign of a wrapped token transfer in its supply: mints add, burns subtract.
+fn supply_sign(from_address: &str, to_address: &str) -> i8 {
+    if Address::from_str(from_address).is_ok_and(|a| a == Address::ZERO) {
+        1
+    } else if Address::from_str(to_address).is_ok_and(|a| a == Address::ZERO) {
+        -1
+    } else {
+        0
+    }
+}
+
+// Opens a database file read-only and reads every row of every table, so
+// that a truncated or corrupt file (eg a partially copied backup) is reported
+// before anything writes to it. Takes a while on big databases, see
+// --resume-from-backup.
+pub fn check_integrity(path: &Path) -> std::result::Result<(), String> {
+    if !path.exists() {
+        return Err(format!("database {} not found", path.display()));
+    }
+    let corrupt = |e: duckdb::Error| {
+        format!(
+            "database {} appears corrupt or incomplete: {}",
+            path.display(),
+            e
+        )
+    };
+    let config = duckdb::Config::default()
+        .access_mode(duckdb::AccessMode::ReadOnly)
+        .map_err(corrupt)?;
+    let conn = Connection::open_with_flags(path, config).map_err(corrupt)?;
+    conn.execute_batch("PRAGMA database_size;").map_err(corrupt)?;
+
+    let tables = conn
+        .prepare("SELECT table_name FROM duckdb_tables() ORDER BY table_name")
+        .and_then(|mut stmt| {
+            let tables = stmt
+                .query_map([], |row| row.get::<usize, String>(0))?
+                .collect::<Result<Vec<_>>>();
+            tables
+        })
+        .map_err(corrupt)?;
+    for table in tables {
+        let mut stmt = conn
+            .prepare(&format!("SELECT * FROM \"{}\"", table))
+            .map_err(corrupt)?;
+        let mut rows = stmt.query([]).map_err(corrupt)?;
+        while rows.next().map_err(corrupt)?.is_some() {}
+    }
+    Ok(())
+}
+
+// Tables with one row per indexed log.
+pub const EVENT_TABLES: [&str; 9] = [
+    "bridge_events",
+    "claim_events",
+    "new_wrapped_token_events",
+    "wrapped_transfer_events",
+    "holder_transfer_events",
+    "bridge_transfer_events",
+    "verify_batches_events",
+    "proxy_events",
+    "global_exit_root_events",
+];
+
+// Tables with the transfers that move a wrapped token between holders, see
+// Database::token_holders.
+const HOLDER_TRANSFER_TABLES: [&str; 3] = [
+    "wrapped_transfer_events",
+    "holder_transfer_events",
+    "bridge_transfer_events",
+];
+
+// Blocks per parquet partition of a snapshot.
+const SNAPSHOT_PARTITION_BLOCKS: i64 = 1_000_000;
+
+// Tables with an unconfirmed counterpart, see Database::unconfirmed.
+const UNCONFIRMED_TABLES: [&str; 2] = ["bridge_events", "claim_events"];
+
+// Views over other tables, which a snapshot already contains, and the
+// unconfirmed events.
+const SNAPSHOT_SKIPPED_TABLES: [&str; 3] = [
+    "all_transfers",
+    "bridge_events_unconfirmed",
+    "claim_events_unconfirmed",
+];
+
+// Tables derived from the imported ones, rebuilt after an import, and the
+// unconfirmed events.
+const IMPORT_SKIPPED_TABLES: [&str; 4] = [
+    "all_transfers",
+    "token_balances",
+    "bridge_events_unconfirmed",
+    "claim_events_unconfirmed",
+];
+
+// Bridge the rows indexed before the bridge address was recorded were compared
+// against.
+const LEGACY_BRIDGE_ADDRESS: &str = "0x2a3dd3eb832af982ec71669e178424b10dca2ede";
+
+// Sign of a transfer in the bridge balance. Rows indexed before the direction
+// was recorded fall back to comparing the addresses with every bridge address
+// recorded for the rollup, as the bridge may have moved.
+fn bridge_sign(
+    direction: Option<&str>,
+    from_address: &str,
+    to_address: &str,
+    bridges: &[String],
+) -> i8 {
+    let is_bridge = |address: &str| {
+        if bridges.is_empty() {
+            address.eq_ignore_ascii_case(LEGACY_BRIDGE_ADDRESS)
+        } else {
+            bridges.iter().any(|b| b.eq_ignore_ascii_case(address))
+        }
+    };
+    match direction {
+        Some("in") => 1,
+        Some("out") => -1,
+        Some(_) => 0,
+        None if from_address.eq_ignore_ascii_case(to_address) => 0,
+        None if is_bridge(from_address) => -1,
+        None if is_bridge(to_address) => 1,
+        None => 0,
+    }
+}
+
+// Balances are summed as 512 bit integers, wide enough for any sum of uint256
+// transfers, so that they are exact instead of clamped.
+type Balance = Signed<512, 8>;
+
+// Parses an amount or balance read from `column`. A value that isn't a decimal
+// integer fails the query instead of counting as zero.
+fn parse_balance(value: &str, column: usize) -> Result<Balance> {
+    Balance::from_dec_str(value).map_err(|e| {
+        duckdb::Error::FromSqlConversionFailure(column, duckdb::types::Type::Text, Box::new(e))
+    })
+}
+
+fn signed_value(value: Balance, sign: i8) -> Balance {
+    match sign {
+        1 => value,
+        -1 => -value,
+        _ => Balance::ZERO,
+    }
+}
+
+fn add_balances(balance: Balance, value: Balance) -> Result<Balance> {
+    balance.checked_add(value).ok_or_else(|| {
+        duckdb::Error::ToSqlConversionFailure(
+            format!("balance {} overflows adding {}", balance, value).into(),
+        )
+    })
+}
+
+// Adds a signed transfer value to a materialized balance, within the
+// transaction that inserted the transfer.
+fn add_to_balance(
+    conn: &Connection,
+    rollup_id: u32,
+    token_address: &Address,
+    kind: &str,
+    value: U256,
+    sign: i8,
+) -> Result<()> {
+    if sign == 0 {
+        return Ok(());
+    }
+    let token_address = token_address.to_string().to_lowercase();
+    let current: Option<String> = conn
+        .query_row(
+            "SELECT balance FROM token_balances
+            WHERE rollup_id = ? AND token_address = ? AND kind = ?",
+            duckdb::params![rollup_id, token_address, kind],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let current = match current {
+        Some(balance) => parse_balance(&balance, 0)?,
+        None => Balance::ZERO,
+    };
+    let value = signed_value(parse_balance(&value.to_string(), 0)?, sign);
+    let balance = add_balances(current, value)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO token_balances (rollup_id, token_address, kind, balance)
+        VALUES (?, ?, ?, ?)",
+        duckdb::params![rollup_id, token_address, kind, balance.to_string()],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::{Log as Log2, B256};
+
+    const ROLLUP: u32 = 1;
+    const TOKEN: Address = Address::repeat_byte(0x22);
+    const HOLDER: Address = Address::repeat_byte(0x33);
+    const BRIDGE: Address = Address::repeat_byte(0xbb);
+
+    async fn memory_database() -> Database {
+        Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap()
+    }
+
+    // A transfer of TOKEN in its own transaction at `block`.
+    fn transfer(from: Address, to: Address, value: U256, block: u64) -> Log<Transfer> {
+        Log {
+            inner: Log2 {
+                address: TOKEN,
+                data: Transfer { from, to, value },
+            },
+            block_hash: Some(B256::with_last_byte(block as u8)),
+            block_number: Some(block),
+            block_timestamp: None,
+            transaction_hash: Some(B256::with_last_byte(block as u8)),
+            transaction_index: Some(0),
+            log_index: Some(0),
+            removed: false,
+        }
+    }
+
+    fn bridge_event(deposit_count: u32, block: u64) -> Log<BridgeEvent> {
+        Log {
+            inner: Log2 {
+                address: Address::repeat_byte(0xbb),
+                data: BridgeEvent {
+                    leafType: 0,
+                    originNetwork: 0,
+                    originAddress: TOKEN,
+                    destinationNetwork: 2,
+                    destinationAddress: HOLDER,
+                    amount: U256::from(1),
+                    metadata: Bytes::new(),
+                    depositCount: deposit_count,
+                },
+            },
+            block_hash: Some(B256::with_last_byte(block as u8)),
+            block_number: Some(block),
+            block_timestamp: None,
+            transaction_hash: Some(B256::with_last_byte(block as u8)),
+            transaction_index: Some(0),
+            log_index: Some(0),
+            removed: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn ranges_are_seeded_for_rollups_indexed_before_them() {
+        let database = memory_database().await;
+        database.insert_rollup(ROLLUP, "test", None).await.unwrap();
+        database
+            .synced_till_block(ROLLUP, 100, false)
+            .await
+            .unwrap();
+        {
+            let conn = database.db().lock().await;
+            conn.execute_batch("DROP TABLE indexed_ranges; UPDATE rollups SET deploy_block = 10;")
+                .unwrap();
+            create_schema(&conn, AmountType::Varchar).unwrap();
+        }
+        let conn = database.db().lock().await;
+        let ranges: Vec<(i64, i64)> = conn
+            .prepare("SELECT from_block, to_block FROM indexed_ranges")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(ranges, vec![(10, 100)]);
+        drop(conn);
+
+        // Only once, when the table is created.
+        create_schema(&*database.db().lock().await, AmountType::Varchar).unwrap();
+        assert_eq!(count(&database, "indexed_ranges").await, 1);
+    }
+
+    #[tokio::test]
+    async fn block_zero_is_an_indexed_block() {
+        let database = memory_database().await;
+        assert_eq!(database.last_indexed_block(ROLLUP).await.unwrap(), None);
+        database.insert_rollup(ROLLUP, "test", None).await.unwrap();
+        assert_eq!(database.last_indexed_block(ROLLUP).await.unwrap(), None);
+
+        database.synced_till_block(ROLLUP, 0, false).await.unwrap();
+        assert_eq!(database.last_indexed_block(ROLLUP).await.unwrap(), Some(0));
+
+        database.reset_cursor(ROLLUP).await.unwrap();
+        assert_eq!(database.last_indexed_block(ROLLUP).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn cursor_only_moves_back_when_rewinding() {
+        let database = memory_database().await;
+        database.insert_rollup(ROLLUP, "test", None).await.unwrap();
+        database
+            .synced_till_block(ROLLUP, 100, false)
+            .await
+            .unwrap();
+
+        assert!(database.synced_till_block(ROLLUP, 50, false).await.is_err());
+        assert_eq!(
+            database.last_indexed_block(ROLLUP).await.unwrap(),
+            Some(100)
+        );
+
+        database.synced_till_block(ROLLUP, 50, true).await.unwrap();
+        assert_eq!(database.last_indexed_block(ROLLUP).await.unwrap(), Some(50));
+    }
+
+    #[tokio::test]
+    async fn last_activity_only_moves_forward() {
+        let database = memory_database().await;
+        let db = database.db();
+        let last_activity = || async move {
+            let conn = db.lock().await;
+            conn.query_row(
+                "SELECT last_block, last_timestamp FROM token_last_activity
+                WHERE rollup_id = ? AND token_address = ?",
+                duckdb::params![ROLLUP, TOKEN.to_string().to_lowercase()],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<i64>>(1)?)),
+            )
+            .unwrap()
+        };
+
+        // The timestamp of the block is taken from block_timestamps.
+        database
+            .insert_block_timestamp(ROLLUP, 5, 500)
+            .await
+            .unwrap();
+        database
+            .insert_wrapped_transfer_event(
+                &transfer(Address::ZERO, HOLDER, U256::from(1), 5),
+                ROLLUP,
+            )
+            .await
+            .unwrap();
+        assert_eq!(last_activity().await, (5, Some(500)));
+
+        // A backfilled transfer is older.
+        database
+            .insert_wrapped_transfer_event(
+                &transfer(Address::ZERO, HOLDER, U256::from(1), 3),
+                ROLLUP,
+            )
+            .await
+            .unwrap();
+        assert_eq!(last_activity().await, (5, Some(500)));
+
+        let mut stamped = transfer(HOLDER, Address::ZERO, U256::from(1), 8);
+        stamped.block_timestamp = Some(800);
+        database
+            .insert_wrapped_transfer_event(&stamped, ROLLUP)
+            .await
+            .unwrap();
+        assert_eq!(last_activity().await, (8, Some(800)));
+
+        database
+            .insert_bridge_transfer_event(
+                &transfer(HOLDER, BRIDGE, U256::from(1), 9),
+                ROLLUP,
+                "in",
+                BRIDGE,
+            )
+            .await
+            .unwrap();
+        assert_eq!(last_activity().await, (9, None));
+    }
+
+    #[test]
+    fn integrity_check_fails_on_truncated_files() {
+        let path =
+            std::env::temp_dir().join(format!("daggboard-integrity-{}.duckdb", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE numbers AS SELECT range AS n FROM range(100000); CHECKPOINT;",
+            )
+            .unwrap();
+        }
+        assert_eq!(check_integrity(&path), Ok(()));
+
+        // As left by an interrupted copy.
+        let size = std::fs::metadata(&path).unwrap().len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(size / 2)
+            .unwrap();
+        assert!(check_integrity(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(check_integrity(&path).is_err());
+    }
+
+    async fn count(database: &Database, table: &str) -> i64 {
+        let conn = database.db().lock().await;
+        conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    async fn balance(database: &Database, kind: &str) -> Option<String> {
+        let conn = database.db().lock().await;
+        conn.query_row(
+            "SELECT balance FROM token_balances
+            WHERE rollup_id = ? AND token_address = ? AND kind = ?",
+            duckdb::params![ROLLUP, TOKEN.to_string().to_lowercase(), kind],
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn balances_past_int256_are_exact() {
+        let database = memory_database().await;
+        // Two mints of 2^255 go past the int256 range, the burn of 1 then
+        // leaves the supply at 2^256 - 1.
+        let half = U256::from(1) << 255;
+        for log in [
+            transfer(Address::ZERO, HOLDER, half, 1),
+            transfer(Address::ZERO, HOLDER, half, 2),
+            transfer(HOLDER, Address::ZERO, U256::from(1), 3),
+        ] {
+            database
+                .insert_wrapped_transfer_event(&log, ROLLUP)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            balance(&database, "supply").await,
+            Some(U256::MAX.to_string())
+        );
+
+        assert_eq!(database.recompute_token_balances().await.unwrap(), 0);
+        assert_eq!(
+            balance(&database, "supply").await,
+            Some(U256::MAX.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn bridge_balance_follows_transfers_in_and_out() {
+        let database = memory_database().await;
+        for (log, direction) in [
+            (transfer(HOLDER, BRIDGE, U256::from(10), 1), "in"),
+            (transfer(BRIDGE, HOLDER, U256::from(3), 2), "out"),
+            (transfer(BRIDGE, BRIDGE, U256::from(4), 3), "self"),
+        ] {
+            database
+                .insert_bridge_transfer_event(&log, ROLLUP, direction, BRIDGE)
+                .await
+                .unwrap();
+        }
+        // A duplicate doesn't count twice.
+        database
+            .insert_bridge_transfer_event(
+                &transfer(HOLDER, BRIDGE, U256::from(10), 1),
+                ROLLUP,
+                "in",
+                BRIDGE,
+            )
+            .await
+            .unwrap();
+        assert_eq!(balance(&database, "bridge").await, Some("7".to_string()));
+        assert_eq!(balance(&database, "supply").await, None);
+
+        assert_eq!(database.recompute_token_balances().await.unwrap(), 0);
+        assert_eq!(balance(&database, "bridge").await, Some("7".to_string()));
+    }
+
+    #[tokio::test]
+    async fn all_transfers_spans_both_transfer_tables() {
+        let database = memory_database().await;
+        database
+            .insert_wrapped_transfer_event(
+                &transfer(Address::ZERO, HOLDER, U256::from(5), 1),
+                ROLLUP,
+            )
+            .await
+            .unwrap();
+        database
+            .insert_bridge_transfer_event(
+                &transfer(HOLDER, BRIDGE, U256::from(2), 2),
+                ROLLUP,
+                "in",
+                BRIDGE,
+            )
+            .await
+            .unwrap();
+
+        let conn = database.db().lock().await;
+        let mut stmt = conn
+            .prepare("SELECT source, block_number, value FROM all_transfers ORDER BY block_number")
+            .unwrap();
+        let rows: Vec<(String, i64, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("wrapped".to_string(), 1, "5".to_string()),
+                ("bridge".to_string(), 2, "2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn legacy_rows_are_signed_against_every_bridge_address() {
+        let old = "0x00000000000000000000000000000000000000aa";
+        let new = "0x00000000000000000000000000000000000000BB";
+        let holder = "0x0000000000000000000000000000000000000033";
+        let bridges = [old.to_string(), new.to_lowercase()];
+
+        // The recorded direction wins.
+        assert_eq!(bridge_sign(Some("in"), holder, holder, &bridges), 1);
+        assert_eq!(bridge_sign(Some("out"), holder, holder, &bridges), -1);
+        assert_eq!(bridge_sign(Some("self"), old, new, &bridges), 0);
+
+        // Rows without one, from before and after the bridge moved.
+        assert_eq!(bridge_sign(None, holder, old, &bridges), 1);
+        assert_eq!(bridge_sign(None, new, holder, &bridges), -1);
+        assert_eq!(bridge_sign(None, new, new, &bridges), 0);
+        assert_eq!(bridge_sign(None, holder, holder, &bridges), 0);
+
+        // Rollups without a recorded bridge compare with the legacy one.
+        assert_eq!(bridge_sign(None, holder, old, &[]), 0);
+        assert_eq!(bridge_sign(None, holder, LEGACY_BRIDGE_ADDRESS, &[]), 1);
+    }
+
+    #[tokio::test]
+    async fn malformed_balance_fails_instead_of_zeroing() {
+        let database = memory_database().await;
+        database
+            .insert_wrapped_transfer_event(
+                &transfer(Address::ZERO, HOLDER, U256::from(5), 1),
+                ROLLUP,
+            )
+            .await
+            .unwrap();
+        database
+            .db()
+            .lock()
+            .await
+            .execute("UPDATE token_balances SET balance = 'not a number'", [])
+            .unwrap();
+
+        assert!(database
+            .insert_wrapped_transfer_event(
+                &transfer(Address::ZERO, HOLDER, U256::from(5), 2),
+                ROLLUP,
+            )
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn token_holders_follow_transfers_between_holders() {
+        let database = memory_database().await;
+        let other = Address::repeat_byte(0x44);
+        let half = U256::from(1) << 255;
+        for log in [
+            transfer(Address::ZERO, HOLDER, half, 1),
+            transfer(Address::ZERO, HOLDER, half, 2),
+            transfer(HOLDER, Address::ZERO, U256::from(1), 4),
+        ] {
+            database
+                .insert_wrapped_transfer_event(&log, ROLLUP)
+                .await
+                .unwrap();
+        }
+        let moved = transfer(HOLDER, other, U256::from(10), 3);
+        database
+            .insert_holder_transfer_event(&moved, ROLLUP)
+            .await
+            .unwrap();
+        // Also stored as a bridge transfer, it only counts once.
+        database
+            .insert_bridge_transfer_event(&moved, ROLLUP, "in", other)
+            .await
+            .unwrap();
+
+        let holders = database.token_holders(ROLLUP, TOKEN).await.unwrap();
+        assert_eq!(
+            holders,
+            vec![
+                (
+                    HOLDER.to_string().to_lowercase(),
+                    (U256::MAX - U256::from(10)).to_string()
+                ),
+                (other.to_string().to_lowercase(), "10".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn wrapped_tokens_are_fetched_with_their_metadata() {
+        use alloy::sol_types::{sol_data, SolType};
+
+        let database = memory_database().await;
+        let metadata = <(sol_data::String, sol_data::String, sol_data::Uint<8>)>::abi_encode_params(
+            &("Wrapped Ether".to_string(), "WETH".to_string(), 18u8),
+        );
+        let token = Log {
+            inner: Log2 {
+                address: BRIDGE,
+                data: NewWrappedToken {
+                    originNetwork: 0,
+                    originTokenAddress: HOLDER,
+                    wrappedTokenAddress: TOKEN,
+                    metadata: metadata.into(),
+                },
+            },
+            block_hash: Some(B256::with_last_byte(1)),
+            block_number: Some(1),
+            block_timestamp: None,
+            transaction_hash: Some(B256::with_last_byte(1)),
+            transaction_index: Some(0),
+            log_index: Some(0),
+            removed: false,
+        };
+        database
+            .insert_new_wrapped_token_event(&token, ROLLUP)
+            .await
+            .unwrap();
+
+        let tokens = database.fetch_wrapped_tokens_full(ROLLUP).await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].address, TOKEN);
+        assert_eq!(tokens[0].origin_network, 0);
+        assert_eq!(tokens[0].origin_token_address, HOLDER);
+        assert_eq!(tokens[0].name.as_deref(), Some("Wrapped Ether"));
+        assert_eq!(tokens[0].symbol.as_deref(), Some("WETH"));
+        assert_eq!(tokens[0].decimals, Some(18));
+        assert!(database
+            .fetch_wrapped_tokens_full(2)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn logs_without_a_transaction_hash_are_skipped() {
+        let database = memory_database().await;
+        let mut deposit = bridge_event(0, 1);
+        deposit.transaction_hash = None;
+        assert!(!database
+            .insert_bridge_event(&deposit, ROLLUP)
+            .await
+            .unwrap());
+        let mut mint = transfer(Address::ZERO, HOLDER, U256::from(5), 2);
+        mint.transaction_hash = None;
+        assert!(!database
+            .insert_wrapped_transfer_event(&mint, ROLLUP)
+            .await
+            .unwrap());
+        assert_eq!(count(&database, "bridge_events").await, 0);
+        assert_eq!(count(&database, "wrapped_transfer_events").await, 0);
+        assert_eq!(balance(&database, "supply").await, None);
+    }
+
+    #[tokio::test]
+    async fn claims_take_the_leaf_type_of_their_deposit() {
+        let database = memory_database().await;
+        // A claim on rollup 2 of deposit 7 of ROLLUP, indexed before it.
+        let claim = Log {
+            inner: Log2 {
+                address: Address::repeat_byte(0xbb),
+                data: ClaimEvent {
+                    globalIndex: U256::from(7),
+                    originNetwork: 0,
+                    originAddress: TOKEN,
+                    destinationAddress: HOLDER,
+                    amount: U256::from(1),
+                },
+            },
+            block_hash: Some(B256::with_last_byte(1)),
+            block_number: Some(1),
+            block_timestamp: None,
+            transaction_hash: Some(B256::with_last_byte(1)),
+            transaction_index: Some(0),
+            log_index: Some(0),
+            removed: false,
+        };
+        database.insert_claim_event(&claim, 2, 2).await.unwrap();
+        database.fill_claim_leaf_types(2, 1, 1).await.unwrap();
+
+        let mut deposit = bridge_event(7, 5);
+        deposit.inner.data.leafType = 1;
+        database
+            .insert_bridge_event(&deposit, ROLLUP)
+            .await
+            .unwrap();
+        database.fill_claim_leaf_types(ROLLUP, 5, 5).await.unwrap();
+
+        let leaf_type: Option<u8> = database
+            .db()
+            .lock()
+            .await
+            .query_row("SELECT leafType FROM claim_events", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(leaf_type, Some(1));
+    }
+
+    #[tokio::test]
+    async fn inconsistent_claims_are_flagged() {
+        let database = memory_database().await;
+        // Deposits 7 and 8 of l1 to rollup 2, the exit root is only updated
+        // after the first one.
+        database
+            .insert_bridge_event(&bridge_event(7, 5), 0)
+            .await
+            .unwrap();
+        database
+            .insert_bridge_event(&bridge_event(8, 9), 0)
+            .await
+            .unwrap();
+        let update = Log {
+            inner: Log2 {
+                address: Address::repeat_byte(0xee),
+                data: UpdateL1InfoTree {
+                    mainnetExitRoot: B256::repeat_byte(1),
+                    rollupExitRoot: B256::repeat_byte(2),
+                },
+            },
+            block_hash: Some(B256::with_last_byte(5)),
+            block_number: Some(5),
+            block_timestamp: None,
+            transaction_hash: Some(B256::with_last_byte(5)),
+            transaction_index: Some(0),
+            log_index: Some(1),
+            removed: false,
+        };
+        database
+            .insert_global_exit_root_event(&update, 0)
+            .await
+            .unwrap();
+
+        // Claims on rollup 2 of the mainnet deposit `deposit_count`.
+        let claim = |deposit_count: u64, amount: u64, block: u64| Log {
+            inner: Log2 {
+                address: Address::repeat_byte(0xbb),
+                data: ClaimEvent {
+                    globalIndex: (U256::from(1) << 64) | U256::from(deposit_count),
+                    originNetwork: 0,
+                    originAddress: TOKEN,
+                    destinationAddress: HOLDER,
+                    amount: U256::from(amount),
+                },
+            },
+            block_hash: Some(B256::with_last_byte(block as u8)),
+            block_number: Some(block),
+            block_timestamp: None,
+            transaction_hash: Some(B256::with_last_byte(block as u8)),
+            transaction_index: Some(0),
+            log_index: Some(0),
+            removed: false,
+        };
+        // Consistent, then a fabricated claim of deposit 7 for more than was
+        // deposited, then a claim of deposit 8 before its exit root is on l1.
+        for log in [claim(7, 1, 1), claim(7, 1_000, 2), claim(8, 1, 3)] {
+            database.insert_claim_event(&log, 2, 2).await.unwrap();
+        }
+        assert_eq!(database.check_claims(2, 1, 3).await.unwrap(), 2);
+
+        let conn = database.db().lock().await;
+        let mut stmt = conn
+            .prepare(
+                "SELECT block_number, local_exit_index, reason FROM claim_anomalies
+                ORDER BY block_number",
+            )
+            .unwrap();
+        let anomalies: Vec<(u64, u32, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            anomalies,
+            vec![
+                (2, 7, "deposit_mismatch".to_string()),
+                (3, 8, "exit_root_not_updated".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn batches_are_stored_on_commit_only() {
+        let database = memory_database().await;
+        let batch = database.begin_batch().await.unwrap();
+        batch
+            .insert_bridge_event(&bridge_event(0, 1), ROLLUP)
+            .await
+            .unwrap();
+        batch
+            .insert_wrapped_transfer_event(
+                &transfer(Address::ZERO, HOLDER, U256::from(5), 2),
+                ROLLUP,
+            )
+            .await
+            .unwrap();
+        assert_eq!(count(&database, "bridge_events").await, 0);
+        batch.rollback().await.unwrap();
+        assert_eq!(count(&database, "wrapped_transfer_events").await, 0);
+
+        let generation = database.generation(ROLLUP);
+        let batch = database.begin_batch().await.unwrap();
+        batch
+            .insert_bridge_event(&bridge_event(0, 1), ROLLUP)
+            .await
+            .unwrap();
+        batch.commit(ROLLUP).await.unwrap();
+        assert_eq!(count(&database, "bridge_events").await, 1);
+        assert!(database.generation(ROLLUP) > generation + 1);
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_tables_ignore_duplicates() {
+        let database = memory_database().await;
+        // As created by older versions, without a primary key.
+        database
+            .db()
+            .lock()
+            .await
+            .execute_batch(
+                "DROP TABLE bridge_events_unconfirmed;
+                CREATE TABLE bridge_events_unconfirmed AS SELECT * FROM bridge_events LIMIT 0;",
+            )
+            .unwrap();
+        create_schema(&*database.db().lock().await, AmountType::Varchar).unwrap();
+
+        let unconfirmed = database.unconfirmed();
+        let log = bridge_event(0, 1);
+        unconfirmed.insert_bridge_event(&log, ROLLUP).await.unwrap();
+        unconfirmed.insert_bridge_event(&log, ROLLUP).await.unwrap();
+        assert_eq!(count(&database, "bridge_events_unconfirmed").await, 1);
+
+        // Kept across restarts.
+        create_schema(&*database.db().lock().await, AmountType::Varchar).unwrap();
+        assert_eq!(count(&database, "bridge_events_unconfirmed").await, 1);
+    }
+
+    #[tokio::test]
+    async fn amounts_out_of_range_are_recorded() {
+        let database = Database::open(DbLocation::Memory, AmountType::Hugeint)
+            .await
+            .unwrap();
+        let mut log = bridge_event(0, 1);
+        log.inner.data.amount = U256::from(1) << 200;
+        assert!(database.insert_bridge_event(&log, ROLLUP).await.unwrap());
+        let fitting = transfer(Address::ZERO, HOLDER, U256::from(1), 2);
+        database
+            .insert_wrapped_transfer_event(&fitting, ROLLUP)
+            .await
+            .unwrap();
+
+        let conn = database.db().lock().await;
+        let amount: Option<i128> = conn
+            .query_row("SELECT amount FROM bridge_events", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(amount, None);
+        let errors: Vec<(String, i64)> = conn
+            .prepare("SELECT kind, block_number FROM indexing_errors")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(errors, vec![("BridgeEvent".to_string(), 1)]);
+    }
+
+    #[tokio::test]
+    async fn extension_names_must_be_plain_identifiers() {
+        let database = memory_database().await;
+        database.load_extensions(&[]).await.unwrap();
+
+        let injected = vec!["parquet; DROP TABLE rollups".to_string()];
+        let error = database.load_extensions(&injected).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid duckdb extension name: parquet; DROP TABLE rollups"
+        );
+        assert_eq!(count(&database, "rollups").await, 0);
+    }
+
+    #[tokio::test]
+    async fn global_index_of_v2_claims_is_decoded() {
+        let database = memory_database().await;
+        let claim = |global_index: U256, block: u64| Log {
+            inner: Log2 {
+                address: BRIDGE,
+                data: ClaimEvent {
+                    globalIndex: global_index,
+                    originNetwork: 0,
+                    originAddress: TOKEN,
+                    destinationAddress: HOLDER,
+                    amount: U256::from(1),
+                },
+            },
+            block_hash: Some(B256::with_last_byte(block as u8)),
+            block_number: Some(block),
+            block_timestamp: None,
+            transaction_hash: Some(B256::with_last_byte(block as u8)),
+            transaction_index: Some(0),
+            log_index: Some(0),
+            removed: false,
+        };
+        // A mainnet deposit, a deposit of rollup index 4 (rollup 5), and a v1
+        // claim whose global index is just the deposit count.
+        let mainnet = (U256::from(1) << 64) | U256::from(3);
+        let rollup = (U256::from(4) << 32) | U256::from(9);
+        for (log, version) in [
+            (claim(mainnet, 1), 2),
+            (claim(rollup, 2), 2),
+            (claim(rollup, 3), 1),
+        ] {
+            database
+                .insert_claim_event(&log, ROLLUP, version)
+                .await
+                .unwrap();
+        }
+
+        let conn = database.db().lock().await;
+        let mut stmt = conn
+            .prepare(
+                "SELECT mainnet_flag, source_rollup_id, local_exit_index FROM claim_events
+                ORDER BY block_number",
+            )
+            .unwrap();
+        let decoded: Vec<(Option<bool>, Option<u32>, Option<u32>)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            decoded,
+            vec![
+                (Some(true), Some(0), Some(3)),
+                (Some(false), Some(5), Some(9)),
+                (None, None, None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rollups_in_their_own_files_are_queried_together() {
+        let dir = std::env::temp_dir().join(format!("daggboard-per-rollup-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let combined = Database::new_combined(&dir, AmountType::Varchar)
+            .await
+            .unwrap();
+        assert!(combined.per_rollup());
+
+        for (rollup_id, deposit_count) in [(1, 0), (2, 1)] {
+            let database = combined.for_rollup(rollup_id).await.unwrap();
+            database
+                .insert_bridge_event(&bridge_event(deposit_count, 1), rollup_id)
+                .await
+                .unwrap();
+            assert_eq!(count(&database, "bridge_events").await, 1);
+        }
+        for rollup_id in [1, 2] {
+            let file = dir.join(format!("data-rollup-{}.duckdb", rollup_id));
+            assert!(file.exists());
+        }
+
+        combined.combine_rollups(&[1, 2]).await.unwrap();
+        assert_eq!(count(&combined, "bridge_events").await, 2);
+        drop(combined);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn health_check_fails_on_a_stuck_database() {
+        let database = memory_database().await;
+        database.health_check().await.unwrap();
+        assert_eq!(database.last_write_ok(), None);
+
+        database
+            .insert_bridge_event(&bridge_event(0, 1), ROLLUP)
+            .await
+            .unwrap();
+        assert!(database.last_write_ok().is_some());
+
+        // As if stuck behind a long write.
+        let _writing = database.db().lock().await;
+        let error = database.health_check().await.unwrap_err();
+        assert_eq!(error.to_string(), "timed out waiting for the database");
+    }
+
+    #[tokio::test]
+    async fn snapshot_is_resumed_from_its_manifest() {
+        let database = memory_database().await;
+        database.insert_rollup(ROLLUP, "test", None).await.unwrap();
+        database
+            .insert_bridge_event(&bridge_event(1, 5), ROLLUP)
+            .await
+            .unwrap();
+        database
+            .insert_bridge_event(&bridge_event(2, 1_500_000), ROLLUP)
+            .await
+            .unwrap();
+        // The second partition isn't synced to its end yet.
+        database
+            .synced_till_block(ROLLUP, 1_200_000, false)
+            .await
+            .unwrap();
+
+        let target =
+            std::env::temp_dir().join(format!("daggboard-snapshot-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&target);
+        let written = database.snapshot(target.to_str().unwrap()).await.unwrap();
+
+        let partitions = target.join("bridge_events").join("rollup_id=1");
+        assert!(partitions.join("blocks_0_999999.parquet").exists());
+        assert!(partitions.join("blocks_1000000_1999999.parquet").exists());
+        let manifest = std::fs::read_to_string(target.join("manifest.json")).unwrap();
+        assert!(manifest.contains("blocks_0_999999.parquet"));
+        assert!(!manifest.contains("blocks_1000000_1999999.parquet"));
+
+        // Only the complete partitions are skipped when running it again.
+        let complete = manifest.lines().count();
+        let rewritten = database.snapshot(target.to_str().unwrap()).await.unwrap();
+        assert_eq!(rewritten, written - complete);
+
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reinserted_events_are_counted_as_duplicates() {
+        let database = memory_database().await;
+        assert!(database
+            .insert_bridge_event(&bridge_event(1, 5), ROLLUP)
+            .await
+            .unwrap());
+        assert!(database.ignored_duplicates().is_empty());
+
+        assert!(!database
+            .insert_bridge_event(&bridge_event(1, 5), ROLLUP)
+            .await
+            .unwrap());
+        assert_eq!(database.ignored_duplicates(), HashMap::from([(ROLLUP, 1)]));
+        assert_eq!(count(&database, "bridge_events").await, 1);
+    }
+
+    #[tokio::test]
+    async fn snapshot_is_imported_into_a_fresh_database() {
+        let database = memory_database().await;
+        database.insert_rollup(ROLLUP, "test", None).await.unwrap();
+        for (deposit_count, block) in [(1, 5), (2, 1_500_000)] {
+            database
+                .insert_bridge_event(&bridge_event(deposit_count, block), ROLLUP)
+                .await
+                .unwrap();
+        }
+        database
+            .synced_till_block(ROLLUP, 2_000_000, false)
+            .await
+            .unwrap();
+
+        let target = std::env::temp_dir().join(format!("daggboard-import-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&target);
+        database.snapshot(target.to_str().unwrap()).await.unwrap();
+
+        let imported = memory_database().await;
+        imported
+            .import(target.to_str().unwrap(), None)
+            .await
+            .unwrap();
+        assert_eq!(count(&imported, "bridge_events").await, 2);
+        assert_eq!(count(&imported, "rollups").await, 1);
+        assert_eq!(
+            imported.last_indexed_block(ROLLUP).await.unwrap(),
+            Some(2_000_000)
+        );
+
+        // Importing it again inserts nothing.
+        assert_eq!(
+            imported
+                .import(target.to_str().unwrap(), None)
+                .await
+                .unwrap(),
+            0
+        );
+
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_databases_are_kept_across_opens() {
+        let path =
+            std::env::temp_dir().join(format!("daggboard-open-{}.duckdb", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let open = || Database::open(DbLocation::File(path.clone()), AmountType::Varchar);
+
+        let database = open().await.unwrap();
+        database.insert_rollup(ROLLUP, "test", None).await.unwrap();
+        drop(database);
+        assert_eq!(count(&open().await.unwrap(), "rollups").await, 1);
+
+        assert_eq!(count(&memory_database().await, "rollups").await, 0);
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_file(path.with_extension("duckdb.wal"));
     }
 }
@@ -1,47 +1,808 @@
+use crate::contracts::PolygonRollupManager::{OnSequenceBatches, VerifyBatchesTrustedAggregator};
 use crate::contracts::PolygonZkEVMBridge::ClaimEvent as ClaimEventV1;
 use crate::contracts::PolygonZkEVMBridgeV2::{
     BridgeEvent, ClaimEvent, EmergencyStateActivated, EmergencyStateDeactivated, Initialized,
     NewWrappedToken,
 };
+use crate::contracts::PolygonZkEVMGlobalExitRootV2::{UpdateGlobalExitRoot, UpdateL1InfoTree};
 use crate::contracts::ERC20::Transfer;
-use crate::database::Database;
-use crate::utils::to_topic;
-use alloy::primitives::address;
-use alloy::primitives::{Address, Log as Log2};
+use crate::database::{Database, ProxyEvent, WrappedToken};
+use crate::sink::EventSink;
+use crate::transport::RetryAfterHttp;
+use crate::utils::{
+    decode_global_index, event_topic0, hash_log, log_position, redact_url, to_topic, to_topic_set,
+};
+use alloy::eips::{BlockId, BlockNumberOrTag};
+use alloy::primitives::{Address, Log as Log2, U256};
 use alloy::providers::fillers::{
     BlobGasFiller, ChainIdFiller, FillProvider, GasFiller, JoinFill, NonceFiller,
 };
 use alloy::providers::{Provider, RootProvider};
 use alloy::rpc::types::Filter;
 use alloy::rpc::types::Log;
-use alloy::transports::http::reqwest::Url;
+use alloy::sol_types::SolEvent;
+use alloy::transports::http::reqwest::header::HeaderMap;
+use alloy::transports::http::reqwest::{Client, Url};
+use alloy::transports::utils::guess_local_url;
 use alloy::{
     providers::ProviderBuilder, rpc::client::RpcClient, transports::layers::RetryBackoffLayer,
 };
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use lru::LruCache;
+use serde_json::json;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use tokio::sync::{mpsc, oneshot, watch, Semaphore};
+use tokio::task::JoinHandle;
 use tokio::time::sleep;
+use tracing::{debug, error, info, instrument, warn};
 
 use crate::contracts::TransparentUpgradeableProxy::{AdminChanged, Upgraded};
 
+// Options shared by all indexers, set from the cli in main.
+#[derive(Debug, Clone)]
+pub struct IndexerConfig {
+    // Max amount of addresses in a single eth_getLogs filter. Some rpcs reject
+    // filters with too many addresses, so bigger sets are split in chunks.
+    pub max_filter_addresses: usize,
+    // Blocks after which the sync progress is persisted within a window.
+    pub progress_chunk_size: u64,
+    // Consecutive failed steps after which the indexer pauses.
+    pub max_consecutive_failures: u32,
+    // Seconds between rpc probes while paused.
+    pub health_probe_interval_secs: u64,
+    // Idle connections kept per rpc host. Reqwest's default if None.
+    pub rpc_pool_max_idle: Option<usize>,
+    // Timeout of each rpc request. None waits forever.
+    pub rpc_timeout_secs: Option<u64>,
+    // eth_getLogs calls of the indexer in flight at once. The filters of a
+    // window are fetched concurrently up to it.
+    pub per_rollup_concurrency: usize,
+    // Headers sent with every request to the rpc of the indexer, eg for
+    // authentication. Their values should be marked sensitive so that they
+    // are not logged.
+    pub rpc_headers: HeaderMap,
+    // Order in which the blocks below the tip are indexed.
+    pub backfill_direction: BackfillDirection,
+    // Index token transfers (wrapped mint/burn and bridge in/out). Without
+    // them only bridge events are indexed and balances are not available.
+    pub index_transfers: bool,
+    // Don't store transfers with a zero value, mostly spam.
+    pub skip_zero_value_transfers: bool,
+    // Store the timestamp of every block with indexed events.
+    pub index_block_timestamps: bool,
+    // Recently seen block timestamps kept in memory, so that logs of the same
+    // block share a single fetch.
+    pub block_timestamp_cache_size: usize,
+    // Blocks behind the latest one that are left unindexed, so that reorgs
+    // near the tip don't leave stale events behind.
+    pub confirmations: u64,
+    // Bridge in transfers that fail to decode fail the window, instead of
+    // only being logged and recorded in indexing_errors.
+    pub strict_decode: bool,
+    // Event types that are indexed, see IndexedEvents.
+    pub events: IndexedEvents,
+    // Decoded events queued for the writer task of each indexer, so that rpc
+    // fetching and database writes don't stall each other. 0 writes them
+    // inline.
+    pub write_queue_capacity: usize,
+    // At the tip, store the bridge and claim events of the blocks above the
+    // confirmed head in the *_unconfirmed tables.
+    pub index_unconfirmed: bool,
+    // Most blocks a single rescan request may index again.
+    pub max_rescan_blocks: u64,
+    // Check the claims of every chunk against the indexed deposits and l1
+    // global exit roots, see Database::check_claims.
+    pub reorg_safe_l1: bool,
+}
+
+// Asc indexes from the start block up to the tip. Desc starts at the tip and
+// backfills towards the start block, so that recent activity shows up first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackfillDirection {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl std::str::FromStr for BackfillDirection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(BackfillDirection::Asc),
+            "desc" => Ok(BackfillDirection::Desc),
+            _ => Err(format!("invalid backfill direction: {} (asc or desc)", s)),
+        }
+    }
+}
+
+// Event types to index: bridge events, claims, wrapped tokens (their creation
+// and mint/burn transfers), transfers in and out of the bridge and transfers of
+// wrapped tokens between holders. Logs of the disabled ones are neither fetched
+// nor stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexedEvents {
+    pub bridge: bool,
+    pub claim: bool,
+    pub wrapped: bool,
+    pub transfer: bool,
+    // Off by default, every transfer of every wrapped token is fetched.
+    pub holder: bool,
+}
+
+impl Default for IndexedEvents {
+    fn default() -> Self {
+        IndexedEvents {
+            bridge: true,
+            claim: true,
+            wrapped: true,
+            transfer: true,
+            holder: false,
+        }
+    }
+}
+
+impl std::str::FromStr for IndexedEvents {
+    type Err = String;
+
+    // Parses a comma separated list, eg "bridge,claim".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut events = IndexedEvents {
+            bridge: false,
+            claim: false,
+            wrapped: false,
+            transfer: false,
+            holder: false,
+        };
+        for event in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match event {
+                "bridge" => events.bridge = true,
+                "claim" => events.claim = true,
+                "wrapped" => events.wrapped = true,
+                "transfer" => events.transfer = true,
+                "holder" => events.holder = true,
+                _ => {
+                    return Err(format!(
+                        "invalid event type: {} (bridge, claim, wrapped, transfer or holder)",
+                        event
+                    ))
+                }
+            }
+        }
+        if events.to_string().is_empty() {
+            return Err(
+                "no event type given (bridge, claim, wrapped, transfer or holder)".to_string(),
+            );
+        }
+        Ok(events)
+    }
+}
+
+impl IndexedEvents {
+    // Whether every event type enabled in `other` is enabled here.
+    pub fn covers(&self, other: &IndexedEvents) -> bool {
+        (self.bridge || !other.bridge)
+            && (self.claim || !other.claim)
+            && (self.wrapped || !other.wrapped)
+            && (self.transfer || !other.transfer)
+            && (self.holder || !other.holder)
+    }
+}
+
+impl std::fmt::Display for IndexedEvents {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = [
+            (self.bridge, "bridge"),
+            (self.claim, "claim"),
+            (self.wrapped, "wrapped"),
+            (self.transfer, "transfer"),
+            (self.holder, "holder"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, name)| enabled.then_some(name))
+        .collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        IndexerConfig {
+            max_filter_addresses: 100,
+            progress_chunk_size: 1_000,
+            max_consecutive_failures: 5,
+            health_probe_interval_secs: 30,
+            rpc_pool_max_idle: None,
+            rpc_timeout_secs: None,
+            per_rollup_concurrency: 2,
+            rpc_headers: HeaderMap::new(),
+            backfill_direction: BackfillDirection::Asc,
+            index_transfers: true,
+            skip_zero_value_transfers: false,
+            index_block_timestamps: false,
+            block_timestamp_cache_size: 1_024,
+            confirmations: 0,
+            strict_decode: false,
+            events: IndexedEvents::default(),
+            write_queue_capacity: 1_024,
+            index_unconfirmed: false,
+            max_rescan_blocks: 100_000,
+            reorg_safe_l1: false,
+        }
+    }
+}
+
+// Health of the indexer as seen by its failure tracking. While paused the
+// indexer stops indexing and only probes the rpc.
+#[derive(Debug, Clone, Default)]
+pub struct IndexerHealth {
+    pub consecutive_failures: u32,
+    pub paused: bool,
+    pub last_error: Option<String>,
+}
+
+// Upper bounds, in seconds, of the buckets of the duration histograms.
+pub const DURATION_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+// Durations in seconds, with cumulative buckets like prometheus histograms.
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    // Observations up to each of DURATION_BUCKETS.
+    pub buckets: [u64; DURATION_BUCKETS.len()],
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+// How long the indexer takes, exported in /metrics. `window` covers every
+// window indexed in full (fetching and storing), `get_logs` every rpc call.
+#[derive(Debug, Clone, Default)]
+pub struct IndexerTimings {
+    pub window: Histogram,
+    pub get_logs: Histogram,
+}
+
+// Owns a spawned indexer task. Dropping it signals the index loop to stop and
+// aborts the task, so forgetting to call shutdown can't leak a running indexer.
+pub struct IndexerGuard {
+    rollup_id: u32,
+    shutdown: Arc<watch::Sender<bool>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl IndexerGuard {
+    pub fn rollup_id(&self) -> u32 {
+        self.rollup_id
+    }
+
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+}
+
+impl Drop for IndexerGuard {
+    fn drop(&mut self) {
+        self.shutdown();
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+    }
+}
+
+// TODO: This is nonsense.
+pub type IndexerProvider = FillProvider<
+    JoinFill<
+        alloy::providers::Identity,
+        JoinFill<GasFiller, JoinFill<BlobGasFiller, JoinFill<NonceFiller, ChainIdFiller>>>,
+    >,
+    RootProvider,
+>;
+
+// Provider used by the indexers, retrying on rate limits. The http client
+// honours the pool, timeout and header settings of the config, and the Retry-After
+// header of rate limited responses (see RetryAfterHttp).
+pub fn build_provider(
+    rpc_url: Url,
+    config: &IndexerConfig,
+) -> Result<IndexerProvider, Box<dyn std::error::Error>> {
+    // TODO: Choose the right values
+    let max_retry = 10;
+    let backoff = 1000;
+    let cups = 100;
+
+    let mut client = Client::builder();
+    if let Some(max_idle) = config.rpc_pool_max_idle {
+        client = client.pool_max_idle_per_host(max_idle);
+    }
+    if let Some(timeout) = config.rpc_timeout_secs {
+        client = client.timeout(Duration::from_secs(timeout));
+    }
+    if !config.rpc_headers.is_empty() {
+        client = client.default_headers(config.rpc_headers.clone());
+    }
+    let is_local = guess_local_url(&rpc_url);
+    let http = RetryAfterHttp::new(client.build()?, rpc_url);
+
+    // TODO: Set retry logic for other cases. This retry is only for rate limit errors.
+    Ok(ProviderBuilder::new().connect_client(
+        RpcClient::builder()
+            .layer(RetryBackoffLayer::new(max_retry, backoff, cups))
+            .transport(http, is_local),
+    ))
+}
+
+// An event decoded by an indexer, to be stored by its writer task.
+pub enum DecodedEvent {
+    Bridge(Log<BridgeEvent>),
+    // With the version of the bridge that emitted it.
+    Claim(Log<ClaimEvent>, u32),
+    NewWrappedToken(Log<NewWrappedToken>),
+    VerifyBatches(Log<VerifyBatchesTrustedAggregator>),
+    SequenceBatches(Log<OnSequenceBatches>),
+    WrappedTransfer(Log<Transfer>),
+    HolderTransfer(Log<Transfer>),
+    // With its direction and the bridge it was relative to.
+    BridgeTransfer(Log<Transfer>, &'static str, Address),
+    Proxy(Log<ProxyEvent>),
+    // UpdateGlobalExitRoot logs are stored as UpdateL1InfoTree, which has
+    // the same fields.
+    GlobalExitRoot(Log<UpdateL1InfoTree>),
+    // A log that could not be decoded, with its kind and the error. Recorded
+    // with the events of its chunk so that a rolled back chunk drops it too.
+    IndexingError(Log, &'static str, String),
+    // Block number and timestamp, see --index-block-timestamps.
+    BlockTimestamp(u64, u64),
+}
+
+impl DecodedEvent {
+    async fn write(
+        &self,
+        database: &Database,
+        rollup_id: u32,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        match self {
+            DecodedEvent::Bridge(log) => database.insert_bridge_event(log, rollup_id).await,
+            DecodedEvent::Claim(log, version) => {
+                database.insert_claim_event(log, rollup_id, *version).await
+            }
+            DecodedEvent::NewWrappedToken(log) => {
+                database
+                    .insert_new_wrapped_token_event(log, rollup_id)
+                    .await
+            }
+            DecodedEvent::VerifyBatches(log) => {
+                database.insert_verify_batches_event(log, rollup_id).await
+            }
+            DecodedEvent::SequenceBatches(log) => {
+                database.insert_sequence_batches_event(log, rollup_id).await
+            }
+            DecodedEvent::WrappedTransfer(log) => {
+                database.insert_wrapped_transfer_event(log, rollup_id).await
+            }
+            DecodedEvent::HolderTransfer(log) => {
+                database.insert_holder_transfer_event(log, rollup_id).await
+            }
+            DecodedEvent::BridgeTransfer(log, direction, bridge_address) => {
+                database
+                    .insert_bridge_transfer_event(log, rollup_id, direction, *bridge_address)
+                    .await
+            }
+            DecodedEvent::Proxy(log) => database.insert_proxy_event(log, rollup_id).await,
+            DecodedEvent::GlobalExitRoot(log) => {
+                database.insert_global_exit_root_event(log, rollup_id).await
+            }
+            DecodedEvent::IndexingError(log, kind, error) => {
+                database
+                    .insert_indexing_error(rollup_id, log, kind, error)
+                    .await?;
+                Ok(true)
+            }
+            DecodedEvent::BlockTimestamp(block_number, timestamp) => {
+                database
+                    .insert_block_timestamp(rollup_id, *block_number, *timestamp)
+                    .await?;
+                Ok(true)
+            }
+        }
+    }
+
+    // The event as published to a sink: the table it is stored in and its
+    // row, with the same id and column names (batch events carry the rollup
+    // of the batches, like in the table). None for logs missing the fields to
+    // place them, which are not stored either, and for the records that are not
+    // events.
+    pub fn to_json(&self, rollup_id: u32) -> Option<serde_json::Value> {
+        let (table, id, position, fields) = match self {
+            DecodedEvent::Bridge(log) => (
+                "bridge_events",
+                hash_log(log, rollup_id)?,
+                log_position(log)?,
+                json!({
+                    "leafType": log.inner.leafType,
+                    "originNetwork": log.inner.originNetwork,
+                    "originAddress": log.inner.originAddress.to_string(),
+                    "destinationNetwork": log.inner.destinationNetwork,
+                    "destinationAddress": log.inner.destinationAddress.to_string(),
+                    "amount": log.inner.amount.to_string(),
+                    "metadata": log.inner.metadata.to_string(),
+                    "depositCount": log.inner.depositCount,
+                }),
+            ),
+            DecodedEvent::Claim(log, version) => {
+                let decoded = (*version >= 2).then(|| decode_global_index(log.inner.globalIndex));
+                (
+                    "claim_events",
+                    hash_log(log, rollup_id)?,
+                    log_position(log)?,
+                    json!({
+                        "version": version,
+                        "globalIndex": log.inner.globalIndex.to_string(),
+                        "originNetwork": log.inner.originNetwork,
+                        "originAddress": log.inner.originAddress.to_string(),
+                        "destinationAddress": log.inner.destinationAddress.to_string(),
+                        "amount": log.inner.amount.to_string(),
+                        "mainnet_flag": decoded.map(|(mainnet_flag, _, _)| mainnet_flag),
+                        "source_rollup_id": decoded.map(|(_, source_rollup_id, _)| source_rollup_id),
+                        "local_exit_index": decoded.map(|(_, _, local_exit_index)| local_exit_index),
+                    }),
+                )
+            }
+            DecodedEvent::NewWrappedToken(log) => (
+                "new_wrapped_token_events",
+                hash_log(log, rollup_id)?,
+                log_position(log)?,
+                json!({
+                    "originNetwork": log.inner.originNetwork,
+                    "originTokenAddress": log.inner.originTokenAddress.to_string(),
+                    "wrappedTokenAddress": log.inner.wrappedTokenAddress.to_string(),
+                    "metadata": log.inner.metadata.to_string(),
+                }),
+            ),
+            DecodedEvent::VerifyBatches(log) => (
+                "verify_batches_events",
+                hash_log(log, rollup_id)?,
+                log_position(log)?,
+                json!({
+                    "event": "VerifyBatchesTrustedAggregator",
+                    "rollup_id": log.inner.rollupID,
+                    "num_batch": log.inner.numBatch,
+                    "state_root": log.inner.stateRoot.to_string(),
+                    "exit_root": log.inner.exitRoot.to_string(),
+                    "aggregator": log.inner.aggregator.to_string(),
+                }),
+            ),
+            DecodedEvent::SequenceBatches(log) => (
+                "verify_batches_events",
+                hash_log(log, rollup_id)?,
+                log_position(log)?,
+                json!({
+                    "event": "OnSequenceBatches",
+                    "rollup_id": log.inner.rollupID,
+                    "num_batch": log.inner.lastBatchSequenced,
+                }),
+            ),
+            DecodedEvent::WrappedTransfer(log) => (
+                "wrapped_transfer_events",
+                hash_log(log, rollup_id)?,
+                log_position(log)?,
+                json!({
+                    "from_address": log.inner.from.to_string(),
+                    "to_address": log.inner.to.to_string(),
+                    "token_address": log.address().to_string(),
+                    "value": log.inner.value.to_string(),
+                }),
+            ),
+            DecodedEvent::HolderTransfer(log) => (
+                "holder_transfer_events",
+                hash_log(log, rollup_id)?,
+                log_position(log)?,
+                json!({
+                    "from_address": log.inner.from.to_string(),
+                    "to_address": log.inner.to.to_string(),
+                    "token_address": log.address().to_string(),
+                    "value": log.inner.value.to_string(),
+                }),
+            ),
+            DecodedEvent::BridgeTransfer(log, direction, bridge_address) => (
+                "bridge_transfer_events",
+                hash_log(log, rollup_id)?,
+                log_position(log)?,
+                json!({
+                    "from_address": log.inner.from.to_string(),
+                    "to_address": log.inner.to.to_string(),
+                    "token_address": log.address().to_string(),
+                    "value": log.inner.value.to_string(),
+                    "direction": direction,
+                    "bridge_address": bridge_address.to_string(),
+                }),
+            ),
+            DecodedEvent::Proxy(log) => {
+                let (previous_admin, new_admin, implementation, version) = match &log.inner.data {
+                    ProxyEvent::AdminChanged {
+                        previous_admin,
+                        new_admin,
+                    } => (Some(*previous_admin), Some(*new_admin), None, None),
+                    ProxyEvent::Upgraded { implementation } => {
+                        (None, None, Some(*implementation), None)
+                    }
+                    ProxyEvent::Initialized { version } => (None, None, None, Some(*version)),
+                    _ => (None, None, None, None),
+                };
+                (
+                    "proxy_events",
+                    hash_log(log, rollup_id)?,
+                    log_position(log)?,
+                    json!({
+                        "contract_address": log.address().to_string(),
+                        "event": log.inner.data.name(),
+                        "previous_admin": previous_admin.map(|a| a.to_string()),
+                        "new_admin": new_admin.map(|a| a.to_string()),
+                        "implementation": implementation.map(|a| a.to_string()),
+                        "version": version,
+                        "emergency_state": log.inner.data.emergency_state(),
+                    }),
+                )
+            }
+            DecodedEvent::GlobalExitRoot(log) => (
+                "global_exit_root_events",
+                hash_log(log, rollup_id)?,
+                log_position(log)?,
+                json!({
+                    "mainnet_exit_root": log.inner.mainnetExitRoot.to_string(),
+                    "rollup_exit_root": log.inner.rollupExitRoot.to_string(),
+                }),
+            ),
+            DecodedEvent::IndexingError(..) | DecodedEvent::BlockTimestamp(..) => return None,
+        };
+        let mut row = json!({
+            "id": id,
+            "rollup_id": rollup_id,
+            "transaction_hash": position.transaction_hash,
+            "block_hash": position.block_hash,
+            "block_number": position.block_number,
+            "transaction_index": position.transaction_index,
+            "log_index": position.log_index,
+        });
+        if let (Some(row), Some(fields)) = (row.as_object_mut(), fields.as_object()) {
+            row.extend(fields.clone());
+        }
+        Some(json!({ "table": table, "row": row }))
+    }
+}
+
+enum WriterMessage {
+    // With the rollup it is stored under, see Indexer::event_rollup_id.
+    Event(Box<DecodedEvent>, u32),
+    // Answered once the events before it are stored along with the progress
+    // of their chunk.
+    Commit(ChunkProgress, oneshot::Sender<Result<(), String>>),
+    // Drops the events sent since the last commit.
+    Rollback,
+}
+
+// Indexed chunk of a window, recorded in the transaction of its events so
+// that the sync marker never covers a partially stored chunk.
+#[derive(Debug, Clone, Copy)]
+struct ChunkProgress {
+    from_block: u64,
+    to_block: u64,
+    events: IndexedEvents,
+    // Whether the sync marker moves to to_block, see index_window.
+    record_progress: bool,
+    // Whether the claims of the chunk are checked, see --reorg-safe-l1.
+    check_claims: bool,
+}
+
+// Spawns the task that stores the events of an indexer, taking up to
+// `capacity` queued messages at a time. The events of a chunk are written in
+// one transaction (see Database::begin_batch), committed with the progress of
+// the chunk. Sending blocks while the queue is full, which slows down the
+// indexer when the database can't keep up. After a failed write the
+// transaction is rolled back and the events are dropped until the commit
+// reports the error, so that the indexer retries the window.
+fn spawn_writer(
+    database: Database,
+    rollup_id: u32,
+    shared_rollups: Arc<Mutex<Vec<u32>>>,
+    capacity: usize,
+) -> mpsc::Sender<WriterMessage> {
+    let (sender, mut receiver) = mpsc::channel(capacity);
+    tokio::spawn(async move {
+        let mut messages = Vec::with_capacity(capacity);
+        let mut batch = None;
+        let mut error: Option<String> = None;
+        while receiver.recv_many(&mut messages, capacity).await > 0 {
+            for message in messages.drain(..) {
+                match message {
+                    WriterMessage::Event(..) if error.is_some() => {}
+                    WriterMessage::Event(event, event_rollup_id) => {
+                        if let Err(e) =
+                            write_batched(&database, &mut batch, &event, event_rollup_id).await
+                        {
+                            error = Some(e);
+                        }
+                    }
+                    WriterMessage::Commit(progress, reply) => {
+                        let committed = match error.take() {
+                            Some(e) => Err(e),
+                            None => {
+                                let shared = shared_rollups.lock().unwrap().clone();
+                                commit_batch(&database, batch.take(), rollup_id, &shared, progress)
+                                    .await
+                            }
+                        };
+                        let _ = reply.send(committed);
+                    }
+                    WriterMessage::Rollback => {
+                        error = None;
+                        if let Some(open) = batch.take() {
+                            let _ = open.rollback().await;
+                        }
+                    }
+                }
+            }
+        }
+    });
+    sender
+}
+
+// Writes an event in the open batch, beginning one if there is none. The
+// batch is rolled back if the write fails.
+async fn write_batched(
+    database: &Database,
+    batch: &mut Option<Database>,
+    event: &DecodedEvent,
+    rollup_id: u32,
+) -> Result<(), String> {
+    let open = match batch.take() {
+        Some(open) => open,
+        None => database.begin_batch().await.map_err(|e| e.to_string())?,
+    };
+    let written = event
+        .write(&open, rollup_id)
+        .await
+        .map_err(|e| e.to_string());
+    match written {
+        Ok(_) => {
+            *batch = Some(open);
+            Ok(())
+        }
+        Err(e) => {
+            let _ = open.rollback().await;
+            Err(e)
+        }
+    }
+}
+
+// Records the progress of a chunk in its batch, beginning one if none of its
+// events was written, and commits it.
+async fn commit_batch(
+    database: &Database,
+    batch: Option<Database>,
+    rollup_id: u32,
+    shared_rollups: &[u32],
+    progress: ChunkProgress,
+) -> Result<(), String> {
+    let batch = match batch {
+        Some(batch) => batch,
+        None => database.begin_batch().await.map_err(|e| e.to_string())?,
+    };
+    if let Err(e) = record_progress(&batch, rollup_id, shared_rollups, progress).await {
+        let _ = batch.rollback().await;
+        return Err(e);
+    }
+    batch.commit(rollup_id).await.map_err(|e| e.to_string())
+}
+
+async fn record_progress(
+    database: &Database,
+    rollup_id: u32,
+    shared_rollups: &[u32],
+    progress: ChunkProgress,
+) -> Result<(), String> {
+    let ChunkProgress {
+        from_block,
+        to_block,
+        events,
+        record_progress,
+        check_claims,
+    } = progress;
+    if events.bridge || events.claim {
+        for rollup_id in std::iter::once(&rollup_id).chain(shared_rollups) {
+            database
+                .fill_claim_leaf_types(*rollup_id, from_block, to_block)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    if check_claims {
+        for rollup_id in std::iter::once(&rollup_id).chain(shared_rollups) {
+            database
+                .check_claims(*rollup_id, from_block, to_block)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    database
+        .insert_indexed_range(rollup_id, from_block, to_block, &events.to_string())
+        .await
+        .map_err(|e| e.to_string())?;
+    if record_progress {
+        database
+            .synced_till_block(rollup_id, to_block, false)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
 // TODO: The clone is most likely not needed.
 #[derive(Clone)]
 pub struct Indexer {
-    // TODO: This is nonsense.
-    pub provider: FillProvider<
-        JoinFill<
-            alloy::providers::Identity,
-            JoinFill<GasFiller, JoinFill<BlobGasFiller, JoinFill<NonceFiller, ChainIdFiller>>>,
-        >,
-        RootProvider,
-    >,
+    pub provider: IndexerProvider,
+    pub rpc_url: Url,
     pub bridge_address: Address,
     pub rollup_id: u32,
     pub database: Database,
-    pub wrapped_tokens: Vec<Address>,
-    pub running: Arc<AtomicBool>,
+    pub wrapped_tokens: Vec<WrappedToken>,
+    // Set to true to stop the index loop, see wait_for_shutdown.
+    pub shutdown: Arc<watch::Sender<bool>>,
+    pub config: IndexerConfig,
+    // First block of the rollup that is worth indexing.
+    pub start_block: u64,
+    // First block to index again, set by request_rescan and consumed by the
+    // index loop.
+    pub rescan_from: Arc<Mutex<Option<u64>>>,
+    pub health: Arc<Mutex<IndexerHealth>>,
+    pub timings: Arc<Mutex<IndexerTimings>>,
+    // Concurrent eth_getLogs calls of the indexer and its clones, see
+    // IndexerConfig::per_rollup_concurrency.
+    pub rpc_permits: Arc<Semaphore>,
+    // Block number to timestamp.
+    pub timestamp_cache: Arc<Mutex<LruCache<u64, u64>>>,
+    // Rollup manager to index batch sequence/verification events from. Only
+    // set for the l1 indexer, see --index-verify-batches.
+    pub rollup_manager_address: Option<Address>,
+    // Global exit root manager to index the global exit root updates from.
+    // Only set for the l1 indexer, see --reorg-safe-l1.
+    pub ger_manager_address: Option<Address>,
+    // Block the indexer never goes past, so that the database is a
+    // reproducible snapshot up to it. See --pin-head.
+    pub pinned_head: Option<u64>,
+    // Latest bridge address and rpc url resolved for the rollup, see
+    // update_endpoint. Shared by all the clones.
+    pub endpoint: Arc<Mutex<(Address, Url)>>,
+    // Queue of the writer task, see --write-queue-capacity. None if events
+    // are written inline.
+    writer: Option<mpsc::Sender<WriterMessage>>,
+    // Open batch of the chunk being indexed when events are written inline,
+    // see commit_chunk.
+    inline_batch: Arc<tokio::sync::Mutex<Option<Database>>>,
+    // Held while the batch of a chunk is open, and by every other write of
+    // the indexer and its clones (eg a token backfill started from the api),
+    // so that none runs alongside the transaction of the chunk.
+    write_lock: Arc<tokio::sync::Mutex<()>>,
+    // Where the events are published in addition to being stored, see
+    // --sink.
+    pub sink: Option<Arc<dyn EventSink>>,
+    // Other rollups on the chain and bridge of this one. The bridge logs are
+    // scanned once, by this indexer, and its bridge and claim events are
+    // stored under the rollup of their destination and origin network when it
+    // is one of these. Shared by all the clones.
+    pub shared_rollups: Arc<Mutex<Vec<u32>>>,
 }
 
 impl Indexer {
@@ -50,37 +811,97 @@ impl Indexer {
         rpc_url: Url,
         rollup_id: u32,
         database: Database,
+        config: IndexerConfig,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        // TODO: Choose the right values
-        let max_retry = 10;
-        let backoff = 1000;
-        let cups = 100;
-
-        // TODO: Set retry logic for other cases. This retry is only for rate limit errors.
-        let provider = ProviderBuilder::new().on_client(
-            RpcClient::builder()
-                .layer(RetryBackoffLayer::new(max_retry, backoff, cups))
-                .http(rpc_url),
-        );
+        let provider = build_provider(rpc_url.clone(), &config)?;
 
-        let wrapped_tokens = database.fetch_wrapped_tokens(rollup_id).await?;
+        let wrapped_tokens = database.fetch_wrapped_tokens_full(rollup_id).await?;
+        let cache_size = NonZeroUsize::new(config.block_timestamp_cache_size)
+            .unwrap_or(NonZeroUsize::MIN);
 
-        println!(
-            "Wrapped tokens: {:?} in rollup {:?}",
-            wrapped_tokens.len(),
-            rollup_id
-        );
+        info!(rollup_id, "Wrapped tokens: {:?}", wrapped_tokens.len());
+
+        let shared_rollups: Arc<Mutex<Vec<u32>>> = Default::default();
+        let writer = (config.write_queue_capacity > 0).then(|| {
+            spawn_writer(
+                database.clone(),
+                rollup_id,
+                shared_rollups.clone(),
+                config.write_queue_capacity,
+            )
+        });
 
         Ok(Indexer {
             provider: provider,
+            rpc_url: rpc_url.clone(),
             bridge_address,
             rollup_id,
             database,
-            running: Arc::new(AtomicBool::new(true)),
-            wrapped_tokens: vec![],
+            shutdown: Arc::new(watch::channel(false).0),
+            wrapped_tokens,
+            start_block: 0,
+            rescan_from: Arc::new(Mutex::new(None)),
+            health: Arc::new(Mutex::new(IndexerHealth::default())),
+            timings: Arc::new(Mutex::new(IndexerTimings::default())),
+            rpc_permits: Arc::new(Semaphore::new(config.per_rollup_concurrency.max(1))),
+            timestamp_cache: Arc::new(Mutex::new(LruCache::new(cache_size))),
+            endpoint: Arc::new(Mutex::new((bridge_address, rpc_url))),
+            rollup_manager_address: None,
+            ger_manager_address: None,
+            pinned_head: None,
+            writer,
+            inline_batch: Default::default(),
+            write_lock: Default::default(),
+            sink: None,
+            shared_rollups,
+            config,
         })
     }
 
+    // Records a newly resolved bridge address and rpc url, eg after the rollup
+    // was upgraded. The index loop switches to them before its next step.
+    // Returns whether they changed.
+    pub fn update_endpoint(&self, bridge_address: Address, rpc_url: Url) -> bool {
+        let mut endpoint = self.endpoint.lock().unwrap();
+        if endpoint.0 == bridge_address && endpoint.1 == rpc_url {
+            return false;
+        }
+        info!(
+            rollup_id = self.rollup_id,
+            "Endpoint changed: bridge {:?} -> {:?}, rpc {:?} -> {:?}",
+            endpoint.0,
+            bridge_address,
+            redact_url(&endpoint.1),
+            redact_url(&rpc_url)
+        );
+        *endpoint = (bridge_address, rpc_url);
+        true
+    }
+
+    // Rebuilds the provider if update_endpoint recorded a new endpoint.
+    fn apply_endpoint_update(&mut self) {
+        let (bridge_address, rpc_url) = self.endpoint.lock().unwrap().clone();
+        if bridge_address == self.bridge_address && rpc_url == self.rpc_url {
+            return;
+        }
+        self.provider = match build_provider(rpc_url.clone(), &self.config) {
+            Ok(provider) => provider,
+            Err(e) => {
+                error!(
+                    rollup_id = self.rollup_id,
+                    "Could not rebuild the provider: {}", e
+                );
+                return;
+            }
+        };
+        self.bridge_address = bridge_address;
+        self.rpc_url = rpc_url;
+    }
+
+    pub fn wrapped_token_addresses(&self) -> Vec<Address> {
+        self.wrapped_tokens.iter().map(|t| t.address).collect()
+    }
+
     pub fn get_block_increment(&self) -> u64 {
         // Every rpc has its own limits. Defaulting to 10k is generally safe but
         // some impose lower limits.
@@ -91,220 +912,2850 @@ impl Indexer {
         }
     }
 
+    // Runs the filter once per chunk of addresses and merges the results, so
+    // that no request exceeds the max addresses per filter.
+    async fn get_logs_chunked(
+        &self,
+        filter: &Filter,
+        addresses: &[Address],
+    ) -> Result<Vec<Log>, Box<dyn std::error::Error>> {
+        let mut logs = Vec::new();
+        for chunk in addresses.chunks(self.config.max_filter_addresses.max(1)) {
+            let chunk_filter = filter.clone().address(chunk.to_vec());
+            logs.extend(self.get_logs(&chunk_filter).await?);
+        }
+        logs.sort_by_key(log_order);
+        Ok(logs)
+    }
+
+    // eth_getLogs, timed into the get_logs histogram. Failed calls count too,
+    // a timeout is as slow as it gets. Some load balanced rpcs return the logs
+    // out of order, they are always returned in (block, log index) order.
+    // Waits for a slot of the rpc budget of the rollup first, see
+    // --per-rollup-concurrency.
+    async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>, Box<dyn std::error::Error>> {
+        let _permit = self.rpc_permits.acquire().await?;
+        let started = Instant::now();
+        let logs = self.provider.get_logs(filter).await;
+        self.timings
+            .lock()
+            .unwrap()
+            .get_logs
+            .observe(started.elapsed().as_secs_f64());
+        let mut logs = logs?;
+        if logs
+            .windows(2)
+            .any(|pair| log_order(&pair[0]) > log_order(&pair[1]))
+        {
+            warn!(
+                rollup_id = self.rollup_id,
+                "Rpc returned {:?} logs out of order, sorting them",
+                logs.len()
+            );
+            logs.sort_by_key(log_order);
+        }
+        Ok(logs)
+    }
+
+    // Finds the block the bridge was deployed at, binary searching the first
+    // block where the contract has code. Needs an rpc serving historical state.
+    // None if the bridge has no code at all.
+    pub async fn detect_deploy_block(&self) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        let head = self.provider.get_block_number().await?;
+        if !self.has_code_at(head).await? {
+            return Ok(None);
+        }
+
+        let mut low = 0;
+        let mut high = head;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.has_code_at(mid).await? {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        Ok(Some(low))
+    }
+
+    // First block at or after `timestamp`, searched in [start_block, head].
+    // Timestamps before the start block resolve to it and timestamps after the
+    // head to the head.
+    pub async fn block_at_timestamp(
+        &self,
+        timestamp: u64,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let mut low = self.start_block;
+        let mut high = self.provider.get_block_number().await?;
+        if low >= high || self.block_timestamp(low).await? >= timestamp {
+            return Ok(low.min(high));
+        }
+
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.block_timestamp(mid).await? >= timestamp {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        Ok(low)
+    }
+
+    async fn has_code_at(&self, block: u64) -> Result<bool, Box<dyn std::error::Error>> {
+        let code = self
+            .provider
+            .get_code_at(self.bridge_address)
+            .block_id(BlockId::number(block))
+            .await?;
+        Ok(!code.is_empty())
+    }
+
+    // Latest block the indexer goes up to, `confirmations` behind the head
+    // and never past the pinned head.
+    pub async fn confirmed_head(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        let head = self.provider.get_block_number().await?;
+        let confirmed = head.saturating_sub(self.config.confirmations);
+        Ok(self.pinned_head.map_or(confirmed, |pin| confirmed.min(pin)))
+    }
+
+    // Distance to the confirmed head, so that the confirmation buffer isn't
+    // reported as lag.
     pub async fn distance_head(&self) -> Result<u64, Box<dyn std::error::Error>> {
         let last_indexed_block = self.database.last_indexed_block(self.rollup_id).await?;
-        let latest_block = self.provider.get_block_number().await?;
+        let latest_block = self.confirmed_head().await?;
 
-        let distance = latest_block - last_indexed_block;
+        let distance = latest_block.saturating_sub(last_indexed_block.unwrap_or(self.start_block));
         Ok(distance)
     }
 
+    // Asks the running index loop to rewind the sync marker by `blocks` so that
+    // they are indexed again, eg to recover a log missed at the tip. Inserts are
+    // idempotent so logs already stored are ignored. The rescan never starts
+    // below the start block. Returns the first block indexed again.
+    pub async fn request_rescan(&self, blocks: u64) -> Result<u64, Box<dyn std::error::Error>> {
+        let Some(last_indexed_block) = self.database.last_indexed_block(self.rollup_id).await?
+        else {
+            return Err("nothing indexed yet".into());
+        };
+        let from = (last_indexed_block + 1)
+            .saturating_sub(blocks)
+            .max(self.start_block);
+        *self.rescan_from.lock().unwrap() = Some(from);
+        Ok(from)
+    }
+
+    fn take_rescan(&self) -> Option<u64> {
+        self.rescan_from.lock().unwrap().take()
+    }
+
+    // Moves the sync marker back so that the next window starts at `from`.
+    // From the start block on the marker is cleared rather than set to the
+    // block before, so that a start block of 0 is indexed again too. Returns
+    // the new marker.
+    async fn rewind_cursor(&self, from: u64) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        if from <= self.start_block {
+            self.database.reset_cursor(self.rollup_id).await?;
+            return Ok(None);
+        }
+        self.database
+            .synced_till_block(self.rollup_id, from - 1, true)
+            .await?;
+        Ok(Some(from - 1))
+    }
+
     pub async fn index(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.check_cursor().await?;
+        self.check_events().await?;
+        // None until the first window is indexed, so that a rollup starting
+        // at block 0 indexes it.
         let mut last_processed_block = self.database.last_indexed_block(self.rollup_id).await?;
-        let mut latest_block = self.provider.get_block_number().await?;
+        let mut latest_block = self.confirmed_head().await?;
         let block_increment = self.get_block_increment();
 
+        // Nothing before the start block is worth indexing.
+        if last_processed_block.is_none_or(|block| block + 1 < self.start_block) {
+            last_processed_block = self.start_block.checked_sub(1);
+        }
+
+        let mut backfilled_down_to = match self.config.backfill_direction {
+            BackfillDirection::Asc => None,
+            BackfillDirection::Desc => {
+                self.init_backfill(&mut last_processed_block, latest_block)
+                    .await?
+            }
+        };
+
         // TODO: Review the logic is correct
 
         // This can help if the rpc allows multiple requests in paralel. Don't set it too high
-        println!("debug: {:?}", last_processed_block);
-        println!(
-            "[Rollup: {:?}] Indexing from block: {:?}",
-            self.rollup_id,
-            last_processed_block + 1
+        debug!(rollup_id = self.rollup_id, "{:?}", last_processed_block);
+        info!(
+            rollup_id = self.rollup_id,
+            "Indexing from block: {:?}",
+            last_processed_block.map_or(self.start_block, |block| block + 1)
         );
 
+        // A shutdown interrupts the step at whatever it's awaiting. The
+        // progress of the window is only persisted per completed chunk, so
+        // the abandoned one is indexed again on the next start.
+        let shutdown = self.shutdown.clone();
         loop {
-            // Check for shutdown signal. This ensures we dont shutdown in the middle of an index.
-            // TODO: I suspect this is not working as expected.
-            if !self.running.load(Ordering::Relaxed) {
-                println!(
-                    "[Rollup: {:?}] Shutdown signal received. Exiting...",
-                    self.rollup_id
-                );
-                break;
-            }
+            self.apply_endpoint_update();
 
-            if last_processed_block >= latest_block {
-                println!(
-                    "[Rollup: {:?}] Reached the latest block {:?} . Sleeping for 60 seconds...",
-                    self.rollup_id, latest_block
+            let stopped = tokio::select! {
+                _ = self.tracked_step(
+                    &mut last_processed_block,
+                    &mut latest_block,
+                    &mut backfilled_down_to,
+                    block_increment,
+                ) => false,
+                _ = wait_for_shutdown(shutdown.subscribe()) => true,
+            };
+            if stopped {
+                info!(
+                    rollup_id = self.rollup_id,
+                    "Shutdown signal received. Exiting..."
                 );
-                sleep(Duration::from_secs(5)).await;
-                latest_block = self.provider.get_block_number().await?;
-                continue;
-            }
-
-            let start_block = last_processed_block + 1;
-            let end_block = std::cmp::min(start_block + block_increment, latest_block);
-            let filter = Filter::new()
-                .from_block(start_block)
-                .to_block(end_block)
-                .address(self.bridge_address);
-
-            let rollup_id = self.rollup_id;
-
-            let logs = self.provider.get_logs(&filter).await?;
-            for log in logs {
-                // Handle log decoding and database insertion
-                if let Ok(dec) = log.log_decode::<BridgeEvent>() {
-                    self.database.insert_bridge_event(&dec, rollup_id).await?;
-                } else if let Ok(dec) = log.log_decode::<ClaimEventV1>() {
-                    // TODO: Dirty. Find a way to convert the event.
-                    // Convert and insert ClaimEventV1
-                    let lol: Log<ClaimEvent> = Log {
-                        inner: Log2 {
-                            address: dec.inner.address,
-                            data: ClaimEvent {
-                                globalIndex: alloy::primitives::Uint::<256, 4>::from(
-                                    dec.inner.index,
-                                ),
-                                destinationAddress: dec.inner.destinationAddress,
-                                amount: dec.inner.amount,
-                                originAddress: dec.inner.originAddress,
-                                originNetwork: dec.inner.originNetwork,
-                            },
-                        },
-                        block_hash: dec.block_hash,
-                        block_number: dec.block_number,
-                        block_timestamp: dec.block_timestamp,
-                        transaction_hash: dec.transaction_hash,
-                        transaction_index: dec.transaction_index,
-                        log_index: dec.log_index,
-                        removed: dec.removed,
-                    };
-                    self.database.insert_claim_event(&lol, rollup_id, 1).await?;
-                } else if let Ok(dec) = log.log_decode::<ClaimEvent>() {
-                    self.database.insert_claim_event(&dec, rollup_id, 2).await?;
-                } else if let Ok(dec) = log.log_decode::<NewWrappedToken>() {
-                    self.database
-                        .insert_new_wrapped_token_event(&dec, rollup_id)
-                        .await?;
-                    self.wrapped_tokens.push(dec.inner.wrappedTokenAddress);
-                } else if let Ok(dec) = log.log_decode::<EmergencyStateActivated>() {
-                } else if let Ok(dec) = log.log_decode::<EmergencyStateDeactivated>() {
-                } else if let Ok(dec) = log.log_decode::<Upgraded>() {
-                } else if let Ok(dec) = log.log_decode::<Initialized>() {
-                } else if let Ok(dec) = log.log_decode::<AdminChanged>() {
-                } else {
-                    panic!("Log could not be decoded: {:?}", log.transaction_hash);
-                }
+                break;
             }
+        }
 
-            // Only index wrapped tokens if there are any
-            if self.wrapped_tokens.len() > 0 {
-                // mint
-                let mint_events = self
-                    .provider
-                    .get_logs(
-                        &Filter::new()
-                            .from_block(start_block)
-                            .to_block(end_block)
-                            .address(self.wrapped_tokens.clone())
-                            .event("Transfer(address,address,uint256)")
-                            .topic1(to_topic(address!(
-                                "0x0000000000000000000000000000000000000000"
-                            ))),
-                    )
-                    .await?;
-
-                // burn
-                let burn_events = self
-                    .provider
-                    .get_logs(
-                        &Filter::new()
-                            .from_block(start_block)
-                            .to_block(end_block)
-                            .address(self.wrapped_tokens.clone())
-                            .event("Transfer(address,address,uint256)")
-                            .topic2(to_topic(address!(
-                                "0x0000000000000000000000000000000000000000"
-                            ))),
-                    )
-                    .await?;
+        Ok(())
+    }
 
-                for log in mint_events {
-                    let dec = log.log_decode::<Transfer>()?;
-                    self.database
-                        .insert_wrapped_transfer_event(&dec, self.rollup_id)
-                        .await?;
-                }
+    // Indexes blocks [from, to] once, window by window, without the waits
+    // and failure tracking of the index loop. The first error is returned.
+    pub async fn index_range(&mut self, from: u64, to: u64) -> Result<(), Box<dyn std::error::Error>> {
+        let block_increment = self.get_block_increment();
+        let mut start = from;
+        while start <= to {
+            let end = std::cmp::min(start + block_increment - 1, to);
+            self.index_window(start, end, true).await?;
+            start = end + 1;
+        }
+        Ok(())
+    }
 
-                for log in burn_events {
-                    let dec = log.log_decode::<Transfer>()?;
-                    self.database
-                        .insert_wrapped_transfer_event(&dec, self.rollup_id)
-                        .await?;
-                }
+    // Runs a step, tracking its failures (see handle_failure).
+    async fn tracked_step(
+        &mut self,
+        last_processed_block: &mut Option<u64>,
+        latest_block: &mut u64,
+        backfilled_down_to: &mut Option<u64>,
+        block_increment: u64,
+    ) {
+        // Errors are turned into strings right away so that the boxed
+        // error is not held across awaits.
+        let result = self
+            .index_step(
+                last_processed_block,
+                latest_block,
+                backfilled_down_to,
+                block_increment,
+            )
+            .await
+            .map_err(|e| e.to_string());
+        match result {
+            Ok(()) => self.health.lock().unwrap().consecutive_failures = 0,
+            Err(error) => {
+                self.reload_cursor(last_processed_block).await;
+                self.handle_failure(error).await
             }
+        }
+    }
 
-            let bridge_out_events = self
-                .provider
-                .get_logs(
-                    &Filter::new()
-                        .from_block(start_block)
-                        .to_block(end_block)
-                        .event("Transfer(address,address,uint256)")
-                        .topic1(to_topic(self.bridge_address)),
-                )
-                .await?;
+    // Moves the local cursor up to the stored one. A window that fails after
+    // persisting some of its sub-chunks is retried from the first sub-chunk
+    // that wasn't stored, since the stored cursor can't move back.
+    async fn reload_cursor(&self, last_processed_block: &mut Option<u64>) {
+        match self.database.last_indexed_block(self.rollup_id).await {
+            Ok(stored) if stored > *last_processed_block => *last_processed_block = stored,
+            Ok(_) => {}
+            Err(e) => error!(
+                rollup_id = self.rollup_id,
+                "Could not reload the sync marker: {}", e
+            ),
+        }
+    }
 
-            let bridge_in_events = self
-                .provider
-                .get_logs(
-                    &Filter::new()
-                        .from_block(start_block)
-                        .to_block(end_block)
-                        .event("Transfer(address,address,uint256)")
-                        .topic2(to_topic(self.bridge_address)),
-                )
+    // Walks the sync marker back if it's ahead of what was actually stored,
+    // eg persisted by a buggy or interrupted write, so that the missing blocks
+    // are indexed again. Everything up to the end of the last recorded range
+    // or the last stored log is known to be indexed. Rollups without ranges
+    // (indexed by older versions) and desc backfills, whose marker starts at
+    // the tip, can't be checked.
+    async fn check_cursor(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self
+            .database
+            .backfilled_down_to(self.rollup_id)
+            .await?
+            .is_some()
+        {
+            return Ok(());
+        }
+        let Some(range_end) = self.database.last_indexed_range_end(self.rollup_id).await? else {
+            return Ok(());
+        };
+        let last_log = self.database.last_stored_log(self.rollup_id).await?;
+        let stored_till = range_end.max(last_log.map(|(block, _)| block).unwrap_or_default());
+
+        let Some(cursor) = self.database.last_indexed_block(self.rollup_id).await? else {
+            return Ok(());
+        };
+        if cursor > stored_till {
+            warn!(
+                rollup_id = self.rollup_id,
+                "Sync marker {:?} is ahead of the stored data (last range end {:?}, last log {:?}), walking it back to {:?}",
+                cursor, range_end, last_log, stored_till
+            );
+            self.database
+                .synced_till_block(self.rollup_id, stored_till, true)
                 .await?;
+        }
+        Ok(())
+    }
 
-            for log in bridge_out_events {
-                let dec = log.log_decode::<Transfer>()?;
-                self.database
-                    .insert_bridge_transfer_event(&dec, self.rollup_id)
-                    .await?;
+    // Walks the sync marker back to the first range indexed without one of the
+    // event types enabled now (see --events), so that their logs are fetched.
+    // Inserts are idempotent, the events already stored are ignored. The
+    // ranges from there on are recorded again as they are indexed. Like
+    // check_cursor, desc backfills are not checked.
+    async fn check_events(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self
+            .database
+            .backfilled_down_to(self.rollup_id)
+            .await?
+            .is_some()
+        {
+            return Ok(());
+        }
+        let mut first_missing: Option<u64> = None;
+        for (events, from_block) in self.database.indexed_event_sets(self.rollup_id).await? {
+            let covered = events
+                .parse::<IndexedEvents>()
+                .is_ok_and(|events| events.covers(&self.config.events));
+            if !covered {
+                first_missing = Some(first_missing.map_or(from_block, |b| b.min(from_block)));
             }
+        }
+        let Some(from_block) = first_missing else {
+            return Ok(());
+        };
+        let Some(cursor) = self.database.last_indexed_block(self.rollup_id).await? else {
+            return Ok(());
+        };
+        if cursor >= from_block {
+            info!(
+                rollup_id = self.rollup_id,
+                "Blocks from {:?} were indexed without some of the events {}, rescanning them",
+                from_block,
+                self.config.events
+            );
+            self.rewind_cursor(from_block).await?;
+        }
+        self.database
+            .remove_indexed_ranges(self.rollup_id, from_block)
+            .await?;
+        Ok(())
+    }
+
+    // In desc mode two cursors are kept. latest_bridge_synced_block moves
+    // forward from the tip as in asc mode, while backfilled_down_to moves
+    // backwards towards the start block. Everything in [backfilled_down_to,
+    // latest_bridge_synced_block] is indexed. A fresh rollup places both at the
+    // tip. A rollup that already made progress ascending just carries on
+    // ascending. Returns the backward cursor, None if there is nothing to
+    // backfill.
+    async fn init_backfill(
+        &self,
+        last_processed_block: &mut Option<u64>,
+        latest_block: u64,
+    ) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        if let Some(down) = self.database.backfilled_down_to(self.rollup_id).await? {
+            return Ok(Some(down));
+        }
+        if last_processed_block.is_some_and(|block| block > self.start_block) {
+            return Ok(None);
+        }
+
+        let _writing = self.write_lock.lock().await;
+        self.database
+            .synced_till_block(self.rollup_id, latest_block, false)
+            .await?;
+        self.database
+            .set_backfilled_down_to(self.rollup_id, latest_block + 1)
+            .await?;
+        *last_processed_block = Some(latest_block);
+        Ok(Some(latest_block + 1))
+    }
 
-            println!(
-                "indexing from {:?} to {:?} bridge_address: {:?}",
-                start_block, end_block, self.bridge_address
+    // Indexes the next window, or waits for new blocks if already at the tip.
+    // In desc mode the time at the tip is used to backfill one window below.
+    async fn index_step(
+        &mut self,
+        last_processed_block: &mut Option<u64>,
+        latest_block: &mut u64,
+        backfilled_down_to: &mut Option<u64>,
+        block_increment: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(from) = self.take_rescan() {
+            info!(
+                rollup_id = self.rollup_id,
+                "Rescanning from block {:?}", from
             );
-            for log in bridge_in_events {
-                // TODO: Bug ?? https://github.com/alloy-rs/alloy/issues/2243
-                match log.log_decode::<Transfer>() {
-                    Ok(dec) => {
-                        self.database
-                            .insert_bridge_transfer_event(&dec, self.rollup_id)
-                            .await?;
-                    }
-                    Err(e) => {
-                        println!("Error decoding log: {:?}", e);
-                    }
-                }
+            *last_processed_block = self.rewind_cursor(from).await?;
+        }
+
+        let next_block = last_processed_block.map_or(self.start_block, |block| block + 1);
+        if next_block > *latest_block {
+            if let Some(down) = backfilled_down_to.filter(|d| *d > self.start_block) {
+                let start = down
+                    .saturating_sub(block_increment + 1)
+                    .max(self.start_block);
+                self.backfill_window(start, down - 1).await?;
+                *backfilled_down_to = Some(start);
+                *latest_block = self.confirmed_head().await?;
+                return Ok(());
             }
 
-            latest_block = self.provider.get_block_number().await?;
-            last_processed_block = end_block;
+            if self.config.index_unconfirmed && self.config.confirmations > 0 {
+                self.index_unconfirmed(next_block - 1).await?;
+            }
 
-            let percentage_indexed = (end_block as f64 / latest_block as f64) * 100.0;
-            println!(
-                "[Rollup: {:?}] Indexed {:.2}% of the blocks. {:?}/{:?}",
-                self.rollup_id, percentage_indexed, end_block, latest_block
+            let wait = jittered(
+                Duration::from_secs(TIP_POLL_SECS),
+                TIP_POLL_JITTER,
+                random_u64(),
             );
-            self.database
-                .synced_till_block(self.rollup_id, end_block)
-                .await?;
+            info!(
+                rollup_id = self.rollup_id,
+                "Reached the latest block {:?} . Sleeping for {:?}...", latest_block, wait
+            );
+            sleep(wait).await;
+            *latest_block = self.confirmed_head().await?;
+            return Ok(());
         }
 
+        let start_block = next_block;
+        let end_block = std::cmp::min(start_block + block_increment, *latest_block);
+
+        info!(
+            rollup_id = self.rollup_id,
+            "indexing from {:?} to {:?} bridge_address: {:?}",
+            start_block,
+            end_block,
+            self.bridge_address
+        );
+        self.index_window(start_block, end_block, true).await?;
+
+        *latest_block = self.confirmed_head().await?;
+        *last_processed_block = Some(end_block);
+
+        let percentage_indexed = (end_block as f64 / *latest_block as f64) * 100.0;
+        info!(
+            rollup_id = self.rollup_id,
+            "Indexed {:.2}% of the blocks. {:?}/{:?}", percentage_indexed, end_block, latest_block
+        );
         Ok(())
     }
 
-    // TODO: Im sure there are fancier ways of doing this.
-    // TODO: Unsure if this works as expected.
-    pub fn shutdown(&self) {
-        println!("Shutting down indexer {:?}", self.rollup_id);
-        self.running.store(false, Ordering::Relaxed);
+    // Replaces the unconfirmed bridge and claim events of the rollup with the
+    // ones of the blocks after `confirmed_block`. Each block is indexed into
+    // the regular tables once confirmed, and its events leave the unconfirmed
+    // ones on the next call. Claims of the v1 bridge are not included, it's
+    // not deployed anymore.
+    async fn index_unconfirmed(
+        &self,
+        confirmed_block: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let head = self.provider.get_block_number().await?;
+        let head = self.pinned_head.map_or(head, |pin| head.min(pin));
+        let mut logs = Vec::new();
+        if head > confirmed_block {
+            logs = self
+                .get_logs(
+                    &Filter::new()
+                        .from_block(confirmed_block + 1)
+                        .to_block(head)
+                        .address(self.bridge_address),
+                )
+                .await?;
+        }
+
+        let database = self.database.unconfirmed();
+        let events = self.config.events;
+        database.clear_unconfirmed(self.rollup_id).await?;
+        for log in logs.iter() {
+            if let Ok(dec) = log.log_decode::<BridgeEvent>() {
+                if events.bridge {
+                    database.insert_bridge_event(&dec, self.rollup_id).await?;
+                }
+            } else if let Ok(dec) = log.log_decode::<ClaimEvent>() {
+                if events.claim {
+                    database.insert_claim_event(&dec, self.rollup_id, 2).await?;
+                }
+            }
+        }
+        if head > confirmed_block {
+            database
+                .fill_claim_leaf_types(self.rollup_id, confirmed_block + 1, head)
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Indexes [start_block, end_block] below the forward cursor and moves the
+    // backward cursor to start_block. Wrapped tokens created in the window had
+    // their later transfers skipped, since the blocks above were indexed
+    // before the token was known, so those are backfilled too.
+    async fn backfill_window(
+        &mut self,
+        start_block: u64,
+        end_block: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        info!(
+            rollup_id = self.rollup_id,
+            "Backfilling from {:?} to {:?}", start_block, end_block
+        );
+        let known_tokens = self.wrapped_tokens.len();
+        self.index_window(start_block, end_block, false).await?;
+
+        if self.config.index_transfers {
+            let new_tokens = self.wrapped_token_addresses().split_off(known_tokens);
+            for token in new_tokens {
+                self.backfill_wrapped_token(token, end_block + 1).await?;
+            }
+        }
+
+        let _writing = self.write_lock.lock().await;
+        self.database
+            .set_backfilled_down_to(self.rollup_id, start_block)
+            .await?;
+        Ok(())
+    }
+
+    // Records a failed step and retries after a short delay. After
+    // max_consecutive_failures the indexer pauses and just probes the rpc every
+    // health_probe_interval_secs, resuming once it answers again.
+    async fn handle_failure(&self, error: String) {
+        let failures = {
+            let mut health = self.health.lock().unwrap();
+            health.consecutive_failures += 1;
+            health.last_error = Some(error.clone());
+            health.consecutive_failures
+        };
+        error!(
+            rollup_id = self.rollup_id,
+            "Indexing failed ({:?} in a row): {}", failures, error
+        );
+
+        if failures < self.config.max_consecutive_failures {
+            sleep(Duration::from_secs(5)).await;
+            return;
+        }
+
+        error!(
+            rollup_id = self.rollup_id,
+            "Too many consecutive failures. Pausing until the rpc recovers..."
+        );
+        self.health.lock().unwrap().paused = true;
+
+        loop {
+            sleep(Duration::from_secs(self.config.health_probe_interval_secs)).await;
+            if self.provider.get_block_number().await.is_ok() {
+                info!(rollup_id = self.rollup_id, "Rpc recovered. Resuming...");
+                let mut health = self.health.lock().unwrap();
+                health.paused = false;
+                health.consecutive_failures = 0;
+                return;
+            }
+        }
+    }
+
+    pub fn health(&self) -> IndexerHealth {
+        self.health.lock().unwrap().clone()
+    }
+
+    // Fetches all the logs in [start_block, end_block] and then processes them in
+    // sub-chunks of progress_chunk_size blocks, persisting the sync progress after
+    // each one. This way a crash resumes from the last completed sub-chunk rather
+    // than from the start of the window. The events of a sub-chunk are committed
+    // along with its progress, so a failure never leaves part of one behind.
+    // Windows below the forward cursor (desc backfill) don't record progress.
+    // Logged within a span with the rollup and the block range, see --json-logs.
+    #[instrument(name = "window", skip(self, record_progress), fields(rollup_id = self.rollup_id))]
+    async fn index_window(
+        &mut self,
+        start_block: u64,
+        end_block: u64,
+        record_progress: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let started = Instant::now();
+        self.rollback_chunk().await?;
+        let events = self.config.events;
+        let mut bridge_logs = Vec::new();
+        if events.bridge || events.claim || events.wrapped {
+            bridge_logs = self
+                .get_logs(
+                    &Filter::new()
+                        .from_block(start_block)
+                        .to_block(end_block)
+                        .address(self.bridge_address),
+                )
+                .await?;
+        }
+
+        // New wrapped tokens must be known before fetching their transfers. A
+        // retried window finds them again.
+        for log in bridge_logs.iter() {
+            if let Ok(dec) = log.log_decode::<NewWrappedToken>() {
+                let address = dec.inner.wrappedTokenAddress;
+                if self
+                    .wrapped_tokens
+                    .iter()
+                    .any(|token| token.address == address)
+                {
+                    continue;
+                }
+                self.wrapped_tokens.push(WrappedToken::new(
+                    dec.inner.wrappedTokenAddress,
+                    dec.inner.originNetwork,
+                    dec.inner.originTokenAddress,
+                    &dec.inner.metadata,
+                ));
+            }
+        }
+
+        // Transfers are most of the rpc load, see --no-transfer-indexing. Only
+        // index wrapped tokens if there are any.
+        let index_transfers = self.config.index_transfers;
+        let wrapped_tokens = if index_transfers && (events.wrapped || events.holder) {
+            self.wrapped_token_addresses()
+        } else {
+            Vec::new()
+        };
+        let transfer_filter = Filter::new()
+            .from_block(start_block)
+            .to_block(end_block)
+            .event_signature(event_topic0(TRANSFER_SIGNATURE));
+
+        // The remaining filters are fetched concurrently, within the rpc budget
+        // of the rollup (see get_logs). Errors are turned into strings so that
+        // the joined future stays Send.
+        let (
+            (mint_events, burn_events),
+            holder_events,
+            bridge_out_events,
+            bridge_in_events,
+            rollup_manager_logs,
+            ger_logs,
+        ) = tokio::try_join!(
+            async {
+                if wrapped_tokens.is_empty() || !events.wrapped {
+                    return Ok((Vec::new(), Vec::new()));
+                }
+                self.get_mint_burn_logs(start_block, end_block, &wrapped_tokens)
+                    .await
+                    .map_err(|e| e.to_string())
+            },
+            async {
+                if wrapped_tokens.is_empty() || !events.holder {
+                    return Ok(Vec::new());
+                }
+                self.get_holder_transfer_logs(&transfer_filter, &wrapped_tokens)
+                    .await
+                    .map_err(|e| e.to_string())
+            },
+            async {
+                if !index_transfers || !events.transfer {
+                    return Ok(Vec::new());
+                }
+                self.get_logs(
+                    &transfer_filter
+                        .clone()
+                        .topic1(to_topic(self.bridge_address)),
+                )
+                .await
+                .map_err(|e| e.to_string())
+            },
+            async {
+                if !index_transfers || !events.transfer {
+                    return Ok(Vec::new());
+                }
+                self.get_logs(
+                    &transfer_filter
+                        .clone()
+                        .topic2(to_topic(self.bridge_address)),
+                )
+                .await
+                .map_err(|e| e.to_string())
+            },
+            async {
+                let Some(rollup_manager_address) = self.rollup_manager_address else {
+                    return Ok(Vec::new());
+                };
+                self.get_logs(
+                    &Filter::new()
+                        .from_block(start_block)
+                        .to_block(end_block)
+                        .address(rollup_manager_address)
+                        .event_signature(vec![
+                            VerifyBatchesTrustedAggregator::SIGNATURE_HASH,
+                            OnSequenceBatches::SIGNATURE_HASH,
+                        ]),
+                )
+                .await
+                .map_err(|e| e.to_string())
+            },
+            async {
+                let Some(ger_manager_address) = self.ger_manager_address else {
+                    return Ok(Vec::new());
+                };
+                self.get_logs(
+                    &Filter::new()
+                        .from_block(start_block)
+                        .to_block(end_block)
+                        .address(ger_manager_address)
+                        .event_signature(vec![
+                            UpdateGlobalExitRoot::SIGNATURE_HASH,
+                            UpdateL1InfoTree::SIGNATURE_HASH,
+                        ]),
+                )
+                .await
+                .map_err(|e| e.to_string())
+            },
+        )?;
+
+        let bridge_topic = self.bridge_address.into_word();
+        let chunk_size = self.config.progress_chunk_size.max(1);
+        let mut chunk_start = start_block;
+        while chunk_start <= end_block {
+            let chunk_end = std::cmp::min(chunk_start + chunk_size - 1, end_block);
+
+            // Logs are inserted in (block, log index) order regardless of the
+            // filter they came from, so reindexing always inserts the same
+            // sequence.
+            let mut chunk_logs: Vec<(LogKind, &Log)> = in_range(&bridge_logs, chunk_start, chunk_end)
+                .map(|log| (LogKind::Bridge, log))
+                .chain(
+                    in_range(&mint_events, chunk_start, chunk_end)
+                        .chain(in_range(&burn_events, chunk_start, chunk_end))
+                        .map(|log| (LogKind::WrappedTransfer, log)),
+                )
+                .chain(
+                    in_range(&bridge_out_events, chunk_start, chunk_end)
+                        .map(|log| (LogKind::BridgeOut, log)),
+                )
+                .chain(
+                    in_range(&rollup_manager_logs, chunk_start, chunk_end)
+                        .map(|log| (LogKind::RollupManager, log)),
+                )
+                .chain(
+                    in_range(&ger_logs, chunk_start, chunk_end)
+                        .map(|log| (LogKind::GlobalExitRoot, log)),
+                )
+                .chain(
+                    // Bridge to bridge transfers match both filters, they are
+                    // already in with the bridge out ones.
+                    in_range(&bridge_in_events, chunk_start, chunk_end)
+                        .filter(|log| log.topics().get(1) != Some(&bridge_topic))
+                        .map(|log| (LogKind::BridgeIn, log)),
+                )
+                .chain(
+                    // Transfers from or to the bridge are stored as bridge
+                    // transfers, which come first.
+                    in_range(&holder_events, chunk_start, chunk_end)
+                        .map(|log| (LogKind::HolderTransfer, log)),
+                )
+                .collect();
+            sort_logs(&mut chunk_logs);
+            dedup_logs(&mut chunk_logs);
+
+            let progress = ChunkProgress {
+                from_block: chunk_start,
+                to_block: chunk_end,
+                events,
+                record_progress,
+                check_claims: self.config.reorg_safe_l1 && events.claim,
+            };
+            let write_lock = self.write_lock.clone();
+            let _writing = write_lock.lock().await;
+            // The error is turned into a string so that it's not held
+            // across the rollback.
+            let stored = self
+                .store_chunk(chunk_logs, progress)
+                .await
+                .map_err(|e| e.to_string());
+            if let Err(e) = stored {
+                self.rollback_chunk().await?;
+                return Err(e.into());
+            }
+            chunk_start = chunk_end + 1;
+        }
+
+        self.timings
+            .lock()
+            .unwrap()
+            .window
+            .observe(started.elapsed().as_secs_f64());
+        Ok(())
+    }
+
+    // Stores the logs of a chunk along with its progress, in one transaction.
+    // On error the chunk is left open, the caller rolls it back.
+    async fn store_chunk(
+        &mut self,
+        chunk_logs: Vec<(LogKind, &Log)>,
+        progress: ChunkProgress,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.config.index_block_timestamps {
+            for (_, log) in chunk_logs.iter() {
+                self.record_block_timestamp(log).await?;
+            }
+        }
+
+        for (kind, log) in chunk_logs {
+            match kind {
+                LogKind::Bridge => self.process_bridge_log(log).await?,
+                LogKind::RollupManager => self.process_rollup_manager_log(log).await?,
+                LogKind::GlobalExitRoot => self.process_global_exit_root_log(log).await?,
+                LogKind::WrappedTransfer => {
+                    let dec = log.log_decode::<Transfer>()?;
+                    if self.skip_transfer(&dec.inner) {
+                        continue;
+                    }
+                    self.write(DecodedEvent::WrappedTransfer(dec)).await?;
+                }
+                LogKind::HolderTransfer => {
+                    let dec = log.log_decode::<Transfer>()?;
+                    if self.skip_transfer(&dec.inner) {
+                        continue;
+                    }
+                    self.write(DecodedEvent::HolderTransfer(dec)).await?;
+                }
+                LogKind::BridgeOut => {
+                    let dec = decode_transfer(log)?;
+                    if self.skip_transfer(&dec.inner) {
+                        continue;
+                    }
+                    let direction = self.bridge_direction(&dec.inner);
+                    self.write(DecodedEvent::BridgeTransfer(
+                        dec,
+                        direction,
+                        self.bridge_address,
+                    ))
+                    .await?;
+                }
+                // Anything sent to the bridge matches, including logs that only
+                // share the Transfer signature.
+                LogKind::BridgeIn => match decode_transfer(log) {
+                    Ok(dec) if self.skip_transfer(&dec.inner) => {}
+                    Ok(dec) => {
+                        let direction = self.bridge_direction(&dec.inner);
+                        self.write(DecodedEvent::BridgeTransfer(
+                            dec,
+                            direction,
+                            self.bridge_address,
+                        ))
+                        .await?;
+                    }
+                    Err(e) => {
+                        self.write(DecodedEvent::IndexingError(
+                            log.clone(),
+                            "BridgeIn",
+                            e.to_string(),
+                        ))
+                        .await?;
+                        if self.config.strict_decode {
+                            return Err(format!(
+                                "could not decode bridge in transfer {:?} log {:?}: {}",
+                                log.transaction_hash, log.log_index, e
+                            )
+                            .into());
+                        }
+                        warn!(
+                            rollup_id = self.rollup_id,
+                            "Error decoding bridge in transfer {:?} topics {:?}: {:?}",
+                            log.transaction_hash,
+                            log.topics(),
+                            e
+                        );
+                    }
+                },
+            }
+        }
+
+        self.commit_chunk(progress).await
+    }
+
+    // Stores a decoded event, through the writer task if there is one. With a
+    // sink the event is published first, see EventSink.
+    async fn write(&self, event: DecodedEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let rollup_id = self.event_rollup_id(&event);
+        if let Some(sink) = &self.sink {
+            if let Some(json) = event.to_json(rollup_id) {
+                sink.publish(rollup_id, serde_json::to_vec(&json)?).await?;
+            }
+        }
+        match &self.writer {
+            Some(writer) => writer
+                .send(WriterMessage::Event(Box::new(event), rollup_id))
+                .await
+                .map_err(|_| "writer task stopped")?,
+            None => {
+                let mut batch = self.inline_batch.lock().await;
+                write_batched(&self.database, &mut batch, &event, rollup_id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Rollup an event is stored under: the one of the indexer, unless it is a
+    // bridge (claim) event whose destination (origin) network is one of the
+    // rollups sharing its bridge, see shared_rollups.
+    fn event_rollup_id(&self, event: &DecodedEvent) -> u32 {
+        let network = match event {
+            DecodedEvent::Bridge(log) => log.inner.destinationNetwork,
+            DecodedEvent::Claim(log, _) => log.inner.originNetwork,
+            _ => return self.rollup_id,
+        };
+        if self.shared_rollups.lock().unwrap().contains(&network) {
+            network
+        } else {
+            self.rollup_id
+        }
+    }
+
+    // Stores the events written since the last chunk along with its progress,
+    // in one transaction. Fails with the first error writing them.
+    async fn commit_chunk(
+        &self,
+        progress: ChunkProgress,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match &self.writer {
+            Some(writer) => {
+                let (reply, committed) = oneshot::channel();
+                writer
+                    .send(WriterMessage::Commit(progress, reply))
+                    .await
+                    .map_err(|_| "writer task stopped")?;
+                committed.await.map_err(|_| "writer task stopped")??;
+            }
+            None => {
+                let batch = self.inline_batch.lock().await.take();
+                let shared = self.shared_rollups.lock().unwrap().clone();
+                commit_batch(&self.database, batch, self.rollup_id, &shared, progress).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Drops the events written since the last chunk, left by a window that
+    // failed before committing it.
+    async fn rollback_chunk(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match &self.writer {
+            Some(writer) => writer
+                .send(WriterMessage::Rollback)
+                .await
+                .map_err(|_| "writer task stopped")?,
+            None => {
+                if let Some(batch) = self.inline_batch.lock().await.take() {
+                    batch.rollback().await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Zero value transfers don't change balances, see
+    // --skip-zero-value-transfers.
+    fn skip_transfer(&self, transfer: &Log2<Transfer>) -> bool {
+        self.config.skip_zero_value_transfers && transfer.value.is_zero()
+    }
+
+    // Direction of a transfer relative to the bridge, as stored in
+    // bridge_transfer_events.
+    fn bridge_direction(&self, transfer: &Log2<Transfer>) -> &'static str {
+        match (
+            transfer.from == self.bridge_address,
+            transfer.to == self.bridge_address,
+        ) {
+            (true, true) => "self",
+            (true, false) => "out",
+            _ => "in",
+        }
+    }
+
+    // Stores the timestamp of the block the log belongs to. Taken from the log
+    // when the rpc includes it, otherwise from the cache or fetched.
+    async fn record_block_timestamp(&self, log: &Log) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(block_number) = log.block_number else {
+            return Ok(());
+        };
+        let timestamp = match log.block_timestamp {
+            Some(timestamp) => timestamp,
+            None => self.block_timestamp(block_number).await?,
+        };
+        self.write(DecodedEvent::BlockTimestamp(block_number, timestamp))
+            .await
+    }
+
+    // Timestamp of a block, fetched only if it's not in the cache.
+    pub async fn block_timestamp(&self, block_number: u64) -> Result<u64, Box<dyn std::error::Error>> {
+        if let Some(timestamp) = self.timestamp_cache.lock().unwrap().get(&block_number) {
+            return Ok(*timestamp);
+        }
+
+        let block = self
+            .provider
+            .get_block_by_number(BlockNumberOrTag::Number(block_number))
+            .await?
+            .ok_or_else(|| format!("block {} not found", block_number))?;
+        let timestamp = block.header.timestamp;
+        self.timestamp_cache
+            .lock()
+            .unwrap()
+            .put(block_number, timestamp);
+        Ok(timestamp)
+    }
+
+    // Fetches the mint (from the zero address) and burn (to the zero address)
+    // Transfer logs of the given tokens in [start_block, end_block].
+    async fn get_mint_burn_logs(
+        &self,
+        start_block: u64,
+        end_block: u64,
+        tokens: &[Address],
+    ) -> Result<(Vec<Log>, Vec<Log>), Box<dyn std::error::Error>> {
+        let transfer_filter = Filter::new()
+            .from_block(start_block)
+            .to_block(end_block)
+            .event_signature(event_topic0(TRANSFER_SIGNATURE));
+        let (mint_events, burn_events) = tokio::try_join!(
+            async {
+                self.get_logs_chunked(
+                    &transfer_filter.clone().topic1(to_topic_set(&[Address::ZERO])),
+                    tokens,
+                )
+                .await
+                .map_err(|e| e.to_string())
+            },
+            async {
+                self.get_logs_chunked(
+                    &transfer_filter.clone().topic2(to_topic_set(&[Address::ZERO])),
+                    tokens,
+                )
+                .await
+                .map_err(|e| e.to_string())
+            },
+        )?;
+
+        Ok((mint_events, burn_events))
+    }
+
+    // Fetches the Transfer logs of the given tokens matching `transfer_filter`
+    // between two holders, ie other than mints and burns.
+    async fn get_holder_transfer_logs(
+        &self,
+        transfer_filter: &Filter,
+        tokens: &[Address],
+    ) -> Result<Vec<Log>, Box<dyn std::error::Error>> {
+        let zero = Address::ZERO.into_word();
+        let mut logs = self.get_logs_chunked(transfer_filter, tokens).await?;
+        logs.retain(|log| match log.topics() {
+            [_, from, to, ..] => *from != zero && *to != zero,
+            _ => false,
+        });
+        Ok(logs)
+    }
+
+    // Indexes the mint/burn transfers of a single wrapped token from
+    // `from_block` to the head. Meant for tokens whose activity predates their
+    // discovery. Doesn't touch the sync marker. Each range is stored in its own
+    // transaction, taken under the write lock so that it waits for the chunk the
+    // index loop may be storing. Returns the amount of logs found.
+    #[instrument(name = "token_backfill", skip(self), fields(rollup_id = self.rollup_id))]
+    pub async fn backfill_wrapped_token(
+        &self,
+        token: Address,
+        from_block: u64,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let head = self.provider.get_block_number().await?;
+        let block_increment = self.get_block_increment();
+
+        let mut indexed = 0;
+        let mut start = from_block;
+        while start <= head {
+            let end = std::cmp::min(start + block_increment - 1, head);
+            let (mint_events, burn_events) = self.get_mint_burn_logs(start, end, &[token]).await?;
+            let mut logs: Vec<(LogKind, &Log)> = mint_events
+                .iter()
+                .chain(burn_events.iter())
+                .map(|log| (LogKind::WrappedTransfer, log))
+                .collect();
+            sort_logs(&mut logs);
+            let mut transfers = Vec::new();
+            for (_, log) in logs {
+                let dec = log.log_decode::<Transfer>()?;
+                if !self.skip_transfer(&dec.inner) {
+                    transfers.push(dec);
+                }
+            }
+
+            let _writing = self.write_lock.lock().await;
+            let batch = self.database.begin_batch().await?;
+            for dec in transfers.iter() {
+                let inserted = batch
+                    .insert_wrapped_transfer_event(dec, self.rollup_id)
+                    .await
+                    .map_err(|e| e.to_string());
+                if let Err(e) = inserted {
+                    batch.rollback().await?;
+                    return Err(e.into());
+                }
+            }
+            batch.commit(self.rollup_id).await?;
+            indexed += transfers.len();
+            start = end + 1;
+        }
+
+        Ok(indexed)
+    }
+
+    // Decodes a batch sequence/verification log of the rollup manager and
+    // stores it.
+    async fn process_rollup_manager_log(
+        &self,
+        log: &Log,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Ok(dec) = log.log_decode::<VerifyBatchesTrustedAggregator>() {
+            self.write(DecodedEvent::VerifyBatches(dec)).await?;
+        } else if let Ok(dec) = log.log_decode::<OnSequenceBatches>() {
+            self.write(DecodedEvent::SequenceBatches(dec)).await?;
+        }
+        Ok(())
+    }
+
+    // Decodes a global exit root update of the global exit root manager and
+    // stores it.
+    async fn process_global_exit_root_log(
+        &self,
+        log: &Log,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Ok(dec) = log.log_decode::<UpdateL1InfoTree>() {
+            self.write(DecodedEvent::GlobalExitRoot(dec)).await?;
+        } else if let Ok(dec) = log.log_decode::<UpdateGlobalExitRoot>() {
+            let update = UpdateL1InfoTree {
+                mainnetExitRoot: dec.inner.mainnetExitRoot,
+                rollupExitRoot: dec.inner.rollupExitRoot,
+            };
+            self.write(DecodedEvent::GlobalExitRoot(with_data(log, update)))
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Decodes a log emitted by the bridge contract and stores it.
+    async fn process_bridge_log(&mut self, log: &Log) -> Result<(), Box<dyn std::error::Error>> {
+        let events = self.config.events;
+        if let Ok(dec) = log.log_decode::<BridgeEvent>() {
+            if events.bridge {
+                self.write(DecodedEvent::Bridge(dec)).await?;
+            }
+        } else if let Ok(dec) = log.log_decode::<ClaimEventV1>() {
+            if events.claim {
+                // TODO: Dirty. Find a way to convert the event.
+                // Convert and insert ClaimEventV1
+                let lol: Log<ClaimEvent> = Log {
+                    inner: Log2 {
+                        address: dec.inner.address,
+                        data: ClaimEvent {
+                            globalIndex: alloy::primitives::Uint::<256, 4>::from(dec.inner.index),
+                            destinationAddress: dec.inner.destinationAddress,
+                            amount: dec.inner.amount,
+                            originAddress: dec.inner.originAddress,
+                            originNetwork: dec.inner.originNetwork,
+                        },
+                    },
+                    block_hash: dec.block_hash,
+                    block_number: dec.block_number,
+                    block_timestamp: dec.block_timestamp,
+                    transaction_hash: dec.transaction_hash,
+                    transaction_index: dec.transaction_index,
+                    log_index: dec.log_index,
+                    removed: dec.removed,
+                };
+                self.write(DecodedEvent::Claim(lol, 1)).await?;
+            }
+        } else if let Ok(dec) = log.log_decode::<ClaimEvent>() {
+            // Checked against the deposit and the l1 global exit roots with
+            // its chunk, see --reorg-safe-l1.
+            if events.claim {
+                self.write(DecodedEvent::Claim(dec, 2)).await?;
+            }
+        } else if let Ok(dec) = log.log_decode::<NewWrappedToken>() {
+            // The token itself was already tracked when the window was fetched.
+            if events.wrapped {
+                self.write(DecodedEvent::NewWrappedToken(dec)).await?;
+            }
+        } else if let Some(event) = decode_proxy_event(log) {
+            self.write(DecodedEvent::Proxy(with_data(log, event)))
+                .await?;
+        } else {
+            panic!("Log could not be decoded: {:?}", log.transaction_hash);
+        }
+        Ok(())
+    }
+
+    // Runs the index loop in a background task. The indexer is stopped when
+    // the returned guard is dropped.
+    pub fn spawn(mut self) -> IndexerGuard {
+        let rollup_id = self.rollup_id;
+        let shutdown = self.shutdown.clone();
+        let handle = tokio::spawn(async move {
+            if let Err(e) = self.index().await {
+                error!(rollup_id, "indexer encountered an error: {:?}", e);
+                if let Some(source) = e.source() {
+                    error!(rollup_id, "Caused by: {:?}", source);
+                }
+                panic!("indexer of rollup {:?} error: {:?}", rollup_id, e);
+            }
+        });
+        IndexerGuard {
+            rollup_id,
+            shutdown,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn shutdown(&self) {
+        info!(rollup_id = self.rollup_id, "Shutting down indexer");
+        self.shutdown.send_replace(true);
+    }
+}
+
+// Resolves once the shutdown flag of an indexer is set.
+async fn wait_for_shutdown(mut shutdown: watch::Receiver<bool>) {
+    while !*shutdown.borrow_and_update() {
+        if shutdown.changed().await.is_err() {
+            // The indexer is gone, nothing left to stop.
+            return;
+        }
+    }
+}
+
+// Signature of the erc20 Transfer event, whose topic1 and topic2 are the from
+// and to addresses.
+const TRANSFER_SIGNATURE: &str = "Transfer(address,address,uint256)";
+
+// Seconds between polls for new blocks once at the tip.
+const TIP_POLL_SECS: u64 = 5;
+
+// Fraction the tip poll is randomly shortened or lengthened by, so that the
+// indexers sharing an rpc don't poll it in lockstep.
+const TIP_POLL_JITTER: f64 = 0.2;
+
+// `base` scaled by a factor in [1 - jitter, 1 + jitter] picked by `random`.
+fn jittered(base: Duration, jitter: f64, random: u64) -> Duration {
+    let unit = random as f64 / u64::MAX as f64 * 2.0 - 1.0;
+    base.mul_f64(1.0 + jitter * unit)
+}
+
+// Good enough randomness for jitter without pulling in a rng crate, std seeds
+// each RandomState differently.
+fn random_u64() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish()
+}
+
+// Where a fetched log is stored.
+#[derive(Debug, Clone, Copy)]
+enum LogKind {
+    Bridge,
+    RollupManager,
+    GlobalExitRoot,
+    WrappedTransfer,
+    BridgeOut,
+    BridgeIn,
+    HolderTransfer,
+}
+
+// Sorts logs in canonical (block number, log index) order. The sort is stable,
+// so a log fetched by two filters keeps the order of its kinds.
+fn sort_logs(logs: &mut [(LogKind, &Log)]) {
+    logs.sort_by_key(|(_, log)| log_order(log));
+}
+
+// Canonical position of a log, (block number, log index).
+fn log_order(log: &Log) -> (u64, u64) {
+    (
+        log.block_number.unwrap_or_default(),
+        log.log_index.unwrap_or_default(),
+    )
+}
+
+// Decodes an erc20 Transfer. Some tokens declare the value as indexed, so it
+// comes as a fourth topic with empty data and the standard decode fails on them
+// (https://github.com/alloy-rs/alloy/issues/2243). The value is then taken from
+// the topic. An erc721 Transfer has the same layout, with the token id as value,
+// but the bridge doesn't hold nfts.
+fn decode_transfer(log: &Log) -> Result<Log<Transfer>, alloy::sol_types::Error> {
+    let err = match log.log_decode::<Transfer>() {
+        Ok(dec) => return Ok(dec),
+        Err(e) => e,
+    };
+    match log.topics() {
+        [signature, from, to, value]
+            if *signature == Transfer::SIGNATURE_HASH && log.data().data.is_empty() =>
+        {
+            Ok(with_data(
+                log,
+                Transfer {
+                    from: Address::from_word(*from),
+                    to: Address::from_word(*to),
+                    value: U256::from_be_bytes(value.0),
+                },
+            ))
+        }
+        _ => Err(err),
+    }
+}
+
+// Decodes the admin, upgrade, initialization and emergency state events of
+// the bridge, None for any other log.
+fn decode_proxy_event(log: &Log) -> Option<ProxyEvent> {
+    if let Ok(dec) = log.log_decode::<AdminChanged>() {
+        Some(ProxyEvent::AdminChanged {
+            previous_admin: dec.inner.previousAdmin,
+            new_admin: dec.inner.newAdmin,
+        })
+    } else if let Ok(dec) = log.log_decode::<Upgraded>() {
+        Some(ProxyEvent::Upgraded {
+            implementation: dec.inner.implementation,
+        })
+    } else if let Ok(dec) = log.log_decode::<Initialized>() {
+        Some(ProxyEvent::Initialized {
+            version: dec.inner.version,
+        })
+    } else if log.log_decode::<EmergencyStateActivated>().is_ok() {
+        Some(ProxyEvent::EmergencyStateActivated)
+    } else if log.log_decode::<EmergencyStateDeactivated>().is_ok() {
+        Some(ProxyEvent::EmergencyStateDeactivated)
+    } else {
+        None
+    }
+}
+
+// The log with its data replaced, keeping its address and position.
+fn with_data<T>(log: &Log, data: T) -> Log<T> {
+    Log {
+        inner: Log2 {
+            address: log.address(),
+            data,
+        },
+        block_hash: log.block_hash,
+        block_number: log.block_number,
+        block_timestamp: log.block_timestamp,
+        transaction_hash: log.transaction_hash,
+        transaction_index: log.transaction_index,
+        log_index: log.log_index,
+        removed: log.removed,
+    }
+}
+
+// Keeps a single kind of every log fetched by more than one filter, so that
+// each transfer is stored in exactly one table. Run after sort_logs, the kept
+// one is the first of the chain in index_window: a wrapped token mint or burn
+// that is also a transfer to or from the bridge goes to
+// wrapped_transfer_events, since it changes the supply. Bridge to bridge
+// transfers are filtered out of the bridge in ones before.
+fn dedup_logs(logs: &mut Vec<(LogKind, &Log)>) {
+    logs.dedup_by_key(|(_, log)| (log.block_number, log.log_index));
+}
+
+// Logs of the slice that belong to blocks [from, to].
+fn in_range(logs: &[Log], from: u64, to: u64) -> impl Iterator<Item = &Log> {
+    logs.iter().filter(move |log| {
+        let block = log.block_number.unwrap_or_default();
+        block >= from && block <= to
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{AmountType, DbLocation};
+    use alloy::primitives::{Bytes, LogData, B256, U64};
+    use alloy::transports::mock::Asserter;
+
+    const BRIDGE: Address = Address::repeat_byte(0xbb);
+
+    // Indexer of rollup 1 over an in-memory database, talking to `asserter`
+    // instead of an rpc. Events are written inline.
+    async fn mock_indexer(asserter: &Asserter, config: IndexerConfig) -> Indexer {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        database.insert_rollup(1, "test", None).await.unwrap();
+        let config = IndexerConfig {
+            write_queue_capacity: 0,
+            ..config
+        };
+        let mut indexer = Indexer::new(
+            BRIDGE,
+            "http://localhost:8545".parse().unwrap(),
+            1,
+            database,
+            config,
+        )
+        .await
+        .unwrap();
+        indexer.provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        indexer
+    }
+
+    // A log as returned by eth_getLogs, at `block` and `log_index`.
+    fn rpc_log(address: Address, data: LogData, block: u64, log_index: u64) -> Log {
+        Log {
+            inner: Log2 { address, data },
+            block_hash: Some(B256::with_last_byte(block as u8)),
+            block_number: Some(block),
+            block_timestamp: None,
+            transaction_hash: Some(B256::repeat_byte(log_index as u8 + 1)),
+            transaction_index: Some(0),
+            log_index: Some(log_index),
+            removed: false,
+        }
+    }
+
+    fn bridge_log(block: u64, deposit_count: u32) -> Log {
+        let event = BridgeEvent {
+            leafType: 0,
+            originNetwork: 0,
+            originAddress: Address::ZERO,
+            destinationNetwork: 1,
+            destinationAddress: Address::repeat_byte(0x11),
+            amount: U256::from(1),
+            metadata: Bytes::new(),
+            depositCount: deposit_count,
+        };
+        rpc_log(BRIDGE, event.encode_log_data(), block, 0)
+    }
+
+    #[tokio::test]
+    async fn failed_window_resumes_after_stored_chunks() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                progress_chunk_size: 10,
+                max_consecutive_failures: 1,
+                health_probe_interval_secs: 0,
+                index_transfers: false,
+                index_block_timestamps: true,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+
+        // The window [0, 29] fails in its third sub-chunk, fetching the
+        // timestamp of block 25, after [0, 9] and [10, 19] were stored.
+        let mut stamped = bridge_log(5, 0);
+        stamped.block_timestamp = Some(1_000);
+        asserter.push_success(&vec![stamped, bridge_log(25, 1)]);
+        asserter.push_failure_msg("block unavailable");
+        // Rpc probe of the paused indexer.
+        asserter.push_success(&U64::from(30));
+
+        let mut last_processed_block = None;
+        let mut latest_block = 30;
+        indexer
+            .tracked_step(&mut last_processed_block, &mut latest_block, &mut None, 29)
+            .await;
+        assert_eq!(
+            indexer.database.last_indexed_block(1).await.unwrap(),
+            Some(19)
+        );
+        assert_eq!(last_processed_block, Some(19));
+
+        // The retry starts at block 20 instead of moving the cursor back.
+        asserter.push_success(&Vec::<Log>::new());
+        asserter.push_success(&U64::from(30));
+        indexer
+            .tracked_step(&mut last_processed_block, &mut latest_block, &mut None, 29)
+            .await;
+        assert_eq!(indexer.health().consecutive_failures, 0);
+        assert_eq!(
+            indexer.database.last_indexed_block(1).await.unwrap(),
+            Some(30)
+        );
+    }
+
+    #[tokio::test]
+    async fn paused_indexer_resumes_once_the_rpc_answers() {
+        let asserter = Asserter::new();
+        let indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                max_consecutive_failures: 1,
+                health_probe_interval_secs: 0,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+
+        // The first probe fails, the second one answers.
+        asserter.push_failure_msg("rpc down");
+        asserter.push_success(&U64::from(30));
+        indexer.handle_failure("rpc down".to_string()).await;
+
+        let health = indexer.health();
+        assert!(!health.paused);
+        assert_eq!(health.consecutive_failures, 0);
+        assert_eq!(health.last_error.as_deref(), Some("rpc down"));
+        // Both probes were made.
+        assert!(indexer.provider.get_block_number().await.is_err());
+    }
+
+    fn chunk_progress(from_block: u64, to_block: u64) -> ChunkProgress {
+        ChunkProgress {
+            from_block,
+            to_block,
+            events: IndexedEvents::default(),
+            record_progress: true,
+            check_claims: false,
+        }
+    }
+
+    // A bridge event at block `deposit_count`, in a transaction of its own.
+    async fn send_bridge_event(writer: &mpsc::Sender<WriterMessage>, deposit_count: u32) {
+        let mut log = bridge_log(deposit_count as u64, deposit_count)
+            .log_decode::<BridgeEvent>()
+            .unwrap();
+        log.transaction_hash = Some(B256::with_last_byte(deposit_count as u8));
+        writer
+            .send(WriterMessage::Event(Box::new(DecodedEvent::Bridge(log)), 1))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn writer_stores_queued_events_on_commit() {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        database.insert_rollup(1, "test", None).await.unwrap();
+        let writer = spawn_writer(database.clone(), 1, Default::default(), 2);
+        for deposit_count in 0..5 {
+            send_bridge_event(&writer, deposit_count).await;
+        }
+        let (reply, written) = oneshot::channel();
+        writer
+            .send(WriterMessage::Commit(chunk_progress(0, 4), reply))
+            .await
+            .unwrap();
+        written.await.unwrap().unwrap();
+
+        assert_eq!(database.last_indexed_block(1).await.unwrap(), Some(4));
+        let db = database.db().lock().await;
+        let stored: i64 = db
+            .query_row("SELECT COUNT(*) FROM bridge_events", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, 5);
+    }
+
+    #[tokio::test]
+    async fn rolled_back_chunk_leaves_no_events() {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        database.insert_rollup(1, "test", None).await.unwrap();
+        let writer = spawn_writer(database.clone(), 1, Default::default(), 2);
+
+        // A window that failed after writing part of its first chunk.
+        send_bridge_event(&writer, 0).await;
+        send_bridge_event(&writer, 1).await;
+        writer.send(WriterMessage::Rollback).await.unwrap();
+
+        // Its retry stores the chunk once.
+        send_bridge_event(&writer, 0).await;
+        let (reply, written) = oneshot::channel();
+        writer
+            .send(WriterMessage::Commit(chunk_progress(0, 0), reply))
+            .await
+            .unwrap();
+        written.await.unwrap().unwrap();
+
+        assert_eq!(database.last_indexed_block(1).await.unwrap(), Some(0));
+        let db = database.db().lock().await;
+        let stored: Vec<u32> = db
+            .prepare("SELECT depositCount FROM bridge_events")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(stored, vec![0]);
+    }
+
+    #[tokio::test]
+    async fn retried_window_keeps_wrapped_tokens_once() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                index_transfers: false,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+        let event = NewWrappedToken {
+            originNetwork: 0,
+            originTokenAddress: Address::repeat_byte(0x11),
+            wrappedTokenAddress: Address::repeat_byte(0x22),
+            metadata: Bytes::new(),
+        };
+        let log = rpc_log(BRIDGE, event.encode_log_data(), 5, 0);
+        for _ in 0..2 {
+            asserter.push_success(&vec![log.clone()]);
+            indexer.index_window(0, 9, true).await.unwrap();
+        }
+        assert_eq!(
+            indexer.wrapped_token_addresses(),
+            vec![Address::repeat_byte(0x22)]
+        );
+    }
+
+    #[tokio::test]
+    async fn sink_gets_the_rows_of_stored_events() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                events: "bridge".parse().unwrap(),
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+        let sink = crate::sink::MemorySink::default();
+        indexer.sink = Some(Arc::new(sink.clone()));
+
+        asserter.push_success(&vec![bridge_log(5, 7)]);
+        indexer.index_window(0, 9, true).await.unwrap();
+
+        let published = sink.published();
+        assert_eq!(published.len(), 1);
+        let (rollup_id, payload) = &published[0];
+        assert_eq!(*rollup_id, 1);
+        let event: serde_json::Value = serde_json::from_slice(payload).unwrap();
+        assert_eq!(event["table"], "bridge_events");
+        assert_eq!(event["row"]["depositCount"], 7);
+        assert_eq!(event["row"]["block_number"], 5);
+
+        let db = indexer.database.db().lock().await;
+        let id: String = db
+            .query_row("SELECT id FROM bridge_events", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(event["row"]["id"], id);
+    }
+
+    #[tokio::test]
+    async fn shared_bridge_logs_are_partitioned_by_network() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                events: "bridge".parse().unwrap(),
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+        indexer
+            .database
+            .insert_rollup(2, "shared", None)
+            .await
+            .unwrap();
+        indexer.shared_rollups.lock().unwrap().push(2);
+
+        // Deposits to rollups 2 (shared), 3 (not shared) and 1.
+        let logs: Vec<Log> = [2, 3, 1]
+            .into_iter()
+            .enumerate()
+            .map(|(i, destination)| {
+                let event = BridgeEvent {
+                    leafType: 0,
+                    originNetwork: 0,
+                    originAddress: Address::ZERO,
+                    destinationNetwork: destination,
+                    destinationAddress: Address::repeat_byte(0x11),
+                    amount: U256::from(1),
+                    metadata: Bytes::new(),
+                    depositCount: i as u32,
+                };
+                rpc_log(BRIDGE, event.encode_log_data(), 5, i as u64)
+            })
+            .collect();
+        // A single eth_getLogs response: a second scan of the bridge would
+        // find the asserter empty and fail the window.
+        asserter.push_success(&logs);
+        indexer.index_window(0, 9, true).await.unwrap();
+
+        let db = indexer.database.db().lock().await;
+        let mut stmt = db
+            .prepare("SELECT depositCount, rollup_id FROM bridge_events ORDER BY depositCount")
+            .unwrap();
+        let stored: Vec<(u32, u32)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(stored, vec![(0, 2), (1, 1), (2, 1)]);
+    }
+
+    #[tokio::test]
+    async fn deploy_block_is_the_first_block_with_code() {
+        let asserter = Asserter::new();
+        let indexer = mock_indexer(&asserter, IndexerConfig::default()).await;
+        let code = Bytes::from(vec![0x60, 0x80]);
+
+        // Deployed at block 2 of 3: code at 3, none at 1, code at 2.
+        asserter.push_success(&U64::from(3));
+        asserter.push_success(&code);
+        asserter.push_success(&Bytes::new());
+        asserter.push_success(&code);
+        assert_eq!(indexer.detect_deploy_block().await.unwrap(), Some(2));
+
+        // No code at the head, the bridge is not deployed.
+        asserter.push_success(&U64::from(3));
+        asserter.push_success(&Bytes::new());
+        assert_eq!(indexer.detect_deploy_block().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn timestamps_resolve_to_the_first_block_at_or_after_them() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(&asserter, IndexerConfig::default()).await;
+        indexer.start_block = 10;
+        // Block n is at timestamp 10 * n, the cache saves mocking the blocks.
+        for block in 0..=100 {
+            indexer
+                .timestamp_cache
+                .lock()
+                .unwrap()
+                .put(block, block * 10);
+        }
+
+        for (timestamp, block) in [(505, 51), (510, 51), (0, 10), (5_000, 100)] {
+            asserter.push_success(&U64::from(100));
+            assert_eq!(indexer.block_at_timestamp(timestamp).await.unwrap(), block);
+        }
+    }
+
+    #[tokio::test]
+    async fn holder_transfers_skip_mints_and_burns() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                events: "holder".parse().unwrap(),
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+        let token = Address::repeat_byte(0x22);
+        let holder = Address::repeat_byte(0x33);
+        indexer
+            .wrapped_tokens
+            .push(WrappedToken::new(token, 0, Address::ZERO, &[]));
+
+        let transfer = |from, to, log_index| {
+            let event = Transfer {
+                from,
+                to,
+                value: U256::from(1),
+            };
+            rpc_log(token, event.encode_log_data(), 5, log_index)
+        };
+        asserter.push_success(&vec![
+            transfer(Address::ZERO, holder, 0),
+            transfer(holder, Address::repeat_byte(0x44), 1),
+            transfer(holder, Address::ZERO, 2),
+        ]);
+        indexer.index_window(0, 9, true).await.unwrap();
+
+        let db = indexer.database.db().lock().await;
+        let stored: Vec<(String, String)> = db
+            .prepare("SELECT from_address, to_address FROM holder_transfer_events")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            stored,
+            vec![(holder.to_string(), Address::repeat_byte(0x44).to_string())]
+        );
+    }
+
+    #[test]
+    fn logs_are_sorted_by_block_and_log_index() {
+        let logs = [
+            bridge_log(7, 0),
+            rpc_log(BRIDGE, LogData::default(), 5, 3),
+            rpc_log(BRIDGE, LogData::default(), 5, 1),
+            bridge_log(6, 1),
+        ];
+        let mut sorted: Vec<(LogKind, &Log)> = vec![
+            (LogKind::Bridge, &logs[0]),
+            (LogKind::BridgeIn, &logs[1]),
+            (LogKind::Bridge, &logs[2]),
+            (LogKind::BridgeOut, &logs[1]),
+            (LogKind::Bridge, &logs[3]),
+        ];
+        sort_logs(&mut sorted);
+        let order: Vec<_> = sorted
+            .iter()
+            .map(|(kind, log)| (format!("{:?}", kind), log_order(log)))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                ("Bridge".to_string(), (5, 1)),
+                // A log fetched by two filters keeps the order of its kinds.
+                ("BridgeIn".to_string(), (5, 3)),
+                ("BridgeOut".to_string(), (5, 3)),
+                ("Bridge".to_string(), (6, 0)),
+                ("Bridge".to_string(), (7, 0)),
+            ]
+        );
+    }
+
+    // A log with the Transfer signature and `topics` after it, without data.
+    fn transfer_topics_log(topics: &[B256], block: u64) -> Log {
+        let topics = std::iter::once(Transfer::SIGNATURE_HASH)
+            .chain(topics.iter().copied())
+            .collect();
+        let data = LogData::new_unchecked(topics, Bytes::new());
+        rpc_log(Address::repeat_byte(0x22), data, block, 0)
+    }
+
+    #[test]
+    fn decodes_transfers_with_an_indexed_value() {
+        let from = Address::repeat_byte(0x33);
+        let value = U256::from(5);
+        let standard = Transfer {
+            from,
+            to: BRIDGE,
+            value,
+        };
+        let dec = decode_transfer(&rpc_log(
+            Address::repeat_byte(0x22),
+            standard.encode_log_data(),
+            1,
+            0,
+        ))
+        .unwrap();
+        assert_eq!(
+            (dec.inner.from, dec.inner.to, dec.inner.value),
+            (from, BRIDGE, value)
+        );
+
+        let indexed = transfer_topics_log(
+            &[
+                from.into_word(),
+                BRIDGE.into_word(),
+                B256::from(value.to_be_bytes::<32>()),
+            ],
+            1,
+        );
+        let dec = decode_transfer(&indexed).unwrap();
+        assert_eq!(
+            (dec.inner.from, dec.inner.to, dec.inner.value),
+            (from, BRIDGE, value)
+        );
+
+        let without_value = transfer_topics_log(&[from.into_word(), BRIDGE.into_word()], 1);
+        assert!(decode_transfer(&without_value).is_err());
+    }
+
+    #[tokio::test]
+    async fn undecodable_bridge_in_logs_are_recorded() {
+        for strict_decode in [false, true] {
+            let asserter = Asserter::new();
+            let mut indexer = mock_indexer(
+                &asserter,
+                IndexerConfig {
+                    events: "transfer".parse().unwrap(),
+                    // Transfers out of the bridge are fetched first.
+                    per_rollup_concurrency: 1,
+                    strict_decode,
+                    ..IndexerConfig::default()
+                },
+            )
+            .await;
+            let undecodable = transfer_topics_log(
+                &[Address::repeat_byte(0x33).into_word(), BRIDGE.into_word()],
+                5,
+            );
+            asserter.push_success(&Vec::<Log>::new());
+            asserter.push_success(&vec![undecodable]);
+
+            let indexed = indexer.index_window(0, 9, true).await;
+            assert_eq!(indexed.is_err(), strict_decode);
+            let db = indexer.database.db().lock().await;
+            let errors: Vec<(String, i64)> = db
+                .prepare("SELECT kind, block_number FROM indexing_errors")
+                .unwrap()
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            // A failed chunk is rolled back along with its errors.
+            let expected = match strict_decode {
+                true => vec![],
+                false => vec![("BridgeIn".to_string(), 5)],
+            };
+            assert_eq!(errors, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn strict_decode_fails_the_window_on_a_decode_error() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                events: "transfer".parse().unwrap(),
+                per_rollup_concurrency: 1,
+                strict_decode: true,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+        let undecodable = transfer_topics_log(
+            &[Address::repeat_byte(0x33).into_word(), BRIDGE.into_word()],
+            5,
+        );
+        asserter.push_success(&Vec::<Log>::new());
+        asserter.push_success(&vec![undecodable]);
+
+        let error = indexer.index_window(0, 9, true).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("could not decode bridge in transfer"));
+        assert_eq!(indexer.database.last_indexed_block(1).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn token_backfill_waits_for_the_chunk_being_stored() {
+        let asserter = Asserter::new();
+        let indexer = mock_indexer(&asserter, IndexerConfig::default()).await;
+        let token = Address::repeat_byte(0x22);
+        let mint = Transfer {
+            from: Address::ZERO,
+            to: Address::repeat_byte(0x33),
+            value: U256::from(5),
+        };
+        asserter.push_success(&U64::from(9));
+        asserter.push_success(&vec![rpc_log(token, mint.encode_log_data(), 3, 0)]);
+        asserter.push_success(&Vec::<Log>::new());
+
+        // The index loop storing a chunk.
+        let writing = indexer.write_lock.clone().lock_owned().await;
+        let backfill = tokio::spawn({
+            let indexer = indexer.clone();
+            async move {
+                indexer
+                    .backfill_wrapped_token(token, 0)
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+        });
+        sleep(Duration::from_millis(100)).await;
+        assert!(!backfill.is_finished());
+
+        drop(writing);
+        assert_eq!(backfill.await.unwrap().unwrap(), 1);
+        let db = indexer.database.db().lock().await;
+        let stored: i64 = db
+            .query_row("SELECT COUNT(*) FROM wrapped_transfer_events", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(stored, 1);
+    }
+
+    #[tokio::test]
+    async fn shutdown_interrupts_the_index_loop() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(&asserter, IndexerConfig::default()).await;
+        // The head the loop starts from. The rpc fails from then on, and the
+        // indexer waits 5 seconds before retrying.
+        asserter.push_success(&U64::from(100));
+        let shutdown = indexer.shutdown.clone();
+        let index = tokio::spawn(async move { indexer.index().await.map_err(|e| e.to_string()) });
+
+        sleep(Duration::from_millis(100)).await;
+        shutdown.send_replace(true);
+        tokio::time::timeout(Duration::from_secs(1), index)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn dropping_the_guard_stops_the_indexer() {
+        let asserter = Asserter::new();
+        let indexer = mock_indexer(&asserter, IndexerConfig::default()).await;
+        let shutdown = indexer.shutdown.clone();
+        // Held by the test and by the task of the indexer.
+        let health = indexer.health.clone();
+        let guard = indexer.spawn();
+        assert_eq!(guard.rollup_id(), 1);
+        assert_eq!(Arc::strong_count(&health), 2);
+
+        drop(guard);
+        assert!(*shutdown.borrow());
+        // The aborted task drops the indexer.
+        for _ in 0..100 {
+            if Arc::strong_count(&health) == 1 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(Arc::strong_count(&health), 1);
+    }
+
+    #[tokio::test]
+    async fn wait_for_shutdown_resolves_once_stopped() {
+        let (sender, receiver) = watch::channel(false);
+        let waiting = tokio::spawn(wait_for_shutdown(receiver));
+        sleep(Duration::from_millis(10)).await;
+        assert!(!waiting.is_finished());
+        sender.send_replace(true);
+        waiting.await.unwrap();
+
+        // Also when the indexer is gone.
+        let (sender, receiver) = watch::channel(false);
+        drop(sender);
+        wait_for_shutdown(receiver).await;
+    }
+
+    #[test]
+    fn jitter_stays_within_its_fraction() {
+        let base = Duration::from_secs(5);
+        let jitter = |random| jittered(base, TIP_POLL_JITTER, random);
+        assert_eq!(jitter(0), Duration::from_secs(4));
+        assert_eq!(jitter(u64::MAX), Duration::from_secs(6));
+        let middle = jitter(u64::MAX / 2);
+        assert!(middle.as_secs_f64() - base.as_secs_f64() < 0.001);
+        assert!(base.as_secs_f64() - middle.as_secs_f64() < 0.001);
+        for _ in 0..100 {
+            let delay = jitter(random_u64());
+            assert!(delay >= Duration::from_secs(4) && delay <= Duration::from_secs(6));
+        }
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_events_are_replaced_on_each_pass() {
+        let asserter = Asserter::new();
+        let indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                confirmations: 10,
+                index_unconfirmed: true,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+        let stored = |table: &'static str| {
+            let database = indexer.database.clone();
+            async move {
+                let db = database.db().lock().await;
+                db.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                    row.get::<_, i64>(0)
+                })
+                .unwrap()
+            }
+        };
+
+        asserter.push_success(&U64::from(20));
+        asserter.push_success(&vec![bridge_log(15, 3)]);
+        indexer.index_unconfirmed(10).await.unwrap();
+        assert_eq!(stored("bridge_events_unconfirmed").await, 1);
+        assert_eq!(stored("bridge_events").await, 0);
+
+        // The deposit was reorged out.
+        asserter.push_success(&U64::from(21));
+        asserter.push_success(&Vec::<Log>::new());
+        indexer.index_unconfirmed(11).await.unwrap();
+        assert_eq!(stored("bridge_events_unconfirmed").await, 0);
+    }
+
+    #[tokio::test]
+    async fn address_filters_are_split_and_merged_in_order() {
+        let asserter = Asserter::new();
+        let indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                max_filter_addresses: 2,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+        let tokens: Vec<Address> = (1..=3).map(Address::repeat_byte).collect();
+        let mint = |token, block| {
+            let event = Transfer {
+                from: Address::ZERO,
+                to: Address::repeat_byte(0x33),
+                value: U256::from(1),
+            };
+            rpc_log(token, event.encode_log_data(), block, 0)
+        };
+
+        // One request for the first two tokens and one for the third.
+        asserter.push_success(&vec![mint(tokens[0], 7)]);
+        asserter.push_success(&vec![mint(tokens[2], 3)]);
+        let logs = indexer
+            .get_logs_chunked(&Filter::new(), &tokens)
+            .await
+            .unwrap();
+        let blocks: Vec<u64> = logs.iter().filter_map(|log| log.block_number).collect();
+        assert_eq!(blocks, vec![3, 7]);
+    }
+
+    #[tokio::test]
+    async fn address_filters_take_one_request_per_chunk() {
+        let asserter = Asserter::new();
+        // A limit of 0 is taken as 1 address per request.
+        let indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                max_filter_addresses: 0,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+        let tokens: Vec<Address> = (1..=3).map(Address::repeat_byte).collect();
+        for _ in &tokens {
+            asserter.push_success(&Vec::<Log>::new());
+        }
+        indexer
+            .get_logs_chunked(&Filter::new(), &tokens)
+            .await
+            .unwrap();
+        // Every response was taken, one more request finds none.
+        assert!(indexer.get_logs(&Filter::new()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rpc_calls_wait_for_the_budget_of_the_rollup() {
+        let asserter = Asserter::new();
+        let indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                per_rollup_concurrency: 1,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+        let permit = indexer.rpc_permits.clone().acquire_owned().await.unwrap();
+        asserter.push_success(&Vec::<Log>::new());
+        let filter = Filter::new();
+        let mut fetch = Box::pin(indexer.get_logs(&filter));
+        assert!(tokio::time::timeout(Duration::from_millis(50), &mut fetch)
+            .await
+            .is_err());
+
+        drop(permit);
+        assert!(fetch.await.unwrap().is_empty());
+        assert_eq!(indexer.rpc_permits.available_permits(), 1);
+    }
+
+    #[test]
+    fn parses_event_types() {
+        let events: IndexedEvents = "claim, bridge".parse().unwrap();
+        assert_eq!(events.to_string(), "bridge,claim");
+        assert!("".parse::<IndexedEvents>().is_err());
+        assert!(" , ".parse::<IndexedEvents>().is_err());
+        assert!("claim,deposit".parse::<IndexedEvents>().is_err());
+    }
+
+    #[test]
+    fn event_sets_cover_their_subsets() {
+        let claim: IndexedEvents = "claim".parse().unwrap();
+        let all = IndexedEvents::default();
+        assert!(all.covers(&claim));
+        assert!(claim.covers(&claim));
+        assert!(!claim.covers(&all));
+    }
+
+    #[tokio::test]
+    async fn newly_enabled_events_are_rescanned() {
+        let asserter = Asserter::new();
+        let indexer = mock_indexer(&asserter, IndexerConfig::default()).await;
+        let database = &indexer.database;
+        database
+            .insert_indexed_range(1, 0, 9, "bridge,claim,wrapped,transfer")
+            .await
+            .unwrap();
+        database
+            .insert_indexed_range(1, 10, 19, "claim")
+            .await
+            .unwrap();
+        database.synced_till_block(1, 19, false).await.unwrap();
+
+        indexer.check_events().await.unwrap();
+        assert_eq!(database.last_indexed_block(1).await.unwrap(), Some(9));
+        assert_eq!(database.last_indexed_range_end(1).await.unwrap(), Some(9));
+
+        // Nothing is left to rescan once the range is recorded again.
+        database
+            .insert_indexed_range(1, 10, 19, "bridge,claim,wrapped,transfer")
+            .await
+            .unwrap();
+        database.synced_till_block(1, 19, false).await.unwrap();
+        indexer.check_events().await.unwrap();
+        assert_eq!(database.last_indexed_block(1).await.unwrap(), Some(19));
+    }
+
+    #[tokio::test]
+    async fn rescan_reaches_block_zero() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                index_transfers: false,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+        indexer
+            .database
+            .synced_till_block(1, 5, false)
+            .await
+            .unwrap();
+
+        assert_eq!(indexer.request_rescan(100).await.unwrap(), 0);
+        assert_eq!(indexer.request_rescan(3).await.unwrap(), 3);
+
+        // The window [0, 0] is indexed again from a cleared marker.
+        indexer.request_rescan(6).await.unwrap();
+        asserter.push_success(&vec![bridge_log(0, 0)]);
+        asserter.push_success(&U64::from(10));
+        let mut last_processed_block = Some(5);
+        let mut latest_block = 0;
+        indexer
+            .index_step(&mut last_processed_block, &mut latest_block, &mut None, 0)
+            .await
+            .unwrap();
+        assert_eq!(last_processed_block, Some(0));
+        let db = indexer.database.db().lock().await;
+        let stored: i64 = db
+            .query_row("SELECT COUNT(*) FROM bridge_events", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, 1);
+    }
+
+    #[tokio::test]
+    async fn desc_backfill_walks_down_from_the_tip() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                index_transfers: false,
+                backfill_direction: BackfillDirection::Desc,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+
+        // A fresh rollup places both cursors at the tip.
+        let mut last_processed_block = None;
+        let mut backfilled_down_to = indexer
+            .init_backfill(&mut last_processed_block, 100)
+            .await
+            .unwrap();
+        assert_eq!(backfilled_down_to, Some(101));
+        assert_eq!(last_processed_block, Some(100));
+
+        // At the tip, each step indexes the window below the backward cursor.
+        let mut latest_block = 100;
+        for (logs, down) in [(vec![bridge_log(60, 0)], 51), (vec![], 1), (vec![], 0)] {
+            asserter.push_success(&logs);
+            asserter.push_success(&U64::from(100));
+            indexer
+                .index_step(
+                    &mut last_processed_block,
+                    &mut latest_block,
+                    &mut backfilled_down_to,
+                    49,
+                )
+                .await
+                .unwrap();
+            assert_eq!(backfilled_down_to, Some(down));
+            assert_eq!(
+                indexer.database.backfilled_down_to(1).await.unwrap(),
+                Some(down)
+            );
+        }
+        assert_eq!(
+            indexer.database.last_indexed_block(1).await.unwrap(),
+            Some(100)
+        );
+
+        // A restart resumes from the stored backward cursor.
+        let mut last_processed_block = Some(100);
+        assert_eq!(
+            indexer
+                .init_backfill(&mut last_processed_block, 200)
+                .await
+                .unwrap(),
+            Some(0)
+        );
+        let db = indexer.database.db().lock().await;
+        let stored: i64 = db
+            .query_row("SELECT COUNT(*) FROM bridge_events", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, 1);
+    }
+
+    #[tokio::test]
+    async fn window_logs_are_inserted_in_canonical_order() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                index_transfers: false,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+
+        let logs: Vec<Log> = [(7, 0), (3, 2), (3, 1), (1, 5)]
+            .into_iter()
+            .enumerate()
+            .map(|(deposit_count, (block, log_index))| {
+                let mut log = bridge_log(block, deposit_count as u32);
+                log.log_index = Some(log_index);
+                log.transaction_hash = Some(B256::repeat_byte(deposit_count as u8 + 1));
+                log
+            })
+            .collect();
+        asserter.push_success(&logs);
+        indexer.index_range(0, 10).await.unwrap();
+
+        let db = indexer.database.db().lock().await;
+        let mut stmt = db
+            .prepare("SELECT block_number, log_index FROM bridge_events ORDER BY rowid")
+            .unwrap();
+        let stored: Vec<(i64, i64)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(stored, vec![(1, 5), (3, 1), (3, 2), (7, 0)]);
+    }
+
+    #[tokio::test]
+    async fn logs_of_a_block_share_one_timestamp_fetch() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                index_transfers: false,
+                index_block_timestamps: true,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+
+        let mut second = bridge_log(5, 1);
+        second.log_index = Some(1);
+        asserter.push_success(&vec![bridge_log(5, 0), second]);
+        let mut block = alloy::rpc::types::Block::<alloy::rpc::types::Transaction>::default();
+        block.header.inner.number = 5;
+        block.header.inner.timestamp = 1_000;
+        // A second fetch would find no response and fail the window.
+        asserter.push_success(&block);
+        indexer.index_range(0, 10).await.unwrap();
+
+        // Later lookups are served from the cache too.
+        assert_eq!(indexer.block_timestamp(5).await.unwrap(), 1_000);
+        let db = indexer.database.db().lock().await;
+        let stored: i64 = db
+            .query_row("SELECT COUNT(*) FROM block_timestamps", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(stored, 1);
+    }
+
+    #[tokio::test]
+    async fn changed_endpoint_rebuilds_the_provider() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(&asserter, IndexerConfig::default()).await;
+        let upgraded = Address::repeat_byte(0xcc);
+        let sequencer: Url = "http://127.0.0.1:1".parse().unwrap();
+
+        // The watcher updates a clone, the index loop picks it up.
+        let watched = indexer.clone();
+        assert!(!watched.update_endpoint(BRIDGE, indexer.rpc_url.clone()));
+        assert!(watched.update_endpoint(upgraded, sequencer.clone()));
+        assert!(!watched.update_endpoint(upgraded, sequencer.clone()));
+
+        indexer.apply_endpoint_update();
+        assert_eq!(indexer.bridge_address, upgraded);
+        assert_eq!(indexer.rpc_url, sequencer);
+        // The mocked provider was replaced by one of the new url.
+        asserter.push_success(&U64::from(30));
+        assert!(indexer.provider.get_block_number().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn bridge_to_bridge_transfers_are_stored_once() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(&asserter, IndexerConfig::default()).await;
+        let token = Address::repeat_byte(0x22);
+        let holder = Address::repeat_byte(0x33);
+        let transfer = |from, to, log_index| {
+            let event = Transfer {
+                from,
+                to,
+                value: U256::from(1),
+            };
+            rpc_log(token, event.encode_log_data(), 5, log_index)
+        };
+        let transfers = vec![
+            transfer(BRIDGE, holder, 0),
+            transfer(holder, BRIDGE, 1),
+            transfer(BRIDGE, BRIDGE, 2),
+        ];
+
+        // Bridge logs, then the bridge out and bridge in filters. The bridge to
+        // bridge transfer matches both.
+        asserter.push_success(&Vec::<Log>::new());
+        asserter.push_success(&transfers);
+        asserter.push_success(&transfers);
+        indexer.index_range(0, 10).await.unwrap();
+
+        let db = indexer.database.db().lock().await;
+        let mut stmt = db
+            .prepare("SELECT log_index, direction FROM bridge_transfer_events ORDER BY log_index")
+            .unwrap();
+        let stored: Vec<(i64, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            stored,
+            vec![
+                (0, "out".to_string()),
+                (1, "in".to_string()),
+                (2, "self".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn bridge_only_mode_fetches_no_transfers() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                index_transfers: false,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+        indexer.wrapped_tokens.push(WrappedToken::new(
+            Address::repeat_byte(0x22),
+            0,
+            Address::repeat_byte(0x44),
+            &[],
+        ));
+
+        // Only the bridge logs are fetched, any other request would find no
+        // response and fail the window.
+        asserter.push_success(&vec![bridge_log(5, 0)]);
+        indexer.index_range(0, 10).await.unwrap();
+
+        let db = indexer.database.db().lock().await;
+        let stored: i64 = db
+            .query_row("SELECT COUNT(*) FROM bridge_events", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, 1);
+    }
+
+    #[tokio::test]
+    async fn zero_value_transfers_are_skipped() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                skip_zero_value_transfers: true,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+        let token = Address::repeat_byte(0x22);
+        let holder = Address::repeat_byte(0x33);
+        let transfers: Vec<Log> = [0u64, 5]
+            .into_iter()
+            .enumerate()
+            .map(|(log_index, value)| {
+                let event = Transfer {
+                    from: holder,
+                    to: BRIDGE,
+                    value: U256::from(value),
+                };
+                rpc_log(token, event.encode_log_data(), 5, log_index as u64)
+            })
+            .collect();
+
+        // Bridge logs, then the bridge out and bridge in filters.
+        asserter.push_success(&Vec::<Log>::new());
+        asserter.push_success(&Vec::<Log>::new());
+        asserter.push_success(&transfers);
+        indexer.index_range(0, 10).await.unwrap();
+
+        let db = indexer.database.db().lock().await;
+        let values: Vec<String> = db
+            .prepare("SELECT value FROM bridge_transfer_events")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(values, vec!["5".to_string()]);
+    }
+
+    // Rpc on localhost answering eth_blockNumber with 1 over keep-alive
+    // connections, counting the connections opened to it and keeping the
+    // (lowercased) head of every request.
+    async fn keep_alive_rpc(
+        connections: Arc<std::sync::atomic::AtomicUsize>,
+        heads: Arc<Mutex<Vec<String>>>,
+    ) -> Url {
+        use std::sync::atomic::Ordering;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                connections.fetch_add(1, Ordering::SeqCst);
+                let heads = heads.clone();
+                tokio::spawn(async move {
+                    let mut buf = Vec::new();
+                    let mut chunk = [0u8; 4096];
+                    loop {
+                        let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+                            match stream.read(&mut chunk).await {
+                                Ok(0) | Err(_) => return,
+                                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                            }
+                            continue;
+                        };
+                        let head = String::from_utf8_lossy(&buf[..end]).to_lowercase();
+                        let length: usize = head
+                            .lines()
+                            .find_map(|line| line.strip_prefix("content-length:"))
+                            .map_or(0, |len| len.trim().parse().unwrap());
+                        heads.lock().unwrap().push(head);
+                        while buf.len() < end + 4 + length {
+                            match stream.read(&mut chunk).await {
+                                Ok(0) | Err(_) => return,
+                                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                            }
+                        }
+                        let request: serde_json::Value =
+                            serde_json::from_slice(&buf[end + 4..end + 4 + length]).unwrap();
+                        buf.drain(..end + 4 + length);
+                        let body =
+                            json!({ "jsonrpc": "2.0", "id": request["id"], "result": "0x1" })
+                                .to_string();
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                            body.len(),
+                            body
+                        );
+                        if stream.write_all(response.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        url.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn idle_connections_are_reused_unless_disabled() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        for (max_idle, expected) in [(None, 1), (Some(0), 2)] {
+            let connections = Arc::new(AtomicUsize::new(0));
+            let url = keep_alive_rpc(connections.clone(), Default::default()).await;
+            let config = IndexerConfig {
+                rpc_pool_max_idle: max_idle,
+                rpc_timeout_secs: Some(5),
+                ..IndexerConfig::default()
+            };
+            let provider = build_provider(url, &config).unwrap();
+            for _ in 0..2 {
+                assert_eq!(provider.get_block_number().await.unwrap(), 1);
+            }
+            assert_eq!(
+                connections.load(Ordering::SeqCst),
+                expected,
+                "{:?}",
+                max_idle
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_events_of_the_rollup_manager_are_stored() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                index_transfers: false,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+        let rollup_manager = Address::repeat_byte(0xaa);
+        indexer.rollup_manager_address = Some(rollup_manager);
+
+        let sequenced = OnSequenceBatches {
+            rollupID: 1,
+            lastBatchSequenced: 7,
+        };
+        let verified = VerifyBatchesTrustedAggregator {
+            rollupID: 1,
+            numBatch: 6,
+            stateRoot: B256::repeat_byte(0x01),
+            exitRoot: B256::repeat_byte(0x02),
+            aggregator: Address::repeat_byte(0x03),
+        };
+        // Bridge logs, then the rollup manager ones.
+        asserter.push_success(&Vec::<Log>::new());
+        asserter.push_success(&vec![
+            rpc_log(rollup_manager, sequenced.encode_log_data(), 5, 0),
+            rpc_log(rollup_manager, verified.encode_log_data(), 6, 1),
+        ]);
+        indexer.index_range(0, 10).await.unwrap();
+
+        let db = indexer.database.db().lock().await;
+        let stored: Vec<(String, i64)> = db
+            .prepare("SELECT event, num_batch FROM verify_batches_events ORDER BY block_number")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            stored,
+            vec![
+                ("OnSequenceBatches".to_string(), 7),
+                ("VerifyBatchesTrustedAggregator".to_string(), 6)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn confirmations_are_not_reported_as_lag() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                confirmations: 12,
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+        indexer
+            .database
+            .synced_till_block(1, 88, false)
+            .await
+            .unwrap();
+
+        // Fully synced up to the confirmed head, 12 blocks behind the head.
+        asserter.push_success(&U64::from(100));
+        assert_eq!(indexer.distance_head().await.unwrap(), 0);
+        asserter.push_success(&U64::from(110));
+        assert_eq!(indexer.distance_head().await.unwrap(), 10);
+
+        // The pinned head caps the confirmed head.
+        indexer.pinned_head = Some(90);
+        asserter.push_success(&U64::from(110));
+        assert_eq!(indexer.confirmed_head().await.unwrap(), 90);
+    }
+
+    #[tokio::test]
+    async fn wrapped_mints_to_the_bridge_are_stored_once() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(&asserter, IndexerConfig::default()).await;
+        let token = Address::repeat_byte(0x22);
+        indexer
+            .wrapped_tokens
+            .push(WrappedToken::new(token, 0, Address::repeat_byte(0x44), &[]));
+        let event = Transfer {
+            from: Address::ZERO,
+            to: BRIDGE,
+            value: U256::from(1),
+        };
+        let mint = vec![rpc_log(token, event.encode_log_data(), 5, 0)];
+
+        // Bridge logs, then the mint, burn, bridge out and bridge in filters.
+        // The mint to the bridge matches both the mint and bridge in ones.
+        asserter.push_success(&Vec::<Log>::new());
+        asserter.push_success(&mint);
+        asserter.push_success(&Vec::<Log>::new());
+        asserter.push_success(&Vec::<Log>::new());
+        asserter.push_success(&mint);
+        indexer.index_range(0, 10).await.unwrap();
+
+        let db = indexer.database.db().lock().await;
+        let count = |table: &str| -> i64 {
+            db.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        assert_eq!(count("wrapped_transfer_events"), 1);
+        assert_eq!(count("bridge_transfer_events"), 0);
+    }
+
+    #[tokio::test]
+    async fn cursor_ahead_of_the_stored_data_is_walked_back() {
+        let asserter = Asserter::new();
+        let indexer = mock_indexer(&asserter, IndexerConfig::default()).await;
+        let database = &indexer.database;
+        database
+            .insert_indexed_range(1, 0, 50, "bridge")
+            .await
+            .unwrap();
+        database
+            .insert_bridge_event(&bridge_log(60, 0).log_decode().unwrap(), 1)
+            .await
+            .unwrap();
+        database.synced_till_block(1, 100, false).await.unwrap();
+
+        // The last stored log is past the last range.
+        indexer.check_cursor().await.unwrap();
+        assert_eq!(database.last_indexed_block(1).await.unwrap(), Some(60));
+
+        // A cursor behind the stored data is left alone.
+        database.synced_till_block(1, 55, true).await.unwrap();
+        indexer.check_cursor().await.unwrap();
+        assert_eq!(database.last_indexed_block(1).await.unwrap(), Some(55));
+    }
+
+    #[tokio::test]
+    async fn rpc_headers_are_sent_with_every_request() {
+        use alloy::transports::http::reqwest::header::{HeaderValue, AUTHORIZATION};
+
+        let heads = Arc::new(Mutex::new(Vec::new()));
+        let url = keep_alive_rpc(Default::default(), heads.clone()).await;
+        let mut value = HeaderValue::from_static("Bearer key");
+        value.set_sensitive(true);
+        let config = IndexerConfig {
+            rpc_headers: HeaderMap::from_iter([(AUTHORIZATION, value)]),
+            rpc_timeout_secs: Some(5),
+            ..IndexerConfig::default()
+        };
+        let provider = build_provider(url, &config).unwrap();
+        for _ in 0..2 {
+            assert_eq!(provider.get_block_number().await.unwrap(), 1);
+        }
+
+        let heads = heads.lock().unwrap();
+        assert_eq!(heads.len(), 2);
+        assert!(heads
+            .iter()
+            .all(|head| head.lines().any(|line| line == "authorization: bearer key")));
+    }
+
+    #[tokio::test]
+    async fn bridge_upgrades_are_stored_as_proxy_events() {
+        let asserter = Asserter::new();
+        let mut indexer = mock_indexer(
+            &asserter,
+            IndexerConfig {
+                events: "bridge".parse().unwrap(),
+                ..IndexerConfig::default()
+            },
+        )
+        .await;
+        let admin_changed = AdminChanged {
+            previousAdmin: Address::repeat_byte(0xa1),
+            newAdmin: Address::repeat_byte(0xa2),
+        };
+        let upgraded = Upgraded {
+            implementation: Address::repeat_byte(0xc1),
+        };
+        asserter.push_success(&vec![
+            rpc_log(BRIDGE, admin_changed.encode_log_data(), 5, 0),
+            rpc_log(BRIDGE, upgraded.encode_log_data(), 5, 1),
+        ]);
+        indexer.index_range(0, 10).await.unwrap();
+
+        let db = indexer.database.db().lock().await;
+        let mut stmt = db
+            .prepare(
+                "SELECT event, previous_admin, new_admin, implementation, contract_address
+                FROM proxy_events ORDER BY log_index",
+            )
+            .unwrap();
+        type Row = (
+            String,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            String,
+        );
+        let stored: Vec<Row> = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let address = |byte: u8| Some(Address::repeat_byte(byte).to_string());
+        assert_eq!(
+            stored,
+            vec![
+                (
+                    "AdminChanged".to_string(),
+                    address(0xa1),
+                    address(0xa2),
+                    None,
+                    BRIDGE.to_string()
+                ),
+                (
+                    "Upgraded".to_string(),
+                    None,
+                    None,
+                    address(0xc1),
+                    BRIDGE.to_string()
+                ),
+            ]
+        );
     }
 }
@@ -1,4 +1,7 @@
 pub mod contracts;
 pub mod database;
 pub mod indexer;
+pub mod models;
+pub mod sink;
+pub mod transport;
 pub mod utils;
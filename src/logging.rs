@@ -0,0 +1,117 @@
+use tracing::Subscriber;
+use tracing_subscriber::filter::{EnvFilter, ParseError};
+use tracing_subscriber::fmt::MakeWriter;
+
+// Subscriber writing the events allowed by `directives` to `writer`. The
+// directives are the ones of RUST_LOG: a default level followed by per module
+// levels, eg "info,daggboard::indexer=debug,daggboard::api=warn". With `json`
+// every event is a JSON object on its own line, with the fields of the spans
+// it happened in, eg the rollup and block range of an indexing window.
+pub fn subscriber<W>(
+    directives: &str,
+    json: bool,
+    writer: W,
+) -> Result<Box<dyn Subscriber + Send + Sync>, ParseError>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let filter = EnvFilter::try_new(directives)?;
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer);
+    Ok(match json {
+        true => Box::new(builder.json().with_current_span(true).finish()),
+        false => Box::new(builder.finish()),
+    })
+}
+
+// Logs to stdout for the rest of the process, see --log-level and
+// --json-logs.
+pub fn init(directives: &str, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    tracing::subscriber::set_global_default(subscriber(directives, json, std::io::stdout)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    // Writer keeping the logs in memory.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Captured;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    impl Captured {
+        fn logs(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn module_directives_filter_their_module_only() {
+        let captured = Captured::default();
+        let subscriber =
+            subscriber("info,daggboard::indexer=warn", false, captured.clone()).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: "daggboard::indexer", "indexed a window");
+            tracing::warn!(target: "daggboard::indexer", "rpc failed");
+            tracing::info!(target: "daggboard::api", "served a request");
+            tracing::debug!(target: "daggboard::api", "request details");
+        });
+
+        let logs = captured.logs();
+        assert!(!logs.contains("indexed a window"));
+        assert!(logs.contains("rpc failed"));
+        assert!(logs.contains("served a request"));
+        assert!(!logs.contains("request details"));
+    }
+
+    #[test]
+    fn invalid_directives_are_rejected() {
+        assert!(subscriber("daggboard::indexer=loud", false, Captured::default()).is_err());
+    }
+
+    #[test]
+    fn json_logs_carry_the_span_fields() {
+        let captured = Captured::default();
+        let subscriber = subscriber("info", true, captured.clone()).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            let window =
+                tracing::info_span!("window", rollup_id = 1, start_block = 10, end_block = 19);
+            let _entered = window.enter();
+            tracing::info!(target: "daggboard::indexer", "indexed a window");
+        });
+
+        let logs = captured.logs();
+        let lines: Vec<&str> = logs.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let log: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(log["level"], "INFO");
+        assert_eq!(log["target"], "daggboard::indexer");
+        assert_eq!(log["fields"]["message"], "indexed a window");
+        assert_eq!(log["span"]["name"], "window");
+        assert_eq!(log["span"]["rollup_id"], 1);
+        assert_eq!(log["span"]["start_block"], 10);
+        assert_eq!(log["span"]["end_block"], 19);
+    }
+}
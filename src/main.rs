@@ -1,46 +1,340 @@
 use alloy::primitives::address;
 use alloy::{
-    providers::ProviderBuilder, rpc::client::RpcClient, transports::layers::RetryBackoffLayer,
+    providers::{Provider, ProviderBuilder},
+    rpc::client::RpcClient,
+    transports::layers::RetryBackoffLayer,
 };
 use daggboard::contracts::{PolygonRollupBaseEtrog, PolygonRollupManager};
-use daggboard::database::Database;
-use daggboard::indexer::Indexer;
+use daggboard::database::{AmountType, Database, DbLocation, RollupMetadata};
+use daggboard::indexer::{BackfillDirection, IndexedEvents, Indexer, IndexerConfig};
+use daggboard::utils::redact_url;
 use eyre::Result;
 
-use alloy::primitives::Address;
-use alloy::transports::http::reqwest::Url;
-use clap::Parser;
+use alloy::primitives::{Address, I256};
+use alloy::transports::http::reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use alloy::transports::http::reqwest::{Client, Url};
+use clap::{Parser, Subcommand};
 
 use axum::{
     extract::{Query, State},
     http::StatusCode,
-    routing::get,
+    routing::{get, post},
     Json, Router,
 };
+use axum_server::tls_rustls::RustlsConfig;
 use hex;
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::{error, info, warn};
 
+mod alert;
 mod api;
+mod logging;
+mod selftest;
+mod verify;
 
 #[derive(Parser)]
 #[command(name = "daggboard")]
 #[command(about = "daggboard", long_about = None)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// RPC URL for the Ethereum L1 network. Example:
     /// https://mainnet.infura.io/v3/xxx
-    #[arg(long)]
-    rpc_url: String,
+    /// Falls back to DAGGBOARD_RPC_URL, which avoids leaking the key in the
+    /// process list. The flag takes precedence over the env var.
+    /// Required unless running a command.
+    #[arg(long, env = "DAGGBOARD_RPC_URL", hide_env_values = true)]
+    rpc_url: Option<String>,
 
     /// Contract address of the polygon aggregation layer rollup manager.
     /// Example: 0x5132A183E9F3CB7C848b0AAC5Ae0c4f0491B7aB2
-    #[arg(default_value = "0x5132A183E9F3CB7C848b0AAC5Ae0c4f0491B7aB2")]
+    /// Falls back to DAGGBOARD_ROLLUP_MANAGER, then to the default.
+    #[arg(
+        env = "DAGGBOARD_ROLLUP_MANAGER",
+        default_value = "0x5132A183E9F3CB7C848b0AAC5Ae0c4f0491B7aB2"
+    )]
     rollup_manager_address: String,
+
+    /// Max amount of addresses in a single eth_getLogs filter. Filters with
+    /// more addresses are split in chunks.
+    #[arg(long, default_value_t = 100)]
+    max_filter_addresses: usize,
+
+    /// Blocks after which the sync progress is persisted while indexing a
+    /// window, so that a restart resumes close to where it stopped.
+    #[arg(long, default_value_t = 1_000)]
+    progress_chunk_size: u64,
+
+    /// Consecutive indexing failures after which an indexer pauses until its
+    /// rpc recovers.
+    #[arg(long, default_value_t = 5)]
+    max_consecutive_failures: u32,
+
+    /// Seconds between rpc health probes while an indexer is paused.
+    #[arg(long, default_value_t = 30)]
+    health_probe_interval: u64,
+
+    /// Detect the block each bridge was deployed at and start indexing from
+    /// it. The detected block is stored, so it's only computed once. Requires
+    /// an rpc serving historical state.
+    #[arg(long)]
+    genesis_from_receipt: bool,
+
+    /// Idle connections kept open per rpc host by the indexers.
+    #[arg(long)]
+    rpc_pool_max_idle: Option<usize>,
+
+    /// Timeout in seconds of each rpc request made by the indexers.
+    #[arg(long)]
+    rpc_timeout: Option<u64>,
+
+    /// eth_getLogs requests each indexer has in flight at once. The filters
+    /// of a window (bridge, transfers, mints and burns) are fetched
+    /// concurrently up to it, lower it for strict rpcs.
+    #[arg(long, default_value_t = 2)]
+    per_rollup_concurrency: usize,
+
+    /// Order in which history is indexed: asc (from the start block up) or
+    /// desc (from the tip down, so recent activity is available first). Desc
+    /// only applies to rollups that were not indexed yet.
+    #[arg(long, default_value = "asc")]
+    backfill_direction: BackfillDirection,
+
+    /// Skip token transfers (wrapped mint/burn and bridge in/out) and only
+    /// index bridge events. Much lighter on the rpc, but balances are not
+    /// available.
+    #[arg(long)]
+    no_transfer_indexing: bool,
+
+    /// Comma separated event types to index: bridge, claim, wrapped (new
+    /// wrapped tokens and their mint/burn transfers), transfer (transfers in
+    /// and out of the bridge) and holder (transfers of wrapped tokens between
+    /// holders, see /holders). The others are neither fetched nor stored.
+    #[arg(long, default_value = "bridge,claim,wrapped,transfer")]
+    events: IndexedEvents,
+
+    /// Decoded events queued per rollup for the task that writes them, so
+    /// that slow database writes don't stall rpc fetching and the other way
+    /// around. 0 writes them inline.
+    #[arg(long, default_value_t = 1024)]
+    write_queue_capacity: usize,
+
+    /// At the tip, also index the bridge and claim events of the blocks
+    /// within --confirmations of the head into the bridge_events_unconfirmed
+    /// and claim_events_unconfirmed tables. They are replaced on every poll,
+    /// and the events move to the regular tables once confirmed.
+    #[arg(long)]
+    index_unconfirmed: bool,
+
+    /// Most blocks that a POST /indexer/{rollup_id}/rescan may index again,
+    /// larger requests are rejected.
+    #[arg(long, default_value_t = 100_000)]
+    max_rescan_blocks: u64,
+
+    /// Also publish every decoded event as json to a message bus, one topic
+    /// per rollup ({prefix}.{rollup_id}): kafka://broker[,broker..][/prefix]
+    /// or nats://server[/prefix]. Needs the sink-kafka or sink-nats feature.
+    #[arg(long, env = "DAGGBOARD_SINK", hide_env_values = true)]
+    sink: Option<String>,
+
+    /// Don't store transfers with a zero value. They don't affect balances
+    /// and are mostly spam.
+    #[arg(long)]
+    skip_zero_value_transfers: bool,
+
+    /// Also index the batch sequence and verification events of the rollup
+    /// manager on l1 into verify_batches_events, to monitor rollup liveness.
+    #[arg(long)]
+    index_verify_batches: bool,
+
+    /// Index the global exit root updates of l1 and check every claim
+    /// against the deposit its global index points to and the global exit
+    /// roots. Inconsistent claims are recorded in claim_anomalies, see
+    /// /anomalies.
+    #[arg(long)]
+    reorg_safe_l1: bool,
+
+    /// Fail the window on bridge in transfers that fail to decode, instead of
+    /// only logging them and recording them in indexing_errors.
+    #[arg(long)]
+    strict_decode: bool,
+
+    /// Blocks behind the head left unindexed to avoid indexing events that
+    /// are later reorged. Sync distances are reported against this depth.
+    #[arg(long, default_value_t = 0)]
+    confirmations: u64,
+
+    /// Store the timestamp of every block with indexed events in the
+    /// block_timestamps table. Costs one extra request per block if the rpc
+    /// doesn't include timestamps in the logs.
+    #[arg(long)]
+    index_block_timestamps: bool,
+
+    /// Block timestamps kept in memory, so logs of the same or recent blocks
+    /// don't fetch the block again.
+    #[arg(long, default_value_t = 1_024)]
+    max_block_range_cache: usize,
+
+    /// Seconds between re-resolving the sequencer url and bridge address of
+    /// each rollup, so that upgrades are picked up. 0 disables it.
+    #[arg(long, default_value_t = 3_600)]
+    resolve_interval: u64,
+
+    /// Start block used when the deploy block can't be detected.
+    #[arg(long, default_value_t = 0)]
+    default_start_block: u64,
+
+    /// Start indexing a rollup from the first block at or after a unix
+    /// timestamp, as rollup_id=timestamp. Can be repeated. Applied after the
+    /// deploy block detection, timestamps before the start block are ignored.
+    #[arg(long, value_parser = parse_rollup_kv::<u64>)]
+    start_timestamp: Vec<(u32, u64)>,
+
+    /// Stop indexing a rollup at a block, as rollup_id=block, so that the
+    /// database is a reproducible snapshot up to it. Can be repeated.
+    #[arg(long, value_parser = parse_rollup_kv::<u64>)]
+    pin_head: Vec<(u32, u64)>,
+
+    /// Name shown for a rollup instead of its on-chain network name, as
+    /// rollup_id=name. Can be repeated. The on-chain name is still stored.
+    #[arg(long, value_parser = parse_rollup_kv::<String>)]
+    rollup_name: Vec<(u32, String)>,
+
+    /// Sequencer rpc used for a rollup instead of its on-chain trusted
+    /// sequencer url, as rollup_id=url. Can be repeated. Rollups with an
+    /// empty or invalid url and no override are registered but not indexed.
+    #[arg(long, value_parser = parse_rollup_kv::<Url>)]
+    rollup_rpc: Vec<(u32, Url)>,
+
+    /// Header sent with every request to --rpc-url, as "Name: Value", for
+    /// providers that authenticate with a header instead of a key in the url.
+    /// Not sent to the rollup rpcs, see --rollup-rpc-header. Can be repeated.
+    #[arg(long, value_parser = parse_rpc_auth_header)]
+    rpc_auth_header: Vec<(HeaderName, HeaderValue)>,
+
+    /// Header sent with every request to the rpc of a rollup, as
+    /// "rollup_id=Name: Value". Can be repeated.
+    #[arg(long, value_parser = parse_rollup_kv::<RpcHeader>)]
+    rollup_rpc_header: Vec<(u32, RpcHeader)>,
+
+    /// Index each rollup into its own file, data-rollup-{id}.duckdb, instead
+    /// of a single data.duckdb. The api queries them combined.
+    #[arg(long)]
+    db_per_rollup: bool,
+
+    /// Check that the database files are complete and readable before
+    /// anything writes to them, eg after restoring them from a backup. Every
+    /// table is read, so it takes a while on big databases.
+    #[arg(long)]
+    resume_from_backup: bool,
+
+    /// Column type of the token amounts: varchar (exact), hugeint or
+    /// decimal38. Numeric types aggregate faster, but amounts out of their
+    /// range are stored as NULL and recorded in indexing_errors. Only applies
+    /// to newly created tables.
+    #[arg(long, default_value = "varchar")]
+    amount_type: AmountType,
+
+    /// Address the http api listens on.
+    #[arg(long, default_value = "0.0.0.0:3000")]
+    listen_addr: String,
+
+    /// PEM certificate (chain) to serve the api over https with, along with
+    /// --tls-key. HTTP/2 is negotiated with clients that support it. Plain
+    /// http if unset.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<std::path::PathBuf>,
+
+    /// PEM private key of --tls-cert.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<std::path::PathBuf>,
+
+    /// Optional address to serve /metrics and /health on, eg an internal only
+    /// port. If unset, they are served by the main api.
+    #[arg(long)]
+    metrics_addr: Option<String>,
+
+    /// Comma separated duckdb extensions to install and load at startup, eg
+    /// parquet,httpfs.
+    #[arg(long, value_delimiter = ',')]
+    duckdb_extensions: Vec<String>,
+
+    /// Api key required in the x-api-key header by the /admin endpoints.
+    /// Falls back to DAGGBOARD_ADMIN_API_KEY. The endpoints are disabled if
+    /// unset.
+    #[arg(long, env = "DAGGBOARD_ADMIN_API_KEY", hide_env_values = true)]
+    admin_api_key: Option<String>,
+
+    /// Max requests served at once by the api (all but /health and /metrics).
+    /// Requests over the limit get a 429. Unlimited if unset.
+    #[arg(long)]
+    api_max_concurrency: Option<usize>,
+
+    /// Max requests per second served by the api (all but /health and
+    /// /metrics). Requests over the limit get a 429. Unlimited if unset.
+    #[arg(long)]
+    api_rate_limit: Option<u32>,
+
+    /// Seconds the balance and stats responses are cached for. An entry is
+    /// dropped earlier when an event of its rollup is indexed. 0 disables
+    /// the cache.
+    #[arg(long, default_value_t = 10)]
+    api_cache_ttl: u64,
+
+    /// Output hex values (blobs, hashes, addresses) without the 0x prefix,
+    /// lowercase. Empty values stay 0x. The responses are rewritten, so they
+    /// are capped by --max-response-bytes.
+    #[arg(long)]
+    no_0x_prefix: bool,
+
+    /// Webhook to POST an alert to when an indexer stays behind the head by
+    /// more than --alert-lag-threshold blocks. No alerts if unset.
+    #[arg(long)]
+    alert_webhook: Option<String>,
+
+    /// Blocks behind the head after which an alert is sent.
+    #[arg(long, default_value_t = 10_000)]
+    alert_lag_threshold: u64,
+
+    /// Min seconds between two alerts for the same rollup.
+    #[arg(long, default_value_t = 3_600)]
+    alert_cooldown: u64,
+
+    /// Max size in bytes of the rows returned by /query, or by all the
+    /// queries of a /query/batch. Queries going over it fail with a 413
+    /// instead of building the whole response. Also caps the responses
+    /// re-encoded as msgpack. Unlimited if unset.
+    #[arg(long)]
+    max_response_bytes: Option<usize>,
+
+    /// Reject /query and /query/batch queries without a top level LIMIT, so
+    /// that clients can't run unbounded scans by mistake.
+    #[arg(long)]
+    require_limit: bool,
+
+    /// Log levels, a default one optionally followed by per module ones, eg
+    /// "info,daggboard::indexer=debug,daggboard::api=warn". See the README for
+    /// the modules.
+    #[arg(long, env = "RUST_LOG", default_value = "info")]
+    log_level: String,
+
+    /// Log JSON objects, one per line, for log collectors. The events of an
+    /// indexing window carry its rollup and block range as span fields.
+    #[arg(long)]
+    json_logs: bool,
 }
 
 #[derive(Clone)]
 struct AppState {
     database: Database,
+    max_response_bytes: Option<usize>,
+    require_limit: bool,
+    // Whether blobs are output 0x prefixed, see --no-0x-prefix.
+    hex_prefix: bool,
 }
 
 #[derive(Deserialize)]
@@ -52,8 +346,61 @@ async fn query_handler(
     State(state): State<AppState>,
     Query(params): Query<QueryParams>,
 ) -> std::result::Result<Json<Vec<HashMap<String, String>>>, (StatusCode, String)> {
-    println!("----debuggg");
     let query = params.q;
+    info!("query: {}", query);
+    check_read_only(&query)?;
+
+    // Acquire DB connection
+    let conn = state.database.db().lock().await;
+    if state.require_limit {
+        check_limit(&conn, &query)?;
+    }
+    let mut budget = state.max_response_bytes;
+    run_query(&conn, &query, &[], &mut budget, state.hex_prefix).map(Json)
+}
+
+#[derive(Deserialize)]
+struct BatchQuery {
+    q: String,
+    #[serde(default)]
+    params: Vec<String>,
+}
+
+// Runs several read only queries on a single acquired connection, eg for the
+// panels of a dashboard. Fails as a whole if any of them fails.
+async fn query_batch_handler(
+    State(state): State<AppState>,
+    Json(queries): Json<Vec<BatchQuery>>,
+) -> std::result::Result<Json<Vec<Vec<HashMap<String, String>>>>, (StatusCode, String)> {
+    for query in queries.iter() {
+        info!("batch query: {}", query.q);
+        check_read_only(&query.q)?;
+    }
+
+    let conn = state.database.db().lock().await;
+    if state.require_limit {
+        for (i, query) in queries.iter().enumerate() {
+            check_limit(&conn, &query.q)
+                .map_err(|(status, e)| (status, format!("query {}: {}", i, e)))?;
+        }
+    }
+    let mut budget = state.max_response_bytes;
+    let mut results = Vec::with_capacity(queries.len());
+    for (i, query) in queries.iter().enumerate() {
+        let rows = run_query(
+            &conn,
+            &query.q,
+            &query.params,
+            &mut budget,
+            state.hex_prefix,
+        )
+        .map_err(|(status, e)| (status, format!("query {}: {}", i, e)))?;
+        results.push(rows);
+    }
+    Ok(Json(results))
+}
+
+fn check_read_only(query: &str) -> std::result::Result<(), (StatusCode, String)> {
     let lowered = query.to_lowercase();
     // Disallow mutating queries
     let prohibited = [
@@ -65,10 +412,70 @@ async fn query_handler(
             "Mutating queries are not allowed".to_string(),
         ));
     }
+    Ok(())
+}
 
-    // Acquire DB connection
-    let conn = state.database.db().lock().await;
-    let mut stmt = match conn.prepare(&query) {
+// Fails unless every statement of the query is a SELECT with a LIMIT on its
+// outermost level, see --require-limit. The query is parsed by duckdb itself
+// (json_serialize_sql), which also fails on anything else than a SELECT.
+fn check_limit(
+    conn: &duckdb::Connection,
+    query: &str,
+) -> std::result::Result<(), (StatusCode, String)> {
+    let rejected = || {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "Only SELECT queries with a LIMIT are allowed (--require-limit)".to_string(),
+        ))
+    };
+    let ast: String = conn
+        .query_row("SELECT json_serialize_sql(?::VARCHAR)", [query], |row| {
+            row.get(0)
+        })
+        .map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Failed to parse query: {}", e),
+            )
+        })?;
+    let ast: serde_json::Value = serde_json::from_str(&ast)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if ast["error"].as_bool() != Some(false) {
+        return rejected();
+    }
+    let Some(statements) = ast["statements"].as_array() else {
+        return rejected();
+    };
+    let limited = statements.iter().all(|statement| {
+        statement["node"]["modifiers"]
+            .as_array()
+            .is_some_and(|modifiers| {
+                modifiers.iter().any(|m| {
+                    matches!(
+                        m["type"].as_str(),
+                        Some("LIMIT_MODIFIER" | "LIMIT_PERCENT_MODIFIER")
+                    )
+                })
+            })
+    });
+    if statements.is_empty() || !limited {
+        return rejected();
+    }
+    Ok(())
+}
+
+// Runs a query binding `params` in order, with every value as a string.
+// `budget` is the bytes left for the response (see --max-response-bytes),
+// reduced by the json size of every row read. The query is abandoned as soon
+// as a row doesn't fit. Blobs are hex encoded, 0x prefixed if `hex_prefix`.
+fn run_query(
+    conn: &duckdb::Connection,
+    query: &str,
+    params: &[String],
+    budget: &mut Option<usize>,
+    hex_prefix: bool,
+) -> std::result::Result<Vec<HashMap<String, String>>, (StatusCode, String)> {
+    let mut stmt = match conn.prepare(query) {
         Ok(s) => s,
         Err(e) => {
             return Err((
@@ -78,7 +485,7 @@ async fn query_handler(
         }
     };
 
-    let mut rows = match stmt.query([]) {
+    let mut rows = match stmt.query(duckdb::params_from_iter(params.iter())) {
         Ok(r) => r,
         Err(e) => {
             return Err((
@@ -114,8 +521,11 @@ async fn query_handler(
             } else if let Ok(v) = row.get::<usize, f64>(i) {
                 v.to_string()
             } else if let Ok(v) = row.get::<usize, Vec<u8>>(i) {
-                // blob
-                hex::encode(v)
+                // blob, an empty one keeps its 0x to not read as missing.
+                match hex_prefix || v.is_empty() {
+                    true => format!("0x{}", hex::encode(v)),
+                    false => hex::encode(v),
+                }
             } else if let Ok(v) = row.get::<usize, Option<String>>(i) {
                 v.unwrap_or_default()
             } else {
@@ -123,37 +533,484 @@ async fn query_handler(
             };
             map.insert(col.clone(), value);
         }
+        if let Some(left) = budget {
+            // Quotes, colon and comma around every field, braces around the row.
+            let size: usize = map
+                .iter()
+                .map(|(k, v)| k.len() + v.len() + 6)
+                .sum::<usize>()
+                + 2;
+            *left = left.checked_sub(size).ok_or_else(|| {
+                (
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!(
+                        "Response larger than the limit after {} rows, select fewer columns or paginate with LIMIT and OFFSET",
+                        results.len()
+                    ),
+                )
+            })?;
+        }
         results.push(map);
     }
 
-    Ok(Json(results))
+    Ok(results)
+}
+
+// Bridge indexed as rollup 0.
+// TODO: Should not be hardcoded
+const L1_BRIDGE_ADDRESS: Address = address!("0x2a3dd3eb832af982ec71669e178424b10dca2ede");
+
+// What the rollup manager and the rollup contract report about a rollup.
+struct ResolvedRollup {
+    name: String,
+    bridge_address: Address,
+    // None when the contract reports an empty or invalid url.
+    trusted_seq: Option<Url>,
+    metadata: RollupMetadata,
+}
+
+async fn resolve_rollup<P: Provider + Clone>(
+    provider: &P,
+    rollup_manager_address: Address,
+    rollup_id: u32,
+) -> Result<ResolvedRollup, Box<dyn std::error::Error>> {
+    let rollup_manager = PolygonRollupManager::new(rollup_manager_address, provider.clone());
+    let rollup = rollup_manager
+        .rollupIDToRollupData(rollup_id)
+        .call()
+        .await?;
+
+    let metadata = RollupMetadata {
+        rollup_address: rollup.rollupContract,
+        chain_id: rollup.chainID,
+        fork_id: rollup.forkID,
+        rollup_type_id: rollup.rollupTypeID,
+        verifier_type: rollup.rollupVerifierType as u8,
+        verifier: rollup.verifier,
+    };
+
+    let base_etrog = PolygonRollupBaseEtrog::new(rollup.rollupContract, provider.clone());
+    let trusted_seq_str = base_etrog.trustedSequencerURL().call().await?;
+    let mut name = base_etrog.networkName().call().await?;
+    let bridge_address_str = base_etrog.bridgeAddress().call().await?.to_string();
+
+    // Some rollups are misconfigured on-chain, don't let them stop the others.
+    if name.trim().is_empty() {
+        name = format!("rollup-{}", rollup_id);
+        warn!(rollup_id, "empty network name, using {:?}", name);
+    }
+    let trusted_seq = match trusted_seq_str.trim().parse::<Url>() {
+        Ok(url) => Some(url),
+        Err(e) => {
+            warn!(
+                rollup_id,
+                "invalid trusted sequencer url {:?}: {}", trusted_seq_str, e
+            );
+            None
+        }
+    };
+
+    Ok(ResolvedRollup {
+        name,
+        bridge_address: bridge_address_str.parse::<Address>()?,
+        trusted_seq,
+        metadata,
+    })
+}
+
+// Re-resolves the sequencer url and bridge of every rollup every `interval`,
+// so that the indexers follow upgrades. The l1 (rollup 0) is never resolved.
+// Rollups in `rpc_overrides` (see --rollup-rpc) keep their url and only follow
+// the bridge address.
+async fn watch_endpoints<P: Provider + Clone>(
+    provider: P,
+    rollup_manager_address: Address,
+    indexers: Vec<Indexer>,
+    rpc_overrides: HashMap<u32, Url>,
+    interval: std::time::Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        for indexer in indexers.iter().filter(|i| i.rollup_id != 0) {
+            let resolved = resolve_rollup(&provider, rollup_manager_address, indexer.rollup_id)
+                .await
+                .map_err(|e| e.to_string());
+            match resolved {
+                Ok(resolved) => {
+                    let rpc_url = rpc_overrides
+                        .get(&indexer.rollup_id)
+                        .cloned()
+                        .or(resolved.trusted_seq);
+                    // Without a usable url the current endpoint is kept.
+                    if let Some(rpc_url) = rpc_url {
+                        indexer.update_endpoint(resolved.bridge_address, rpc_url);
+                    }
+                }
+                Err(e) => error!(
+                    rollup_id = indexer.rollup_id,
+                    "Error resolving rollup: {}", e
+                ),
+            }
+        }
+    }
+}
+
+// Maintenance commands, they run against the database and exit instead of
+// indexing.
+#[derive(Subcommand)]
+enum Command {
+    /// Rebuilds the token_balances table from the full transfer history and
+    /// reports how many balances didn't match. Bridge transfers also stored
+    /// as wrapped mint/burn by older versions are deleted first.
+    Recompute,
+    /// Exports every table to partitioned parquet files with a manifest of
+    /// the block ranges, resuming a previous snapshot to the same target.
+    Snapshot {
+        /// Local directory or s3://bucket/prefix to write to.
+        #[arg(long)]
+        to: String,
+    },
+    /// Loads a snapshot, or a single parquet or json lines file, into the
+    /// database and moves the sync cursors past the imported blocks.
+    Import {
+        /// Target of a snapshot, or a .parquet, .ndjson or .jsonl file.
+        #[arg(long)]
+        from: String,
+        /// Table a single file is imported into. Defaults to the file name.
+        #[arg(long)]
+        table: Option<String>,
+    },
+    /// Checks that bridge balances reconcile with the circulating supply,
+    /// that there are no coverage gaps and that no event lacks a required
+    /// field. Exits with an error if any check fails.
+    Verify {
+        /// Difference between a bridge balance and the circulating supply of
+        /// the token that is still accepted, in token units.
+        #[arg(long, default_value = "0")]
+        tolerance: I256,
+    },
+    /// Indexes a block range of a rollup into an in-memory database and
+    /// checks that events were found, that they decoded and that the
+    /// balances reconcile. Exits with an error if any check fails. The range
+    /// should cover the wrapping of the tokens it bridges.
+    Selftest {
+        #[arg(long)]
+        rollup_id: u32,
+        /// First block of the range.
+        #[arg(long)]
+        from: u64,
+        /// Last block of the range, included.
+        #[arg(long)]
+        to: u64,
+    },
+}
+
+// Parses the value of a per rollup option, rollup_id=value, such as
+// --start-timestamp or --rollup-rpc.
+fn parse_rollup_kv<T>(value: &str) -> Result<(u32, T), String>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let (rollup_id, rollup_value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected rollup_id=value, got {}", value))?;
+    let rollup_id = rollup_id
+        .parse::<u32>()
+        .map_err(|e| format!("invalid rollup id {}: {}", rollup_id, e))?;
+    let rollup_value = rollup_value
+        .parse::<T>()
+        .map_err(|e| format!("invalid value {}: {}", rollup_value, e))?;
+    Ok((rollup_id, rollup_value))
+}
+
+// The value of a per rollup option for a rollup, if any.
+fn rollup_value<T>(values: &[(u32, T)], rollup_id: u32) -> Option<&T> {
+    values
+        .iter()
+        .find(|(id, _)| *id == rollup_id)
+        .map(|(_, value)| value)
+}
+
+// Registers a rollup that gets no indexer, so that it still shows in the api.
+async fn register_unindexed(
+    database: &Database,
+    cli: &Cli,
+    rollup_id: u32,
+    name: &str,
+    metadata: Option<&RollupMetadata>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let database = database.for_rollup(rollup_id).await?;
+    database
+        .insert_rollup(rollup_id, name, rollup_name(cli, rollup_id))
+        .await?;
+    if let Some(metadata) = metadata {
+        database.update_rollup_metadata(rollup_id, metadata).await?;
+    }
+    Ok(())
+}
+
+// The --rollup-name of a rollup, if any.
+fn rollup_name(cli: &Cli, rollup_id: u32) -> Option<&str> {
+    rollup_value(&cli.rollup_name, rollup_id).map(String::as_str)
+}
+
+// Parses a --rpc-auth-header value, "Name: Value". The value is marked
+// sensitive so that it's redacted when the headers are debug printed.
+fn parse_rpc_auth_header(value: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, header_value) = value
+        .split_once(':')
+        .ok_or_else(|| "expected Name: Value".to_string())?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|e| format!("invalid header name {}: {}", name.trim(), e))?;
+    let mut header_value = HeaderValue::from_str(header_value.trim())
+        .map_err(|e| format!("invalid value for header {}: {}", name, e))?;
+    header_value.set_sensitive(true);
+    Ok((name, header_value))
+}
+
+// Header of a --rollup-rpc-header value, parsed as a --rpc-auth-header one.
+#[derive(Clone, Debug)]
+struct RpcHeader(HeaderName, HeaderValue);
+
+impl FromStr for RpcHeader {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (name, header_value) = parse_rpc_auth_header(value)?;
+        Ok(RpcHeader(name, header_value))
+    }
+}
+
+// Headers sent to the rpc of a rollup: the --rpc-auth-header ones to the l1
+// rpc and the --rollup-rpc-header ones of the rollup to its rpc, so that
+// credentials only reach the rpc they were given for.
+fn rpc_headers(cli: &Cli, rollup_id: u32) -> HeaderMap {
+    if rollup_id == 0 {
+        return cli.rpc_auth_header.iter().cloned().collect();
+    }
+    cli.rollup_rpc_header
+        .iter()
+        .filter(|(id, _)| *id == rollup_id)
+        .map(|(_, RpcHeader(name, value))| (name.clone(), value.clone()))
+        .collect()
+}
+
+// Files of the database: the one of DbLocation::default(), or the
+// data-rollup-{id}.duckdb of every rollup with --db-per-rollup.
+fn database_files(per_rollup: bool) -> std::io::Result<Vec<PathBuf>> {
+    if !per_rollup {
+        return Ok(match DbLocation::default() {
+            DbLocation::File(path) => vec![path],
+            DbLocation::Memory => Vec::new(),
+        });
+    }
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(".")? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        if name.starts_with("data-rollup-") && name.ends_with(".duckdb") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Indexer settings of the command line. The rpc headers depend on the rollup,
+// see rpc_headers.
+fn indexer_config(cli: &Cli) -> IndexerConfig {
+    IndexerConfig {
+        max_filter_addresses: cli.max_filter_addresses,
+        progress_chunk_size: cli.progress_chunk_size,
+        max_consecutive_failures: cli.max_consecutive_failures,
+        health_probe_interval_secs: cli.health_probe_interval,
+        rpc_pool_max_idle: cli.rpc_pool_max_idle,
+        rpc_timeout_secs: cli.rpc_timeout,
+        per_rollup_concurrency: cli.per_rollup_concurrency,
+        rpc_headers: HeaderMap::new(),
+        backfill_direction: cli.backfill_direction,
+        index_transfers: !cli.no_transfer_indexing,
+        skip_zero_value_transfers: cli.skip_zero_value_transfers,
+        index_block_timestamps: cli.index_block_timestamps,
+        block_timestamp_cache_size: cli.max_block_range_cache,
+        confirmations: cli.confirmations,
+        strict_decode: cli.strict_decode,
+        events: cli.events,
+        write_queue_capacity: cli.write_queue_capacity,
+        index_unconfirmed: cli.index_unconfirmed,
+        max_rescan_blocks: cli.max_rescan_blocks,
+        reorg_safe_l1: cli.reorg_safe_l1,
+    }
+}
+
+// Certificate and key of --tls-cert and --tls-key, None to serve plain http.
+async fn tls_config(cli: &Cli) -> std::io::Result<Option<RustlsConfig>> {
+    match (&cli.tls_cert, &cli.tls_key) {
+        (Some(cert), Some(key)) => {
+            // rustls can't choose between the providers enabled by the
+            // dependencies. Fails if one was already installed, which is fine.
+            let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+            Ok(Some(RustlsConfig::from_pem_file(cert, key).await?))
+        }
+        _ => Ok(None),
+    }
+}
+
+// Serves the api on `listener` in the background, over https if `tls` is set.
+fn spawn_api(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    tls: Option<RustlsConfig>,
+) -> std::io::Result<()> {
+    match tls {
+        Some(tls) => {
+            let server = axum_server::from_tcp_rustls(listener.into_std()?, tls)
+                .serve(app.into_make_service());
+            tokio::spawn(async move {
+                if let Err(e) = server.await {
+                    error!("HTTPS server error: {}", e);
+                }
+            });
+        }
+        None => {
+            let server = axum::serve(listener, app);
+            tokio::spawn(async move {
+                if let Err(e) = server.await {
+                    error!("HTTP server error: {}", e);
+                }
+            });
+        }
+    }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
+    logging::init(&cli.log_level, cli.json_logs)?;
+
+    // Fail fast rather than silently not decoding logs.
+    daggboard::contracts::check_abis()?;
+
+    // Before opening the database, which may be locked by a running indexer.
+    if let Some(Command::Selftest {
+        rollup_id,
+        from,
+        to,
+    }) = &cli.command
+    {
+        let checks = selftest::selftest(&cli, *rollup_id, *from, *to).await?;
+        for (passed, check) in checks.iter() {
+            println!("{} {}", if *passed { "PASS" } else { "FAIL" }, check);
+        }
+        if checks.iter().any(|(passed, _)| !passed) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if cli.resume_from_backup {
+        for path in database_files(cli.db_per_rollup)? {
+            daggboard::database::check_integrity(&path)?;
+            println!("Database {} is readable", path.display());
+        }
+    }
 
     // Initialize the database connection
-    let database = Database::new(false).await?;
+    let database = if cli.db_per_rollup {
+        Database::new_combined(Path::new("."), cli.amount_type).await?
+    } else {
+        Database::open(DbLocation::default(), cli.amount_type).await?
+    };
+    if !cli.duckdb_extensions.is_empty() {
+        database.load_extensions(&cli.duckdb_extensions).await?;
+        info!("Loaded duckdb extensions: {:?}", cli.duckdb_extensions);
+    }
+
+    if let Some(Command::Snapshot { to }) = &cli.command {
+        let written = database.snapshot(to).await?;
+        println!("Snapshot to {} done, {} partitions written", to, written);
+        return Ok(());
+    }
+
+    if let Some(Command::Import { from, table }) = &cli.command {
+        if database.per_rollup() {
+            return Err("import is not supported with --db-per-rollup".into());
+        }
+        let imported = database.import(from, table.as_deref()).await?;
+        println!("Imported {} rows from {}", imported, from);
+        return Ok(());
+    }
+
+    if let Some(Command::Verify { tolerance }) = &cli.command {
+        if database.per_rollup() {
+            return Err("verify is not supported with --db-per-rollup".into());
+        }
+        let failures = verify::verify(&database, *tolerance).await?;
+        if failures.is_empty() {
+            println!("All checks passed");
+            return Ok(());
+        }
+        for failure in failures.iter() {
+            println!("{}", failure);
+        }
+        eprintln!("{} checks failed", failures.len());
+        std::process::exit(1);
+    }
+
+    if let Some(Command::Recompute) = cli.command {
+        if database.per_rollup() {
+            return Err("recompute is not supported with --db-per-rollup".into());
+        }
+        let removed = database.remove_duplicate_transfers().await?;
+        if removed > 0 {
+            println!(
+                "Removed {} bridge transfers stored as wrapped mint/burn",
+                removed
+            );
+        }
+        let mismatches = database.recompute_token_balances().await?;
+        println!("Recomputed token balances, {} did not match", mismatches);
+        return Ok(());
+    }
 
-    println!("Starting agglayer-indexer");
+    info!("Starting agglayer-indexer");
 
     // Use the rpc_url from the command line arguments
-    let rpc_url: Url = cli.rpc_url.parse()?;
+    let rpc_url: Url = cli
+        .rpc_url
+        .as_deref()
+        .ok_or("--rpc-url (or DAGGBOARD_RPC_URL) is required")?
+        .parse()?;
     let rollup_manager_address: Address = cli.rollup_manager_address.parse()?;
 
-    println!("Using rpc url: {:?}", rpc_url.as_str());
-    println!("Using rollup manager address: {:?}", rollup_manager_address);
+    info!("Using rpc url: {:?}", redact_url(&rpc_url));
+    info!("Using rollup manager address: {:?}", rollup_manager_address);
 
     let max_retry = 100;
     let backoff = 2000;
     let cups = 100;
 
+    let l1_headers = rpc_headers(&cli, 0);
+    if !l1_headers.is_empty() {
+        info!(
+            "Sending rpc headers: {:?}",
+            l1_headers.keys().collect::<Vec<_>>()
+        );
+    }
+
     // TODO: This just retries on rate limit errors.
-    let provider = ProviderBuilder::new().on_client(
+    let provider = ProviderBuilder::new().connect_client(
         RpcClient::builder()
             .layer(RetryBackoffLayer::new(max_retry, backoff, cups))
-            .http(rpc_url.clone()),
+            .http_with_client(
+                Client::builder().default_headers(l1_headers).build()?,
+                rpc_url.clone(),
+            ),
     );
 
     let rollup_manager = PolygonRollupManager::new(rollup_manager_address, provider.clone());
@@ -163,13 +1020,42 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // TODO remove for tests.
     //let rollup_count = 2;
 
-    println!("rollup count: {:?}", rollup_count);
+    info!("rollup count: {:?}", rollup_count);
+
+    let ger_manager_address = if cli.reorg_safe_l1 {
+        let address = rollup_manager.globalExitRootManager().call().await?;
+        info!("Indexing global exit roots from: {:?}", address);
+        Some(address)
+    } else {
+        None
+    };
+
+    let indexer_config = indexer_config(&cli);
+
+    let sink = match &cli.sink {
+        Some(url) => {
+            let sink = daggboard::sink::connect(url).await?;
+            info!(
+                "Publishing events to a {} sink",
+                url.split("://").next().unwrap_or_default()
+            );
+            Some(sink)
+        }
+        None => None,
+    };
 
     let mut bridge_address;
     let mut trusted_seq;
     let mut name;
+    let mut metadata;
 
-    let mut indexers = Vec::new();
+    let mut indexers: Vec<Indexer> = Vec::new();
+    // Rollups in the database, including the ones without an indexer.
+    let mut registered = Vec::new();
+    // Rollup indexing each (chain id, bridge address).
+    let mut shared_bridges: HashMap<(u64, Address), u32> = HashMap::new();
+    // Dropping a guard stops its indexer.
+    let mut guards = Vec::new();
     for rollup_id in 0..=rollup_count {
         // We consider rollup 0 as layer 1
         //if rollup_id != 3 && rollup_id != 1 {
@@ -178,87 +1064,566 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         if rollup_id == 0 {
             name = "l1".to_string();
             trusted_seq = rpc_url.clone();
-            // TODO: Should not be hardcoded
-            bridge_address = address!("0x2a3dd3eb832af982ec71669e178424b10dca2ede");
+            bridge_address = L1_BRIDGE_ADDRESS;
+            metadata = None;
         } else {
-            let rollup = rollup_manager
-                .rollupIDToRollupData(rollup_id)
-                .call()
-                .await?;
-
-            let base_etrog = PolygonRollupBaseEtrog::new(rollup.rollupContract, provider.clone());
-            let trusted_seq_str = base_etrog.trustedSequencerURL().call().await?;
-            name = base_etrog.networkName().call().await?;
-            let bridge_address_str = base_etrog.bridgeAddress().call().await?.to_string();
-            bridge_address = bridge_address_str.parse::<Address>()?;
-            trusted_seq = trusted_seq_str.parse::<Url>()?;
-            println!("trusted_seq: {:?}", trusted_seq);
+            let resolved = resolve_rollup(&provider, rollup_manager_address, rollup_id).await?;
+            name = resolved.name;
+            bridge_address = resolved.bridge_address;
+            metadata = Some(resolved.metadata);
+            let rpc_override = rollup_value(&cli.rollup_rpc, rollup_id).cloned();
+            trusted_seq = match rpc_override.or(resolved.trusted_seq) {
+                Some(url) => url,
+                None => {
+                    // Register the rollup so it shows in the api, but there is
+                    // nothing to index transfers and bridges from.
+                    warn!(
+                        rollup_id,
+                        "{:?}: no usable sequencer url, not indexing it (set --rollup-rpc {}=<url>)",
+                        name,
+                        rollup_id
+                    );
+                    register_unindexed(&database, &cli, rollup_id, &name, metadata.as_ref())
+                        .await?;
+                    registered.push(rollup_id);
+                    continue;
+                }
+            };
+            // Rollups with the same chain and bridge would scan the same logs.
+            // The indexer of the first one scans them once, and stores the
+            // events of the others under their rollup, see shared_rollups.
+            if let Some(metadata) = &metadata {
+                let key = (metadata.chain_id, bridge_address);
+                if let Some(first) = shared_bridges.get(&key) {
+                    info!(
+                        rollup_id,
+                        "{:?}: chain {:?} and bridge {:?} shared with rollup {:?}, indexed by it",
+                        name,
+                        metadata.chain_id,
+                        bridge_address,
+                        first
+                    );
+                    register_unindexed(&database, &cli, rollup_id, &name, Some(metadata)).await?;
+                    registered.push(rollup_id);
+                    if let Some(indexer) = indexers.iter().find(|i| i.rollup_id == *first) {
+                        indexer.shared_rollups.lock().unwrap().push(rollup_id);
+                    }
+                    continue;
+                }
+                shared_bridges.insert(key, rollup_id);
+            }
+            info!(rollup_id, "trusted_seq: {:?}", redact_url(&trusted_seq));
             if rollup_id == 3 {
                 //trusted_seq = Url::parse("https://xlayerrpc.okx.com/unlimited/abc")?;
             }
         }
 
-        database.insert_rollup(rollup_id, &name).await?;
-        println!(
-            "name: {:?} rollup_id: {:?} trusted_seq: {:?}",
-            name, rollup_id, trusted_seq
+        let database = database.for_rollup(rollup_id).await?;
+        database
+            .insert_rollup(rollup_id, &name, rollup_name(&cli, rollup_id))
+            .await?;
+        registered.push(rollup_id);
+        if let Some(metadata) = &metadata {
+            database.update_rollup_metadata(rollup_id, metadata).await?;
+        }
+        info!(
+            rollup_id,
+            "name: {:?} trusted_seq: {:?}",
+            name,
+            redact_url(&trusted_seq)
         );
 
+        let mut config = indexer_config.clone();
+        config.rpc_headers = rpc_headers(&cli, rollup_id);
         let mut indexer = Indexer::new(
             bridge_address,
             trusted_seq.clone(),
             rollup_id,
             database.clone(),
+            config,
         )
         .await?;
 
+        if rollup_id == 0 && cli.index_verify_batches {
+            indexer.rollup_manager_address = Some(rollup_manager_address);
+        }
+        if rollup_id == 0 {
+            indexer.ger_manager_address = ger_manager_address;
+        }
+
+        if cli.genesis_from_receipt {
+            indexer.start_block = match database.deploy_block(rollup_id).await? {
+                Some(block) => block,
+                None => match indexer.detect_deploy_block().await {
+                    Ok(Some(block)) => {
+                        database.set_deploy_block(rollup_id, block).await?;
+                        block
+                    }
+                    Ok(None) => {
+                        warn!(
+                            rollup_id,
+                            "bridge has no code, using start block {:?}", cli.default_start_block
+                        );
+                        cli.default_start_block
+                    }
+                    Err(e) => {
+                        warn!(
+                            rollup_id,
+                            "could not detect deploy block ({}), using start block {:?}",
+                            e,
+                            cli.default_start_block
+                        );
+                        cli.default_start_block
+                    }
+                },
+            };
+            info!(
+                rollup_id,
+                "indexing from start block {:?}", indexer.start_block
+            );
+        }
+
+        if let Some(timestamp) = rollup_value(&cli.start_timestamp, rollup_id) {
+            let block = match database
+                .start_timestamp_block(rollup_id, *timestamp)
+                .await?
+            {
+                Some(block) => block,
+                None => {
+                    let block = indexer.block_at_timestamp(*timestamp).await?;
+                    database
+                        .set_start_timestamp_block(rollup_id, *timestamp, block)
+                        .await?;
+                    block
+                }
+            };
+            indexer.start_block = indexer.start_block.max(block);
+            info!(
+                rollup_id,
+                "timestamp {:?} resolved to start block {:?}", timestamp, indexer.start_block
+            );
+        }
+
+        if let Some(block) = rollup_value(&cli.pin_head, rollup_id) {
+            indexer.pinned_head = Some(*block);
+            info!(rollup_id, "pinned at block {:?}", block);
+        }
+        indexer.sink = sink.clone();
+
         // TODO: Most likely wrong use of clone
         indexers.push(indexer.clone());
 
-        tokio::spawn(async move {
-            if let Err(e) = indexer.index().await {
-                eprintln!(
-                    "indexer of rollup {:?} encountered an error: {:?}",
-                    rollup_id, e
-                );
-                if let Some(source) = e.source() {
-                    eprintln!("Caused by: {:?}", source);
-                }
-                panic!("indexer of rollup {:?} error: {:?}", rollup_id, e);
-            }
-        });
+        guards.push(indexer.spawn());
+    }
+
+    if cli.db_per_rollup {
+        database.combine_rollups(&registered).await?;
     }
 
     // ---- HTTP server (initialized after indexers are ready)
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    let tls = tls_config(&cli).await?;
+    let listener = tokio::net::TcpListener::bind(cli.listen_addr.as_str()).await?;
+    info!(
+        "Serving api on: {:?} ({})",
+        cli.listen_addr,
+        if tls.is_some() { "https" } else { "http" }
+    );
 
     let app_state = AppState {
         database: database.clone(),
+        max_response_bytes: cli.max_response_bytes,
+        require_limit: cli.require_limit,
+        hex_prefix: !cli.no_0x_prefix,
     };
+    if let Some(webhook) = &cli.alert_webhook {
+        let webhook: Url = webhook.parse()?;
+        info!("Sending lag alerts to: {:?}", redact_url(&webhook));
+        tokio::spawn(alert::watch_lag(
+            indexers.clone(),
+            webhook,
+            cli.alert_lag_threshold,
+            std::time::Duration::from_secs(cli.alert_cooldown),
+        ));
+    }
+
+    if cli.resolve_interval > 0 {
+        tokio::spawn(watch_endpoints(
+            provider.clone(),
+            rollup_manager_address,
+            indexers.clone(),
+            cli.rollup_rpc.iter().cloned().collect(),
+            std::time::Duration::from_secs(cli.resolve_interval),
+        ));
+    }
+
     let query_router = Router::new()
         .route("/query", get(query_handler))
+        .route("/query/batch", post(query_batch_handler))
         .with_state(app_state);
 
-    let api_router = api::create_router(database.db().clone(), indexers.clone());
-    let app = query_router.merge(api_router);
+    let server_config = api::ServerConfig(serde_json::json!({
+        "rpc_url": redact_url(&rpc_url),
+        "rollup_manager_address": rollup_manager_address.to_string(),
+        "listen_addr": cli.listen_addr,
+        "tls": cli.tls_cert.is_some(),
+        "metrics_addr": cli.metrics_addr,
+        "db_per_rollup": cli.db_per_rollup,
+        "amount_type": format!("{:?}", cli.amount_type).to_lowercase(),
+        "events": cli.events.to_string(),
+        "write_queue_capacity": cli.write_queue_capacity,
+        "index_unconfirmed": cli.index_unconfirmed,
+        "sink": cli.sink.is_some(),
+        "duckdb_extensions": cli.duckdb_extensions,
+        "rollup_names": cli.rollup_name,
+        "rollup_rpcs": cli
+            .rollup_rpc
+            .iter()
+            .map(|(id, url)| (id, redact_url(url)))
+            .collect::<Vec<_>>(),
+        "rpc_auth_headers": cli
+            .rpc_auth_header
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>(),
+        "rollup_rpc_headers": cli
+            .rollup_rpc_header
+            .iter()
+            .map(|(id, RpcHeader(name, _))| (id, name.as_str()))
+            .collect::<Vec<_>>(),
+        "start_timestamps": cli.start_timestamp,
+        "pinned_heads": cli.pin_head,
+        "resolve_interval": cli.resolve_interval,
+        "admin_api": cli.admin_api_key.is_some(),
+        "api_max_concurrency": cli.api_max_concurrency,
+        "api_rate_limit": cli.api_rate_limit,
+        "api_cache_ttl": cli.api_cache_ttl,
+        "max_response_bytes": cli.max_response_bytes,
+        "require_limit": cli.require_limit,
+        "no_0x_prefix": cli.no_0x_prefix,
+        "alert_webhook": cli.alert_webhook.is_some(),
+    }));
+    let cache = api::ResponseCache::new(
+        database.clone(),
+        std::time::Duration::from_secs(cli.api_cache_ttl),
+    );
+    let api_router = api::create_router(
+        database.db().clone(),
+        indexers.clone(),
+        server_config,
+        cache.clone(),
+    );
+    let mut app = query_router.merge(api_router);
+    if cli.api_max_concurrency.is_some() || cli.api_rate_limit.is_some() {
+        let limits = api::ApiLimits::new(cli.api_max_concurrency, cli.api_rate_limit);
+        app = app.layer(axum::middleware::from_fn_with_state(
+            limits,
+            api::limit_requests,
+        ));
+    }
 
-    let server = axum::serve(listener, app);
-    tokio::spawn(async move {
-        if let Err(e) = server.await {
-            eprintln!("HTTP server error: {}", e);
-        }
-    });
+    if let Some(api_key) = &cli.admin_api_key {
+        app = app.merge(api::create_admin_router(database.clone(), api_key.clone()));
+    }
+
+    let metrics_router = api::create_metrics_router(indexers.clone(), database.clone(), cache);
+    if let Some(metrics_addr) = &cli.metrics_addr {
+        let metrics_listener = tokio::net::TcpListener::bind(metrics_addr.as_str()).await?;
+        info!("Serving metrics on: {:?}", metrics_addr);
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(metrics_listener, metrics_router).await {
+                error!("Metrics server error: {}", e);
+            }
+        });
+    } else {
+        app = app.merge(metrics_router);
+    }
+
+    if cli.no_0x_prefix {
+        app = app.layer(axum::middleware::from_fn_with_state(
+            cli.max_response_bytes,
+            api::strip_hex_prefix,
+        ));
+    }
+
+    app = api::trace_requests(app);
+
+    #[cfg(feature = "msgpack")]
+    {
+        app = app.layer(axum::middleware::from_fn_with_state(
+            cli.max_response_bytes,
+            api::negotiate_encoding,
+        ));
+    }
+
+    spawn_api(listener, app, tls)?;
 
     tokio::signal::ctrl_c()
         .await
         .expect("failed to listen for event");
-    println!("Received Ctrl+C, shutting down...");
+    info!("Received Ctrl+C, shutting down...");
 
-    // TODO: ugly but important
-    println!("Shutting down indexers");
-    for indexer in indexers {
-        indexer.shutdown();
-    }
+    info!("Shutting down indexers");
+    drop(guards);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_per_rollup_values() {
+        assert_eq!(parse_rollup_kv::<u64>("3=100"), Ok((3, 100)));
+        assert_eq!(
+            parse_rollup_kv::<String>("3=a=b"),
+            Ok((3, "a=b".to_string()))
+        );
+        assert!(parse_rollup_kv::<u64>("3").is_err());
+        assert!(parse_rollup_kv::<u64>("x=100").is_err());
+        assert!(parse_rollup_kv::<u64>("3=latest").is_err());
+
+        let (rollup_id, RpcHeader(name, value)) =
+            parse_rollup_kv::<RpcHeader>("3=Authorization: Bearer key").unwrap();
+        assert_eq!(rollup_id, 3);
+        assert_eq!(name.as_str(), "authorization");
+        assert!(value.is_sensitive());
+
+        let values = [(1, 10), (3, 30)];
+        assert_eq!(rollup_value(&values, 3), Some(&30));
+        assert_eq!(rollup_value(&values, 2), None);
+    }
+
+    #[test]
+    fn rpc_and_rollup_manager_fall_back_to_the_environment() {
+        std::env::set_var("DAGGBOARD_RPC_URL", "http://env:8545");
+        std::env::set_var(
+            "DAGGBOARD_ROLLUP_MANAGER",
+            "0x0000000000000000000000000000000000000001",
+        );
+        let cli = Cli::parse_from(["daggboard"]);
+        assert_eq!(cli.rpc_url.as_deref(), Some("http://env:8545"));
+        assert_eq!(
+            cli.rollup_manager_address,
+            "0x0000000000000000000000000000000000000001"
+        );
+
+        // The flags take precedence.
+        let cli = Cli::parse_from([
+            "daggboard",
+            "--rpc-url",
+            "http://flag:8545",
+            "0x0000000000000000000000000000000000000002",
+        ]);
+        std::env::remove_var("DAGGBOARD_RPC_URL");
+        std::env::remove_var("DAGGBOARD_ROLLUP_MANAGER");
+        assert_eq!(cli.rpc_url.as_deref(), Some("http://flag:8545"));
+        assert_eq!(
+            cli.rollup_manager_address,
+            "0x0000000000000000000000000000000000000002"
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_https_with_the_given_certificate() {
+        let certified = rcgen::generate_simple_self_signed(["127.0.0.1".to_string()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("daggboard-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert, certified.cert.pem()).unwrap();
+        std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+
+        let cli = Cli::parse_from([
+            "daggboard",
+            "--tls-cert",
+            cert.to_str().unwrap(),
+            "--tls-key",
+            key.to_str().unwrap(),
+        ]);
+        let tls = tls_config(&cli).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(tls.is_some());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        spawn_api(listener, app, tls).unwrap();
+
+        let client = Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let response = client
+            .get(format!("https://127.0.0.1:{}/health", port))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+    }
+
+    #[test]
+    fn blobs_are_hex_encoded_with_the_configured_prefix() {
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let query = "SELECT '\\x01\\xAB'::BLOB AS data, ''::BLOB AS empty";
+
+        let rows = run_query(&conn, query, &[], &mut None, true).unwrap();
+        assert_eq!(rows[0]["data"], "0x01ab");
+        let rows = run_query(&conn, query, &[], &mut None, false).unwrap();
+        assert_eq!(rows[0]["data"], "01ab");
+        assert_eq!(rows[0]["empty"], "0x");
+    }
+
+    #[test]
+    fn responses_stop_at_the_byte_budget() {
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        // Every row, {"n":"1"}, counts 10 bytes.
+        let query = "SELECT range::VARCHAR AS n FROM range(1, 10)";
+
+        let mut budget = Some(90);
+        assert_eq!(
+            run_query(&conn, query, &[], &mut budget, true)
+                .unwrap()
+                .len(),
+            9
+        );
+        assert_eq!(budget, Some(0));
+        // A batch shares the budget between its queries.
+        let (status, _) = run_query(&conn, query, &[], &mut budget, true).unwrap_err();
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let (status, _) = run_query(&conn, query, &[], &mut Some(89), true).unwrap_err();
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            run_query(&conn, query, &[], &mut None, true).unwrap().len(),
+            9
+        );
+    }
+
+    async fn batch(
+        queries: &[(&str, &[&str])],
+    ) -> std::result::Result<Vec<Vec<HashMap<String, String>>>, (StatusCode, String)> {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        let state = AppState {
+            database,
+            max_response_bytes: None,
+            require_limit: false,
+            hex_prefix: true,
+        };
+        let queries = queries
+            .iter()
+            .map(|(q, params)| BatchQuery {
+                q: q.to_string(),
+                params: params.iter().map(|p| p.to_string()).collect(),
+            })
+            .collect();
+        query_batch_handler(State(state), Json(queries))
+            .await
+            .map(|Json(results)| results)
+    }
+
+    #[tokio::test]
+    async fn batch_runs_every_query_with_its_params() {
+        let results = batch(&[
+            ("SELECT ? AS a", &["1"]),
+            (
+                "SELECT COUNT(*)::VARCHAR AS n FROM range(?::INTEGER)",
+                &["3"],
+            ),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0][0]["a"], "1");
+        assert_eq!(results[1][0]["n"], "3");
+    }
+
+    #[tokio::test]
+    async fn batch_fails_as_a_whole() {
+        let (status, error) = batch(&[("SELECT 1", &[]), ("SELECT * FROM missing", &[])])
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(error.starts_with("query 1: "), "{}", error);
+
+        let (status, _) = batch(&[("SELECT 1", &[]), ("DROP TABLE rollups", &[])])
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn only_limited_selects_pass_the_limit_check() {
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        for query in [
+            "SELECT * FROM range(10) LIMIT 5",
+            "SELECT * FROM range(10) LIMIT 10 PERCENT",
+            "SELECT 1 LIMIT 1; SELECT 2 LIMIT 1",
+        ] {
+            assert!(check_limit(&conn, query).is_ok(), "{}", query);
+        }
+        for query in [
+            "SELECT * FROM range(10)",
+            // Only the outermost level counts.
+            "SELECT * FROM (SELECT * FROM range(10) LIMIT 5)",
+            "SELECT 1 LIMIT 1; SELECT 2",
+            "PRAGMA database_size",
+            "SELEC 1 LIMIT 1",
+            "",
+        ] {
+            let (status, _) = check_limit(&conn, query).unwrap_err();
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", query);
+        }
+    }
+
+    #[test]
+    fn checked_database_file_is_the_opened_one() {
+        let DbLocation::File(path) = DbLocation::default() else {
+            panic!("the default database is a file");
+        };
+        assert_eq!(database_files(false).unwrap(), [path]);
+    }
+
+    #[tokio::test]
+    async fn misconfigured_rollups_are_still_resolved() {
+        use alloy::primitives::Bytes;
+        use alloy::providers::ProviderBuilder;
+        use alloy::sol_types::SolCall;
+        use alloy::transports::mock::Asserter;
+        use daggboard::contracts::PolygonRollupBaseEtrog::{
+            bridgeAddressCall, networkNameCall, trustedSequencerURLCall,
+        };
+
+        let asserter = Asserter::new();
+        let provider = &ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        let bridge = Address::repeat_byte(0xbb);
+        // rollupIDToRollupData returns a struct of 12 static fields, the
+        // first being the rollup contract.
+        let mut rollup_data = vec![0u8; 12 * 32];
+        rollup_data[12..32].copy_from_slice(Address::repeat_byte(0xaa).as_slice());
+        let resolve = |url: &str, name: &str| {
+            asserter.push_success(&Bytes::from(rollup_data.clone()));
+            asserter.push_success(&Bytes::from(trustedSequencerURLCall::abi_encode_returns(
+                &url.to_string(),
+            )));
+            asserter.push_success(&Bytes::from(networkNameCall::abi_encode_returns(
+                &name.to_string(),
+            )));
+            asserter.push_success(&Bytes::from(bridgeAddressCall::abi_encode_returns(&bridge)));
+            resolve_rollup(provider, Address::repeat_byte(0x01), 7)
+        };
+
+        let resolved = resolve("https://rpc.example.com", "zkevm").await.unwrap();
+        assert_eq!(resolved.name, "zkevm");
+        assert_eq!(
+            resolved.trusted_seq,
+            Some("https://rpc.example.com".parse().unwrap())
+        );
+        assert_eq!(resolved.bridge_address, bridge);
+
+        let resolved = resolve("", " ").await.unwrap();
+        assert_eq!(resolved.name, "rollup-7");
+        assert_eq!(resolved.trusted_seq, None);
+    }
+}
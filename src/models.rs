@@ -0,0 +1,237 @@
+use duckdb::Row;
+use serde::{Deserialize, Serialize};
+
+// Typed rows of the event tables, mirroring the schema in database.rs.
+// Columns are read by name, so the select lists below can be used as is or
+// with `SELECT *` when amounts are stored as text (see AmountType). Amounts
+// are None when out of the range of the column type.
+
+// A row of bridge_events.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BridgeEventRow {
+    pub id: String,
+    pub rollup_id: i64,
+    pub transaction_hash: String,
+    pub block_hash: String,
+    pub block_number: i64,
+    pub transaction_index: i64,
+    pub log_index: i64,
+    pub leaf_type: i64,
+    pub origin_network: i64,
+    pub origin_address: String,
+    pub destination_network: i64,
+    pub destination_address: String,
+    pub amount: Option<String>,
+    pub metadata: String,
+    pub deposit_count: i64,
+}
+
+impl BridgeEventRow {
+    pub const COLUMNS: &'static str = "id, rollup_id, transaction_hash, block_hash, \
+        block_number, transaction_index, log_index, leafType, originNetwork, originAddress, \
+        destinationNetwork, destinationAddress, CAST(amount AS TEXT) AS amount, metadata, \
+        depositCount";
+
+    pub fn from_row(row: &Row) -> duckdb::Result<Self> {
+        Ok(BridgeEventRow {
+            id: row.get("id")?,
+            rollup_id: row.get("rollup_id")?,
+            transaction_hash: row.get("transaction_hash")?,
+            block_hash: row.get("block_hash")?,
+            block_number: row.get("block_number")?,
+            transaction_index: row.get("transaction_index")?,
+            log_index: row.get("log_index")?,
+            leaf_type: row.get("leafType")?,
+            origin_network: row.get("originNetwork")?,
+            origin_address: row.get("originAddress")?,
+            destination_network: row.get("destinationNetwork")?,
+            destination_address: row.get("destinationAddress")?,
+            amount: row.get("amount")?,
+            metadata: row.get("metadata")?,
+            deposit_count: row.get("depositCount")?,
+        })
+    }
+}
+
+// A row of claim_events. The global index fields are only decoded for v2
+// claims.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClaimEventRow {
+    pub id: String,
+    pub rollup_id: i64,
+    pub transaction_hash: String,
+    pub block_hash: String,
+    pub block_number: i64,
+    pub transaction_index: i64,
+    pub log_index: i64,
+    pub version: i64,
+    pub global_index: String,
+    pub origin_network: i64,
+    pub origin_address: String,
+    pub destination_address: String,
+    pub amount: Option<String>,
+    pub mainnet_flag: Option<bool>,
+    pub source_rollup_id: Option<i64>,
+    pub local_exit_index: Option<i64>,
+    // From the claimed bridge event, see create_schema.
+    pub leaf_type: Option<i64>,
+}
+
+impl ClaimEventRow {
+    pub const COLUMNS: &'static str = "id, rollup_id, transaction_hash, block_hash, \
+        block_number, transaction_index, log_index, version, globalIndex, originNetwork, \
+        originAddress, destinationAddress, CAST(amount AS TEXT) AS amount, mainnet_flag, \
+        source_rollup_id, local_exit_index, leafType";
+
+    pub fn from_row(row: &Row) -> duckdb::Result<Self> {
+        Ok(ClaimEventRow {
+            id: row.get("id")?,
+            rollup_id: row.get("rollup_id")?,
+            transaction_hash: row.get("transaction_hash")?,
+            block_hash: row.get("block_hash")?,
+            block_number: row.get("block_number")?,
+            transaction_index: row.get("transaction_index")?,
+            log_index: row.get("log_index")?,
+            version: row.get("version")?,
+            global_index: row.get("globalIndex")?,
+            origin_network: row.get("originNetwork")?,
+            origin_address: row.get("originAddress")?,
+            destination_address: row.get("destinationAddress")?,
+            amount: row.get("amount")?,
+            mainnet_flag: row.get("mainnet_flag")?,
+            source_rollup_id: row.get("source_rollup_id")?,
+            local_exit_index: row.get("local_exit_index")?,
+            leaf_type: row.get("leafType")?,
+        })
+    }
+}
+
+// A row of the all_transfers view, `source` being "wrapped" or "bridge".
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransferRow {
+    pub source: String,
+    pub id: String,
+    pub rollup_id: i64,
+    pub transaction_hash: String,
+    pub block_hash: String,
+    pub block_number: i64,
+    pub transaction_index: i64,
+    pub log_index: i64,
+    pub from_address: String,
+    pub to_address: String,
+    pub token_address: String,
+    pub value: Option<String>,
+}
+
+impl TransferRow {
+    pub const COLUMNS: &'static str = "source, id, rollup_id, transaction_hash, block_hash, \
+        block_number, transaction_index, log_index, from_address, to_address, token_address, \
+        CAST(value AS TEXT) AS value";
+
+    pub fn from_row(row: &Row) -> duckdb::Result<Self> {
+        Ok(TransferRow {
+            source: row.get("source")?,
+            id: row.get("id")?,
+            rollup_id: row.get("rollup_id")?,
+            transaction_hash: row.get("transaction_hash")?,
+            block_hash: row.get("block_hash")?,
+            block_number: row.get("block_number")?,
+            transaction_index: row.get("transaction_index")?,
+            log_index: row.get("log_index")?,
+            from_address: row.get("from_address")?,
+            to_address: row.get("to_address")?,
+            token_address: row.get("token_address")?,
+            value: row.get("value")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::PolygonZkEVMBridgeV2::BridgeEvent;
+    use crate::contracts::ERC20::Transfer;
+    use crate::database::{AmountType, Database, DbLocation};
+    use alloy::primitives::{Address, Bytes, Log as Log2, B256, U256};
+    use alloy::rpc::types::Log;
+
+    fn log<T>(address: Address, data: T) -> Log<T> {
+        Log {
+            inner: Log2 { address, data },
+            block_hash: Some(B256::with_last_byte(1)),
+            block_number: Some(1),
+            block_timestamp: None,
+            transaction_hash: Some(B256::with_last_byte(1)),
+            transaction_index: Some(0),
+            log_index: Some(0),
+            removed: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn bridge_events_are_read_into_rows() {
+        let database = Database::open(DbLocation::Memory, AmountType::Hugeint)
+            .await
+            .unwrap();
+        let event = BridgeEvent {
+            leafType: 0,
+            originNetwork: 0,
+            originAddress: Address::repeat_byte(0x22),
+            destinationNetwork: 1,
+            destinationAddress: Address::repeat_byte(0x33),
+            amount: U256::from(1000),
+            metadata: Bytes::new(),
+            depositCount: 7,
+        };
+        database
+            .insert_bridge_event(&log(Address::repeat_byte(0xbb), event), 1)
+            .await
+            .unwrap();
+
+        let conn = database.db().lock().await;
+        let row = conn
+            .query_row(
+                &format!("SELECT {} FROM bridge_events", BridgeEventRow::COLUMNS),
+                [],
+                BridgeEventRow::from_row,
+            )
+            .unwrap();
+        assert_eq!(row.rollup_id, 1);
+        assert_eq!(row.block_number, 1);
+        assert_eq!(row.deposit_count, 7);
+        assert_eq!(row.destination_network, 1);
+        assert_eq!(row.amount.as_deref(), Some("1000"));
+
+        let json = serde_json::to_value(&row).unwrap();
+        assert_eq!(json["deposit_count"], 7);
+        assert_eq!(serde_json::from_value::<BridgeEventRow>(json).unwrap(), row);
+    }
+
+    #[tokio::test]
+    async fn transfers_are_read_into_rows() {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        let transfer = Transfer {
+            from: Address::ZERO,
+            to: Address::repeat_byte(0x33),
+            value: U256::from(5),
+        };
+        database
+            .insert_wrapped_transfer_event(&log(Address::repeat_byte(0x22), transfer), 1)
+            .await
+            .unwrap();
+
+        let conn = database.db().lock().await;
+        let row = conn
+            .query_row(
+                &format!("SELECT {} FROM all_transfers", TransferRow::COLUMNS),
+                [],
+                TransferRow::from_row,
+            )
+            .unwrap();
+        assert_eq!(row.source, "wrapped");
+        assert_eq!(row.from_address, Address::ZERO.to_string());
+        assert_eq!(row.value.as_deref(), Some("5"));
+    }
+}
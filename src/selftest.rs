@@ -0,0 +1,227 @@
+use crate::{
+    indexer_config, resolve_rollup, rollup_name, rollup_value, rpc_headers, Cli, L1_BRIDGE_ADDRESS,
+};
+use alloy::primitives::{Address, I256};
+use alloy::transports::http::reqwest::Url;
+use daggboard::database::{Database, DbLocation, EVENT_TABLES};
+use daggboard::indexer::{build_provider, Indexer};
+use tracing::info;
+
+// Indexes blocks [from, to] of a rollup against the configured rpcs into an
+// in-memory database, then checks that:
+// - events were found in the range.
+// - every bridge in transfer decoded, none is in indexing_errors.
+// - the checks of verify pass: bridge balances reconcile exactly with the
+//   circulating supply and no event lacks a required field. Tokens wrapped
+//   before the range have no supply and are not reconciled.
+// Returns each check with whether it passed. A failure to index the range is
+// a failed check, not an error.
+pub async fn selftest(
+    cli: &Cli,
+    rollup_id: u32,
+    from: u64,
+    to: u64,
+) -> Result<Vec<(bool, String)>, Box<dyn std::error::Error>> {
+    if from > to {
+        return Err(format!("empty range, --from {} is after --to {}", from, to).into());
+    }
+    let rpc_url: Url = cli
+        .rpc_url
+        .as_deref()
+        .ok_or("--rpc-url (or DAGGBOARD_RPC_URL) is required")?
+        .parse()?;
+    let mut config = indexer_config(cli);
+    // Everything is written before the checks run.
+    config.write_queue_capacity = 0;
+    config.rpc_headers = rpc_headers(cli, 0);
+
+    let (name, bridge_address, rollup_rpc) = if rollup_id == 0 {
+        ("l1".to_string(), L1_BRIDGE_ADDRESS, rpc_url)
+    } else {
+        let rollup_manager_address: Address = cli.rollup_manager_address.parse()?;
+        let provider = build_provider(rpc_url, &config)?;
+        let resolved = resolve_rollup(&provider, rollup_manager_address, rollup_id).await?;
+        let rpc_override = rollup_value(&cli.rollup_rpc, rollup_id).cloned();
+        let Some(rollup_rpc) = rpc_override.or(resolved.trusted_seq) else {
+            return Err(format!(
+                "rollup {} has no usable sequencer url, set --rollup-rpc {}=<url>",
+                rollup_id, rollup_id
+            )
+            .into());
+        };
+        (resolved.name, resolved.bridge_address, rollup_rpc)
+    };
+    config.rpc_headers = rpc_headers(cli, rollup_id);
+
+    let database = Database::open(DbLocation::Memory, cli.amount_type).await?;
+    database
+        .insert_rollup(rollup_id, &name, rollup_name(cli, rollup_id))
+        .await?;
+    let mut indexer = Indexer::new(
+        bridge_address,
+        rollup_rpc,
+        rollup_id,
+        database.clone(),
+        config,
+    )
+    .await?;
+    indexer.start_block = from;
+
+    info!(rollup_id, "Indexing blocks {} to {} ({})", from, to, name);
+    check_range(&mut indexer, &database, from, to).await
+}
+
+// Indexes blocks [from, to] with an indexer writing inline to `database` and
+// runs the checks of selftest on them.
+async fn check_range(
+    indexer: &mut Indexer,
+    database: &Database,
+    from: u64,
+    to: u64,
+) -> Result<Vec<(bool, String)>, Box<dyn std::error::Error>> {
+    let rollup_id = indexer.rollup_id;
+    if let Err(e) = indexer.index_range(from, to).await {
+        return Ok(vec![(
+            false,
+            format!("indexing blocks {} to {}: {}", from, to, e),
+        )]);
+    }
+
+    let mut checks = Vec::new();
+    {
+        let db = database.db().lock().await;
+        let mut events = 0;
+        for table in EVENT_TABLES {
+            let count: i64 = db.query_row(
+                &format!("SELECT COUNT(*) FROM {} WHERE rollup_id = ?", table),
+                [rollup_id],
+                |row| row.get(0),
+            )?;
+            events += count;
+        }
+        checks.push((
+            events > 0,
+            format!("{} events found in blocks {} to {}", events, from, to),
+        ));
+
+        let undecodable: i64 = db.query_row(
+            "SELECT COUNT(*) FROM indexing_errors WHERE rollup_id = ?",
+            [rollup_id],
+            |row| row.get(0),
+        )?;
+        checks.push((
+            undecodable == 0,
+            format!("{} logs could not be decoded", undecodable),
+        ));
+    }
+
+    let failures = crate::verify::verify(database, I256::ZERO).await?;
+    if failures.is_empty() {
+        checks.push((
+            true,
+            "balances reconcile and no event lacks a required field".to_string(),
+        ));
+    }
+    checks.extend(failures.into_iter().map(|failure| (false, failure)));
+    Ok(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::{Bytes, B256, U256};
+    use alloy::providers::ProviderBuilder;
+    use alloy::rpc::types::Log;
+    use alloy::sol_types::SolEvent;
+    use alloy::transports::mock::Asserter;
+    use daggboard::contracts::PolygonZkEVMBridgeV2::BridgeEvent;
+    use daggboard::database::AmountType;
+    use daggboard::indexer::IndexerConfig;
+
+    const BRIDGE: Address = Address::repeat_byte(0xbb);
+
+    async fn mock_indexer(asserter: &Asserter) -> (Indexer, Database) {
+        let database = Database::open(DbLocation::Memory, AmountType::Varchar)
+            .await
+            .unwrap();
+        database.insert_rollup(1, "test", None).await.unwrap();
+        let config = IndexerConfig {
+            write_queue_capacity: 0,
+            index_transfers: false,
+            ..IndexerConfig::default()
+        };
+        let mut indexer = Indexer::new(
+            BRIDGE,
+            "http://localhost:8545".parse().unwrap(),
+            1,
+            database.clone(),
+            config,
+        )
+        .await
+        .unwrap();
+        indexer.provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        (indexer, database)
+    }
+
+    fn deposit(block: u64) -> Log {
+        let event = BridgeEvent {
+            leafType: 0,
+            originNetwork: 0,
+            originAddress: Address::ZERO,
+            destinationNetwork: 2,
+            destinationAddress: Address::repeat_byte(0x11),
+            amount: U256::from(1),
+            metadata: Bytes::new(),
+            depositCount: 0,
+        };
+        Log {
+            inner: alloy::primitives::Log {
+                address: BRIDGE,
+                data: event.encode_log_data(),
+            },
+            block_hash: Some(B256::with_last_byte(block as u8)),
+            block_number: Some(block),
+            block_timestamp: None,
+            transaction_hash: Some(B256::with_last_byte(1)),
+            transaction_index: Some(0),
+            log_index: Some(0),
+            removed: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn passes_on_a_consistent_range() {
+        let asserter = Asserter::new();
+        let (mut indexer, database) = mock_indexer(&asserter).await;
+        asserter.push_success(&vec![deposit(5)]);
+
+        let checks = check_range(&mut indexer, &database, 0, 9).await.unwrap();
+        assert!(checks.iter().all(|(passed, _)| *passed), "{:?}", checks);
+        assert_eq!(checks[0].1, "1 events found in blocks 0 to 9");
+    }
+
+    #[tokio::test]
+    async fn fails_on_an_empty_range() {
+        let asserter = Asserter::new();
+        let (mut indexer, database) = mock_indexer(&asserter).await;
+        asserter.push_success(&Vec::<Log>::new());
+
+        let checks = check_range(&mut indexer, &database, 0, 9).await.unwrap();
+        assert_eq!(
+            checks[0],
+            (false, "0 events found in blocks 0 to 9".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn indexing_errors_are_a_failed_check() {
+        let asserter = Asserter::new();
+        let (mut indexer, database) = mock_indexer(&asserter).await;
+        asserter.push_failure_msg("block range too large");
+
+        let checks = check_range(&mut indexer, &database, 0, 9).await.unwrap();
+        assert_eq!(checks.len(), 1);
+        assert!(!checks[0].0);
+        assert!(checks[0].1.contains("block range too large"));
+    }
+}
@@ -0,0 +1,170 @@
+use futures::future::BoxFuture;
+use std::sync::Arc;
+
+// Publishes the decoded events of the indexers to a message bus, see --sink.
+// Events are published before they are stored, and a failed publish fails
+// the window like a failed insert, so the sync cursor never moves past an
+// event that was not published. Events of a retried window are published
+// again: delivery is at least once, consumers dedup on the event id.
+pub trait EventSink: Send + Sync {
+    fn publish(&self, rollup_id: u32, payload: Vec<u8>) -> BoxFuture<'_, Result<(), String>>;
+}
+
+// Prefix of the topics (kafka) or subjects (nats) when the url has no path.
+const DEFAULT_TOPIC_PREFIX: &str = "daggboard";
+
+// Each rollup is published to its own topic, {prefix}.{rollup_id}.
+#[cfg(any(feature = "sink-kafka", feature = "sink-nats"))]
+fn topic(prefix: &str, rollup_id: u32) -> String {
+    format!("{}.{}", prefix, rollup_id)
+}
+
+// Connects to the sink of a --sink url, kafka://broker[,broker..][/prefix]
+// or nats://server[/prefix]. They need the sink-kafka and sink-nats features.
+pub async fn connect(url: &str) -> Result<Arc<dyn EventSink>, String> {
+    let (scheme, rest) = url
+        .split_once("://")
+        .ok_or_else(|| format!("invalid sink url: {}", url))?;
+    #[allow(unused_variables)]
+    let (servers, prefix) = match rest.split_once('/') {
+        Some((servers, prefix)) if !prefix.is_empty() => (servers, prefix),
+        Some((servers, _)) => (servers, DEFAULT_TOPIC_PREFIX),
+        None => (rest, DEFAULT_TOPIC_PREFIX),
+    };
+    match scheme {
+        #[cfg(feature = "sink-kafka")]
+        "kafka" => Ok(Arc::new(KafkaSink::new(servers, prefix)?)),
+        #[cfg(feature = "sink-nats")]
+        "nats" => Ok(Arc::new(NatsSink::connect(servers, prefix).await?)),
+        "kafka" | "nats" => Err(format!(
+            "{} sink not available, build with --features sink-{}",
+            scheme, scheme
+        )),
+        _ => Err(format!(
+            "unsupported sink {}, use kafka:// or nats://",
+            scheme
+        )),
+    }
+}
+
+// Keeps the published events in memory, for embedding the indexer (see
+// Indexer::sink) and checking what would be published.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    published: Arc<std::sync::Mutex<Vec<Published>>>,
+}
+
+// A published event as (rollup_id, payload).
+pub type Published = (u32, Vec<u8>);
+
+impl MemorySink {
+    // Events published so far, in order.
+    pub fn published(&self) -> Vec<Published> {
+        self.published.lock().unwrap().clone()
+    }
+}
+
+impl EventSink for MemorySink {
+    fn publish(&self, rollup_id: u32, payload: Vec<u8>) -> BoxFuture<'_, Result<(), String>> {
+        self.published.lock().unwrap().push((rollup_id, payload));
+        Box::pin(async { Ok(()) })
+    }
+}
+
+// Waits for the broker to acknowledge every message, with idempotence on so
+// that producer retries don't duplicate them.
+#[cfg(feature = "sink-kafka")]
+pub struct KafkaSink {
+    producer: rdkafka::producer::FutureProducer,
+    prefix: String,
+}
+
+#[cfg(feature = "sink-kafka")]
+impl KafkaSink {
+    pub fn new(brokers: &str, prefix: &str) -> Result<Self, String> {
+        let producer = rdkafka::config::ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("enable.idempotence", "true")
+            .set("acks", "all")
+            .create()
+            .map_err(|e| format!("failed to create kafka producer: {}", e))?;
+        Ok(KafkaSink {
+            producer,
+            prefix: prefix.to_string(),
+        })
+    }
+}
+
+#[cfg(feature = "sink-kafka")]
+impl EventSink for KafkaSink {
+    fn publish(&self, rollup_id: u32, payload: Vec<u8>) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            let topic = topic(&self.prefix, rollup_id);
+            let record = rdkafka::producer::FutureRecord::<(), _>::to(&topic).payload(&payload);
+            self.producer
+                .send(record, std::time::Duration::from_secs(30))
+                .await
+                .map_err(|(e, _)| format!("failed to publish to {}: {}", topic, e))?;
+            Ok(())
+        })
+    }
+}
+
+// Publishes through JetStream and waits for the ack, so the subjects must be
+// captured by a stream. Core nats publishes are fire and forget.
+#[cfg(feature = "sink-nats")]
+pub struct NatsSink {
+    jetstream: async_nats::jetstream::Context,
+    prefix: String,
+}
+
+#[cfg(feature = "sink-nats")]
+impl NatsSink {
+    pub async fn connect(servers: &str, prefix: &str) -> Result<Self, String> {
+        let client = async_nats::connect(servers)
+            .await
+            .map_err(|e| format!("failed to connect to nats: {}", e))?;
+        Ok(NatsSink {
+            jetstream: async_nats::jetstream::new(client),
+            prefix: prefix.to_string(),
+        })
+    }
+}
+
+#[cfg(feature = "sink-nats")]
+impl EventSink for NatsSink {
+    fn publish(&self, rollup_id: u32, payload: Vec<u8>) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            let subject = topic(&self.prefix, rollup_id);
+            self.jetstream
+                .publish(subject.clone(), payload.into())
+                .await
+                .map_err(|e| format!("failed to publish to {}: {}", subject, e))?
+                .await
+                .map_err(|e| format!("no ack for {}: {}", subject, e))?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rejects_unknown_sinks() {
+        assert!(connect("localhost:9092").await.is_err());
+        assert!(connect("redis://localhost").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn memory_sink_keeps_events_in_order() {
+        let sink = MemorySink::default();
+        sink.publish(1, b"first".to_vec()).await.unwrap();
+        sink.publish(2, b"second".to_vec()).await.unwrap();
+        assert_eq!(
+            sink.published(),
+            vec![(1, b"first".to_vec()), (2, b"second".to_vec())]
+        );
+    }
+}
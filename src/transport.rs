@@ -0,0 +1,183 @@
+use alloy::rpc::json_rpc::{RequestPacket, ResponsePacket};
+use alloy::transports::http::reqwest::header::{HeaderValue, RETRY_AFTER};
+use alloy::transports::http::reqwest::{Client, StatusCode, Url};
+use alloy::transports::{TransportError, TransportErrorKind, TransportFut};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower::Service;
+
+// Retries of a request answered with a 429 and a Retry-After header. Other
+// rate limit responses are left to the RetryBackoffLayer above.
+const MAX_RETRY_AFTER_RETRIES: u32 = 5;
+
+// Longest Retry-After that is honoured, so that a bogus header can't stall
+// the indexer.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+// Http transport that waits as long as the Retry-After header of a 429
+// response asks before retrying the request. RetryBackoffLayer only sees the
+// status and body of the response, so it can only use its fixed backoff.
+#[derive(Debug, Clone)]
+pub struct RetryAfterHttp {
+    client: Client,
+    url: Url,
+}
+
+impl RetryAfterHttp {
+    pub fn new(client: Client, url: Url) -> Self {
+        RetryAfterHttp { client, url }
+    }
+
+    async fn send(self, request: RequestPacket) -> Result<ResponsePacket, TransportError> {
+        let mut retries = 0;
+        loop {
+            let response = self
+                .client
+                .post(self.url.clone())
+                .json(&request)
+                .send()
+                .await
+                .map_err(TransportErrorKind::custom)?;
+            let status = response.status();
+
+            if status == StatusCode::TOO_MANY_REQUESTS && retries < MAX_RETRY_AFTER_RETRIES {
+                if let Some(wait) = retry_after(response.headers().get(RETRY_AFTER)) {
+                    retries += 1;
+                    tokio::time::sleep(wait).await;
+                    continue;
+                }
+            }
+
+            let body = response.bytes().await.map_err(TransportErrorKind::custom)?;
+            if !status.is_success() {
+                return Err(TransportErrorKind::http_error(
+                    status.as_u16(),
+                    String::from_utf8_lossy(&body).into_owned(),
+                ));
+            }
+            return serde_json::from_slice(&body)
+                .map_err(|e| TransportError::deser_err(e, String::from_utf8_lossy(&body)));
+        }
+    }
+}
+
+impl Service<RequestPacket> for RetryAfterHttp {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: RequestPacket) -> Self::Future {
+        Box::pin(self.clone().send(request))
+    }
+}
+
+// Wait asked by a Retry-After header, capped to MAX_RETRY_AFTER. Only the
+// delay in seconds form is supported, not the http date one.
+pub fn retry_after(value: Option<&HeaderValue>) -> Option<Duration> {
+    let seconds: u64 = value?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(seconds).min(MAX_RETRY_AFTER))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::providers::{Provider, ProviderBuilder};
+    use alloy::rpc::client::RpcClient;
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Rpc answering its first request with a 429 asking to retry after
+    // `retry_after`, and the others with block 1. Records when each request
+    // came in.
+    async fn rate_limited_rpc(
+        retry_after: &'static str,
+        requests: Arc<Mutex<Vec<Instant>>>,
+    ) -> Url {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 4096];
+                let end = loop {
+                    let n = stream.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break end;
+                    }
+                };
+                let head = String::from_utf8_lossy(&buf[..end]).to_lowercase();
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .map_or(0, |len| len.trim().parse().unwrap());
+                while buf.len() < end + 4 + length {
+                    let n = stream.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                }
+                let request: serde_json::Value =
+                    serde_json::from_slice(&buf[end + 4..end + 4 + length]).unwrap();
+
+                let first = {
+                    let mut requests = requests.lock().unwrap();
+                    requests.push(Instant::now());
+                    requests.len() == 1
+                };
+                let response = if first {
+                    format!(
+                        "HTTP/1.1 429 Too Many Requests\r\nretry-after: {}\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+                        retry_after
+                    )
+                } else {
+                    let body = serde_json::json!({ "jsonrpc": "2.0", "id": request["id"], "result": "0x1" })
+                        .to_string();
+                    format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        url.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn rate_limited_requests_wait_as_asked() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let url = rate_limited_rpc("1", requests.clone()).await;
+        let provider = ProviderBuilder::new().connect_client(RpcClient::new(
+            RetryAfterHttp::new(Client::new(), url),
+            true,
+        ));
+
+        assert_eq!(provider.get_block_number().await.unwrap(), 1);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let waited = requests[1] - requests[0];
+        assert!(waited >= Duration::from_secs(1), "waited {:?}", waited);
+        assert!(waited < Duration::from_secs(2), "waited {:?}", waited);
+    }
+
+    #[test]
+    fn retry_after_is_capped_and_only_in_seconds() {
+        let header = |value: &'static str| Some(HeaderValue::from_static(value));
+        assert_eq!(
+            retry_after(header(" 3 ").as_ref()),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(retry_after(header("3600").as_ref()), Some(MAX_RETRY_AFTER));
+        assert_eq!(
+            retry_after(header("Wed, 21 Oct 2015 07:28:00 GMT").as_ref()),
+            None
+        );
+        assert_eq!(retry_after(None), None);
+    }
+}
@@ -1,21 +1,118 @@
 use alloy::{
-    primitives::{Address, FixedBytes},
+    primitives::{keccak256, Address, FixedBytes, U256},
     rpc::types::{FilterSet, Log, Topic},
+    sol_types::{sol_data, SolType},
+    transports::http::reqwest::Url,
 };
 use sha2::{Digest, Sha256};
 
 // Calculates a unique identifier for each log. It uses the tx hash,
-// the log index and the rollup id.
-pub fn hash_log<T>(log: &Log<T>, rollup_id: u32) -> String {
+// the log index and the rollup id. None if the log lacks any of them.
+pub fn hash_log<T>(log: &Log<T>, rollup_id: u32) -> Option<String> {
     let mut hasher = Sha256::new();
-    hasher.update(log.transaction_hash.unwrap().to_string());
-    hasher.update(log.log_index.unwrap().to_string());
+    hasher.update(log.transaction_hash?.to_string());
+    hasher.update(log.log_index?.to_string());
     hasher.update(rollup_id.to_string());
-    format!("{:x}", hasher.finalize())
+    Some(format!("{:x}", hasher.finalize()))
+}
+
+// Position of a log in the chain. All stored logs must have it, but pending
+// logs (and some rpcs) leave these fields empty.
+#[derive(Debug, Clone)]
+pub struct LogPosition {
+    pub transaction_hash: String,
+    pub block_hash: String,
+    pub block_number: u64,
+    pub transaction_index: u64,
+    pub log_index: u64,
+}
+
+pub fn log_position<T>(log: &Log<T>) -> Option<LogPosition> {
+    Some(LogPosition {
+        transaction_hash: log.transaction_hash?.to_string(),
+        block_hash: log.block_hash?.to_string(),
+        block_number: log.block_number?,
+        transaction_index: log.transaction_index?,
+        log_index: log.log_index?,
+    })
 }
 
 pub fn to_topic(address: Address) -> Topic {
-    let mut address_bytes = [0u8; 32];
-    address_bytes[12..].copy_from_slice(address.as_slice());
-    FilterSet::from(FixedBytes::<32>::from_slice(&address_bytes))
+    to_topic_set(&[address])
+}
+
+// Topic matching any of the addresses, for filters on an indexed address
+// (eg topic1/topic2 of a Transfer).
+pub fn to_topic_set(addresses: &[Address]) -> Topic {
+    let words: Vec<FixedBytes<32>> = addresses.iter().map(|a| a.into_word()).collect();
+    FilterSet::from(words)
+}
+
+// topic0 of an event, the keccak256 of its signature, eg
+// "Transfer(address,address,uint256)".
+pub fn event_topic0(signature: &str) -> FixedBytes<32> {
+    keccak256(signature)
+}
+
+// Decodes the token metadata attached to NewWrappedToken and BridgeEvent, which
+// is the abi encoded (name, symbol, decimals) of the origin token. Returns None
+// when the metadata is empty (eg the gas token) or malformed.
+pub fn decode_token_metadata(metadata: &[u8]) -> Option<(String, String, u8)> {
+    <(sol_data::String, sol_data::String, sol_data::Uint<8>)>::abi_decode_params(metadata).ok()
+}
+
+// Decodes the global index of a v2 claim. Bits 0..32 are the local exit root
+// index, bits 32..64 the rollup index and bit 64 the mainnet flag. Returns the
+// mainnet flag, the source rollup id (0 for mainnet, rollup index + 1
+// otherwise) and the local exit root index.
+pub fn decode_global_index(global_index: U256) -> (bool, u32, u32) {
+    let mask = U256::from(u32::MAX);
+    let mainnet_flag = global_index.bit(64);
+    let rollup_index = ((global_index >> 32usize) & mask).to::<u32>();
+    let local_exit_index = (global_index & mask).to::<u32>();
+    let source_rollup_id = if mainnet_flag {
+        0
+    } else {
+        rollup_index.saturating_add(1)
+    };
+    (mainnet_flag, source_rollup_id, local_exit_index)
+}
+
+// Hides the credentials of an rpc url so it can be logged. Keeps the scheme,
+// host and port but masks the user info, the path (eg infura's /v3/<key>) and
+// the query.
+pub fn redact_url(url: &Url) -> String {
+    let mut redacted = format!("{}://{}", url.scheme(), url.host_str().unwrap_or_default());
+    if let Some(port) = url.port() {
+        redacted.push_str(&format!(":{}", port));
+    }
+    if !url.path().is_empty() && url.path() != "/" {
+        redacted.push_str("/***");
+    }
+    if url.query().is_some() {
+        redacted.push_str("?***");
+    }
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::b256;
+
+    #[test]
+    fn topics_match_the_indexed_addresses() {
+        assert_eq!(
+            event_topic0("Transfer(address,address,uint256)"),
+            b256!("ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef")
+        );
+
+        let (a, b) = (Address::repeat_byte(0x11), Address::repeat_byte(0x22));
+        let topic = to_topic_set(&[a, b]);
+        assert_eq!(topic.len(), 2);
+        assert!(topic.contains(&a.into_word()));
+        assert!(topic.contains(&b.into_word()));
+        assert!(!topic.contains(&Address::repeat_byte(0x33).into_word()));
+        assert_eq!(to_topic(a), to_topic_set(&[a]));
+    }
 }
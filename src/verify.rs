@@ -0,0 +1,159 @@
+use crate::api::{coverage_gaps, merge_ranges};
+use alloy::primitives::I256;
+use daggboard::database::{Database, EVENT_TABLES};
+use duckdb::Connection;
+use std::collections::HashMap;
+
+// Columns every event row must have.
+const REQUIRED_COLUMNS: [&str; 6] = [
+    "id",
+    "rollup_id",
+    "transaction_hash",
+    "block_hash",
+    "block_number",
+    "log_index",
+];
+
+// Checks the consistency of the indexed data, the same way /reconcile and
+// /coverage do for a single token or rollup:
+// - the bridge balance of every wrapped token matches its circulating supply,
+//   give or take `tolerance` (in token units).
+// - the blocks from the start of each rollup up to its sync marker were all
+//   indexed. Blocks indexed before the ranges were recorded are covered by
+//   the range seeded when the table was created.
+// - no event row lacks a required column.
+// Returns a line per failed check, empty if everything is consistent.
+pub async fn verify(
+    database: &Database,
+    tolerance: I256,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let db = database.db().lock().await;
+    let mut failures = unbalanced_tokens(&db, tolerance)?;
+    failures.extend(coverage_failures(&db)?);
+    failures.extend(missing_fields(&db)?);
+    Ok(failures)
+}
+
+fn unbalanced_tokens(db: &Connection, tolerance: I256) -> Result<Vec<String>, duckdb::Error> {
+    let mut balances: HashMap<(i64, String), (String, String)> = HashMap::new();
+    let mut stmt = db.prepare(
+        "SELECT rollup_id, token_address, kind, balance FROM token_balances \
+        ORDER BY rollup_id, token_address",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let kind: String = row.get(2)?;
+        let balance: String = row.get(3)?;
+        let entry = balances
+            .entry((row.get(0)?, row.get(1)?))
+            .or_insert_with(|| ("0".to_string(), "0".to_string()));
+        match kind.as_str() {
+            "bridge" => entry.0 = balance,
+            _ => entry.1 = balance,
+        }
+    }
+
+    let mut failures = Vec::new();
+    let mut tokens: Vec<_> = balances.into_iter().collect();
+    tokens.sort();
+    for ((rollup_id, token_address), (bridge_balance, circulating_supply)) in tokens {
+        // Only wrapped tokens have a supply to reconcile against.
+        if circulating_supply == "0" {
+            continue;
+        }
+        let difference = match (
+            I256::from_dec_str(&bridge_balance),
+            I256::from_dec_str(&circulating_supply),
+        ) {
+            (Ok(bridge), Ok(supply)) => bridge.checked_sub(supply),
+            _ => None,
+        };
+        match difference {
+            Some(difference) if difference.unsigned_abs() <= tolerance.unsigned_abs() => {}
+            Some(difference) => failures.push(format!(
+                "rollup {} token {}: bridge balance {} and circulating supply {} differ by {}",
+                rollup_id, token_address, bridge_balance, circulating_supply, difference
+            )),
+            None => failures.push(format!(
+                "rollup {} token {}: balances out of range",
+                rollup_id, token_address
+            )),
+        }
+    }
+    Ok(failures)
+}
+
+fn coverage_failures(db: &Connection) -> Result<Vec<String>, duckdb::Error> {
+    let mut stmt = db.prepare(
+        "SELECT rollup_id, COALESCE(start_timestamp_block, deploy_block), \
+            latest_bridge_synced_block \
+        FROM rollups ORDER BY rollup_id",
+    )?;
+    let rollups = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<usize, i64>(0)?,
+                row.get::<usize, Option<i64>>(1)?,
+                row.get::<usize, Option<i64>>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut failures = Vec::new();
+    for (rollup_id, start_block, synced_block) in rollups {
+        let (Some(start_block), Some(synced_block)) = (start_block, synced_block) else {
+            continue;
+        };
+        let mut stmt = db.prepare(
+            "SELECT from_block, to_block FROM indexed_ranges WHERE rollup_id = ? \
+            ORDER BY from_block, to_block",
+        )?;
+        let ranges = stmt
+            .query_map([rollup_id], |row| {
+                Ok((
+                    row.get::<usize, i64>(0)? as u64,
+                    row.get::<usize, i64>(1)? as u64,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let gaps = coverage_gaps(
+            &merge_ranges(ranges),
+            start_block as u64,
+            synced_block.max(0) as u64,
+        );
+        if !gaps.is_empty() {
+            failures.push(format!(
+                "rollup {}: {} coverage gaps up to block {}, first {:?}",
+                rollup_id,
+                gaps.len(),
+                synced_block,
+                gaps[0]
+            ));
+        }
+    }
+    Ok(failures)
+}
+
+fn missing_fields(db: &Connection) -> Result<Vec<String>, duckdb::Error> {
+    let condition = REQUIRED_COLUMNS
+        .iter()
+        .map(|column| format!("{} IS NULL", column))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+
+    let mut failures = Vec::new();
+    for table in EVENT_TABLES {
+        let count: i64 = db.query_row(
+            &format!("SELECT COUNT(*) FROM {} WHERE {}", table, condition),
+            [],
+            |row| row.get(0),
+        )?;
+        if count > 0 {
+            failures.push(format!(
+                "{}: {} rows without a required field",
+                table, count
+            ));
+        }
+    }
+    Ok(failures)
+}